@@ -14,7 +14,7 @@ fn main() {
         println!("  * {}", device.friendly_name());
     }
 
-    if let Some(first_device) = devices.get(0) {
+    if let Some(first_device) = devices.first() {
         println!("Content of {}:", first_device.friendly_name());
         show_content(first_device).unwrap();
     }
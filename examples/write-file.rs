@@ -13,7 +13,7 @@ fn main() {
         println!("  * {}", device.friendly_name());
     }
 
-    if let Some(first_device) = devices.get(0) {
+    if let Some(first_device) = devices.first() {
         println!("Writing a file to {}...", first_device.friendly_name());
         send_file_to_device(first_device).unwrap();
     }
@@ -0,0 +1,458 @@
+//! A device whose storages are folders of the local file system
+//!
+//! A [`DirectoryDevice`] exposes local folders as storages, with WPD-like object IDs (e.g. `s10001` for storages, `o2C` for other objects).
+//! Object IDs are assigned the first time a file is listed, and remain the same as long as this device lives, even if the file is renamed or moved.
+//!
+//! This is mostly useful to serve local content to MTP initiators, through a [`Responder`](crate::responder::Responder).
+//!
+//! ```
+//! use std::rc::Rc;
+//! use winmtp::backend::directory::DirectoryDevice;
+//! use winmtp::device::BasicDevice;
+//!
+//! let device = DirectoryDevice::new("Shared folders");
+//! device.add_storage("Temp", std::env::temp_dir());
+//!
+//! let basic_device = BasicDevice::new(
+//!     widestring::U16CString::from_str_truncate(device.device_id()),
+//!     device.friendly_name().to_string(),
+//!     Rc::new(device),
+//! );
+//! ```
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use windows::Win32::Devices::PortableDevices::{
+    WPD_DEVICE_OBJECT_ID, WPD_OBJECT_ID, WPD_OBJECT_PARENT_ID, WPD_OBJECT_NAME, WPD_OBJECT_ORIGINAL_FILE_NAME,
+    WPD_OBJECT_CONTENT_TYPE, WPD_OBJECT_SIZE, WPD_OBJECT_DATE_CREATED, WPD_OBJECT_DATE_MODIFIED, WPD_OBJECT_ISHIDDEN,
+    WPD_OBJECT_CONTAINER_FUNCTIONAL_OBJECT_ID,
+    WPD_FUNCTIONAL_OBJECT_CATEGORY, WPD_FUNCTIONAL_CATEGORY_DEVICE, WPD_FUNCTIONAL_CATEGORY_STORAGE,
+    WPD_CONTENT_TYPE_FUNCTIONAL_OBJECT, WPD_CONTENT_TYPE_FOLDER, WPD_CONTENT_TYPE_UNSPECIFIED,
+};
+use widestring::{U16CStr, U16CString};
+
+use crate::backend::{DeviceBackend, OpenedDeviceBackend, ContentBackend, ReadStreamBackend, WriteStreamBackend, for_each_object, for_each_property};
+use crate::device::device_values::{AppIdentifiers, DeviceValues, PropertyValue};
+use crate::error::MtpError;
+use crate::object::{ObjectId, ObjectIdRef};
+use crate::protocol::mapping::{MTP_OBJECT_PROTECTION_STATUS, PROTECTION_NONE, PROTECTION_READ_ONLY_DATA};
+
+const OPTIMAL_TRANSFER_SIZE: u32 = 256 * 1024;
+
+/// A device that serves local folders.
+///
+/// Cloning this struct returns another handle to the same device.
+#[derive(Debug, Clone)]
+pub struct DirectoryDevice {
+    device_id: String,
+    friendly_name: String,
+    state: Rc<RefCell<DirectoryState>>,
+}
+
+#[derive(Debug)]
+struct DirectoryState {
+    storages: Vec<DirectoryStorage>,
+    paths: HashMap<U16CString, PathBuf>,
+    ids: HashMap<PathBuf, U16CString>,
+    next_object_id: u32,
+}
+
+#[derive(Debug)]
+struct DirectoryStorage {
+    id: U16CString,
+    name: String,
+    root: PathBuf,
+}
+
+/// What an object ID designates
+enum Location {
+    Device,
+    Storage(usize),
+    Path{ path: PathBuf, storage: usize },
+}
+
+fn device_object_id() -> U16CString {
+    unsafe{ U16CString::from_ptr_str(WPD_DEVICE_OBJECT_ID.as_ptr()) }
+}
+
+fn string(value: &str) -> PropertyValue {
+    PropertyValue::String(U16CString::from_str_truncate(value))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+impl DirectoryDevice {
+    /// Create a device that has no storage yet
+    pub fn new(friendly_name: &str) -> Self {
+        Self {
+            device_id: format!("directory:{}", friendly_name),
+            friendly_name: friendly_name.to_string(),
+            state: Rc::new(RefCell::new(DirectoryState{
+                storages: Vec::new(),
+                paths: HashMap::new(),
+                ids: HashMap::new(),
+                next_object_id: 1,
+            })),
+        }
+    }
+
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    pub fn friendly_name(&self) -> &str {
+        &self.friendly_name
+    }
+
+    /// Expose a local folder as a storage, and return the ID of this storage
+    pub fn add_storage(&self, name: &str, root: impl Into<PathBuf>) -> ObjectId {
+        let mut state = self.state.borrow_mut();
+        let id = U16CString::from_str_truncate(format!("s{:X}", 0x10001 + state.storages.len()));
+        state.storages.push(DirectoryStorage{ id: id.clone(), name: name.to_string(), root: root.into() });
+        id.into()
+    }
+
+    /// The local path of an object, if it is a file or a folder
+    pub fn path(&self, object_id: &ObjectIdRef) -> Option<PathBuf> {
+        self.state.borrow().paths.get(object_id.as_ucstr()).cloned()
+    }
+}
+
+impl DirectoryState {
+    fn locate(&self, object_id: &U16CStr) -> Result<Location, MtpError> {
+        if object_id == device_object_id().as_ucstr() {
+            return Ok(Location::Device);
+        }
+        if let Some(index) = self.storages.iter().position(|storage| storage.id.as_ucstr() == object_id) {
+            return Ok(Location::Storage(index));
+        }
+        let path = self.paths.get(object_id).ok_or(MtpError::ObjectNotFound)?;
+        let storage = self.storages
+            .iter()
+            .position(|storage| path.starts_with(&storage.root))
+            .ok_or(MtpError::ObjectNotFound)?;
+        Ok(Location::Path{ path: path.clone(), storage })
+    }
+
+    /// The folder an object ID designates, if objects can be put into it
+    fn folder(&self, object_id: &U16CStr) -> Result<PathBuf, MtpError> {
+        match self.locate(object_id)? {
+            Location::Device => Err(MtpError::InvalidArgument("Objects cannot be put at the root of a device, only in its storages".to_string())),
+            Location::Storage(index) => Ok(self.storages[index].root.clone()),
+            Location::Path{ path, .. } if path.is_dir() => Ok(path),
+            Location::Path{ .. } => Err(MtpError::InvalidArgument("Destination is not a folder".to_string())),
+        }
+    }
+
+    fn id_of(&mut self, path: &Path) -> U16CString {
+        if let Some(id) = self.ids.get(path) {
+            return id.clone();
+        }
+        let id = U16CString::from_str_truncate(format!("o{:X}", self.next_object_id));
+        self.next_object_id += 1;
+        self.paths.insert(id.clone(), path.to_path_buf());
+        self.ids.insert(path.to_path_buf(), id.clone());
+        id
+    }
+
+    /// The ID of the parent of a file or folder
+    fn parent_id_of(&mut self, path: &Path, storage: usize) -> U16CString {
+        match path.parent() {
+            Some(parent) if parent != self.storages[storage].root => self.id_of(parent),
+            _ => self.storages[storage].id.clone(),
+        }
+    }
+
+    /// Keep the IDs of a file or folder (and of its descendants) after it has been renamed or moved
+    fn rebase(&mut self, old_path: &Path, new_path: &Path) {
+        for path in self.paths.values_mut() {
+            if let Ok(relative) = path.strip_prefix(old_path) {
+                *path = new_path.join(relative);
+            }
+        }
+        self.ids = self.paths.iter().map(|(id, path)| (path.clone(), id.clone())).collect();
+    }
+
+    /// Forget the IDs of a deleted file or folder (and of its descendants)
+    fn forget(&mut self, deleted_path: &Path) {
+        self.paths.retain(|_, path| !path.starts_with(deleted_path));
+        self.ids.retain(|path, _| !path.starts_with(deleted_path));
+    }
+
+    fn all_properties(&mut self, object_id: &U16CStr) -> Result<DeviceValues, MtpError> {
+        let mut values = DeviceValues::new();
+        values.set(WPD_OBJECT_ID, PropertyValue::String(object_id.to_ucstring()));
+        values.set(WPD_OBJECT_CONTENT_TYPE, PropertyValue::Guid(WPD_CONTENT_TYPE_FUNCTIONAL_OBJECT));
+
+        match self.locate(object_id)? {
+            Location::Device => {
+                values.set(WPD_OBJECT_PARENT_ID, PropertyValue::String(U16CString::new()));
+                values.set(WPD_OBJECT_NAME, PropertyValue::String(device_object_id()));
+                values.set(WPD_FUNCTIONAL_OBJECT_CATEGORY, PropertyValue::Guid(WPD_FUNCTIONAL_CATEGORY_DEVICE));
+            },
+            Location::Storage(index) => {
+                values.set(WPD_OBJECT_PARENT_ID, PropertyValue::String(device_object_id()));
+                values.set(WPD_OBJECT_NAME, string(&self.storages[index].name));
+                values.set(WPD_FUNCTIONAL_OBJECT_CATEGORY, PropertyValue::Guid(WPD_FUNCTIONAL_CATEGORY_STORAGE));
+            },
+            Location::Path{ path, storage } => {
+                let metadata = fs::metadata(&path)?;
+                let name = file_name(&path);
+                values.set(WPD_OBJECT_PARENT_ID, PropertyValue::String(self.parent_id_of(&path, storage)));
+                values.set(WPD_OBJECT_CONTAINER_FUNCTIONAL_OBJECT_ID, PropertyValue::String(self.storages[storage].id.clone()));
+                values.set(WPD_OBJECT_NAME, string(&name));
+                values.set(WPD_OBJECT_ORIGINAL_FILE_NAME, string(&name));
+                values.set(WPD_OBJECT_ISHIDDEN, PropertyValue::Bool(name.starts_with('.')));
+                if metadata.is_dir() {
+                    values.set(WPD_OBJECT_CONTENT_TYPE, PropertyValue::Guid(WPD_CONTENT_TYPE_FOLDER));
+                } else {
+                    values.set(WPD_OBJECT_CONTENT_TYPE, PropertyValue::Guid(WPD_CONTENT_TYPE_UNSPECIFIED));
+                    values.set(WPD_OBJECT_SIZE, PropertyValue::U64(metadata.len()));
+                }
+                // Whether a file can be deleted depends on its folder, not on its own permissions
+                let protection_status = if metadata.permissions().readonly() { PROTECTION_READ_ONLY_DATA } else { PROTECTION_NONE };
+                values.set(MTP_OBJECT_PROTECTION_STATUS, PropertyValue::U32(protection_status));
+                if let Ok(modified) = metadata.modified() {
+                    values.set(WPD_OBJECT_DATE_MODIFIED, PropertyValue::Date(modified));
+                }
+                if let Ok(created) = metadata.created() {
+                    values.set(WPD_OBJECT_DATE_CREATED, PropertyValue::Date(created));
+                }
+            },
+        }
+        Ok(values)
+    }
+}
+
+/// The file name an object should be created with
+fn name_for_creation(properties: &DeviceValues) -> Result<String, MtpError> {
+    let name = properties.get_string(&WPD_OBJECT_ORIGINAL_FILE_NAME)
+        .or_else(|_| properties.get_string(&WPD_OBJECT_NAME))?
+        .to_string_lossy();
+    // Objects must not escape their folder
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(MtpError::InvalidProperty);
+    }
+    Ok(name)
+}
+
+impl DeviceBackend for DirectoryDevice {
+    fn open(&self, _app_identifiers: &AppIdentifiers) -> Result<Box<dyn OpenedDeviceBackend>, MtpError> {
+        Ok(Box::new(self.clone()))
+    }
+}
+
+impl OpenedDeviceBackend for DirectoryDevice {
+    fn content(&self) -> Result<Rc<dyn ContentBackend>, MtpError> {
+        Ok(Rc::new(self.clone()))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl ContentBackend for DirectoryDevice {
+    fn children(&self, parent_id: &U16CStr) -> Result<Box<dyn Iterator<Item = U16CString>>, MtpError> {
+        let mut state = self.state.borrow_mut();
+        let folder = match state.locate(parent_id)? {
+            Location::Device => {
+                let storage_ids: Vec<_> = state.storages.iter().map(|storage| storage.id.clone()).collect();
+                return Ok(Box::new(storage_ids.into_iter()));
+            },
+            Location::Storage(index) => state.storages[index].root.clone(),
+            Location::Path{ path, .. } if path.is_dir() => path,
+            Location::Path{ .. } => return Ok(Box::new(std::iter::empty())),
+        };
+
+        let mut paths = fs::read_dir(folder)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.sort();
+        let children: Vec<_> = paths.iter().map(|path| state.id_of(path)).collect();
+        Ok(Box::new(children.into_iter()))
+    }
+
+    fn properties(&self, object_id: &U16CStr, properties_to_fetch: &[crate::PROPERTYKEY]) -> Result<DeviceValues, MtpError> {
+        let all_values = self.state.borrow_mut().all_properties(object_id)?;
+
+        let mut values = DeviceValues::new();
+        for key in properties_to_fetch {
+            if let Some(value) = all_values.get(key) {
+                values.set(*key, value.clone());
+            }
+        }
+        Ok(values)
+    }
+
+    fn set_properties(&self, object_id: &U16CStr, values: &DeviceValues) -> Result<Vec<Result<(), MtpError>>, MtpError> {
+        let mut state = self.state.borrow_mut();
+        let is_path = matches!(state.locate(object_id)?, Location::Path{ .. });
+
+        for_each_property(values, |key, value| {
+            if !is_path {
+                return Err(MtpError::AccessDenied("Property is read-only".to_string()));
+            }
+            if *key == WPD_OBJECT_NAME || *key == WPD_OBJECT_ORIGINAL_FILE_NAME {
+                let mut rename = DeviceValues::new();
+                rename.set(WPD_OBJECT_ORIGINAL_FILE_NAME, value.clone());
+                let current_path = state.paths.get(object_id).ok_or(MtpError::ObjectNotFound)?.clone();
+                let new_path = current_path.with_file_name(name_for_creation(&rename)?);
+                if new_path != current_path {
+                    if new_path.exists() {
+                        return Err(MtpError::AlreadyExists);
+                    }
+                    fs::rename(&current_path, &new_path)?;
+                    state.rebase(&current_path, &new_path);
+                }
+            } else if *key == WPD_OBJECT_DATE_MODIFIED {
+                let PropertyValue::Date(date) = value else {
+                    return Err(MtpError::InvalidProperty);
+                };
+                let current_path = state.paths.get(object_id).ok_or(MtpError::ObjectNotFound)?;
+                File::open(current_path)?.set_modified(*date)?;
+            } else {
+                return Err(MtpError::AccessDenied("Property is read-only".to_string()));
+            }
+            Ok(())
+        })
+    }
+
+    fn create_object(&self, properties: &DeviceValues) -> Result<U16CString, MtpError> {
+        if properties.get_guid(&WPD_OBJECT_CONTENT_TYPE).ok() != Some(WPD_CONTENT_TYPE_FOLDER) {
+            return Err(MtpError::InvalidArgument("Only folders can be created without data".to_string()));
+        }
+
+        let mut state = self.state.borrow_mut();
+        let path = state.folder(&properties.get_string(&WPD_OBJECT_PARENT_ID)?)?.join(name_for_creation(properties)?);
+        fs::create_dir(&path)?;
+        Ok(state.id_of(&path))
+    }
+
+    fn create_object_with_data(&self, properties: &DeviceValues) -> Result<(Box<dyn WriteStreamBackend>, u32), MtpError> {
+        let path = self.state.borrow().folder(&properties.get_string(&WPD_OBJECT_PARENT_ID)?)?.join(name_for_creation(properties)?);
+        let stream = DirectoryWriteStream{
+            state: Rc::clone(&self.state),
+            path,
+            expected_size: properties.get_u64(&WPD_OBJECT_SIZE).ok(),
+            date_modified: properties.get_date(&WPD_OBJECT_DATE_MODIFIED).ok(),
+            data: Vec::new(),
+            created_id: None,
+        };
+        Ok((Box::new(stream), OPTIMAL_TRANSFER_SIZE))
+    }
+
+    fn open_read_stream(&self, object_id: &U16CStr) -> Result<(Box<dyn ReadStreamBackend>, u32), MtpError> {
+        let Location::Path{ path, .. } = self.state.borrow().locate(object_id)? else {
+            return Err(MtpError::InvalidArgument("Object has no data".to_string()));
+        };
+        if path.is_dir() {
+            return Err(MtpError::InvalidArgument("Object has no data".to_string()));
+        }
+        Ok((Box::new(File::open(path)?), OPTIMAL_TRANSFER_SIZE))
+    }
+
+    fn delete(&self, object_ids: &[&U16CStr], recursive: bool) -> Result<Vec<Result<(), MtpError>>, MtpError> {
+        let mut state = self.state.borrow_mut();
+        for_each_object(object_ids, |object_id| {
+            let Location::Path{ path, .. } = state.locate(object_id)? else {
+                return Err(MtpError::AccessDenied("Functional objects cannot be deleted".to_string()));
+            };
+            if !path.is_dir() {
+                fs::remove_file(&path)?;
+            } else if recursive {
+                fs::remove_dir_all(&path)?;
+            } else if fs::read_dir(&path)?.next().is_some() {
+                return Err(MtpError::InvalidArgument("Folder is not empty".to_string()));
+            } else {
+                fs::remove_dir(&path)?;
+            }
+            state.forget(&path);
+            Ok(())
+        })
+    }
+
+    fn move_objects(&self, object_ids: &[&U16CStr], destination_folder_id: &U16CStr) -> Result<Vec<Result<(), MtpError>>, MtpError> {
+        let mut state = self.state.borrow_mut();
+        let destination = state.folder(destination_folder_id)?;
+        for_each_object(object_ids, |object_id| {
+            let Location::Path{ path, .. } = state.locate(object_id)? else {
+                return Err(MtpError::AccessDenied("Functional objects cannot be moved".to_string()));
+            };
+            if destination.starts_with(&path) {
+                return Err(MtpError::InvalidArgument("Cannot move a folder into itself".to_string()));
+            }
+            let new_path = destination.join(path.file_name().ok_or(MtpError::ObjectNotFound)?);
+            if new_path.exists() {
+                return Err(MtpError::AlreadyExists);
+            }
+            fs::rename(&path, &new_path)?;
+            state.rebase(&path, &new_path);
+            Ok(())
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl ReadStreamBackend for File {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        Seek::seek(self, position)
+    }
+}
+
+/// Data is buffered, and the file is only written when the stream is committed, so that aborted transfers leave nothing behind
+struct DirectoryWriteStream {
+    state: Rc<RefCell<DirectoryState>>,
+    path: PathBuf,
+    expected_size: Option<u64>,
+    date_modified: Option<std::time::SystemTime>,
+    data: Vec<u8>,
+    created_id: Option<U16CString>,
+}
+
+impl Write for DirectoryWriteStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.data.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WriteStreamBackend for DirectoryWriteStream {
+    fn commit(&mut self) -> Result<(), MtpError> {
+        if self.created_id.is_some() {
+            return Ok(());
+        }
+        if let Some(expected_size) = self.expected_size {
+            if expected_size != self.data.len() as u64 {
+                return Err(MtpError::InvalidArgument(format!("Expected {} bytes, got {}", expected_size, self.data.len())));
+            }
+        }
+
+        let mut file = File::options().write(true).create_new(true).open(&self.path)?;
+        file.write_all(&self.data)?;
+        if let Some(date_modified) = self.date_modified {
+            file.set_modified(date_modified)?;
+        }
+        self.data = Vec::new();
+        self.created_id = Some(self.state.borrow_mut().id_of(&self.path));
+        Ok(())
+    }
+
+    fn object_id(&self) -> Option<U16CString> {
+        self.created_id.clone()
+    }
+}
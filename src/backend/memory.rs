@@ -0,0 +1,581 @@
+//! A fully in-memory device, mostly useful for testing
+//!
+//! A [`MemoryDevice`] holds a tree of storages, folders and files, that are identified by WPD-like object IDs
+//! (e.g. `s10001` for storages, `o2C` for other objects), and that have the usual WPD properties
+//! (name, original file name, content type, size, dates...).
+//! Storages have no capacity, unless one is set with [`MemoryDevice::set_storage_capacity`].
+//! Their paths are case-sensitive, unless [`MemoryDevice::set_storage_case_insensitive`] is called.
+//!
+//! ```
+//! use std::rc::Rc;
+//! use winmtp::backend::memory::{MemoryDevice, MemoryProvider};
+//!
+//! let device = MemoryDevice::new("Fake phone");
+//! let storage_id = device.add_storage("Internal shared storage");
+//! let download_id = device.add_folder(&storage_id, "Download").unwrap();
+//! device.add_file(&download_id, "notes.txt", b"hello").unwrap();
+//!
+//! let memory_provider = MemoryProvider::new();
+//! memory_provider.add_device(device);
+//! let mut provider = winmtp::Provider::empty();
+//! provider.add_backend(Rc::new(memory_provider));
+//! assert_eq!(provider.enumerate_devices().unwrap()[0].friendly_name(), "Fake phone");
+//! ```
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Seek, SeekFrom, Write};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+use windows::Win32::Devices::PortableDevices::{
+    WPD_DEVICE_OBJECT_ID, WPD_OBJECT_ID, WPD_OBJECT_PARENT_ID, WPD_OBJECT_NAME, WPD_OBJECT_ORIGINAL_FILE_NAME, WPD_OBJECT_PERSISTENT_UNIQUE_ID,
+    WPD_OBJECT_CONTENT_TYPE, WPD_OBJECT_SIZE, WPD_OBJECT_DATE_CREATED, WPD_OBJECT_DATE_MODIFIED, WPD_OBJECT_CONTAINER_FUNCTIONAL_OBJECT_ID,
+    WPD_FUNCTIONAL_OBJECT_CATEGORY, WPD_FUNCTIONAL_CATEGORY_DEVICE, WPD_FUNCTIONAL_CATEGORY_STORAGE,
+    WPD_CONTENT_TYPE_FUNCTIONAL_OBJECT, WPD_CONTENT_TYPE_FOLDER, WPD_CONTENT_TYPE_UNSPECIFIED,
+    WPD_STORAGE_TYPE, WPD_STORAGE_TYPE_FIXED_RAM, WPD_STORAGE_ACCESS_CAPABILITY, WPD_STORAGE_ACCESS_CAPABILITY_READWRITE,
+    WPD_STORAGE_CAPACITY, WPD_STORAGE_FREE_SPACE_IN_BYTES,
+};
+use widestring::{U16CStr, U16CString};
+
+use crate::backend::{ProviderBackend, DeviceBackend, OpenedDeviceBackend, ContentBackend, ReadStreamBackend, WriteStreamBackend, for_each_object, for_each_property};
+use crate::device::{BasicDevice, DeviceEvent, EventSink, Subscription};
+use crate::device::device_values::{AppIdentifiers, DeviceValues, PropertyValue};
+use crate::error::MtpError;
+use crate::object::{ObjectId, ObjectIdRef, ObjectType};
+use crate::watcher::{WatchSink, WatchSinks};
+
+/// Lists [`MemoryDevice`]s
+#[derive(Default)]
+pub struct MemoryProvider {
+    devices: RefCell<Vec<MemoryDevice>>,
+    watchers: WatchSinks,
+}
+
+impl MemoryProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make a device visible. This is the equivalent of plugging a device in.
+    pub fn add_device(&self, device: MemoryDevice) {
+        self.devices.borrow_mut().push(device);
+        self.watchers.notify();
+    }
+
+    /// Make a device invisible. This is the equivalent of unplugging a device.
+    pub fn remove_device(&self, device_id: &str) {
+        for device in self.devices.borrow().iter().filter(|device| device.device_id() == device_id) {
+            device.store.borrow().notify(DeviceEvent::DeviceRemoved);
+        }
+        self.devices.borrow_mut().retain(|device| device.device_id() != device_id);
+        self.watchers.notify();
+    }
+}
+
+impl ProviderBackend for MemoryProvider {
+    fn enumerate_devices(&self) -> Result<Vec<BasicDevice>, MtpError> {
+        Ok(self.devices
+            .borrow()
+            .iter()
+            .map(|device| BasicDevice::new(
+                U16CString::from_str_truncate(device.device_id()),
+                device.friendly_name().to_string(),
+                Rc::new(device.clone()),
+            ))
+            .collect())
+    }
+
+    fn watch(&self, sink: WatchSink) -> Result<Subscription, MtpError> {
+        Ok(self.watchers.add(sink))
+    }
+}
+
+/// An in-memory device.
+///
+/// Cloning this struct returns another handle to the same content, so that a test can inspect and alter the content of a device while it is used.
+#[derive(Debug, Clone)]
+pub struct MemoryDevice {
+    device_id: String,
+    friendly_name: String,
+    store: Rc<RefCell<MemoryStore>>,
+}
+
+#[derive(Debug)]
+struct MemoryStore {
+    objects: HashMap<U16CString, MemoryObject>,
+    next_object_id: u32,
+    next_storage_id: u32,
+    next_persistent_id: u128,
+    /// Storages where names that only differ by their case collide, like on a FAT filesystem
+    case_insensitive_storages: HashSet<U16CString>,
+    subscribers: Vec<(u64, EventSink)>,
+    next_subscriber_id: u64,
+}
+
+#[derive(Debug)]
+struct MemoryObject {
+    properties: DeviceValues,
+    children: Vec<U16CString>,
+    data: Option<Vec<u8>>,
+}
+
+impl MemoryObject {
+    fn object_type(&self) -> ObjectType {
+        self.properties
+            .get_guid(&WPD_OBJECT_CONTENT_TYPE)
+            .map(ObjectType::from_guid)
+            .unwrap_or(ObjectType::Unknown)
+    }
+
+    fn is_container(&self) -> bool {
+        matches!(self.object_type(), ObjectType::Folder | ObjectType::FunctionalObject)
+    }
+}
+
+fn device_object_id() -> U16CString {
+    unsafe{ U16CString::from_ptr_str(WPD_DEVICE_OBJECT_ID.as_ptr()) }
+}
+
+impl MemoryDevice {
+    /// Create a device that only contains its root object
+    pub fn new(friendly_name: &str) -> Self {
+        let root_id = device_object_id();
+        let mut root_properties = DeviceValues::new();
+        root_properties.set(WPD_OBJECT_ID, PropertyValue::String(root_id.clone()));
+        root_properties.set(WPD_OBJECT_PARENT_ID, PropertyValue::String(U16CString::new()));
+        root_properties.set(WPD_OBJECT_NAME, PropertyValue::String(root_id.clone()));
+        root_properties.set(WPD_OBJECT_CONTENT_TYPE, PropertyValue::Guid(WPD_CONTENT_TYPE_FUNCTIONAL_OBJECT));
+        root_properties.set(WPD_FUNCTIONAL_OBJECT_CATEGORY, PropertyValue::Guid(WPD_FUNCTIONAL_CATEGORY_DEVICE));
+
+        let mut objects = HashMap::new();
+        objects.insert(root_id, MemoryObject{ properties: root_properties, children: Vec::new(), data: None });
+
+        Self {
+            device_id: format!("memory:{}", friendly_name),
+            friendly_name: friendly_name.to_string(),
+            store: Rc::new(RefCell::new(MemoryStore{ objects, next_object_id: 1, next_storage_id: 0x10001, next_persistent_id: 1, case_insensitive_storages: HashSet::new(), subscribers: Vec::new(), next_subscriber_id: 0 })),
+        }
+    }
+
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    pub fn friendly_name(&self) -> &str {
+        &self.friendly_name
+    }
+
+    /// Add a storage (a functional object right under the device root), and return its ID
+    pub fn add_storage(&self, name: &str) -> ObjectId {
+        let mut store = self.store.borrow_mut();
+        let id = U16CString::from_str_truncate(format!("s{:X}", store.next_storage_id));
+        store.next_storage_id += 1;
+
+        let mut properties = DeviceValues::new();
+        properties.set(WPD_OBJECT_NAME, PropertyValue::String(U16CString::from_str_truncate(name)));
+        properties.set(WPD_OBJECT_CONTENT_TYPE, PropertyValue::Guid(WPD_CONTENT_TYPE_FUNCTIONAL_OBJECT));
+        properties.set(WPD_FUNCTIONAL_OBJECT_CATEGORY, PropertyValue::Guid(WPD_FUNCTIONAL_CATEGORY_STORAGE));
+        properties.set(WPD_STORAGE_TYPE, PropertyValue::U32(WPD_STORAGE_TYPE_FIXED_RAM.0 as u32));
+        properties.set(WPD_STORAGE_ACCESS_CAPABILITY, PropertyValue::U32(WPD_STORAGE_ACCESS_CAPABILITY_READWRITE.0 as u32));
+        store.insert(id.clone(), &device_object_id(), properties, None);
+        id.into()
+    }
+
+    /// Give a storage a capacity, in bytes. Its free space is then what the data of its files does not use.
+    ///
+    /// Writes are not limited by this capacity.
+    pub fn set_storage_capacity(&self, storage_id: &ObjectIdRef, capacity: u64) -> Result<(), MtpError> {
+        let mut store = self.store.borrow_mut();
+        let storage = store.objects.get_mut(storage_id.as_ucstr()).ok_or(MtpError::ObjectNotFound)?;
+        storage.properties.set(WPD_STORAGE_CAPACITY, PropertyValue::U64(capacity));
+        Ok(())
+    }
+
+    /// Make a storage behave like a case-insensitive filesystem: creating an object whose name only differs by its case from one of its siblings fails
+    pub fn set_storage_case_insensitive(&self, storage_id: &ObjectIdRef) -> Result<(), MtpError> {
+        let mut store = self.store.borrow_mut();
+        store.get(storage_id.as_ucstr())?;
+        store.case_insensitive_storages.insert(storage_id.as_ucstr().to_ucstring());
+        Ok(())
+    }
+
+    /// Set a property of the device object, e.g. its `WPD_DEVICE_POWER_LEVEL`, which [`Device::info`](crate::device::Device::info) reads
+    pub fn set_device_property(&self, key: crate::PROPERTYKEY, value: PropertyValue) {
+        let mut store = self.store.borrow_mut();
+        if let Some(root) = store.objects.get_mut(device_object_id().as_ucstr()) {
+            root.properties.set(key, value);
+        }
+    }
+
+    /// Add a folder, and return its ID
+    pub fn add_folder(&self, parent_id: &ObjectIdRef, name: &str) -> Result<ObjectId, MtpError> {
+        let mut properties = DeviceValues::new();
+        properties.set(WPD_OBJECT_PARENT_ID, PropertyValue::String(parent_id.as_ucstr().to_ucstring()));
+        properties.set(WPD_OBJECT_ORIGINAL_FILE_NAME, PropertyValue::String(U16CString::from_str_truncate(name)));
+        properties.set(WPD_OBJECT_CONTENT_TYPE, PropertyValue::Guid(WPD_CONTENT_TYPE_FOLDER));
+        self.store.borrow_mut().create(properties, None).map(ObjectId::from)
+    }
+
+    /// Add a file, and return its ID
+    pub fn add_file(&self, parent_id: &ObjectIdRef, name: &str, data: &[u8]) -> Result<ObjectId, MtpError> {
+        let mut properties = DeviceValues::new();
+        properties.set(WPD_OBJECT_PARENT_ID, PropertyValue::String(parent_id.as_ucstr().to_ucstring()));
+        properties.set(WPD_OBJECT_ORIGINAL_FILE_NAME, PropertyValue::String(U16CString::from_str_truncate(name)));
+        self.store.borrow_mut().create(properties, Some(data.to_vec())).map(ObjectId::from)
+    }
+
+    /// Get the content of a file
+    pub fn data(&self, object_id: &ObjectIdRef) -> Option<Vec<u8>> {
+        self.store.borrow().objects.get(object_id.as_ucstr())?.data.clone()
+    }
+
+    /// Whether an object with this ID exists
+    pub fn contains(&self, object_id: &ObjectIdRef) -> bool {
+        self.store.borrow().objects.contains_key(object_id.as_ucstr())
+    }
+}
+
+impl MemoryStore {
+    fn get(&self, object_id: &U16CStr) -> Result<&MemoryObject, MtpError> {
+        self.objects.get(object_id).ok_or(MtpError::ObjectNotFound)
+    }
+
+    /// The size of the data of an object and of its descendants
+    fn used_bytes(&self, object_id: &U16CStr) -> u64 {
+        let Some(object) = self.objects.get(object_id) else {
+            return 0;
+        };
+        let own_size = object.data.as_ref().map_or(0, |data| data.len() as u64);
+        own_size + object.children.iter().map(|child_id| self.used_bytes(child_id)).sum::<u64>()
+    }
+
+    /// Events are sent synchronously, as soon as something changes
+    fn notify(&self, event: DeviceEvent) {
+        for (_, sink) in &self.subscribers {
+            sink.send(event.clone());
+        }
+    }
+
+    fn parent_id(&self, object_id: &U16CStr) -> Result<U16CString, MtpError> {
+        self.get(object_id)?.properties.get_string(&WPD_OBJECT_PARENT_ID)
+    }
+
+    /// The storage an object belongs to (a storage belongs to itself). The device root belongs to none.
+    fn storage_id(&self, object_id: &U16CStr) -> Option<U16CString> {
+        let mut current = object_id.to_ucstring();
+        loop {
+            let parent_id = self.parent_id(&current).ok()?;
+            if parent_id.is_empty() {
+                return None;
+            }
+            if parent_id == device_object_id() {
+                return Some(current);
+            }
+            current = parent_id;
+        }
+    }
+
+    /// Create an object from the properties a client would send
+    fn create(&mut self, mut properties: DeviceValues, data: Option<Vec<u8>>) -> Result<U16CString, MtpError> {
+        let parent_id = properties.get_string(&WPD_OBJECT_PARENT_ID)?;
+        if !self.get(&parent_id)?.is_container() {
+            return Err(MtpError::InvalidArgument("Parent object is not a folder".to_string()));
+        }
+        if self.storage_id(&parent_id).is_some_and(|storage_id| self.case_insensitive_storages.contains(&storage_id)) {
+            let name = properties.get_string(&WPD_OBJECT_ORIGINAL_FILE_NAME)
+                .or_else(|_| properties.get_string(&WPD_OBJECT_NAME))?
+                .to_string_lossy()
+                .to_lowercase();
+            let collides = self.get(&parent_id)?.children.iter().any(|sibling_id| {
+                self.objects.get(sibling_id)
+                    .and_then(|sibling| sibling.properties.get_string(&WPD_OBJECT_ORIGINAL_FILE_NAME).ok())
+                    .is_some_and(|sibling_name| sibling_name.to_string_lossy().to_lowercase() == name)
+            });
+            if collides {
+                return Err(MtpError::AlreadyExists);
+            }
+        }
+
+        // Fill in the properties a real device would compute
+        let original_file_name = properties.get_string(&WPD_OBJECT_ORIGINAL_FILE_NAME)
+            .or_else(|_| properties.get_string(&WPD_OBJECT_NAME))?;
+        if properties.get(&WPD_OBJECT_NAME).is_none() {
+            properties.set(WPD_OBJECT_NAME, PropertyValue::String(original_file_name.clone()));
+        }
+        if properties.get(&WPD_OBJECT_ORIGINAL_FILE_NAME).is_none() {
+            properties.set(WPD_OBJECT_ORIGINAL_FILE_NAME, PropertyValue::String(original_file_name));
+        }
+        if properties.get(&WPD_OBJECT_CONTENT_TYPE).is_none() {
+            properties.set(WPD_OBJECT_CONTENT_TYPE, PropertyValue::Guid(WPD_CONTENT_TYPE_UNSPECIFIED));
+        }
+        let now = SystemTime::now();
+        if properties.get(&WPD_OBJECT_DATE_CREATED).is_none() {
+            properties.set(WPD_OBJECT_DATE_CREATED, PropertyValue::Date(now));
+        }
+        if properties.get(&WPD_OBJECT_DATE_MODIFIED).is_none() {
+            properties.set(WPD_OBJECT_DATE_MODIFIED, PropertyValue::Date(now));
+        }
+        if let Some(data) = &data {
+            properties.set(WPD_OBJECT_SIZE, PropertyValue::U64(data.len() as u64));
+        }
+
+        let id = U16CString::from_str_truncate(format!("o{:X}", self.next_object_id));
+        self.next_object_id += 1;
+        self.insert(id.clone(), &parent_id, properties, data);
+        Ok(id)
+    }
+
+    fn insert(&mut self, id: U16CString, parent_id: &U16CStr, mut properties: DeviceValues, data: Option<Vec<u8>>) {
+        properties.set(WPD_OBJECT_ID, PropertyValue::String(id.clone()));
+        // Like MTP 128-bit identifiers, in hex. They are kept when objects are moved or renamed, and never reused.
+        properties.set(WPD_OBJECT_PERSISTENT_UNIQUE_ID, PropertyValue::String(U16CString::from_str_truncate(format!("{:032X}", self.next_persistent_id))));
+        self.next_persistent_id += 1;
+        properties.set(WPD_OBJECT_PARENT_ID, PropertyValue::String(parent_id.to_ucstring()));
+        if let Some(parent) = self.objects.get_mut(parent_id) {
+            parent.children.push(id.clone());
+        }
+        self.objects.insert(id.clone(), MemoryObject{ properties, children: Vec::new(), data });
+
+        if parent_id == device_object_id().as_ucstr() {
+            self.notify(DeviceEvent::StorageChanged{ id: Some(id.into()) });
+        } else {
+            self.notify(DeviceEvent::ObjectAdded{ id: id.into(), parent: Some(parent_id.into()) });
+        }
+    }
+
+    fn delete(&mut self, object_id: &U16CStr, recursive: bool) -> Result<(), MtpError> {
+        let object = self.get(object_id)?;
+        if object.object_type() == ObjectType::FunctionalObject {
+            return Err(MtpError::AccessDenied("Functional objects cannot be deleted".to_string()));
+        }
+        if !object.children.is_empty() && !recursive {
+            return Err(MtpError::InvalidArgument("Folder is not empty".to_string()));
+        }
+
+        let parent_id = self.parent_id(object_id)?;
+        if let Some(parent) = self.objects.get_mut(parent_id.as_ucstr()) {
+            parent.children.retain(|child| child.as_ucstr() != object_id);
+        }
+        self.remove_subtree(object_id);
+        Ok(())
+    }
+
+    fn remove_subtree(&mut self, object_id: &U16CStr) {
+        if let Some(removed) = self.objects.remove(object_id) {
+            for child in removed.children {
+                self.remove_subtree(&child);
+            }
+            self.notify(DeviceEvent::ObjectRemoved{ id: object_id.into() });
+        }
+    }
+
+    fn move_object(&mut self, object_id: &U16CStr, destination_folder_id: &U16CStr) -> Result<(), MtpError> {
+        if self.get(object_id)?.object_type() == ObjectType::FunctionalObject {
+            return Err(MtpError::AccessDenied("Functional objects cannot be moved".to_string()));
+        }
+        if !self.get(destination_folder_id)?.is_container() {
+            return Err(MtpError::InvalidArgument("Destination is not a folder".to_string()));
+        }
+        // Refuse to move a folder into itself or into one of its descendants
+        let mut ancestor = destination_folder_id.to_ucstring();
+        while !ancestor.is_empty() {
+            if ancestor.as_ucstr() == object_id {
+                return Err(MtpError::InvalidArgument("Cannot move a folder into itself".to_string()));
+            }
+            ancestor = self.parent_id(&ancestor)?;
+        }
+
+        let parent_id = self.parent_id(object_id)?;
+        if let Some(parent) = self.objects.get_mut(parent_id.as_ucstr()) {
+            parent.children.retain(|child| child.as_ucstr() != object_id);
+        }
+        if let Some(destination) = self.objects.get_mut(destination_folder_id) {
+            destination.children.push(object_id.to_ucstring());
+        }
+        if let Some(object) = self.objects.get_mut(object_id) {
+            object.properties.set(WPD_OBJECT_PARENT_ID, PropertyValue::String(destination_folder_id.to_ucstring()));
+        }
+        self.notify(DeviceEvent::ObjectUpdated{ id: object_id.into() });
+        Ok(())
+    }
+}
+
+/// Properties that cannot be changed by [`ContentBackend::set_properties`]
+const READ_ONLY_PROPERTIES: [crate::PROPERTYKEY; 5] = [
+    WPD_OBJECT_ID,
+    WPD_OBJECT_PARENT_ID,
+    WPD_OBJECT_CONTENT_TYPE,
+    WPD_OBJECT_SIZE,
+    WPD_FUNCTIONAL_OBJECT_CATEGORY,
+];
+
+impl DeviceBackend for MemoryDevice {
+    fn open(&self, _app_identifiers: &AppIdentifiers) -> Result<Box<dyn OpenedDeviceBackend>, MtpError> {
+        Ok(Box::new(self.clone()))
+    }
+}
+
+impl OpenedDeviceBackend for MemoryDevice {
+    fn content(&self) -> Result<Rc<dyn ContentBackend>, MtpError> {
+        Ok(Rc::new(self.clone()))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl ContentBackend for MemoryDevice {
+    fn children(&self, parent_id: &U16CStr) -> Result<Box<dyn Iterator<Item = U16CString>>, MtpError> {
+        let children = self.store.borrow().get(parent_id)?.children.clone();
+        Ok(Box::new(children.into_iter()))
+    }
+
+    fn properties(&self, object_id: &U16CStr, properties_to_fetch: &[crate::PROPERTYKEY]) -> Result<DeviceValues, MtpError> {
+        let store = self.store.borrow();
+        let object = store.get(object_id)?;
+
+        let mut values = DeviceValues::new();
+        for key in properties_to_fetch {
+            if let Some(value) = object.properties.get(key) {
+                values.set(*key, value.clone());
+            }
+        }
+        if properties_to_fetch.contains(&WPD_STORAGE_FREE_SPACE_IN_BYTES) {
+            if let Ok(capacity) = object.properties.get_u64(&WPD_STORAGE_CAPACITY) {
+                let free_space = capacity.saturating_sub(store.used_bytes(object_id));
+                values.set(WPD_STORAGE_FREE_SPACE_IN_BYTES, PropertyValue::U64(free_space));
+            }
+        }
+        if properties_to_fetch.contains(&WPD_OBJECT_CONTAINER_FUNCTIONAL_OBJECT_ID) {
+            if let Some(storage_id) = store.storage_id(object_id) {
+                values.set(WPD_OBJECT_CONTAINER_FUNCTIONAL_OBJECT_ID, PropertyValue::String(storage_id));
+            }
+        }
+        Ok(values)
+    }
+
+    fn set_properties(&self, object_id: &U16CStr, values: &DeviceValues) -> Result<Vec<Result<(), MtpError>>, MtpError> {
+        let mut store = self.store.borrow_mut();
+        let object = store.objects.get_mut(object_id).ok_or(MtpError::ObjectNotFound)?;
+
+        let results = for_each_property(values, |key, value| {
+            if READ_ONLY_PROPERTIES.contains(key) {
+                return Err(MtpError::AccessDenied("Property is read-only".to_string()));
+            }
+            object.properties.set(*key, value.clone());
+            Ok(())
+        })?;
+        if results.iter().any(Result::is_ok) {
+            store.notify(DeviceEvent::ObjectUpdated{ id: object_id.into() });
+        }
+        Ok(results)
+    }
+
+    fn create_object(&self, properties: &DeviceValues) -> Result<U16CString, MtpError> {
+        self.store.borrow_mut().create(properties.clone(), None)
+    }
+
+    fn create_object_with_data(&self, properties: &DeviceValues) -> Result<(Box<dyn WriteStreamBackend>, u32), MtpError> {
+        // Check the parent right away, like a real device would
+        let parent_id = properties.get_string(&WPD_OBJECT_PARENT_ID)?;
+        self.store.borrow().get(&parent_id)?;
+
+        let stream = MemoryWriteStream{
+            store: Rc::clone(&self.store),
+            properties: properties.clone(),
+            data: Vec::new(),
+            created_id: None,
+        };
+        Ok((Box::new(stream), OPTIMAL_TRANSFER_SIZE))
+    }
+
+    fn open_read_stream(&self, object_id: &U16CStr) -> Result<(Box<dyn ReadStreamBackend>, u32), MtpError> {
+        let data = self.store
+            .borrow()
+            .get(object_id)?
+            .data
+            .clone()
+            .ok_or_else(|| MtpError::InvalidArgument("Object has no data".to_string()))?;
+        Ok((Box::new(Cursor::new(data)), OPTIMAL_TRANSFER_SIZE))
+    }
+
+    fn delete(&self, object_ids: &[&U16CStr], recursive: bool) -> Result<Vec<Result<(), MtpError>>, MtpError> {
+        let mut store = self.store.borrow_mut();
+        for_each_object(object_ids, |object_id| store.delete(object_id, recursive))
+    }
+
+    fn move_objects(&self, object_ids: &[&U16CStr], destination_folder_id: &U16CStr) -> Result<Vec<Result<(), MtpError>>, MtpError> {
+        let mut store = self.store.borrow_mut();
+        for_each_object(object_ids, |object_id| store.move_object(object_id, destination_folder_id))
+    }
+
+    fn subscribe(&self, sink: EventSink) -> Result<Subscription, MtpError> {
+        let mut store = self.store.borrow_mut();
+        let subscriber_id = store.next_subscriber_id;
+        store.next_subscriber_id += 1;
+        store.subscribers.push((subscriber_id, sink));
+
+        let store = Rc::downgrade(&self.store);
+        Ok(Subscription::new(move || {
+            if let Some(store) = store.upgrade() {
+                store.borrow_mut().subscribers.retain(|(id, _)| *id != subscriber_id);
+            }
+        }))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+const OPTIMAL_TRANSFER_SIZE: u32 = 64 * 1024;
+
+impl ReadStreamBackend for Cursor<Vec<u8>> {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        Seek::seek(self, position)
+    }
+}
+
+/// Data is buffered, and the object is only created when the stream is committed
+struct MemoryWriteStream {
+    store: Rc<RefCell<MemoryStore>>,
+    properties: DeviceValues,
+    data: Vec<u8>,
+    created_id: Option<U16CString>,
+}
+
+impl Write for MemoryWriteStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.data.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WriteStreamBackend for MemoryWriteStream {
+    fn commit(&mut self) -> Result<(), MtpError> {
+        if self.created_id.is_some() {
+            return Ok(());
+        }
+
+        // Like the MTP protocol, require the size to be announced beforehand
+        if let Ok(expected_size) = self.properties.get_u64(&WPD_OBJECT_SIZE) {
+            if expected_size != self.data.len() as u64 {
+                return Err(MtpError::InvalidArgument(format!("Expected {} bytes, got {}", expected_size, self.data.len())));
+            }
+        }
+
+        let data = std::mem::take(&mut self.data);
+        self.created_id = Some(self.store.borrow_mut().create(self.properties.clone(), Some(data))?);
+        Ok(())
+    }
+
+    fn object_id(&self) -> Option<U16CString> {
+        self.created_id.clone()
+    }
+}
@@ -0,0 +1,222 @@
+//! Backends that actually talk to devices
+//!
+//! The public types of this crate ([`crate::Provider`], [`crate::device::BasicDevice`], [`crate::device::Device`],
+//! [`crate::device::Content`] and [`crate::object::Object`]) do not talk to devices by themselves.
+//! Instead, they dispatch every operation to a backend, that implements the traits of this module.
+//!
+//! On Windows, the default backend is [`wpd`], which uses the Windows Portable Devices COM API.
+//! On Linux, the default backend is [`usb`], which speaks MTP to USB devices by itself, thanks to the [`mtp`] module.<br/>
+//! Other backends can be plugged into a provider with [`crate::Provider::add_backend`], e.g. the [`memory`] backend, which is handy for tests,
+//! the [`directory`] backend, that exposes local folders, or the [`ptpip`] backend, for devices reachable over the network.
+//!
+//! Objects are always designated by their MTP object ID, and their properties are exchanged as [`DeviceValues`],
+//! keyed by WPD `PROPERTYKEY`s (e.g. [`WPD_OBJECT_NAME`](crate::PortableDevices::WPD_OBJECT_NAME)), whatever the backend.
+
+use std::any::Any;
+use std::io::{Read, SeekFrom, Write};
+use std::rc::Rc;
+
+use windows::core::GUID;
+use widestring::{U16CStr, U16CString};
+
+use crate::device::{BasicDevice, EventSink, PropertyDescription, Subscription};
+use crate::device::device_values::{AppIdentifiers, DeviceValues, PropertyValue};
+use crate::error::{ErrorKind, MtpError};
+use crate::watcher::WatchSink;
+
+#[cfg(windows)]
+pub mod wpd;
+pub mod memory;
+pub mod directory;
+pub mod mtp;
+pub mod ptpip;
+#[cfg(target_os = "linux")]
+pub mod usb;
+
+/// Something that is able to list devices
+pub trait ProviderBackend {
+    /// List the devices currently reachable through this backend
+    fn enumerate_devices(&self) -> Result<Vec<BasicDevice>, MtpError>;
+
+    /// Tell `sink` whenever devices may have been connected or disconnected, until the returned subscription is dropped
+    ///
+    /// Backends that do not support this are polled by [`crate::DeviceWatcher`]s.
+    fn watch(&self, _sink: WatchSink) -> Result<Subscription, MtpError> {
+        Err(MtpError::Unsupported)
+    }
+}
+
+/// A device that has been enumerated, but not opened yet
+pub trait DeviceBackend {
+    /// Open a connection to this device
+    fn open(&self, app_identifiers: &AppIdentifiers) -> Result<Box<dyn OpenedDeviceBackend>, MtpError>;
+}
+
+/// A device that has been opened
+pub trait OpenedDeviceBackend {
+    /// Get access to the content of the device
+    fn content(&self) -> Result<Rc<dyn ContentBackend>, MtpError>;
+
+    /// Get what the device supports. The default implementation returns [`MtpError::Unsupported`], for backends that cannot tell.
+    fn capabilities(&self) -> Result<Rc<dyn CapabilitiesBackend>, MtpError> {
+        Err(MtpError::Unsupported)
+    }
+
+    /// Used to retrieve the concrete backend type, e.g. to access the underlying COM objects
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// Access to the objects of an opened device
+pub trait ContentBackend: std::fmt::Debug {
+    /// List the IDs of the direct children of an object
+    fn children(&self, parent_id: &U16CStr) -> Result<Box<dyn Iterator<Item = U16CString>>, MtpError>;
+
+    /// Read some properties of an object.
+    ///
+    /// Properties the device does not provide are simply missing from the result.
+    fn properties(&self, object_id: &U16CStr, properties_to_fetch: &[crate::PROPERTYKEY]) -> Result<DeviceValues, MtpError>;
+
+    /// List the direct children of an object, with some of their properties.
+    ///
+    /// By default, this calls [`Self::properties`] for every child. Backends that can read the properties of many objects at once override it.
+    fn children_properties(&self, parent_id: &U16CStr, properties_to_fetch: &[crate::PROPERTYKEY]) -> Result<Vec<(U16CString, DeviceValues)>, MtpError> {
+        self.children(parent_id)?
+            .map(|child_id| {
+                let values = self.properties(&child_id, properties_to_fetch)?;
+                Ok((child_id, values))
+            })
+            .collect()
+    }
+
+    /// Write some properties of an object
+    ///
+    /// This returns a result for every property, in the same order as `values`, so that the rejected ones can be told apart.
+    fn set_properties(&self, object_id: &U16CStr, values: &DeviceValues) -> Result<Vec<Result<(), MtpError>>, MtpError>;
+
+    /// Create an object that has no data (e.g. a folder), and return its ID
+    fn create_object(&self, properties: &DeviceValues) -> Result<U16CString, MtpError>;
+
+    /// Create an object with some data (e.g. a file).
+    ///
+    /// The object is only created when the returned stream is committed.<br/>
+    /// Also returns the optimal transfer buffer size (in bytes).
+    fn create_object_with_data(&self, properties: &DeviceValues) -> Result<(Box<dyn WriteStreamBackend>, u32), MtpError>;
+
+    /// Open the default resource of an object for reading.
+    ///
+    /// Also returns the optimal transfer buffer size (in bytes).
+    fn open_read_stream(&self, object_id: &U16CStr) -> Result<(Box<dyn ReadStreamBackend>, u32), MtpError>;
+
+    /// Read at most `length` bytes of the default resource of an object, starting at `offset`.
+    ///
+    /// Fewer bytes are returned when the end of the object is reached.
+    /// By default, this seeks into a stream opened by [`Self::open_read_stream`], and fails if the stream cannot seek.
+    fn read_range(&self, object_id: &U16CStr, offset: u64, length: usize) -> Result<Vec<u8>, MtpError> {
+        let (mut stream, _optimal_transfer_size) = self.open_read_stream(object_id)?;
+        stream.seek(SeekFrom::Start(offset))?;
+        let mut data = Vec::new();
+        stream.by_ref().take(length as u64).read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Delete objects
+    ///
+    /// This returns a result for every object, in the same order as `object_ids`, unless the whole call failed.
+    fn delete(&self, object_ids: &[&U16CStr], recursive: bool) -> Result<Vec<Result<(), MtpError>>, MtpError>;
+
+    /// Move objects into another folder
+    ///
+    /// This returns a result for every object, in the same order as `object_ids`, unless the whole call failed.
+    fn move_objects(&self, object_ids: &[&U16CStr], destination_folder_id: &U16CStr) -> Result<Vec<Result<(), MtpError>>, MtpError>;
+
+    /// Send the events of the device to `sink`, until the returned subscription is dropped.
+    ///
+    /// Events may be sent from another thread. The default implementation returns [`MtpError::Unsupported`], for backends that do not report events.
+    fn subscribe(&self, _sink: EventSink) -> Result<Subscription, MtpError> {
+        Err(MtpError::Unsupported)
+    }
+
+    /// Used to retrieve the concrete backend type, e.g. to access the underlying COM objects
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// What an opened device supports
+///
+/// This mirrors [`IPortableDeviceCapabilities`](https://learn.microsoft.com/en-us/windows/win32/wpd_sdk/iportabledevicecapabilities):
+/// functional categories, content types and formats are WPD GUIDs, and commands and properties are WPD `PROPERTYKEY`s, whatever the backend.
+pub trait CapabilitiesBackend {
+    fn supported_commands(&self) -> Result<Vec<crate::PROPERTYKEY>, MtpError>;
+
+    fn functional_categories(&self) -> Result<Vec<GUID>, MtpError>;
+
+    /// The content types the functional objects of a category support
+    fn content_types(&self, functional_category: &GUID) -> Result<Vec<GUID>, MtpError>;
+
+    /// The formats objects of a content type can have
+    fn formats(&self, content_type: &GUID) -> Result<Vec<GUID>, MtpError>;
+
+    /// The properties objects of a format can have
+    fn format_properties(&self, format: &GUID) -> Result<Vec<crate::PROPERTYKEY>, MtpError>;
+
+    /// The attributes of a property of objects of a format
+    fn property_description(&self, format: &GUID, property: &crate::PROPERTYKEY) -> Result<PropertyDescription, MtpError>;
+}
+
+/// A stream to read data from an object
+pub trait ReadStreamBackend: Read {
+    /// Move to another position in the stream, like [`std::io::Seek::seek`].
+    ///
+    /// Streams that cannot seek keep this default implementation, that returns an [`std::io::ErrorKind::Unsupported`] error.
+    fn seek(&mut self, _position: SeekFrom) -> std::io::Result<u64> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+}
+
+/// A stream to write data into an object that is being created
+pub trait WriteStreamBackend: Write {
+    /// Finalize the transfer. The object only exists on the device after this call.
+    fn commit(&mut self) -> Result<(), MtpError>;
+
+    /// Give up the transfer, so that the object is not created.
+    ///
+    /// By default, this does nothing, which suits backends that only create the object when the stream is committed.
+    fn abort(&mut self) -> Result<(), MtpError> {
+        Ok(())
+    }
+
+    /// The ID of the object that has been created, once the stream is committed, if the backend knows it
+    fn object_id(&self) -> Option<U16CString> {
+        None
+    }
+}
+
+/// Run `f` for every object of a batch, for backends that cannot process a batch at once.
+///
+/// Failures only affect their own object, except when the device is gone, which fails the whole batch.
+pub(crate) fn for_each_object<F>(object_ids: &[&U16CStr], mut f: F) -> Result<Vec<Result<(), MtpError>>, MtpError>
+where
+    F: FnMut(&U16CStr) -> Result<(), MtpError>,
+{
+    object_ids.iter()
+        .map(|object_id| batch_item_result(f(object_id)))
+        .collect()
+}
+
+/// Run `f` for every property to write, for backends that cannot write them at once.
+///
+/// Just like [`for_each_object`], failures only affect their own property, except when the device is gone.
+pub(crate) fn for_each_property<F>(values: &DeviceValues, mut f: F) -> Result<Vec<Result<(), MtpError>>, MtpError>
+where
+    F: FnMut(&crate::PROPERTYKEY, &PropertyValue) -> Result<(), MtpError>,
+{
+    values.iter()
+        .map(|(key, value)| batch_item_result(f(key, value)))
+        .collect()
+}
+
+fn batch_item_result(result: Result<(), MtpError>) -> Result<Result<(), MtpError>, MtpError> {
+    match result {
+        Err(err) if err.kind() == ErrorKind::DeviceGone => Err(err),
+        result => Ok(result),
+    }
+}
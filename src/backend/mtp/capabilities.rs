@@ -0,0 +1,177 @@
+//! What an MTP device supports, derived from its `DeviceInfo` dataset and from its object property descriptions
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use windows::core::GUID;
+use windows::Win32::Devices::PortableDevices::{
+    WPD_FUNCTIONAL_CATEGORY_DEVICE, WPD_FUNCTIONAL_CATEGORY_STORAGE, WPD_FUNCTIONAL_CATEGORY_STILL_IMAGE_CAPTURE, WPD_CONTENT_TYPE_ALL,
+    WPD_COMMAND_OBJECT_ENUMERATION_START_FIND, WPD_COMMAND_OBJECT_ENUMERATION_FIND_NEXT, WPD_COMMAND_OBJECT_ENUMERATION_END_FIND,
+    WPD_COMMAND_OBJECT_PROPERTIES_GET_SUPPORTED, WPD_COMMAND_OBJECT_PROPERTIES_GET_ATTRIBUTES, WPD_COMMAND_OBJECT_PROPERTIES_GET,
+    WPD_COMMAND_OBJECT_PROPERTIES_GET_ALL, WPD_COMMAND_OBJECT_PROPERTIES_SET,
+    WPD_COMMAND_OBJECT_RESOURCES_OPEN, WPD_COMMAND_OBJECT_RESOURCES_READ, WPD_COMMAND_OBJECT_RESOURCES_SEEK, WPD_COMMAND_OBJECT_RESOURCES_CLOSE,
+    WPD_COMMAND_OBJECT_MANAGEMENT_CREATE_OBJECT_WITH_PROPERTIES_ONLY, WPD_COMMAND_OBJECT_MANAGEMENT_CREATE_OBJECT_WITH_PROPERTIES_AND_DATA,
+    WPD_COMMAND_OBJECT_MANAGEMENT_WRITE_OBJECT_DATA, WPD_COMMAND_OBJECT_MANAGEMENT_COMMIT_OBJECT,
+    WPD_COMMAND_OBJECT_MANAGEMENT_DELETE_OBJECTS, WPD_COMMAND_OBJECT_MANAGEMENT_MOVE_OBJECTS, WPD_COMMAND_OBJECT_MANAGEMENT_COPY_OBJECTS,
+    WPD_COMMAND_STORAGE_FORMAT, WPD_COMMAND_STILL_IMAGE_CAPTURE_INITIATE,
+};
+use widestring::U16CString;
+
+use crate::backend::CapabilitiesBackend;
+use crate::backend::mtp::Session;
+use crate::device::{AllowedValues, PropertyDescription};
+use crate::device::device_values::PropertyValue;
+use crate::error::MtpError;
+use crate::object::ObjectType;
+use crate::protocol::codes::{ObjectFormatCode, ObjectPropertyCode, OperationCode};
+use crate::protocol::data::Value;
+use crate::protocol::datasets::PropertyForm;
+
+/// Commands every MTP device supports, as they only need mandatory operations
+const MANDATORY_COMMANDS: [crate::PROPERTYKEY; 8] = [
+    WPD_COMMAND_OBJECT_ENUMERATION_START_FIND,
+    WPD_COMMAND_OBJECT_ENUMERATION_FIND_NEXT,
+    WPD_COMMAND_OBJECT_ENUMERATION_END_FIND,
+    WPD_COMMAND_OBJECT_PROPERTIES_GET_SUPPORTED,
+    WPD_COMMAND_OBJECT_PROPERTIES_GET,
+    WPD_COMMAND_OBJECT_PROPERTIES_GET_ALL,
+    WPD_COMMAND_OBJECT_RESOURCES_OPEN,
+    WPD_COMMAND_OBJECT_RESOURCES_CLOSE,
+];
+
+/// Commands that are supported when the device supports an operation
+const OPERATION_COMMANDS: [(OperationCode, crate::PROPERTYKEY); 12] = [
+    (OperationCode::GetObject, WPD_COMMAND_OBJECT_RESOURCES_READ),
+    (OperationCode::GetPartialObject, WPD_COMMAND_OBJECT_RESOURCES_SEEK),
+    (OperationCode::GetObjectPropDesc, WPD_COMMAND_OBJECT_PROPERTIES_GET_ATTRIBUTES),
+    (OperationCode::SetObjectPropValue, WPD_COMMAND_OBJECT_PROPERTIES_SET),
+    (OperationCode::SendObjectInfo, WPD_COMMAND_OBJECT_MANAGEMENT_CREATE_OBJECT_WITH_PROPERTIES_ONLY),
+    (OperationCode::SendObject, WPD_COMMAND_OBJECT_MANAGEMENT_CREATE_OBJECT_WITH_PROPERTIES_AND_DATA),
+    (OperationCode::SendObject, WPD_COMMAND_OBJECT_MANAGEMENT_WRITE_OBJECT_DATA),
+    (OperationCode::SendObject, WPD_COMMAND_OBJECT_MANAGEMENT_COMMIT_OBJECT),
+    (OperationCode::DeleteObject, WPD_COMMAND_OBJECT_MANAGEMENT_DELETE_OBJECTS),
+    (OperationCode::MoveObject, WPD_COMMAND_OBJECT_MANAGEMENT_MOVE_OBJECTS),
+    (OperationCode::CopyObject, WPD_COMMAND_OBJECT_MANAGEMENT_COPY_OBJECTS),
+    (OperationCode::FormatStore, WPD_COMMAND_STORAGE_FORMAT),
+];
+
+/// The capabilities of a device an MTP session has been opened with
+///
+/// Like the Windows MTP driver does, storages support the content types of the formats the device can play back,
+/// and still image capture (if the device can capture) supports the ones of the formats it can capture.
+pub struct MtpCapabilities {
+    session: Rc<RefCell<Session>>,
+}
+
+impl MtpCapabilities {
+    pub fn new(session: Rc<RefCell<Session>>) -> Self {
+        Self{ session }
+    }
+
+    fn can_capture(&self) -> bool {
+        self.session.borrow().supports(OperationCode::InitiateCapture)
+    }
+
+    /// The formats the functional objects of a category support
+    fn category_formats(&self, functional_category: &GUID) -> Vec<ObjectFormatCode> {
+        let session = self.session.borrow();
+        let info = session.device_info();
+        let codes = match *functional_category {
+            WPD_FUNCTIONAL_CATEGORY_STORAGE => &info.playback_formats,
+            WPD_FUNCTIONAL_CATEGORY_STILL_IMAGE_CAPTURE if self.can_capture() => &info.capture_formats,
+            _ => return Vec::new(),
+        };
+        codes.iter().map(|code| ObjectFormatCode::from_u16(*code)).collect()
+    }
+}
+
+impl CapabilitiesBackend for MtpCapabilities {
+    fn supported_commands(&self) -> Result<Vec<crate::PROPERTYKEY>, MtpError> {
+        let session = self.session.borrow();
+        let mut commands = MANDATORY_COMMANDS.to_vec();
+        commands.extend(OPERATION_COMMANDS.iter().filter(|(operation, _)| session.supports(*operation)).map(|(_, command)| *command));
+        if session.supports(OperationCode::InitiateCapture) {
+            commands.push(WPD_COMMAND_STILL_IMAGE_CAPTURE_INITIATE);
+        }
+        Ok(commands)
+    }
+
+    fn functional_categories(&self) -> Result<Vec<GUID>, MtpError> {
+        let mut categories = vec![WPD_FUNCTIONAL_CATEGORY_DEVICE, WPD_FUNCTIONAL_CATEGORY_STORAGE];
+        if self.can_capture() {
+            categories.push(WPD_FUNCTIONAL_CATEGORY_STILL_IMAGE_CAPTURE);
+        }
+        Ok(categories)
+    }
+
+    fn content_types(&self, functional_category: &GUID) -> Result<Vec<GUID>, MtpError> {
+        let mut content_types = Vec::new();
+        for format in self.category_formats(functional_category) {
+            let content_type = format.object_type().as_guid();
+            if !content_types.contains(&content_type) {
+                content_types.push(content_type);
+            }
+        }
+        Ok(content_types)
+    }
+
+    fn formats(&self, content_type: &GUID) -> Result<Vec<GUID>, MtpError> {
+        let session = self.session.borrow();
+        let info = session.device_info();
+        let mut formats = Vec::new();
+        for code in info.playback_formats.iter().chain(&info.capture_formats) {
+            let format = ObjectFormatCode::from_u16(*code);
+            let guid = format.as_guid();
+            if (*content_type == WPD_CONTENT_TYPE_ALL || format.object_type() == ObjectType::from_guid(*content_type)) && !formats.contains(&guid) {
+                formats.push(guid);
+            }
+        }
+        Ok(formats)
+    }
+
+    fn format_properties(&self, format: &GUID) -> Result<Vec<crate::PROPERTYKEY>, MtpError> {
+        let format = ObjectFormatCode::from_guid(*format).ok_or_else(|| MtpError::InvalidArgument("Not an MTP format".to_string()))?;
+        let properties = self.session.borrow_mut().object_props_supported(format)?;
+        Ok(properties.iter().filter_map(ObjectPropertyCode::property_key).collect())
+    }
+
+    fn property_description(&self, format: &GUID, property: &crate::PROPERTYKEY) -> Result<PropertyDescription, MtpError> {
+        let format = ObjectFormatCode::from_guid(*format).ok_or_else(|| MtpError::InvalidArgument("Not an MTP format".to_string()))?;
+        let code = ObjectPropertyCode::from_property_key(property).ok_or(MtpError::Unsupported)?;
+        let desc = self.session.borrow_mut().object_prop_desc(code, format)?.ok_or(MtpError::Unsupported)?;
+
+        let value = |value: &Value| code.to_property_value(value).or_else(|| plain_property_value(value));
+        let allowed_values = match &desc.form {
+            PropertyForm::Range{ min, max, step } => match (value(min), value(max), value(step)) {
+                (Some(min), Some(max), Some(step)) => AllowedValues::Range{ min, max, step },
+                _ => AllowedValues::Any,
+            },
+            PropertyForm::Enumeration(values) => AllowedValues::Enumeration(values.iter().filter_map(value).collect()),
+            PropertyForm::RegularExpression(regex) => AllowedValues::RegularExpression(regex.clone()),
+            _ if matches!(code, ObjectPropertyCode::StorageId | ObjectPropertyCode::ParentObject) => AllowedValues::ObjectId,
+            _ => AllowedValues::Any,
+        };
+        Ok(PropertyDescription{
+            writable: desc.writable,
+            // MTP has no way to remove a property
+            can_delete: false,
+            default_value: value(&desc.default_value),
+            allowed_values,
+        })
+    }
+}
+
+/// Convert values of properties [`ObjectPropertyCode::to_property_value`] has no special case for
+fn plain_property_value(value: &Value) -> Option<PropertyValue> {
+    Some(match value {
+        Value::U8(v) => PropertyValue::U32(*v as u32),
+        Value::U16(v) => PropertyValue::U32(*v as u32),
+        Value::U32(v) => PropertyValue::U32(*v),
+        Value::I8(v) => PropertyValue::I32(*v as i32),
+        Value::I16(v) => PropertyValue::I32(*v as i32),
+        Value::I32(v) => PropertyValue::I32(*v),
+        Value::U64(v) => PropertyValue::U64(*v),
+        Value::Str(s) => PropertyValue::String(U16CString::from_str_truncate(s)),
+        _ => return None,
+    })
+}
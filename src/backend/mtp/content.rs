@@ -0,0 +1,702 @@
+//! Mapping of [`ContentBackend`] operations onto MTP operations
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{Read, SeekFrom, Write};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use windows::Win32::Devices::PortableDevices::{
+    WPD_DEVICE_OBJECT_ID, WPD_OBJECT_ID, WPD_OBJECT_PARENT_ID, WPD_OBJECT_NAME, WPD_OBJECT_ORIGINAL_FILE_NAME,
+    WPD_OBJECT_CONTENT_TYPE, WPD_OBJECT_FORMAT, WPD_OBJECT_SIZE, WPD_OBJECT_DATE_CREATED, WPD_OBJECT_DATE_MODIFIED,
+    WPD_OBJECT_KEYWORDS, WPD_OBJECT_CONTAINER_FUNCTIONAL_OBJECT_ID, WPD_OBJECT_CAN_DELETE,
+    WPD_FUNCTIONAL_OBJECT_CATEGORY, WPD_FUNCTIONAL_CATEGORY_DEVICE, WPD_FUNCTIONAL_CATEGORY_STORAGE,
+    WPD_CONTENT_TYPE_FUNCTIONAL_OBJECT, WPD_CONTENT_TYPE_FOLDER,
+    WPD_DEVICE_MANUFACTURER, WPD_DEVICE_MODEL, WPD_DEVICE_FIRMWARE_VERSION, WPD_DEVICE_SERIAL_NUMBER,
+    WPD_DEVICE_PROTOCOL, WPD_DEVICE_TYPE, WPD_DEVICE_POWER_LEVEL,
+    WPD_STORAGE_TYPE, WPD_STORAGE_ACCESS_CAPABILITY, WPD_STORAGE_CAPACITY, WPD_STORAGE_FREE_SPACE_IN_BYTES,
+    WPD_STORAGE_FREE_SPACE_IN_OBJECTS, WPD_STORAGE_FILE_SYSTEM_TYPE, WPD_STORAGE_DESCRIPTION, WPD_STORAGE_SERIAL_NUMBER,
+};
+use widestring::{U16CStr, U16CString};
+
+use crate::backend::{ContentBackend, ReadStreamBackend, WriteStreamBackend, for_each_object, for_each_property};
+use crate::backend::mtp::{Session, ALL};
+use crate::device::{DeviceEvent, EventSink, Subscription};
+use crate::device::device_values::{DeviceValues, PropertyValue};
+use crate::error::MtpError;
+use crate::protocol::codes::{ObjectFormatCode, ObjectPropertyCode, DevicePropertyCode, OperationCode};
+use crate::protocol::data::Value;
+use crate::protocol::datasets::{ObjectInfo, DevicePropDesc, PropertyForm};
+use crate::protocol::datetime::{format_datetime, parse_datetime};
+use crate::protocol::mapping::{object_id_from_handle, handle_from_object_id, object_id_from_storage_id, storage_id_from_object_id, device_event, filesystem_type_name};
+use crate::protocol::mapping::{MTP_OBJECT_PROTECTION_STATUS, PROTECTION_READ_ONLY};
+
+const OPTIMAL_TRANSFER_SIZE: u32 = 256 * 1024;
+
+/// How often the thread that receives events checks whether it should stop
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An object, as designated by its WPD-like object ID
+#[derive(Clone, Copy)]
+enum Target {
+    Device,
+    Storage(u32),
+    Object(u32),
+}
+
+impl Target {
+    fn parse(object_id: &U16CStr) -> Result<Self, MtpError> {
+        if object_id == device_object_id().as_ucstr() {
+            Ok(Self::Device)
+        } else if let Some(storage_id) = storage_id_from_object_id(object_id) {
+            Ok(Self::Storage(storage_id))
+        } else if let Some(handle) = handle_from_object_id(object_id) {
+            Ok(Self::Object(handle))
+        } else {
+            Err(MtpError::ObjectNotFound)
+        }
+    }
+
+    /// The handle of an object that is not the device root or a storage
+    fn handle(object_id: &U16CStr) -> Result<u32, MtpError> {
+        match Self::parse(object_id)? {
+            Self::Object(handle) => Ok(handle),
+            Self::Device | Self::Storage(_) => Err(MtpError::Unsupported),
+        }
+    }
+}
+
+fn device_object_id() -> U16CString {
+    unsafe{ U16CString::from_ptr_str(WPD_DEVICE_OBJECT_ID.as_ptr()) }
+}
+
+fn string(value: &str) -> PropertyValue {
+    PropertyValue::String(U16CString::from_str_truncate(value))
+}
+
+/// The vendor extension of MTP devices
+const MICROSOFT_VENDOR_EXTENSION_ID: u32 = 6;
+
+/// Battery levels are usually ranges (e.g. 0 to 100, or 0 to 4 on older devices), that WPD turns into percentages
+fn battery_percentage(desc: &DevicePropDesc) -> Option<u32> {
+    let current = desc.current_value.as_u64()?;
+    match &desc.form {
+        PropertyForm::Range{ min, max, .. } => {
+            let (min, max) = (min.as_u64()?, max.as_u64()?);
+            if max <= min {
+                return None;
+            }
+            Some((current.clamp(min, max) - min) as u32 * 100 / (max - min) as u32)
+        },
+        _ => Some(current.min(100) as u32),
+    }
+}
+
+/// The content of a device, accessed through an MTP [`Session`]
+#[derive(Clone)]
+pub struct MtpContent {
+    session: Rc<RefCell<Session>>,
+}
+
+impl std::fmt::Debug for MtpContent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MtpContent").finish_non_exhaustive()
+    }
+}
+
+impl MtpContent {
+    pub(crate) fn new(session: Rc<RefCell<Session>>) -> Self {
+        Self{ session }
+    }
+
+    /// Device properties that take a transaction are only read when they are requested
+    fn device_properties(&self, properties_to_fetch: &[crate::PROPERTYKEY]) -> Result<DeviceValues, MtpError> {
+        let mut session = self.session.borrow_mut();
+        let info = session.device_info();
+        // Like the Windows MTP driver, e.g. "MTP: 1.00"
+        let protocol = match info.vendor_extension_id {
+            MICROSOFT_VENDOR_EXTENSION_ID => format!("MTP: {}.{:02}", info.vendor_extension_version / 100, info.vendor_extension_version % 100),
+            _ => format!("PTP: {}.{:02}", info.standard_version / 100, info.standard_version % 100),
+        };
+        let mut values = DeviceValues::new();
+        values.set(WPD_OBJECT_ID, PropertyValue::String(device_object_id()));
+        values.set(WPD_OBJECT_PARENT_ID, PropertyValue::String(U16CString::new()));
+        values.set(WPD_OBJECT_NAME, PropertyValue::String(device_object_id()));
+        values.set(WPD_OBJECT_CONTENT_TYPE, PropertyValue::Guid(WPD_CONTENT_TYPE_FUNCTIONAL_OBJECT));
+        values.set(WPD_FUNCTIONAL_OBJECT_CATEGORY, PropertyValue::Guid(WPD_FUNCTIONAL_CATEGORY_DEVICE));
+        values.set(WPD_DEVICE_MANUFACTURER, string(&info.manufacturer));
+        values.set(WPD_DEVICE_MODEL, string(&info.model));
+        values.set(WPD_DEVICE_FIRMWARE_VERSION, string(&info.device_version));
+        values.set(WPD_DEVICE_SERIAL_NUMBER, string(&info.serial_number));
+        values.set(WPD_DEVICE_PROTOCOL, string(&protocol));
+
+        if properties_to_fetch.contains(&WPD_DEVICE_POWER_LEVEL) {
+            if let Some(desc) = session.device_prop_desc(DevicePropertyCode::BatteryLevel)? {
+                if let Some(level) = battery_percentage(&desc) {
+                    values.set(WPD_DEVICE_POWER_LEVEL, PropertyValue::U32(level));
+                }
+            }
+        }
+        if properties_to_fetch.contains(&WPD_DEVICE_TYPE) {
+            // MTP and WPD use the same values
+            if let Some(device_type) = session.device_prop_desc(DevicePropertyCode::PerceivedDeviceType)?.and_then(|desc| desc.current_value.as_u64()) {
+                values.set(WPD_DEVICE_TYPE, PropertyValue::U32(device_type as u32));
+            }
+        }
+        Ok(values)
+    }
+
+    fn storage_properties(&self, storage_id: u32) -> Result<DeviceValues, MtpError> {
+        let info = self.session.borrow_mut().storage_info(storage_id)?;
+        let name = [&info.storage_description, &info.volume_identifier]
+            .into_iter()
+            .find(|name| !name.is_empty())
+            .cloned()
+            .unwrap_or_else(|| format!("{:X}", storage_id));
+
+        let mut values = DeviceValues::new();
+        values.set(WPD_OBJECT_ID, PropertyValue::String(object_id_from_storage_id(storage_id)));
+        values.set(WPD_OBJECT_PARENT_ID, PropertyValue::String(device_object_id()));
+        values.set(WPD_OBJECT_NAME, string(&name));
+        values.set(WPD_OBJECT_CONTENT_TYPE, PropertyValue::Guid(WPD_CONTENT_TYPE_FUNCTIONAL_OBJECT));
+        values.set(WPD_FUNCTIONAL_OBJECT_CATEGORY, PropertyValue::Guid(WPD_FUNCTIONAL_CATEGORY_STORAGE));
+        // WPD uses the same values as MTP for these two
+        values.set(WPD_STORAGE_TYPE, PropertyValue::U32(info.storage_type as u32));
+        values.set(WPD_STORAGE_ACCESS_CAPABILITY, PropertyValue::U32(info.access_capability as u32));
+        // A storage cannot be that small: this is what responders that do not know their capacity tell
+        if info.max_capacity != 0 {
+            values.set(WPD_STORAGE_CAPACITY, PropertyValue::U64(info.max_capacity));
+            values.set(WPD_STORAGE_FREE_SPACE_IN_BYTES, PropertyValue::U64(info.free_space_in_bytes));
+        }
+        // Storages that do not limit the number of objects tell 0xFFFFFFFF
+        if info.free_space_in_objects != u32::MAX {
+            values.set(WPD_STORAGE_FREE_SPACE_IN_OBJECTS, PropertyValue::U64(info.free_space_in_objects as u64));
+        }
+        if let Some(filesystem_type) = filesystem_type_name(info.filesystem_type) {
+            values.set(WPD_STORAGE_FILE_SYSTEM_TYPE, string(filesystem_type));
+        }
+        values.set(WPD_STORAGE_DESCRIPTION, string(&info.storage_description));
+        values.set(WPD_STORAGE_SERIAL_NUMBER, string(&info.volume_identifier));
+        Ok(values)
+    }
+
+    /// Properties that can be deduced from the `ObjectInfo` dataset
+    fn object_info_properties(handle: u32, info: &ObjectInfo) -> DeviceValues {
+        let parent_id = match info.parent_object {
+            0 | ALL => object_id_from_storage_id(info.storage_id),
+            parent => object_id_from_handle(parent),
+        };
+
+        let mut values = DeviceValues::new();
+        values.set(WPD_OBJECT_ID, PropertyValue::String(object_id_from_handle(handle)));
+        values.set(WPD_OBJECT_PARENT_ID, PropertyValue::String(parent_id));
+        values.set(WPD_OBJECT_CONTAINER_FUNCTIONAL_OBJECT_ID, PropertyValue::String(object_id_from_storage_id(info.storage_id)));
+        values.set(WPD_OBJECT_NAME, string(&info.filename));
+        values.set(WPD_OBJECT_ORIGINAL_FILE_NAME, string(&info.filename));
+        values.set(WPD_OBJECT_CONTENT_TYPE, PropertyValue::Guid(info.object_format.object_type().as_guid()));
+        values.set(WPD_OBJECT_FORMAT, PropertyValue::Guid(info.object_format.as_guid()));
+        // Larger sizes do not fit, they can only be retrieved as an object property
+        if info.object_format != ObjectFormatCode::Association && info.object_compressed_size != u32::MAX {
+            values.set(WPD_OBJECT_SIZE, PropertyValue::U64(info.object_compressed_size as u64));
+        }
+        if let Ok(date) = parse_datetime(&info.date_created) {
+            values.set(WPD_OBJECT_DATE_CREATED, PropertyValue::Date(date));
+        }
+        if let Ok(date) = parse_datetime(&info.date_modified) {
+            values.set(WPD_OBJECT_DATE_MODIFIED, PropertyValue::Date(date));
+        }
+        if !info.keywords.is_empty() {
+            values.set(WPD_OBJECT_KEYWORDS, string(&info.keywords));
+        }
+        values.set(MTP_OBJECT_PROTECTION_STATUS, PropertyValue::U32(info.protection_status as u32));
+        values.set(WPD_OBJECT_CAN_DELETE, PropertyValue::Bool(info.protection_status as u32 != PROTECTION_READ_ONLY));
+        values
+    }
+
+    fn object_properties(&self, handle: u32, properties_to_fetch: &[crate::PROPERTYKEY]) -> Result<DeviceValues, MtpError> {
+        let mut session = self.session.borrow_mut();
+        let info = session.object_info(handle)?;
+        let known_values = Self::object_info_properties(handle, &info);
+
+        let mut values = DeviceValues::new();
+        for key in properties_to_fetch {
+            if let Some(value) = known_values.get(key) {
+                values.set(*key, value.clone());
+            } else if let Some(property) = ObjectPropertyCode::from_property_key(key) {
+                let value = session
+                    .object_prop_value(handle, property, info.object_format)?
+                    .and_then(|value| property.to_property_value(&value));
+                if let Some(value) = value {
+                    values.set(*key, value);
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    /// Properties of an object out of its elements of an object property list, completed like [`Self::object_info_properties`] does.
+    ///
+    /// Returns `None` when the device did not list the properties `ObjectInfo` would have told.
+    fn prop_list_properties(handle: u32, parent: u32, elements: &[(ObjectPropertyCode, Value)]) -> Option<DeviceValues> {
+        let mut values = DeviceValues::new();
+        for (property, value) in elements {
+            if let (Some(key), Some(value)) = (property.property_key(), property.to_property_value(value)) {
+                values.set(key, value);
+            }
+        }
+        let format = elements.iter().find(|(property, _)| *property == ObjectPropertyCode::ObjectFormat)?.1.as_u64()?;
+        let format = ObjectFormatCode::from_u16(format as u16);
+        let filename = values.get_string(&WPD_OBJECT_ORIGINAL_FILE_NAME).ok()?;
+
+        values.set(WPD_OBJECT_ID, PropertyValue::String(object_id_from_handle(handle)));
+        values.set(WPD_OBJECT_PARENT_ID, PropertyValue::String(object_id_from_handle(parent)));
+        values.set(WPD_OBJECT_NAME, PropertyValue::String(filename));
+        values.set(WPD_OBJECT_CONTENT_TYPE, PropertyValue::Guid(format.object_type().as_guid()));
+        if let Ok(status) = values.get_u32(&MTP_OBJECT_PROTECTION_STATUS) {
+            values.set(WPD_OBJECT_CAN_DELETE, PropertyValue::Bool(status != PROTECTION_READ_ONLY));
+        }
+        Some(values)
+    }
+
+    /// Where to create or move an object, as a `(storage ID, parent handle)` pair. The parent handle is `None` for the root of a storage.
+    fn destination(&self, folder_id: &U16CStr) -> Result<(u32, Option<u32>), MtpError> {
+        match Target::parse(folder_id)? {
+            Target::Device => Err(MtpError::InvalidArgument("Objects cannot be put at the root of a device, only in its storages".to_string())),
+            Target::Storage(storage_id) => Ok((storage_id, None)),
+            Target::Object(handle) => {
+                let info = self.session.borrow_mut().object_info(handle)?;
+                if info.object_format != ObjectFormatCode::Association {
+                    return Err(MtpError::InvalidArgument("Destination is not a folder".to_string()));
+                }
+                Ok((info.storage_id, Some(handle)))
+            },
+        }
+    }
+}
+
+/// Build the dataset that describes an object to create
+fn object_info_for_creation(properties: &DeviceValues, storage_id: u32) -> Result<ObjectInfo, MtpError> {
+    let filename = properties.get_string(&WPD_OBJECT_ORIGINAL_FILE_NAME)
+        .or_else(|_| properties.get_string(&WPD_OBJECT_NAME))?
+        .to_string_lossy();
+    let is_folder = properties.get_guid(&WPD_OBJECT_CONTENT_TYPE).is_ok_and(|ty| ty == WPD_CONTENT_TYPE_FOLDER);
+    let object_format = match properties.get_guid(&WPD_OBJECT_FORMAT).ok().and_then(ObjectFormatCode::from_guid) {
+        Some(format) => format,
+        None if is_folder => ObjectFormatCode::Association,
+        None => ObjectFormatCode::Undefined,
+    };
+    let size = properties.get_u64(&WPD_OBJECT_SIZE).unwrap_or(0);
+    let date = |key| properties.get_date(key).map(format_datetime).unwrap_or_default();
+
+    Ok(ObjectInfo{
+        storage_id,
+        object_format,
+        object_compressed_size: u32::try_from(size).unwrap_or(u32::MAX),
+        // "Generic folder"
+        association_type: if object_format == ObjectFormatCode::Association { 1 } else { 0 },
+        filename,
+        date_created: date(&WPD_OBJECT_DATE_CREATED),
+        date_modified: date(&WPD_OBJECT_DATE_MODIFIED),
+        ..Default::default()
+    })
+}
+
+impl ContentBackend for MtpContent {
+    fn children(&self, parent_id: &U16CStr) -> Result<Box<dyn Iterator<Item = U16CString>>, MtpError> {
+        let mut session = self.session.borrow_mut();
+        let children: Vec<_> = match Target::parse(parent_id)? {
+            Target::Device => session.storage_ids()?.into_iter().map(object_id_from_storage_id).collect(),
+            Target::Storage(storage_id) => session.object_handles(storage_id, ALL)?.into_iter().map(object_id_from_handle).collect(),
+            Target::Object(handle) => session.object_handles(ALL, handle)?.into_iter().map(object_id_from_handle).collect(),
+        };
+        Ok(Box::new(children.into_iter()))
+    }
+
+    fn properties(&self, object_id: &U16CStr, properties_to_fetch: &[crate::PROPERTYKEY]) -> Result<DeviceValues, MtpError> {
+        let known_values = match Target::parse(object_id)? {
+            Target::Device => self.device_properties(properties_to_fetch)?,
+            Target::Storage(storage_id) => self.storage_properties(storage_id)?,
+            Target::Object(handle) => return self.object_properties(handle, properties_to_fetch),
+        };
+
+        let mut values = DeviceValues::new();
+        for key in properties_to_fetch {
+            if let Some(value) = known_values.get(key) {
+                values.set(*key, value.clone());
+            }
+        }
+        Ok(values)
+    }
+
+    /// Children of folders are listed with `GetObjectHandles`, then their properties are read with a single `GetObjectPropList`, when the device supports it.
+    ///
+    /// Children the list does not describe well enough are read one by one, as [`Self::properties`] does.
+    fn children_properties(&self, parent_id: &U16CStr, properties_to_fetch: &[crate::PROPERTYKEY]) -> Result<Vec<(U16CString, DeviceValues)>, MtpError> {
+        let Target::Object(parent) = Target::parse(parent_id)? else {
+            return self.children(parent_id)?
+                .map(|child_id| Ok((child_id.clone(), self.properties(&child_id, properties_to_fetch)?)))
+                .collect();
+        };
+
+        let (handles, prop_list) = {
+            let mut session = self.session.borrow_mut();
+            (session.object_handles(ALL, parent)?, session.children_prop_list(parent)?.unwrap_or_default())
+        };
+        let mut elements: HashMap<u32, Vec<(ObjectPropertyCode, Value)>> = HashMap::new();
+        for (handle, property, value) in prop_list {
+            elements.entry(handle).or_default().push((property, value));
+        }
+
+        let mut children = Vec::with_capacity(handles.len());
+        for handle in handles {
+            let listed_values = elements.get(&handle).and_then(|elements| Self::prop_list_properties(handle, parent, elements));
+            let values = match listed_values {
+                Some(listed_values) => {
+                    let mut values = DeviceValues::new();
+                    for key in properties_to_fetch {
+                        if let Some(value) = listed_values.get(key) {
+                            values.set(*key, value.clone());
+                        }
+                    }
+                    values
+                },
+                None => self.object_properties(handle, properties_to_fetch)?,
+            };
+            children.push((object_id_from_handle(handle), values));
+        }
+        Ok(children)
+    }
+
+    fn set_properties(&self, object_id: &U16CStr, values: &DeviceValues) -> Result<Vec<Result<(), MtpError>>, MtpError> {
+        let handle = Target::handle(object_id)?;
+        let mut session = self.session.borrow_mut();
+        for_each_property(values, |key, value| {
+            // The name MTP devices display is their file name
+            let property = if *key == WPD_OBJECT_NAME {
+                ObjectPropertyCode::ObjectFileName
+            } else {
+                ObjectPropertyCode::from_property_key(key).ok_or(MtpError::Unsupported)?
+            };
+            let value = property.to_value(value).ok_or(MtpError::InvalidProperty)?;
+            session.set_object_prop_value(handle, property, &value)
+        })
+    }
+
+    fn create_object(&self, properties: &DeviceValues) -> Result<U16CString, MtpError> {
+        let (storage_id, parent) = self.destination(&properties.get_string(&WPD_OBJECT_PARENT_ID)?)?;
+        let info = object_info_for_creation(properties, storage_id)?;
+        let handle = self.session.borrow_mut().send_object(storage_id, parent.unwrap_or(ALL), &info, None)?;
+        Ok(object_id_from_handle(handle))
+    }
+
+    /// MTP announces the size of objects before their data, so `properties` must contain `WPD_OBJECT_SIZE`
+    fn create_object_with_data(&self, properties: &DeviceValues) -> Result<(Box<dyn WriteStreamBackend>, u32), MtpError> {
+        let (storage_id, parent) = self.destination(&properties.get_string(&WPD_OBJECT_PARENT_ID)?)?;
+        let stream = MtpWriteStream{
+            session: Rc::clone(&self.session),
+            storage_id,
+            parent: parent.unwrap_or(ALL),
+            info: object_info_for_creation(properties, storage_id)?,
+            size: properties.get_u64(&WPD_OBJECT_SIZE)?,
+            written: 0,
+            handle: None,
+            committed: false,
+        };
+        Ok((Box::new(stream), OPTIMAL_TRANSFER_SIZE))
+    }
+
+    fn open_read_stream(&self, object_id: &U16CStr) -> Result<(Box<dyn ReadStreamBackend>, u32), MtpError> {
+        let handle = Target::handle(object_id)?;
+        if self.session.borrow().supports_partial_objects() {
+            let stream = MtpReadStream{
+                session: Rc::clone(&self.session),
+                handle,
+                position: 0,
+                size: None,
+                chunk: Vec::new(),
+                chunk_offset: 0,
+            };
+            return Ok((Box::new(stream), OPTIMAL_TRANSFER_SIZE));
+        }
+        let stream = MtpDownloadStream::open(Rc::clone(&self.session), handle)?;
+        Ok((Box::new(stream), OPTIMAL_TRANSFER_SIZE))
+    }
+
+    fn read_range(&self, object_id: &U16CStr, offset: u64, length: usize) -> Result<Vec<u8>, MtpError> {
+        let handle = Target::handle(object_id)?;
+        if !self.session.borrow().supports_partial_objects() {
+            // The object is downloaded up to the end of the range, only the range is kept
+            let mut stream = MtpDownloadStream::open(Rc::clone(&self.session), handle)?;
+            std::io::copy(&mut stream.by_ref().take(offset), &mut std::io::sink())?;
+            let mut data = Vec::new();
+            stream.by_ref().take(length as u64).read_to_end(&mut data)?;
+            stream.finish()?;
+            return Ok(data);
+        }
+
+        let mut session = self.session.borrow_mut();
+        let mut data = Vec::with_capacity(length.min(OPTIMAL_TRANSFER_SIZE as usize));
+        while data.len() < length {
+            let max_length = u32::try_from(length - data.len()).unwrap_or(u32::MAX);
+            let chunk = session.partial_object(handle, offset + data.len() as u64, max_length)?;
+            if chunk.is_empty() {
+                break;
+            }
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+
+    fn delete(&self, object_ids: &[&U16CStr], recursive: bool) -> Result<Vec<Result<(), MtpError>>, MtpError> {
+        let mut session = self.session.borrow_mut();
+        // MTP has no batch deletion, each object is a transaction
+        for_each_object(object_ids, |object_id| {
+            let handle = Target::handle(object_id)?;
+            // MTP always deletes folders recursively
+            if !recursive && !session.object_handles(ALL, handle)?.is_empty() {
+                return Err(MtpError::InvalidArgument("Folder is not empty".to_string()));
+            }
+            session.delete_object(handle)
+        })
+    }
+
+    fn move_objects(&self, object_ids: &[&U16CStr], destination_folder_id: &U16CStr) -> Result<Vec<Result<(), MtpError>>, MtpError> {
+        let (storage_id, parent) = self.destination(destination_folder_id)?;
+        let mut session = self.session.borrow_mut();
+        for_each_object(object_ids, |object_id| session.move_object(Target::handle(object_id)?, storage_id, parent.unwrap_or(0)))
+    }
+
+    /// Events are received by a thread, that stops when the subscription is dropped, or when the device is gone
+    fn subscribe(&self, sink: EventSink) -> Result<Subscription, MtpError> {
+        let mut source = self.session.borrow_mut().event_source()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let thread = std::thread::spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                match source.next_event(EVENT_POLL_INTERVAL) {
+                    Ok(Some(container)) => {
+                        if let Some(event) = device_event(&container) {
+                            sink.send(event);
+                        }
+                    },
+                    Ok(None) => {},
+                    Err(_) => {
+                        if !thread_stop.load(Ordering::Relaxed) {
+                            sink.send(DeviceEvent::DeviceRemoved);
+                        }
+                        return;
+                    },
+                }
+            }
+        });
+        Ok(Subscription::new(move || {
+            stop.store(true, Ordering::Relaxed);
+            let _ = thread.join();
+        }))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// MTP requires the whole object to be sent in a single data phase: `SendObjectInfo` is sent along with the first bytes,
+/// that go straight into the data phase of `SendObject`, like the following ones.
+///
+/// Other transactions fail until the stream is committed. As MTP cannot interrupt a data phase, streams that are aborted
+/// (or dropped) midway send zeros up to the announced size, then delete the object.
+struct MtpWriteStream {
+    session: Rc<RefCell<Session>>,
+    storage_id: u32,
+    parent: u32,
+    info: ObjectInfo,
+    size: u64,
+    written: u64,
+    /// The object `SendObjectInfo` has created, once the upload has started
+    handle: Option<u32>,
+    committed: bool,
+}
+
+impl MtpWriteStream {
+    fn start(&mut self) -> Result<(), MtpError> {
+        if self.handle.is_some() || self.committed {
+            return Ok(());
+        }
+        let mut session = self.session.borrow_mut();
+        let handle = session.send_object_info(self.storage_id, self.parent, &self.info)?;
+        if let Err(err) = session.start_upload(OperationCode::SendObject, &[], self.size) {
+            let _ = session.delete_object(handle);
+            return Err(err);
+        }
+        self.handle = Some(handle);
+        Ok(())
+    }
+
+    /// Give up an upload that has started
+    fn cancel(&mut self) -> Result<(), MtpError> {
+        let Some(handle) = self.handle.take() else {
+            return Ok(());
+        };
+        let mut session = self.session.borrow_mut();
+        let zeros = vec![0; OPTIMAL_TRANSFER_SIZE as usize];
+        while self.written < self.size {
+            let len = zeros.len().min(usize::try_from(self.size - self.written).unwrap_or(usize::MAX));
+            session.send_data(&zeros[..len])?;
+            self.written += len as u64;
+        }
+        session.finish_transfer()?;
+        session.delete_object(handle)
+    }
+}
+
+impl Write for MtpWriteStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min(usize::try_from(self.size - self.written).unwrap_or(usize::MAX));
+        if len == 0 || self.committed {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Expected {} bytes, got more", self.size)));
+        }
+        self.start().map_err(std::io::Error::other)?;
+        self.session.borrow_mut().send_data(&buf[..len]).map_err(std::io::Error::other)?;
+        self.written += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WriteStreamBackend for MtpWriteStream {
+    fn commit(&mut self) -> Result<(), MtpError> {
+        if self.committed {
+            return Ok(());
+        }
+        if self.written != self.size {
+            let written = self.written;
+            self.cancel()?;
+            return Err(MtpError::InvalidArgument(format!("Expected {} bytes, got {}", self.size, written)));
+        }
+
+        // Empty objects have not started yet
+        self.start()?;
+        self.session.borrow_mut().finish_transfer()?;
+        self.committed = true;
+        Ok(())
+    }
+
+    fn abort(&mut self) -> Result<(), MtpError> {
+        self.cancel()
+    }
+
+    fn object_id(&self) -> Option<U16CString> {
+        self.handle.filter(|_| self.committed).map(object_id_from_handle)
+    }
+}
+
+impl Drop for MtpWriteStream {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = self.cancel();
+        }
+    }
+}
+
+/// Reads objects with a single `GetObject`, whose data phase is streamed, for devices that cannot read parts of objects
+///
+/// Other transactions fail until the whole data has been read, or the stream is dropped, which skips the rest of the data.
+struct MtpDownloadStream {
+    session: Rc<RefCell<Session>>,
+    finished: bool,
+}
+
+impl MtpDownloadStream {
+    fn open(session: Rc<RefCell<Session>>, handle: u32) -> Result<Self, MtpError> {
+        session.borrow_mut().start_download(OperationCode::GetObject, &[handle])?;
+        Ok(Self{ session, finished: false })
+    }
+
+    fn finish(&mut self) -> Result<(), MtpError> {
+        if !self.finished {
+            self.finished = true;
+            self.session.borrow_mut().finish_transfer()?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for MtpDownloadStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.finished || buf.is_empty() {
+            return Ok(0);
+        }
+        let len = self.session.borrow_mut().receive_data(buf).map_err(std::io::Error::other)?;
+        if len == 0 {
+            self.finish().map_err(std::io::Error::other)?;
+        }
+        Ok(len)
+    }
+}
+
+impl ReadStreamBackend for MtpDownloadStream {}
+
+impl Drop for MtpDownloadStream {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Reads objects chunk by chunk with `GetPartialObject(64)`, so that only the requested parts are transferred
+struct MtpReadStream {
+    session: Rc<RefCell<Session>>,
+    handle: u32,
+    position: u64,
+    /// Only fetched when seeking from the end
+    size: Option<u64>,
+    chunk: Vec<u8>,
+    chunk_offset: u64,
+}
+
+impl Read for MtpReadStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let chunk_end = self.chunk_offset + self.chunk.len() as u64;
+        if self.position < self.chunk_offset || self.position >= chunk_end {
+            self.chunk = self.session.borrow_mut().partial_object(self.handle, self.position, OPTIMAL_TRANSFER_SIZE)
+                .map_err(std::io::Error::other)?;
+            self.chunk_offset = self.position;
+        }
+
+        let start = (self.position - self.chunk_offset) as usize;
+        let available = &self.chunk[start.min(self.chunk.len())..];
+        let count = available.len().min(buf.len());
+        buf[..count].copy_from_slice(&available[..count]);
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl ReadStreamBackend for MtpReadStream {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        let new_position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+            SeekFrom::End(delta) => {
+                let size = match self.size {
+                    Some(size) => size,
+                    None => {
+                        let size = self.session.borrow_mut().object_size(self.handle).map_err(std::io::Error::other)?;
+                        *self.size.insert(size)
+                    },
+                };
+                size.checked_add_signed(delta)
+            },
+        };
+        self.position = new_position.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Cannot seek before the start of the object"))?;
+        Ok(self.position)
+    }
+}
@@ -0,0 +1,204 @@
+//! A backend that speaks the MTP protocol itself, over any [`Transport`]
+//!
+//! This is the common ground of backends that do not rely on the Windows API (e.g. the [USB backend](crate::backend::usb) on Linux):
+//! they only have to carry [`Container`]s and data phases back and forth, and this module turns every [`ContentBackend`] operation into MTP transactions.
+//!
+//! Objects are designated the way the Windows MTP driver does it: the device root is `DEVICE`, storages are `s<storage ID>`
+//! and other objects are `o<object handle>` (see [`crate::protocol::mapping`]).
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::backend::{DeviceBackend, OpenedDeviceBackend, ContentBackend, CapabilitiesBackend};
+use crate::device::device_values::AppIdentifiers;
+use crate::error::{MtpError, ProtocolError};
+use crate::protocol::codes::OperationCode;
+use crate::protocol::container::{Container, ContainerHeader, ContainerType};
+
+mod session;
+pub use session::{Session, Response, ALL};
+
+mod content;
+pub use content::MtpContent;
+
+mod capabilities;
+pub use capabilities::MtpCapabilities;
+
+/// Something that carries MTP containers between an initiator (us) and a responder (the device)
+///
+/// Data phases are streamed, so that objects never have to fit in memory: the initiator sends its own with [`Self::start_data`],
+/// [`Self::send_data`] and [`Self::end_data`], and reads the payload of the ones of the responder with [`Self::receive_data`].
+pub trait Transport {
+    /// Send an operation request
+    fn send(&mut self, command: &Container) -> Result<(), MtpError>;
+
+    /// Start the data phase of the last operation request, whose payload is `length` bytes long
+    fn start_data(&mut self, operation: OperationCode, transaction_id: u32, length: u64) -> Result<(), MtpError>;
+
+    /// Send the next bytes of the payload of the data phase that has been started. Exactly `length` bytes must be sent in total.
+    fn send_data(&mut self, data: &[u8]) -> Result<(), MtpError>;
+
+    /// Finish the data phase that has been started
+    fn end_data(&mut self) -> Result<(), MtpError>;
+
+    /// Wait for the next container sent by the responder (a data phase, or a response)
+    ///
+    /// The payload of data phases is not part of the returned container, it is read afterwards with [`Self::receive_data`].
+    fn receive(&mut self) -> Result<Container, MtpError>;
+
+    /// Read the next bytes of the payload of the data phase that has been received. This returns 0 once the whole payload has been read.
+    fn receive_data(&mut self, buf: &mut [u8]) -> Result<usize, MtpError>;
+
+    /// Get what receives the events of the responder, concurrently with transactions.
+    ///
+    /// The default implementation returns [`MtpError::Unsupported`], for transports that do not carry events.
+    fn event_source(&mut self) -> Result<Box<dyn EventSource>, MtpError> {
+        Err(MtpError::Unsupported)
+    }
+}
+
+/// Reads the payload of the data phase a [`Transport`] has received (see [`Transport::receive_data`])
+pub struct DataReader<'a, T: ?Sized>(pub &'a mut T);
+
+impl<T: Transport + ?Sized> Read for DataReader<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.receive_data(buf).map_err(io::Error::other)
+    }
+}
+
+/// Receives the event containers of a responder (e.g. on the interrupt endpoint of a USB device), on another thread than its [`Transport`]
+pub trait EventSource: Send {
+    /// Wait for the next event, for at most `timeout`. This returns `Ok(None)` if none came in time, and an error once the responder is gone.
+    fn next_event(&mut self, timeout: Duration) -> Result<Option<Container>, MtpError>;
+}
+
+/// A [`Transport`] over a byte stream (e.g. a socket or a pipe), in which containers are simply concatenated
+///
+/// This is how [`crate::responder::Responder::serve`] expects to be talked to. Data phases of 4 GiB or more cannot be delimited on such streams.
+pub struct StreamTransport<S> {
+    stream: S,
+    /// What is left to send of the payload of the data phase that has been started
+    sending_left: u64,
+    /// What is left to read of the payload of the data phase that has been received
+    receiving_left: u64,
+}
+
+impl<S: Read + Write> StreamTransport<S> {
+    pub fn new(stream: S) -> Self {
+        Self{ stream, sending_left: 0, receiving_left: 0 }
+    }
+
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+}
+
+impl<S: Read + Write> Transport for StreamTransport<S> {
+    fn send(&mut self, command: &Container) -> Result<(), MtpError> {
+        command.write_to(&mut self.stream)?;
+        Ok(self.stream.flush()?)
+    }
+
+    fn start_data(&mut self, operation: OperationCode, transaction_id: u32, length: u64) -> Result<(), MtpError> {
+        let header = ContainerHeader::data(operation, transaction_id, length);
+        if header.length == ContainerHeader::UNKNOWN_LENGTH {
+            return Err(ProtocolError::InvalidLength(header.length).into());
+        }
+        self.stream.write_all(&header.encode())?;
+        self.sending_left = length;
+        Ok(())
+    }
+
+    fn send_data(&mut self, data: &[u8]) -> Result<(), MtpError> {
+        if data.len() as u64 > self.sending_left {
+            return Err(MtpError::InvalidArgument("More data than the length of the data phase".to_string()));
+        }
+        self.stream.write_all(data)?;
+        self.sending_left -= data.len() as u64;
+        Ok(())
+    }
+
+    fn end_data(&mut self) -> Result<(), MtpError> {
+        if self.sending_left != 0 {
+            return Err(MtpError::InvalidArgument(format!("{} bytes of the data phase have not been sent", self.sending_left)));
+        }
+        Ok(self.stream.flush()?)
+    }
+
+    fn receive(&mut self) -> Result<Container, MtpError> {
+        let header = ContainerHeader::read_from(&mut self.stream).map_err(MtpError::from_connection)?;
+        if header.container_type != ContainerType::Data {
+            return Container::read_payload_from(&header, &mut self.stream).map_err(MtpError::from_connection);
+        }
+        if header.length == ContainerHeader::UNKNOWN_LENGTH {
+            return Err(ProtocolError::InvalidLength(header.length).into());
+        }
+        self.receiving_left = header.payload_len() as u64;
+        Ok(Container::from_header(&header, Vec::new()))
+    }
+
+    fn receive_data(&mut self, buf: &mut [u8]) -> Result<usize, MtpError> {
+        let max_len = buf.len().min(usize::try_from(self.receiving_left).unwrap_or(usize::MAX));
+        if max_len == 0 {
+            return Ok(0);
+        }
+        let len = self.stream.read(&mut buf[..max_len]).map_err(MtpError::from_connection)?;
+        if len == 0 {
+            return Err(ProtocolError::Truncated.into());
+        }
+        self.receiving_left -= len as u64;
+        Ok(len)
+    }
+}
+
+/// A device that is reachable through a [`Transport`]
+///
+/// The transport is only created when the device is opened.
+pub struct MtpDevice {
+    connect: Box<dyn Fn() -> Result<Box<dyn Transport>, MtpError>>,
+}
+
+impl MtpDevice {
+    pub fn new<F>(connect: F) -> Self
+    where F: Fn() -> Result<Box<dyn Transport>, MtpError> + 'static
+    {
+        Self{ connect: Box::new(connect) }
+    }
+}
+
+impl DeviceBackend for MtpDevice {
+    fn open(&self, _app_identifiers: &AppIdentifiers) -> Result<Box<dyn OpenedDeviceBackend>, MtpError> {
+        let transport = (self.connect)()?;
+        let session = Session::open(transport)?;
+        Ok(Box::new(MtpOpenedDevice{ session: Rc::new(RefCell::new(session)) }))
+    }
+}
+
+/// A device an MTP session has been opened with
+pub struct MtpOpenedDevice {
+    session: Rc<RefCell<Session>>,
+}
+
+impl MtpOpenedDevice {
+    /// The underlying session, in case one wants to send operations for which there is no wrapper in this crate
+    pub fn session(&self) -> &RefCell<Session> {
+        &self.session
+    }
+}
+
+impl OpenedDeviceBackend for MtpOpenedDevice {
+    fn content(&self) -> Result<Rc<dyn ContentBackend>, MtpError> {
+        Ok(Rc::new(MtpContent::new(Rc::clone(&self.session))))
+    }
+
+    fn capabilities(&self) -> Result<Rc<dyn CapabilitiesBackend>, MtpError> {
+        Ok(Rc::new(MtpCapabilities::new(Rc::clone(&self.session))))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
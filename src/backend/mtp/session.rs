@@ -0,0 +1,402 @@
+//! MTP sessions, and the transactions they are made of
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+use crate::backend::mtp::{DataReader, EventSource, Transport};
+use crate::error::MtpError;
+use crate::protocol::codes::{OperationCode, ResponseCode, DevicePropertyCode, ObjectFormatCode, ObjectPropertyCode, DataType};
+use crate::protocol::container::{Container, ContainerType};
+use crate::protocol::data::{Reader, Writer, Value};
+use crate::protocol::datasets::{DeviceInfo, DevicePropDesc, StorageInfo, ObjectInfo, ObjectPropDesc};
+
+/// The only session this crate opens with a device
+const SESSION_ID: u32 = 1;
+
+/// Parameter that designates "every storage" or "the root of a storage", depending on the operation
+pub const ALL: u32 = 0xFFFF_FFFF;
+
+/// What a responder answered to a successful transaction
+#[derive(Debug, Default)]
+pub struct Response {
+    pub params: Vec<u32>,
+    /// The data phase sent by the responder, if any
+    pub data: Vec<u8>,
+}
+
+/// An element of an object property list: the handle of an object, one of its properties, and its value
+pub type PropListElement = (u32, ObjectPropertyCode, Value);
+
+/// An MTP session with a device.
+///
+/// The session is closed when this struct is dropped.
+pub struct Session {
+    transport: Box<dyn Transport>,
+    next_transaction_id: u32,
+    device_info: DeviceInfo,
+    prop_descs: HashMap<(ObjectPropertyCode, ObjectFormatCode), Option<ObjectPropDesc>>,
+    streamed_transaction: Option<StreamedTransaction>,
+}
+
+/// A transaction whose data phase is being streamed
+struct StreamedTransaction {
+    transaction_id: u32,
+    /// Whether the data phase goes to the device
+    upload: bool,
+    /// For downloads, the response the device sent instead of a data phase
+    response: Option<Response>,
+}
+
+impl Session {
+    /// Retrieve the device info, then open a session
+    pub fn open(transport: Box<dyn Transport>) -> Result<Self, MtpError> {
+        let mut session = Self{
+            transport,
+            next_transaction_id: 0,
+            device_info: DeviceInfo::default(),
+            prop_descs: HashMap::new(),
+            streamed_transaction: None,
+        };
+
+        // Both operations use transaction ID 0, as they are (or can be) sent outside of a session
+        let device_info = session.execute(0, OperationCode::GetDeviceInfo, &[], None)?;
+        session.device_info = DeviceInfo::decode(&device_info.data)?;
+        match session.execute(0, OperationCode::OpenSession, &[SESSION_ID], None) {
+            // E.g. when a previous process did not close its session properly
+            Ok(_) | Err(MtpError::Response(ResponseCode::SessionAlreadyOpen)) => {},
+            Err(err) => return Err(err),
+        }
+        session.next_transaction_id = 1;
+        Ok(session)
+    }
+
+    pub fn device_info(&self) -> &DeviceInfo {
+        &self.device_info
+    }
+
+    /// Get what receives the events of the device (see [`Transport::event_source`])
+    pub fn event_source(&mut self) -> Result<Box<dyn EventSource>, MtpError> {
+        self.transport.event_source()
+    }
+
+    /// Whether the device claims to support an operation
+    pub fn supports(&self, operation: OperationCode) -> bool {
+        self.device_info.operations_supported.contains(&operation.as_u16())
+    }
+
+    /// Get the description of a device property, including its current value, or `None` if the device does not support it
+    ///
+    /// Unlike object property descriptions, these are not cached, as their values change (e.g. the battery level).
+    pub fn device_prop_desc(&mut self, property: DevicePropertyCode) -> Result<Option<DevicePropDesc>, MtpError> {
+        if !self.supports(OperationCode::GetDevicePropDesc) || !self.device_info.device_properties_supported.contains(&property.as_u16()) {
+            return Ok(None);
+        }
+        match self.transaction(OperationCode::GetDevicePropDesc, &[property.as_u16() as u32], None) {
+            Ok(response) => Ok(Some(DevicePropDesc::decode(&response.data)?)),
+            Err(MtpError::Response(ResponseCode::DevicePropNotSupported)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Run a transaction: send an operation (and its data phase, if any), then wait for its response.
+    ///
+    /// Responses other than `Ok` are turned into errors.
+    pub fn transaction(&mut self, operation: OperationCode, params: &[u32], data: Option<Vec<u8>>) -> Result<Response, MtpError> {
+        let transaction_id = self.next_transaction_id()?;
+        self.execute(transaction_id, operation, params, data)
+    }
+
+    fn next_transaction_id(&mut self) -> Result<u32, MtpError> {
+        if self.streamed_transaction.is_some() {
+            return Err(io::Error::new(io::ErrorKind::ResourceBusy, "Another transfer is in progress").into());
+        }
+        let transaction_id = self.next_transaction_id;
+        // 0 and 0xFFFFFFFF are reserved
+        self.next_transaction_id = match transaction_id.wrapping_add(1) {
+            0 | ALL => 1,
+            next => next,
+        };
+        Ok(transaction_id)
+    }
+
+    fn execute(&mut self, transaction_id: u32, operation: OperationCode, params: &[u32], data: Option<Vec<u8>>) -> Result<Response, MtpError> {
+        self.transport.send(&Container::command(operation, transaction_id, params))?;
+        if let Some(data) = data {
+            self.transport.start_data(operation, transaction_id, data.len() as u64)?;
+            self.transport.send_data(&data)?;
+            self.transport.end_data()?;
+        }
+
+        let mut received_data = Vec::new();
+        loop {
+            let container = self.receive(transaction_id)?;
+            match container.container_type {
+                ContainerType::Data => {
+                    received_data.clear();
+                    DataReader(self.transport.as_mut()).read_to_end(&mut received_data)?;
+                },
+                ContainerType::Response => return response(&container, received_data),
+                other => return Err(MtpError::Backend(format!("Unexpected {:?} container", other))),
+            }
+        }
+    }
+
+    fn receive(&mut self, transaction_id: u32) -> Result<Container, MtpError> {
+        let container = self.transport.receive()?;
+        if container.transaction_id != transaction_id {
+            return Err(MtpError::Backend(format!("Expected transaction {}, got {}", transaction_id, container.transaction_id)));
+        }
+        Ok(container)
+    }
+
+    /// Start a transaction whose data phase, of `length` bytes, is then sent piece by piece with [`Self::send_data`].
+    ///
+    /// The transaction is over once [`Self::finish_transfer`] has been called. Other transactions fail until then.
+    pub fn start_upload(&mut self, operation: OperationCode, params: &[u32], length: u64) -> Result<(), MtpError> {
+        let transaction_id = self.next_transaction_id()?;
+        self.transport.send(&Container::command(operation, transaction_id, params))?;
+        self.transport.start_data(operation, transaction_id, length)?;
+        self.streamed_transaction = Some(StreamedTransaction{ transaction_id, upload: true, response: None });
+        Ok(())
+    }
+
+    /// Send the next bytes of the data phase of the transaction started by [`Self::start_upload`]
+    pub fn send_data(&mut self, data: &[u8]) -> Result<(), MtpError> {
+        match &self.streamed_transaction {
+            Some(StreamedTransaction{ upload: true, .. }) => self.transport.send_data(data),
+            _ => Err(MtpError::InvalidArgument("No upload is in progress".to_string())),
+        }
+    }
+
+    /// Start a transaction whose data phase is then read piece by piece with [`Self::receive_data`].
+    ///
+    /// The transaction is over once [`Self::finish_transfer`] has been called. Other transactions fail until then.
+    pub fn start_download(&mut self, operation: OperationCode, params: &[u32]) -> Result<(), MtpError> {
+        let transaction_id = self.next_transaction_id()?;
+        self.transport.send(&Container::command(operation, transaction_id, params))?;
+        let container = self.receive(transaction_id)?;
+        let response = match container.container_type {
+            ContainerType::Data => None,
+            // The responder has nothing to send, or failed
+            ContainerType::Response => Some(response(&container, Vec::new())?),
+            other => return Err(MtpError::Backend(format!("Unexpected {:?} container", other))),
+        };
+        self.streamed_transaction = Some(StreamedTransaction{ transaction_id, upload: false, response });
+        Ok(())
+    }
+
+    /// Read the next bytes of the data phase of the transaction started by [`Self::start_download`]. This returns 0 at the end of the data.
+    pub fn receive_data(&mut self, buf: &mut [u8]) -> Result<usize, MtpError> {
+        match &self.streamed_transaction {
+            Some(StreamedTransaction{ upload: false, response: None, .. }) => self.transport.receive_data(buf),
+            Some(StreamedTransaction{ upload: false, response: Some(_), .. }) => Ok(0),
+            _ => Err(MtpError::InvalidArgument("No download is in progress".to_string())),
+        }
+    }
+
+    /// Wait for the response of the transaction started by [`Self::start_upload`] (whose data must have been sent entirely)
+    /// or [`Self::start_download`] (whose data that has not been read yet is skipped)
+    pub fn finish_transfer(&mut self) -> Result<Response, MtpError> {
+        let transaction = self.streamed_transaction.take()
+            .ok_or_else(|| MtpError::InvalidArgument("No transfer is in progress".to_string()))?;
+        if let Some(response) = transaction.response {
+            return Ok(response);
+        }
+        if transaction.upload {
+            self.transport.end_data()?;
+        } else {
+            io::copy(&mut DataReader(self.transport.as_mut()), &mut io::sink())?;
+        }
+
+        let container = self.receive(transaction.transaction_id)?;
+        match container.container_type {
+            ContainerType::Response => response(&container, Vec::new()),
+            other => Err(MtpError::Backend(format!("Unexpected {:?} container", other))),
+        }
+    }
+
+    pub fn storage_ids(&mut self) -> Result<Vec<u32>, MtpError> {
+        let response = self.transaction(OperationCode::GetStorageIds, &[], None)?;
+        Ok(Reader::new(&response.data).get_u32_array()?)
+    }
+
+    pub fn storage_info(&mut self, storage_id: u32) -> Result<StorageInfo, MtpError> {
+        let response = self.transaction(OperationCode::GetStorageInfo, &[storage_id], None)?;
+        Ok(StorageInfo::decode(&response.data)?)
+    }
+
+    /// List the direct children of `parent` (which is [`ALL`] for the root of the storage)
+    pub fn object_handles(&mut self, storage_id: u32, parent: u32) -> Result<Vec<u32>, MtpError> {
+        let response = self.transaction(OperationCode::GetObjectHandles, &[storage_id, 0, parent], None)?;
+        Ok(Reader::new(&response.data).get_u32_array()?)
+    }
+
+    pub fn object_info(&mut self, handle: u32) -> Result<ObjectInfo, MtpError> {
+        let response = self.transaction(OperationCode::GetObjectInfo, &[handle], None)?;
+        Ok(ObjectInfo::decode(&response.data)?)
+    }
+
+    pub fn object(&mut self, handle: u32) -> Result<Vec<u8>, MtpError> {
+        Ok(self.transaction(OperationCode::GetObject, &[handle], None)?.data)
+    }
+
+    /// Whether parts of objects can be read, see [`Self::partial_object`]
+    pub fn supports_partial_objects(&self) -> bool {
+        self.supports(OperationCode::GetPartialObject64) || self.supports(OperationCode::GetPartialObject)
+    }
+
+    /// Read at most `max_length` bytes of an object, starting at `offset`. Fewer bytes are returned at the end of the object.
+    ///
+    /// Offsets beyond 4 GiB require the device to support `GetPartialObject64`.
+    pub fn partial_object(&mut self, handle: u32, offset: u64, max_length: u32) -> Result<Vec<u8>, MtpError> {
+        let response = if self.supports(OperationCode::GetPartialObject64) {
+            self.transaction(OperationCode::GetPartialObject64, &[handle, offset as u32, (offset >> 32) as u32, max_length], None)?
+        } else if self.supports(OperationCode::GetPartialObject) {
+            let offset = u32::try_from(offset).map_err(|_| MtpError::Unsupported)?;
+            self.transaction(OperationCode::GetPartialObject, &[handle, offset, max_length], None)?
+        } else {
+            return Err(MtpError::Unsupported);
+        };
+        Ok(response.data)
+    }
+
+    /// The size of an object, even when it does not fit in its `ObjectInfo` dataset
+    pub fn object_size(&mut self, handle: u32) -> Result<u64, MtpError> {
+        let info = self.object_info(handle)?;
+        if info.object_compressed_size != u32::MAX {
+            return Ok(info.object_compressed_size as u64);
+        }
+        self.object_prop_value(handle, ObjectPropertyCode::ObjectSize, info.object_format)?
+            .and_then(|size| size.as_u64())
+            .ok_or(MtpError::Unsupported)
+    }
+
+    /// Get the properties objects of a format can have. This is empty if the device does not support MTP object properties.
+    pub fn object_props_supported(&mut self, format: ObjectFormatCode) -> Result<Vec<ObjectPropertyCode>, MtpError> {
+        if !self.supports(OperationCode::GetObjectPropsSupported) {
+            return Ok(Vec::new());
+        }
+        let response = match self.transaction(OperationCode::GetObjectPropsSupported, &[format.as_u16() as u32], None) {
+            Ok(response) => response,
+            Err(MtpError::Response(ResponseCode::InvalidObjectFormatCode)) => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let codes = Reader::new(&response.data).get_u16_array()?;
+        Ok(codes.into_iter().map(ObjectPropertyCode::from_u16).collect())
+    }
+
+    /// Get the description of a property, or `None` if objects of this format do not have this property
+    pub fn object_prop_desc(&mut self, property: ObjectPropertyCode, format: ObjectFormatCode) -> Result<Option<ObjectPropDesc>, MtpError> {
+        if let Some(desc) = self.prop_descs.get(&(property, format)) {
+            return Ok(desc.clone());
+        }
+
+        let desc = match self.transaction(OperationCode::GetObjectPropDesc, &[property.as_u16() as u32, format.as_u16() as u32], None) {
+            Ok(response) => Some(ObjectPropDesc::decode(&response.data)?),
+            Err(MtpError::Response(ResponseCode::InvalidObjectPropCode | ResponseCode::ObjectPropNotSupported | ResponseCode::InvalidObjectFormatCode)) => None,
+            Err(err) => return Err(err),
+        };
+        self.prop_descs.insert((property, format), desc.clone());
+        Ok(desc)
+    }
+
+    /// Get the value of a property, or `None` if the device does not support it
+    pub fn object_prop_value(&mut self, handle: u32, property: ObjectPropertyCode, format: ObjectFormatCode) -> Result<Option<Value>, MtpError> {
+        if !self.supports(OperationCode::GetObjectPropValue) || !self.supports(OperationCode::GetObjectPropDesc) {
+            return Ok(None);
+        }
+        let Some(desc) = self.object_prop_desc(property, format)? else {
+            return Ok(None);
+        };
+
+        match self.transaction(OperationCode::GetObjectPropValue, &[handle, property.as_u16() as u32], None) {
+            Ok(response) => Ok(Some(Reader::new(&response.data).get_value(desc.data_type)?)),
+            Err(MtpError::Response(ResponseCode::InvalidObjectPropCode | ResponseCode::ObjectPropNotSupported)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Get the values of every property of the direct children of an object, in a single transaction, or `None` if the device does not support it
+    pub fn children_prop_list(&mut self, parent: u32) -> Result<Option<Vec<PropListElement>>, MtpError> {
+        if !self.supports(OperationCode::GetObjectPropList) {
+            return Ok(None);
+        }
+        // Any format, every property, no group, depth 1
+        let response = match self.transaction(OperationCode::GetObjectPropList, &[parent, 0, ALL, 0, 1], None) {
+            Ok(response) => response,
+            Err(MtpError::Response(ResponseCode::SpecificationByGroupUnsupported | ResponseCode::SpecificationByDepthUnsupported)) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let mut reader = Reader::new(&response.data);
+        let count = reader.get_u32()?;
+        let mut elements = Vec::new();
+        for _ in 0..count {
+            let handle = reader.get_u32()?;
+            let property = ObjectPropertyCode::from_u16(reader.get_u16()?);
+            let data_type = DataType::from_u16(reader.get_u16()?);
+            elements.push((handle, property, reader.get_value(data_type)?));
+        }
+        Ok(Some(elements))
+    }
+
+    pub fn set_object_prop_value(&mut self, handle: u32, property: ObjectPropertyCode, value: &Value) -> Result<(), MtpError> {
+        if !self.supports(OperationCode::SetObjectPropValue) {
+            return Err(MtpError::Unsupported);
+        }
+        let mut writer = Writer::new();
+        writer.put_value(value);
+        self.transaction(OperationCode::SetObjectPropValue, &[handle, property.as_u16() as u32], Some(writer.into_bytes()))?;
+        Ok(())
+    }
+
+    /// Create an object, and return its handle.
+    ///
+    /// `parent` is [`ALL`] for the root of the storage.<br/>
+    /// `data` must be `None` for folders, and `Some` for files (even empty ones).
+    pub fn send_object(&mut self, storage_id: u32, parent: u32, info: &ObjectInfo, data: Option<Vec<u8>>) -> Result<u32, MtpError> {
+        let handle = self.send_object_info(storage_id, parent, info)?;
+        if let Some(data) = data {
+            self.transaction(OperationCode::SendObject, &[], Some(data))?;
+        }
+        Ok(handle)
+    }
+
+    /// Announce an object, and return its handle. Its data is then sent by a `SendObject` transaction (see [`Self::start_upload`]).
+    pub fn send_object_info(&mut self, storage_id: u32, parent: u32, info: &ObjectInfo) -> Result<u32, MtpError> {
+        let response = self.transaction(OperationCode::SendObjectInfo, &[storage_id, parent], Some(info.encode()))?;
+        response.params.get(2).copied().ok_or_else(|| MtpError::Backend("SendObjectInfo did not return any handle".to_string()))
+    }
+
+    pub fn delete_object(&mut self, handle: u32) -> Result<(), MtpError> {
+        self.transaction(OperationCode::DeleteObject, &[handle, 0], None)?;
+        Ok(())
+    }
+
+    /// Move an object. `parent` is 0 for the root of the storage.
+    pub fn move_object(&mut self, handle: u32, storage_id: u32, parent: u32) -> Result<(), MtpError> {
+        self.transaction(OperationCode::MoveObject, &[handle, storage_id, parent], None)?;
+        Ok(())
+    }
+}
+
+/// Responses other than `Ok` are turned into errors, that keep their response codes (see `MtpError::kind` to classify them)
+fn response(container: &Container, data: Vec<u8>) -> Result<Response, MtpError> {
+    match container.response_code() {
+        ResponseCode::Ok => Ok(Response{ params: container.params()?, data }),
+        other => Err(MtpError::Response(other)),
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if self.streamed_transaction.is_some() {
+            let _ = self.finish_transfer();
+        }
+        let session_is_open = self.next_transaction_id != 0;
+        if session_is_open {
+            // The device may already be gone, there is nothing to do about it
+            let _ = self.transaction(OperationCode::CloseSession, &[], None);
+        }
+    }
+}
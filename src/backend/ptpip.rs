@@ -0,0 +1,310 @@
+//! A backend for devices that speak PTP/IP, i.e. PTP over TCP/IP (many cameras, and some Android tools)
+//!
+//! Devices cannot be discovered: they are added by address (e.g. `192.168.1.20:15740`) to a [`PtpIpProvider`].
+//! Every time a device is opened, a new PTP/IP connection (a command and an event channel) is established, and an MTP session is opened over it,
+//! thanks to the [`mtp`](crate::backend::mtp) module.
+//!
+//! ```no_run
+//! use std::rc::Rc;
+//! use winmtp::backend::ptpip::PtpIpProvider;
+//!
+//! let ptpip_provider = Rc::new(PtpIpProvider::new());
+//! ptpip_provider.add_device("192.168.1.20:15740");
+//! let mut provider = winmtp::Provider::new().unwrap();
+//! provider.add_backend(ptpip_provider);
+//! ```
+
+use std::cell::RefCell;
+use std::io;
+use std::net::TcpStream;
+use std::rc::Rc;
+use std::time::Duration;
+
+use widestring::U16CString;
+
+use crate::backend::ProviderBackend;
+use crate::backend::mtp::{EventSource, MtpDevice, Transport};
+use crate::device::{BasicDevice, Subscription};
+use crate::error::{MtpError, ProtocolError};
+use crate::protocol::codes::OperationCode;
+use crate::protocol::container::{Container, ContainerType};
+use crate::protocol::ptpip::{Packet, DataPhase, PROTOCOL_VERSION};
+use crate::watcher::{WatchSink, WatchSinks};
+
+/// How we introduce ourselves to responders, that may remember initiators by their GUID
+pub const DEFAULT_INITIATOR_GUID: [u8; 16] = *b"winmtp-initiator";
+pub const DEFAULT_INITIATOR_NAME: &str = "winmtp";
+
+/// Data phases are sent in chunks of this size
+const DATA_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Lists the PTP/IP devices that have been added by address
+pub struct PtpIpProvider {
+    addresses: RefCell<Vec<String>>,
+    guid: [u8; 16],
+    friendly_name: String,
+    watchers: WatchSinks,
+}
+
+impl Default for PtpIpProvider {
+    fn default() -> Self {
+        Self::with_identity(DEFAULT_INITIATOR_GUID, DEFAULT_INITIATOR_NAME)
+    }
+}
+
+impl PtpIpProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use a custom GUID and name to introduce ourselves to responders
+    pub fn with_identity(guid: [u8; 16], friendly_name: &str) -> Self {
+        Self{ addresses: RefCell::new(Vec::new()), guid, friendly_name: friendly_name.to_string(), watchers: WatchSinks::default() }
+    }
+
+    /// Make a device visible, given its address (e.g. `"192.168.1.20:15740"`, or `"camera.local:15740"`)
+    pub fn add_device(&self, address: &str) {
+        let mut addresses = self.addresses.borrow_mut();
+        if !addresses.iter().any(|known| known == address) {
+            addresses.push(address.to_string());
+            drop(addresses);
+            self.watchers.notify();
+        }
+    }
+
+    /// Make a device invisible again
+    pub fn remove_device(&self, address: &str) {
+        self.addresses.borrow_mut().retain(|known| known != address);
+        self.watchers.notify();
+    }
+}
+
+impl ProviderBackend for PtpIpProvider {
+    fn enumerate_devices(&self) -> Result<Vec<BasicDevice>, MtpError> {
+        Ok(self.addresses
+            .borrow()
+            .iter()
+            .map(|address| {
+                let connect_address = address.clone();
+                let guid = self.guid;
+                let friendly_name = self.friendly_name.clone();
+                let backend = MtpDevice::new(move || {
+                    let transport = PtpIpTransport::connect(&connect_address, guid, &friendly_name)?;
+                    Ok(Box::new(transport) as Box<dyn Transport>)
+                });
+                BasicDevice::new(
+                    U16CString::from_str_truncate(format!("ptpip:{}", address)),
+                    format!("PTP/IP device at {}", address),
+                    Rc::new(backend),
+                )
+            })
+            .collect())
+    }
+
+    /// Devices are added and removed by the application, the network is never scanned
+    fn watch(&self, sink: WatchSink) -> Result<Subscription, MtpError> {
+        Ok(self.watchers.add(sink))
+    }
+}
+
+/// A PTP/IP connection to a responder
+///
+/// PTP/IP operation requests tell whether a data phase follows. Because data phases are started after their commands (see [`Transport::start_data`]),
+/// commands are only sent once we know whether a data phase follows them.
+pub struct PtpIpTransport {
+    command_channel: TcpStream,
+    event_channel: TcpStream,
+    connection_number: u32,
+    responder_name: String,
+    /// A command that has not been sent yet
+    pending_command: Option<Container>,
+    /// The operation of the last command, that incoming data phases belong to
+    current_operation: OperationCode,
+    /// The transaction of the data phase being sent, and its bytes that have not been sent yet
+    outgoing_transaction_id: u32,
+    outgoing: Vec<u8>,
+    /// The bytes of the data phase being received that have not been read yet, and whether its last packet has been received
+    incoming: Vec<u8>,
+    incoming_offset: usize,
+    received_all: bool,
+}
+
+impl PtpIpTransport {
+    /// Establish the command and event channels with a responder
+    pub fn connect(address: &str, guid: [u8; 16], friendly_name: &str) -> Result<Self, MtpError> {
+        let mut command_channel = TcpStream::connect(address)?;
+        command_channel.set_nodelay(true)?;
+        Packet::InitCommandRequest{ guid, friendly_name: friendly_name.to_string(), protocol_version: PROTOCOL_VERSION }
+            .write_to(&mut command_channel)?;
+        let (connection_number, responder_name) = match read_packet(&mut command_channel)? {
+            Packet::InitCommandAck{ connection_number, friendly_name, .. } => (connection_number, friendly_name),
+            Packet::InitFail{ reason } => return Err(ProtocolError::ConnectionRefused(reason).into()),
+            other => return Err(MtpError::Backend(format!("Unexpected PTP/IP packet {:?}", other))),
+        };
+
+        let mut event_channel = TcpStream::connect(command_channel.peer_addr()?)?;
+        event_channel.set_nodelay(true)?;
+        Packet::InitEventRequest{ connection_number }.write_to(&mut event_channel)?;
+        match read_packet(&mut event_channel)? {
+            Packet::InitEventAck => {},
+            Packet::InitFail{ reason } => return Err(ProtocolError::ConnectionRefused(reason).into()),
+            other => return Err(MtpError::Backend(format!("Unexpected PTP/IP packet {:?}", other))),
+        }
+
+        Ok(Self{
+            command_channel,
+            event_channel,
+            connection_number,
+            responder_name,
+            pending_command: None,
+            current_operation: OperationCode::Other(0),
+            outgoing_transaction_id: 0,
+            outgoing: Vec::new(),
+            incoming: Vec::new(),
+            incoming_offset: 0,
+            received_all: true,
+        })
+    }
+
+    /// The connection number the responder assigned
+    pub fn connection_number(&self) -> u32 {
+        self.connection_number
+    }
+
+    /// The name the responder introduced itself with
+    pub fn responder_name(&self) -> &str {
+        &self.responder_name
+    }
+
+    /// Wait for the next event sent by the responder. Probe requests are answered on the fly.
+    pub fn receive_event(&mut self) -> Result<Container, MtpError> {
+        loop {
+            match read_packet(&mut self.event_channel)? {
+                Packet::ProbeRequest => Packet::ProbeResponse.write_to(&mut self.event_channel)?,
+                packet @ Packet::Event{ .. } => return packet.to_container().ok_or(MtpError::Unsupported),
+                other => return Err(MtpError::Backend(format!("Unexpected PTP/IP packet {:?}", other))),
+            }
+        }
+    }
+
+    fn send_pending_command(&mut self, data_phase: DataPhase) -> Result<(), MtpError> {
+        if let Some(command) = self.pending_command.take() {
+            Packet::operation_request(&command, data_phase)?.write_to(&mut self.command_channel)?;
+        }
+        Ok(())
+    }
+}
+
+impl Transport for PtpIpTransport {
+    fn send(&mut self, command: &Container) -> Result<(), MtpError> {
+        if command.container_type != ContainerType::Command {
+            return Err(MtpError::Backend(format!("Initiators do not send {:?} containers", command.container_type)));
+        }
+        self.send_pending_command(DataPhase::NoDataOrDataIn)?;
+        self.current_operation = command.operation_code();
+        self.pending_command = Some(command.clone());
+        Ok(())
+    }
+
+    fn start_data(&mut self, _operation: OperationCode, transaction_id: u32, length: u64) -> Result<(), MtpError> {
+        self.send_pending_command(DataPhase::DataOut)?;
+        Packet::StartData{ transaction_id, total_length: length }.write_to(&mut self.command_channel)?;
+        self.outgoing_transaction_id = transaction_id;
+        self.outgoing.clear();
+        Ok(())
+    }
+
+    /// Data is sent in packets of [`DATA_CHUNK_SIZE`] bytes, the last bytes are kept for the final `EndData` packet
+    fn send_data(&mut self, mut data: &[u8]) -> Result<(), MtpError> {
+        while !data.is_empty() {
+            if self.outgoing.len() == DATA_CHUNK_SIZE {
+                let payload = std::mem::replace(&mut self.outgoing, Vec::with_capacity(DATA_CHUNK_SIZE));
+                Packet::Data{ transaction_id: self.outgoing_transaction_id, payload }.write_to(&mut self.command_channel)?;
+            }
+            let len = data.len().min(DATA_CHUNK_SIZE - self.outgoing.len());
+            self.outgoing.extend_from_slice(&data[..len]);
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    fn end_data(&mut self) -> Result<(), MtpError> {
+        let payload = std::mem::take(&mut self.outgoing);
+        Packet::EndData{ transaction_id: self.outgoing_transaction_id, payload }.write_to(&mut self.command_channel)?;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Container, MtpError> {
+        self.send_pending_command(DataPhase::NoDataOrDataIn)?;
+
+        match read_packet(&mut self.command_channel)? {
+            Packet::StartData{ transaction_id, .. } => {
+                self.incoming.clear();
+                self.incoming_offset = 0;
+                self.received_all = false;
+                Ok(Container::data(self.current_operation, transaction_id, Vec::new()))
+            },
+            packet @ Packet::OperationResponse{ .. } => packet.to_container().ok_or(MtpError::Unsupported),
+            other => Err(MtpError::Backend(format!("Unexpected PTP/IP packet {:?}", other))),
+        }
+    }
+
+    fn receive_data(&mut self, buf: &mut [u8]) -> Result<usize, MtpError> {
+        while self.incoming_offset == self.incoming.len() {
+            if self.received_all {
+                return Ok(0);
+            }
+            self.incoming_offset = 0;
+            match read_packet(&mut self.command_channel)? {
+                Packet::Data{ payload, .. } => self.incoming = payload,
+                Packet::EndData{ payload, .. } => {
+                    self.incoming = payload;
+                    self.received_all = true;
+                },
+                other => return Err(MtpError::Backend(format!("Unexpected PTP/IP packet {:?}", other))),
+            }
+        }
+
+        let available = &self.incoming[self.incoming_offset..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.incoming_offset += len;
+        Ok(len)
+    }
+
+    /// Events are read from a clone of the event channel, so [`Self::receive_event`] must not be used at the same time
+    fn event_source(&mut self) -> Result<Box<dyn EventSource>, MtpError> {
+        Ok(Box::new(PtpIpEventSource{ event_channel: self.event_channel.try_clone()? }))
+    }
+}
+
+struct PtpIpEventSource {
+    event_channel: TcpStream,
+}
+
+impl EventSource for PtpIpEventSource {
+    fn next_event(&mut self, timeout: Duration) -> Result<Option<Container>, MtpError> {
+        // Only the beginning of a packet is waited for, so that packets are never read partially
+        self.event_channel.set_read_timeout(Some(timeout))?;
+        match self.event_channel.peek(&mut [0]) {
+            Ok(0) => return Err(MtpError::from_connection(io::Error::from(io::ErrorKind::UnexpectedEof))),
+            Ok(_) => {},
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        self.event_channel.set_read_timeout(None)?;
+        match read_packet(&mut self.event_channel)? {
+            Packet::ProbeRequest => {
+                Packet::ProbeResponse.write_to(&mut self.event_channel)?;
+                Ok(None)
+            },
+            packet @ Packet::Event{ .. } => Ok(packet.to_container()),
+            other => Err(MtpError::Backend(format!("Unexpected PTP/IP packet {:?}", other))),
+        }
+    }
+}
+
+/// Read a packet from a channel. Its end means that the responder is gone.
+fn read_packet(channel: &mut TcpStream) -> Result<Packet, MtpError> {
+    Packet::read_from(channel).map_err(MtpError::from_connection)
+}
@@ -0,0 +1,86 @@
+//! A backend that talks MTP to USB devices directly from userspace, without libmtp (Linux only)
+//!
+//! Devices are found by scanning sysfs for interfaces that look like MTP ones: still image interfaces (class 6, subclass 1, protocol 1),
+//! and vendor-specific interfaces named "MTP" (which is what Android devices expose).<br/>
+//! They are then driven through usbdevfs (`/dev/bus/usb`), so the current user needs read-write access to their device nodes
+//! (this is usually granted by udev rules, e.g. the ones shipped by libmtp).
+//!
+//! The actual transfer logic ([`BulkTransport`]) works on any [`BulkPipe`], so that it can be tested without any hardware.
+
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use widestring::U16CString;
+
+use crate::backend::ProviderBackend;
+use crate::backend::mtp::{MtpDevice, Transport};
+use crate::device::{BasicDevice, Subscription};
+use crate::error::MtpError;
+use crate::watcher::WatchSink;
+
+mod sysfs;
+pub use sysfs::UsbMtpInterface;
+
+mod transport;
+pub use transport::{BulkPipe, BulkTransport};
+
+mod uevent;
+
+mod usbdevfs;
+pub use usbdevfs::UsbPipe;
+
+const DEFAULT_SYSFS_ROOT: &str = "/sys/bus/usb/devices";
+
+/// Lists the MTP devices that are plugged by USB
+pub struct UsbProvider {
+    sysfs_root: PathBuf,
+    devfs_root: PathBuf,
+}
+
+impl Default for UsbProvider {
+    fn default() -> Self {
+        Self::with_roots(DEFAULT_SYSFS_ROOT, "/dev/bus/usb")
+    }
+}
+
+impl UsbProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use other locations than `/sys/bus/usb/devices` and `/dev/bus/usb` (e.g. in a container, or for tests)
+    pub fn with_roots(sysfs_root: impl Into<PathBuf>, devfs_root: impl Into<PathBuf>) -> Self {
+        Self{ sysfs_root: sysfs_root.into(), devfs_root: devfs_root.into() }
+    }
+
+    /// List the MTP interfaces of every plugged device
+    pub fn mtp_interfaces(&self) -> Result<Vec<UsbMtpInterface>, MtpError> {
+        Ok(sysfs::mtp_interfaces(&self.sysfs_root)?)
+    }
+}
+
+impl ProviderBackend for UsbProvider {
+    fn enumerate_devices(&self) -> Result<Vec<BasicDevice>, MtpError> {
+        Ok(self.mtp_interfaces()?
+            .into_iter()
+            .map(|interface| {
+                let device_id = U16CString::from_str_truncate(interface.device_id());
+                let friendly_name = interface.friendly_name();
+                let devfs_root = self.devfs_root.clone();
+                let backend = MtpDevice::new(move || {
+                    let pipe = UsbPipe::open(&devfs_root, &interface)?;
+                    Ok(Box::new(BulkTransport::new(pipe)) as Box<dyn Transport>)
+                });
+                BasicDevice::new(device_id, friendly_name, Rc::new(backend))
+            })
+            .collect())
+    }
+
+    /// Changes are reported by the kernel, but only for the actual sysfs. Providers that use [other locations](UsbProvider::with_roots) are polled.
+    fn watch(&self, sink: WatchSink) -> Result<Subscription, MtpError> {
+        if self.sysfs_root != std::path::Path::new(DEFAULT_SYSFS_ROOT) {
+            return Err(MtpError::Unsupported);
+        }
+        uevent::watch(sink)
+    }
+}
@@ -0,0 +1,153 @@
+//! Discovery of MTP interfaces in sysfs
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// An MTP interface of a USB device
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UsbMtpInterface {
+    pub bus_number: u8,
+    pub device_address: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+    pub interface_number: u8,
+    /// Address of the bulk IN endpoint (e.g. `0x81`)
+    pub bulk_in: u8,
+    /// Address of the bulk OUT endpoint (e.g. `0x01`)
+    pub bulk_out: u8,
+    /// Address of the interrupt IN endpoint, that carries events
+    pub interrupt_in: Option<u8>,
+    /// Max packet size of the bulk endpoints (e.g. 512 bytes for high-speed devices)
+    pub max_packet_size: u16,
+}
+
+impl UsbMtpInterface {
+    /// A device ID, that remains the same as long as the device is not unplugged
+    pub fn device_id(&self) -> String {
+        format!("usb:{:03}:{:03}", self.bus_number, self.device_address)
+    }
+
+    pub fn friendly_name(&self) -> String {
+        match (&self.manufacturer, &self.product) {
+            (Some(manufacturer), Some(product)) => format!("{} {}", manufacturer, product),
+            (None, Some(product)) => product.clone(),
+            _ => format!("USB device {:04x}:{:04x}", self.vendor_id, self.product_id),
+        }
+    }
+}
+
+/// List MTP interfaces, given a root like `/sys/bus/usb/devices`
+pub(crate) fn mtp_interfaces(sysfs_root: &Path) -> io::Result<Vec<UsbMtpInterface>> {
+    let entries = match fs::read_dir(sysfs_root) {
+        Ok(entries) => entries,
+        // No USB support at all
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut device_names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        // Interfaces are named "<device>:<config>.<interface>", root hubs "usbN"
+        .filter(|name| !name.contains(':') && !name.starts_with("usb"))
+        .collect();
+    device_names.sort();
+
+    let mut interfaces = Vec::new();
+    for device_name in device_names {
+        // Devices may be unplugged while we are reading their attributes, let's skip them
+        if let Ok(device_interfaces) = device_mtp_interfaces(sysfs_root, &device_name) {
+            interfaces.extend(device_interfaces);
+        }
+    }
+    Ok(interfaces)
+}
+
+fn device_mtp_interfaces(sysfs_root: &Path, device_name: &str) -> io::Result<Vec<UsbMtpInterface>> {
+    let device_dir = sysfs_root.join(device_name);
+    let bus_number = read_decimal(&device_dir.join("busnum"))?;
+    let device_address = read_decimal(&device_dir.join("devnum"))?;
+    let vendor_id = read_hex(&device_dir.join("idVendor"))?;
+    let product_id = read_hex(&device_dir.join("idProduct"))?;
+
+    let interface_prefix = format!("{}:", device_name);
+    let mut interface_names: Vec<String> = fs::read_dir(&device_dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with(&interface_prefix))
+        .collect();
+    interface_names.sort();
+
+    let mut interfaces = Vec::new();
+    for interface_name in interface_names {
+        let interface_dir = device_dir.join(&interface_name);
+        if !is_mtp_interface(&interface_dir)? {
+            continue;
+        }
+
+        let mut bulk_in = None;
+        let mut bulk_out = None;
+        let mut interrupt_in = None;
+        let mut max_packet_size = 0;
+        for entry in fs::read_dir(&interface_dir)?.filter_map(|entry| entry.ok()) {
+            if !entry.file_name().to_string_lossy().starts_with("ep_") {
+                continue;
+            }
+            let endpoint_dir = entry.path();
+            let address = read_hex(&endpoint_dir.join("bEndpointAddress"))? as u8;
+            let is_in = address & 0x80 != 0;
+            match read_string(&endpoint_dir.join("type"))?.as_str() {
+                "Bulk" => {
+                    max_packet_size = read_hex(&endpoint_dir.join("wMaxPacketSize"))? & 0x7FF;
+                    if is_in { bulk_in = Some(address) } else { bulk_out = Some(address) }
+                },
+                "Interrupt" if is_in => interrupt_in = Some(address),
+                _ => {},
+            }
+        }
+
+        let (Some(bulk_in), Some(bulk_out)) = (bulk_in, bulk_out) else {
+            continue;
+        };
+        interfaces.push(UsbMtpInterface{
+            bus_number,
+            device_address,
+            vendor_id,
+            product_id,
+            manufacturer: read_string(&device_dir.join("manufacturer")).ok(),
+            product: read_string(&device_dir.join("product")).ok(),
+            serial_number: read_string(&device_dir.join("serial")).ok(),
+            interface_number: read_hex(&interface_dir.join("bInterfaceNumber"))? as u8,
+            bulk_in,
+            bulk_out,
+            interrupt_in,
+            max_packet_size,
+        });
+    }
+    Ok(interfaces)
+}
+
+fn is_mtp_interface(interface_dir: &Path) -> io::Result<bool> {
+    let class = read_hex(&interface_dir.join("bInterfaceClass"))?;
+    let subclass = read_hex(&interface_dir.join("bInterfaceSubClass"))?;
+    let protocol = read_hex(&interface_dir.join("bInterfaceProtocol"))?;
+    let is_still_image = class == 0x06 && subclass == 0x01 && protocol == 0x01;
+    let is_named_mtp = class == 0xFF && read_string(&interface_dir.join("interface")).is_ok_and(|name| name.contains("MTP"));
+    Ok(is_still_image || is_named_mtp)
+}
+
+fn read_string(path: &Path) -> io::Result<String> {
+    Ok(fs::read_to_string(path)?.trim().to_string())
+}
+
+fn read_hex(path: &Path) -> io::Result<u16> {
+    u16::from_str_radix(&read_string(path)?, 16).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn read_decimal(path: &Path) -> io::Result<u8> {
+    read_string(path)?.parse().map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
@@ -0,0 +1,207 @@
+//! Framing of MTP containers on USB bulk endpoints
+
+use std::io;
+
+use crate::backend::mtp::{EventSource, Transport};
+use crate::error::{MtpError, ProtocolError};
+use crate::protocol::codes::OperationCode;
+use crate::protocol::container::{Container, ContainerHeader, ContainerType};
+
+/// Size of a single bulk transfer. This is a multiple of every possible max packet size, and the default usbfs limit.
+const TRANSFER_SIZE: usize = 16 * 1024;
+
+/// How many zero-length packets are skipped before a container, before giving up
+const MAX_SKIPPED_EMPTY_TRANSFERS: usize = 4;
+
+/// A pair of USB bulk endpoints
+pub trait BulkPipe {
+    /// Read from the bulk IN endpoint.
+    ///
+    /// Like usbfs does, this returns as soon as a short (or empty) packet has been received, or `buf` is full.
+    fn read_bulk(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Write to the bulk OUT endpoint. Writing an empty buffer sends a zero-length packet.
+    fn write_bulk(&mut self, data: &[u8]) -> io::Result<usize>;
+
+    /// The max packet size of the bulk endpoints
+    fn max_packet_size(&self) -> usize;
+
+    /// Get what reads the interrupt endpoint, that carries events.
+    ///
+    /// The default implementation returns [`MtpError::Unsupported`].
+    fn event_source(&self) -> Result<Box<dyn EventSource>, MtpError> {
+        Err(MtpError::Unsupported)
+    }
+}
+
+/// Sends and receives containers on a [`BulkPipe`], the way the USB still image class specifies it
+///
+/// A container spans as many packets as needed. When its length is a multiple of the max packet size,
+/// it is terminated by a zero-length packet. Data phases of 4 GiB or more have an [unknown length](ContainerHeader::UNKNOWN_LENGTH),
+/// they end with the first short packet.
+pub struct BulkTransport<P> {
+    pipe: P,
+    /// Bytes of the data phase being sent, that do not fill a whole transfer yet
+    outgoing: Vec<u8>,
+    /// How many bytes of the data phase being sent (including its header) have been written, and how many are left to send
+    sent: u64,
+    sending_left: u64,
+    /// Bytes of the data phase being received, that have not been read yet
+    incoming: Vec<u8>,
+    incoming_offset: usize,
+    /// What is left to receive of the payload of the data phase being received, `None` when it ends with a short packet
+    receiving_left: Option<u64>,
+    /// Whether the end of the data phase being received has been reached
+    received_all: bool,
+}
+
+impl<P: BulkPipe> BulkTransport<P> {
+    pub fn new(pipe: P) -> Self {
+        Self{
+            pipe,
+            outgoing: Vec::new(),
+            sent: 0,
+            sending_left: 0,
+            incoming: Vec::new(),
+            incoming_offset: 0,
+            receiving_left: Some(0),
+            received_all: true,
+        }
+    }
+
+    pub fn pipe(&self) -> &P {
+        &self.pipe
+    }
+
+    fn write_all(pipe: &mut P, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let chunk_len = data.len().min(TRANSFER_SIZE);
+            let written = pipe.write_bulk(&data[..chunk_len])?;
+            if written == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            data = &data[written..];
+        }
+        Ok(())
+    }
+
+    /// A zero-length packet must follow transfers whose length is a multiple of the max packet size
+    fn terminate(&mut self, total_len: u64) -> io::Result<()> {
+        let max_packet_size = self.pipe.max_packet_size() as u64;
+        if max_packet_size != 0 && total_len.is_multiple_of(max_packet_size) {
+            self.pipe.write_bulk(&[])?;
+        }
+        Ok(())
+    }
+
+    /// Read the next transfer of the data phase being received, and tell whether it ended with a short packet
+    fn read_transfer(&mut self) -> Result<bool, MtpError> {
+        self.incoming.resize(TRANSFER_SIZE, 0);
+        let len = self.pipe.read_bulk(&mut self.incoming)?;
+        self.incoming.truncate(len);
+        self.incoming_offset = 0;
+        Ok(len < TRANSFER_SIZE)
+    }
+}
+
+impl<P: BulkPipe> Transport for BulkTransport<P> {
+    fn send(&mut self, command: &Container) -> Result<(), MtpError> {
+        let bytes = command.encode();
+        Self::write_all(&mut self.pipe, &bytes)?;
+        self.terminate(bytes.len() as u64)?;
+        Ok(())
+    }
+
+    fn start_data(&mut self, operation: OperationCode, transaction_id: u32, length: u64) -> Result<(), MtpError> {
+        self.outgoing.clear();
+        self.outgoing.extend_from_slice(&ContainerHeader::data(operation, transaction_id, length).encode());
+        self.sent = 0;
+        self.sending_left = length;
+        Ok(())
+    }
+
+    fn send_data(&mut self, mut data: &[u8]) -> Result<(), MtpError> {
+        if data.len() as u64 > self.sending_left {
+            return Err(MtpError::InvalidArgument("More data than the length of the data phase".to_string()));
+        }
+        self.sending_left -= data.len() as u64;
+        // Only whole transfers are written, so that no short packet ends the container early
+        while !data.is_empty() {
+            let len = data.len().min(TRANSFER_SIZE - self.outgoing.len());
+            self.outgoing.extend_from_slice(&data[..len]);
+            data = &data[len..];
+            if self.outgoing.len() == TRANSFER_SIZE {
+                Self::write_all(&mut self.pipe, &self.outgoing)?;
+                self.sent += TRANSFER_SIZE as u64;
+                self.outgoing.clear();
+            }
+        }
+        Ok(())
+    }
+
+    fn end_data(&mut self) -> Result<(), MtpError> {
+        if self.sending_left != 0 {
+            return Err(MtpError::InvalidArgument(format!("{} bytes of the data phase have not been sent", self.sending_left)));
+        }
+        Self::write_all(&mut self.pipe, &self.outgoing)?;
+        self.terminate(self.sent + self.outgoing.len() as u64)?;
+        self.outgoing.clear();
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Container, MtpError> {
+        // Skip the zero-length packets that may terminate a previous container
+        let mut short = false;
+        for _ in 0..MAX_SKIPPED_EMPTY_TRANSFERS {
+            short = self.read_transfer()?;
+            if !self.incoming.is_empty() {
+                break;
+            }
+        }
+        let header = ContainerHeader::decode(&self.incoming)?;
+        self.incoming_offset = ContainerHeader::LEN;
+
+        if header.container_type == ContainerType::Data {
+            self.receiving_left = match header.length {
+                ContainerHeader::UNKNOWN_LENGTH => None,
+                _ => Some(header.payload_len() as u64),
+            };
+            self.received_all = short;
+            return Ok(Container::from_header(&header, Vec::new()));
+        }
+
+        // Other containers only have a few parameters, they always fit in a single transfer
+        let length = header.length as usize;
+        if self.incoming.len() < length {
+            return Err(ProtocolError::Truncated.into());
+        }
+        let container = Container::decode(&self.incoming[..length])?;
+        self.incoming.clear();
+        self.incoming_offset = 0;
+        Ok(container)
+    }
+
+    fn receive_data(&mut self, buf: &mut [u8]) -> Result<usize, MtpError> {
+        while self.incoming_offset == self.incoming.len() {
+            match (self.receiving_left, self.received_all) {
+                (Some(0), _) | (None, true) => return Ok(0),
+                (Some(_), true) => return Err(ProtocolError::Truncated.into()),
+                _ => self.received_all = self.read_transfer()?,
+            }
+        }
+
+        let available = &self.incoming[self.incoming_offset..];
+        let mut len = available.len().min(buf.len());
+        if let Some(left) = self.receiving_left {
+            len = len.min(usize::try_from(left).unwrap_or(usize::MAX));
+            self.receiving_left = Some(left - len as u64);
+        }
+        buf[..len].copy_from_slice(&available[..len]);
+        self.incoming_offset += len;
+        Ok(len)
+    }
+
+    fn event_source(&mut self) -> Result<Box<dyn EventSource>, MtpError> {
+        self.pipe.event_source()
+    }
+}
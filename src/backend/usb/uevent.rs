@@ -0,0 +1,82 @@
+//! Kernel notifications of USB devices being plugged and unplugged, received through a `NETLINK_KOBJECT_UEVENT` socket
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use libc::{c_void, sockaddr, sockaddr_nl, socklen_t, timeval};
+
+use crate::device::Subscription;
+use crate::error::MtpError;
+use crate::watcher::WatchSink;
+
+/// The multicast group of the events sent by the kernel itself (rather than the ones re-broadcast by udev)
+const KERNEL_EVENTS_GROUP: u32 = 1;
+
+/// How long the watching thread waits before it checks whether it should stop
+const RECV_TIMEOUT_MS: u32 = 100;
+
+const MAX_UEVENT_SIZE: usize = 8192;
+
+/// Notify `sink` whenever a USB device or interface is added or removed
+pub(crate) fn watch(sink: WatchSink) -> Result<Subscription, MtpError> {
+    let socket = open_socket()?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread_stop = Arc::clone(&stop);
+    let thread = std::thread::spawn(move || {
+        let mut buffer = vec![0u8; MAX_UEVENT_SIZE];
+        while !thread_stop.load(Ordering::Relaxed) {
+            let received = unsafe{ libc::recv(socket.as_raw_fd(), buffer.as_mut_ptr() as *mut c_void, buffer.len(), 0) };
+            if received < 0 {
+                match io::Error::last_os_error().kind() {
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted => continue,
+                    _ => return,
+                }
+            }
+            if is_usb_plug_event(&buffer[..received as usize]) {
+                sink.notify();
+            }
+        }
+    });
+    Ok(Subscription::new(move || {
+        stop.store(true, Ordering::Relaxed);
+        let _ = thread.join();
+    }))
+}
+
+fn open_socket() -> io::Result<OwnedFd> {
+    let fd = unsafe{ libc::socket(libc::AF_NETLINK, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, libc::NETLINK_KOBJECT_UEVENT) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let socket = unsafe{ OwnedFd::from_raw_fd(fd) };
+
+    let mut address: sockaddr_nl = unsafe{ std::mem::zeroed() };
+    address.nl_family = libc::AF_NETLINK as u16;
+    address.nl_groups = KERNEL_EVENTS_GROUP;
+    let bound = unsafe{ libc::bind(fd, &address as *const sockaddr_nl as *const sockaddr, std::mem::size_of::<sockaddr_nl>() as socklen_t) };
+    if bound < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let timeout = timeval{ tv_sec: 0, tv_usec: (RECV_TIMEOUT_MS * 1000) as libc::suseconds_t };
+    let set = unsafe{ libc::setsockopt(
+        fd,
+        libc::SOL_SOCKET,
+        libc::SO_RCVTIMEO,
+        &timeout as *const timeval as *const c_void,
+        std::mem::size_of::<timeval>() as socklen_t,
+    )};
+    if set < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(socket)
+}
+
+/// Uevents are a header (e.g. `add@/devices/...`), followed by `KEY=value` fields, all of them NUL-terminated
+fn is_usb_plug_event(message: &[u8]) -> bool {
+    let mut fields = message.split(|byte| *byte == 0);
+    let is_plug = fields.next().is_some_and(|header| header.starts_with(b"add@") || header.starts_with(b"remove@"));
+    is_plug && fields.any(|field| field == b"SUBSYSTEM=usb")
+}
@@ -0,0 +1,174 @@
+//! Bulk and interrupt transfers through the Linux usbdevfs ioctls (see `linux/usbdevice_fs.h`)
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::time::Duration;
+
+use libc::{c_int, c_uint, c_void};
+
+use crate::backend::mtp::EventSource;
+use crate::backend::usb::{BulkPipe, UsbMtpInterface};
+use crate::error::MtpError;
+use crate::protocol::container::Container;
+
+/// Some operations (e.g. deleting a large folder) can take a while before the device answers
+const TIMEOUT_MS: c_uint = 60_000;
+
+#[repr(C)]
+struct BulkTransfer {
+    endpoint: c_uint,
+    len: c_uint,
+    timeout_ms: c_uint,
+    data: *mut c_void,
+}
+
+#[repr(C)]
+struct Ioctl {
+    interface_number: c_int,
+    ioctl_code: c_int,
+    data: *mut c_void,
+}
+
+// Equivalent of the _IO* macros, for the architectures that use the generic ioctl layout
+const fn ioc(direction: u32, number: u32, size: usize) -> u32 {
+    (direction << 30) | ((size as u32) << 16) | ((b'U' as u32) << 8) | number
+}
+const IOC_NONE: u32 = 0;
+const IOC_WRITE: u32 = 1;
+const IOC_READ: u32 = 2;
+
+const USBDEVFS_BULK: u32 = ioc(IOC_READ | IOC_WRITE, 2, std::mem::size_of::<BulkTransfer>());
+const USBDEVFS_CLAIMINTERFACE: u32 = ioc(IOC_READ, 15, std::mem::size_of::<c_uint>());
+const USBDEVFS_RELEASEINTERFACE: u32 = ioc(IOC_READ, 16, std::mem::size_of::<c_uint>());
+const USBDEVFS_IOCTL: u32 = ioc(IOC_READ | IOC_WRITE, 18, std::mem::size_of::<Ioctl>());
+const USBDEVFS_DISCONNECT: u32 = ioc(IOC_NONE, 22, 0);
+
+/// Event containers are small: a header and at most three parameters
+const MAX_EVENT_SIZE: usize = 64;
+
+/// The bulk endpoints of an MTP interface, opened through usbdevfs
+///
+/// The interface is claimed as long as this struct lives.
+pub struct UsbPipe {
+    file: File,
+    interface_number: c_uint,
+    bulk_in: u8,
+    bulk_out: u8,
+    interrupt_in: Option<u8>,
+    max_packet_size: usize,
+}
+
+impl UsbPipe {
+    /// Open a device node (e.g. `/dev/bus/usb/001/004`) and claim its MTP interface
+    pub fn open(devfs_root: &Path, interface: &UsbMtpInterface) -> Result<Self, MtpError> {
+        let path = devfs_root
+            .join(format!("{:03}", interface.bus_number))
+            .join(format!("{:03}", interface.device_address));
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        let pipe = Self{
+            file,
+            interface_number: interface.interface_number as c_uint,
+            bulk_in: interface.bulk_in,
+            bulk_out: interface.bulk_out,
+            interrupt_in: interface.interrupt_in,
+            max_packet_size: interface.max_packet_size as usize,
+        };
+        pipe.claim_interface()?;
+        Ok(pipe)
+    }
+
+    fn ioctl(&self, request: u32, arg: *mut c_void) -> io::Result<c_int> {
+        ioctl(&self.file, request, arg)
+    }
+
+    fn claim_interface(&self) -> io::Result<()> {
+        let mut interface_number = self.interface_number;
+        match self.ioctl(USBDEVFS_CLAIMINTERFACE, &mut interface_number as *mut c_uint as *mut c_void) {
+            Err(err) if err.raw_os_error() == Some(libc::EBUSY) => {
+                // Some kernel driver is bound to this interface, let's detach it and retry
+                let mut request = Ioctl{
+                    interface_number: self.interface_number as c_int,
+                    ioctl_code: USBDEVFS_DISCONNECT as c_int,
+                    data: std::ptr::null_mut(),
+                };
+                self.ioctl(USBDEVFS_IOCTL, &mut request as *mut Ioctl as *mut c_void)?;
+                self.ioctl(USBDEVFS_CLAIMINTERFACE, &mut interface_number as *mut c_uint as *mut c_void)?;
+                Ok(())
+            },
+            result => result.map(|_| ()),
+        }
+    }
+
+    fn bulk(&self, endpoint: u8, data: *mut u8, len: usize) -> io::Result<usize> {
+        bulk(&self.file, endpoint, data, len, TIMEOUT_MS)
+    }
+}
+
+fn ioctl(file: &File, request: u32, arg: *mut c_void) -> io::Result<c_int> {
+    let result = unsafe{ libc::ioctl(file.as_raw_fd(), request as _, arg) };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// usbdevfs performs interrupt transfers with the same ioctl as bulk transfers
+fn bulk(file: &File, endpoint: u8, data: *mut u8, len: usize, timeout_ms: c_uint) -> io::Result<usize> {
+    let mut transfer = BulkTransfer{
+        endpoint: endpoint as c_uint,
+        len: len as c_uint,
+        timeout_ms,
+        data: data as *mut c_void,
+    };
+    ioctl(file, USBDEVFS_BULK, &mut transfer as *mut BulkTransfer as *mut c_void).map(|len| len as usize)
+}
+
+impl BulkPipe for UsbPipe {
+    fn read_bulk(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.bulk(self.bulk_in, buf.as_mut_ptr(), buf.len())
+    }
+
+    fn write_bulk(&mut self, data: &[u8]) -> io::Result<usize> {
+        // usbdevfs does not write into OUT buffers
+        self.bulk(self.bulk_out, data.as_ptr() as *mut u8, data.len())
+    }
+
+    fn max_packet_size(&self) -> usize {
+        self.max_packet_size
+    }
+
+    fn event_source(&self) -> Result<Box<dyn EventSource>, MtpError> {
+        let endpoint = self.interrupt_in.ok_or(MtpError::Unsupported)?;
+        Ok(Box::new(UsbEventSource{ file: self.file.try_clone()?, endpoint }))
+    }
+}
+
+/// Reads the interrupt endpoint of an MTP interface, through a duplicate of the file descriptor of its [`UsbPipe`]
+struct UsbEventSource {
+    file: File,
+    endpoint: u8,
+}
+
+impl EventSource for UsbEventSource {
+    fn next_event(&mut self, timeout: Duration) -> Result<Option<Container>, MtpError> {
+        let mut buf = [0; MAX_EVENT_SIZE];
+        let timeout_ms = timeout.as_millis().clamp(1, c_uint::MAX as u128) as c_uint;
+        match bulk(&self.file, self.endpoint, buf.as_mut_ptr(), buf.len(), timeout_ms) {
+            Ok(0) => Ok(None),
+            Ok(len) => Ok(Some(Container::decode(&buf[..len])?)),
+            Err(err) if err.raw_os_error() == Some(libc::ETIMEDOUT) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl Drop for UsbPipe {
+    fn drop(&mut self) {
+        let mut interface_number = self.interface_number;
+        let _ = self.ioctl(USBDEVFS_RELEASEINTERFACE, &mut interface_number as *mut c_uint as *mut c_void);
+    }
+}
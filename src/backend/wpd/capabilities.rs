@@ -0,0 +1,122 @@
+//! What a WPD device supports, as told by its `IPortableDeviceCapabilities`
+
+use windows::core::GUID;
+use windows::Win32::System::Com::StructuredStorage::{PropVariantClear, PROPVARIANT};
+use windows::Win32::Devices::PortableDevices::{
+    IPortableDeviceCapabilities, IPortableDeviceKeyCollection, IPortableDevicePropVariantCollection,
+    WPD_PROPERTY_ATTRIBUTE_FORM, WPD_PROPERTY_ATTRIBUTE_CAN_WRITE, WPD_PROPERTY_ATTRIBUTE_CAN_DELETE, WPD_PROPERTY_ATTRIBUTE_DEFAULT_VALUE,
+    WPD_PROPERTY_ATTRIBUTE_RANGE_MIN, WPD_PROPERTY_ATTRIBUTE_RANGE_MAX, WPD_PROPERTY_ATTRIBUTE_RANGE_STEP,
+    WPD_PROPERTY_ATTRIBUTE_ENUMERATION_ELEMENTS, WPD_PROPERTY_ATTRIBUTE_REGULAR_EXPRESSION,
+    WPD_PROPERTY_ATTRIBUTE_FORM_RANGE, WPD_PROPERTY_ATTRIBUTE_FORM_ENUMERATION, WPD_PROPERTY_ATTRIBUTE_FORM_REGULAR_EXPRESSION,
+    WPD_PROPERTY_ATTRIBUTE_FORM_OBJECT_IDENTIFIER,
+};
+
+use crate::backend::CapabilitiesBackend;
+use crate::backend::wpd::values::{from_com, from_propvariant};
+use crate::device::{AllowedValues, PropertyDescription};
+use crate::device::device_values::PropertyValue;
+use crate::error::MtpError;
+
+/// The capabilities of a device that has been opened through the WPD API
+pub struct WpdCapabilities {
+    com_capabilities: IPortableDeviceCapabilities,
+}
+
+impl WpdCapabilities {
+    pub fn new(com_capabilities: IPortableDeviceCapabilities) -> Self {
+        Self{ com_capabilities }
+    }
+
+    /// Returns the underlying COM object
+    pub fn com_object(&self) -> &IPortableDeviceCapabilities {
+        &self.com_capabilities
+    }
+}
+
+impl CapabilitiesBackend for WpdCapabilities {
+    fn supported_commands(&self) -> Result<Vec<crate::PROPERTYKEY>, MtpError> {
+        read_keys(&unsafe{ self.com_capabilities.GetSupportedCommands() }?)
+    }
+
+    fn functional_categories(&self) -> Result<Vec<GUID>, MtpError> {
+        read_guids(&unsafe{ self.com_capabilities.GetFunctionalCategories() }?)
+    }
+
+    fn content_types(&self, functional_category: &GUID) -> Result<Vec<GUID>, MtpError> {
+        read_guids(&unsafe{ self.com_capabilities.GetSupportedContentTypes(functional_category as *const _) }?)
+    }
+
+    fn formats(&self, content_type: &GUID) -> Result<Vec<GUID>, MtpError> {
+        read_guids(&unsafe{ self.com_capabilities.GetSupportedFormats(content_type as *const _) }?)
+    }
+
+    fn format_properties(&self, format: &GUID) -> Result<Vec<crate::PROPERTYKEY>, MtpError> {
+        read_keys(&unsafe{ self.com_capabilities.GetSupportedFormatProperties(format as *const _) }?)
+    }
+
+    fn property_description(&self, format: &GUID, property: &crate::PROPERTYKEY) -> Result<PropertyDescription, MtpError> {
+        let com_attributes = unsafe{ self.com_capabilities.GetFixedPropertyAttributes(format as *const _, property as *const _) }?;
+        let attributes = from_com(&com_attributes)?;
+
+        let allowed_values = match attributes.get_u32(&WPD_PROPERTY_ATTRIBUTE_FORM).map(|form| form as i32) {
+            Ok(form) if form == WPD_PROPERTY_ATTRIBUTE_FORM_RANGE.0 => {
+                let get = |key| attributes.get(key).cloned();
+                match (get(&WPD_PROPERTY_ATTRIBUTE_RANGE_MIN), get(&WPD_PROPERTY_ATTRIBUTE_RANGE_MAX), get(&WPD_PROPERTY_ATTRIBUTE_RANGE_STEP)) {
+                    (Some(min), Some(max), Some(step)) => AllowedValues::Range{ min, max, step },
+                    _ => AllowedValues::Any,
+                }
+            },
+            // Collections are not plain values, `from_com` skips them
+            Ok(form) if form == WPD_PROPERTY_ATTRIBUTE_FORM_ENUMERATION.0 => {
+                let elements = unsafe{ com_attributes.GetIPortableDevicePropVariantCollectionValue(&WPD_PROPERTY_ATTRIBUTE_ENUMERATION_ELEMENTS as *const _) }?;
+                AllowedValues::Enumeration(read_values(&elements)?)
+            },
+            Ok(form) if form == WPD_PROPERTY_ATTRIBUTE_FORM_REGULAR_EXPRESSION.0 => match attributes.get_string(&WPD_PROPERTY_ATTRIBUTE_REGULAR_EXPRESSION) {
+                Ok(regex) => AllowedValues::RegularExpression(regex.to_string_lossy()),
+                Err(_) => AllowedValues::Any,
+            },
+            Ok(form) if form == WPD_PROPERTY_ATTRIBUTE_FORM_OBJECT_IDENTIFIER.0 => AllowedValues::ObjectId,
+            _ => AllowedValues::Any,
+        };
+
+        Ok(PropertyDescription{
+            writable: attributes.get_bool(&WPD_PROPERTY_ATTRIBUTE_CAN_WRITE).unwrap_or(false),
+            can_delete: attributes.get_bool(&WPD_PROPERTY_ATTRIBUTE_CAN_DELETE).unwrap_or(false),
+            default_value: attributes.get(&WPD_PROPERTY_ATTRIBUTE_DEFAULT_VALUE).cloned(),
+            allowed_values,
+        })
+    }
+}
+
+fn read_keys(collection: &IPortableDeviceKeyCollection) -> Result<Vec<crate::PROPERTYKEY>, MtpError> {
+    let mut count = 0;
+    unsafe{ collection.GetCount(&mut count as *mut _) }?;
+
+    (0..count).map(|index| {
+        let mut key = crate::PROPERTYKEY::default();
+        unsafe{ collection.GetAt(index, &mut key as *mut _) }?;
+        Ok(key)
+    }).collect()
+}
+
+/// Read every value of a collection. Values whose type is not supported are skipped.
+fn read_values(collection: &IPortableDevicePropVariantCollection) -> Result<Vec<PropertyValue>, MtpError> {
+    let mut count = 0;
+    unsafe{ collection.GetCount(&mut count as *mut _) }?;
+
+    let mut values = Vec::new();
+    for index in 0..count {
+        let mut prop_variant = PROPVARIANT::default();
+        unsafe{ collection.GetAt(index, &mut prop_variant as *mut _) }?;
+        values.extend(unsafe{ from_propvariant(&prop_variant) });
+        unsafe{ PropVariantClear(&mut prop_variant as *mut _) }?;
+    }
+    Ok(values)
+}
+
+fn read_guids(collection: &IPortableDevicePropVariantCollection) -> Result<Vec<GUID>, MtpError> {
+    Ok(read_values(collection)?.into_iter().filter_map(|value| match value {
+        PropertyValue::Guid(guid) => Some(guid),
+        _ => None,
+    }).collect())
+}
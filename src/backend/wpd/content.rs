@@ -0,0 +1,311 @@
+use std::any::Any;
+
+use windows::core::{GUID, HRESULT, Interface, PWSTR, PCWSTR};
+use windows::Win32::Foundation::S_OK;
+use windows::Win32::System::Com::{CoCreateInstance, CoTaskMemFree, CLSCTX_ALL};
+use windows::Win32::System::Com::{IStream, STGM, STGM_READ};
+use windows::Win32::System::Com::StructuredStorage::PROPVARIANT;
+use windows::Win32::System::Variant::VT_ERROR;
+use windows::Win32::Devices::PortableDevices::{
+    IPortableDevice, IPortableDeviceContent, IEnumPortableDeviceObjectIDs, IPortableDeviceKeyCollection, PortableDeviceKeyCollection,
+    PortableDevicePropVariantCollection, IPortableDevicePropVariantCollection,
+    PORTABLE_DEVICE_DELETE_WITH_RECURSION, PORTABLE_DEVICE_DELETE_NO_RECURSION, WPD_RESOURCE_DEFAULT,
+};
+use widestring::{U16CStr, U16CString};
+
+use crate::backend::{ContentBackend, ReadStreamBackend, WriteStreamBackend, for_each_property};
+use crate::device::{EventSink, Subscription};
+use crate::device::device_values::DeviceValues;
+use crate::error::MtpError;
+use super::events::event_callback;
+use super::stream::ComStream;
+use super::values;
+
+/// Access to the content of a device, through `IPortableDeviceContent`
+#[derive(Debug)]
+pub struct WpdContent {
+    com_content: IPortableDeviceContent,
+    /// Events are subscribed to on the device itself
+    com_device: IPortableDevice,
+}
+
+impl WpdContent {
+    pub(crate) fn new(com_content: IPortableDeviceContent, com_device: IPortableDevice) -> Self {
+        Self{ com_content, com_device }
+    }
+
+    /// Retrieve the inner COM object
+    pub fn com_object(&self) -> &IPortableDeviceContent {
+        &self.com_content
+    }
+
+    pub(crate) fn open_raw_stream(&self, object_id: &U16CStr, stream_mode: STGM) -> Result<Option<(IStream, u32)>, MtpError> {
+        let resources = unsafe{ self.com_content.Transfer() }?;
+
+        let mut stream = None;
+        let mut optimal_transfer_size_bytes: u32 = 0;
+        unsafe{ resources.GetStream(
+            PCWSTR::from_raw(object_id.as_ptr()),
+            &WPD_RESOURCE_DEFAULT as *const _,  // We are transferring the default resource (which is the entire object's data)
+            stream_mode.0,
+            &mut optimal_transfer_size_bytes as *mut u32,
+            &mut stream as *mut Option<IStream>,
+        )}?;
+
+        Ok(stream.map(|s| (s, optimal_transfer_size_bytes)))
+    }
+
+    pub(crate) fn create_raw_write_stream(&self, properties: &DeviceValues) -> Result<Option<(IStream, u32)>, MtpError> {
+        let file_properties = values::to_com(properties)?;
+
+        let mut write_stream = None;
+        let mut optimal_write_buffer_size = 0;
+        unsafe{ self.com_content.CreateObjectWithPropertiesAndData(
+            &file_properties,
+            &mut write_stream as *mut _,
+            &mut optimal_write_buffer_size,
+            &mut PWSTR::null() as *mut PWSTR,
+        )}?;
+
+        Ok(write_stream.map(|s| (s, optimal_write_buffer_size)))
+    }
+}
+
+impl ContentBackend for WpdContent {
+    fn children(&self, parent_id: &U16CStr) -> Result<Box<dyn Iterator<Item = U16CString>>, MtpError> {
+        let com_iter = unsafe{
+            self.com_content.EnumObjects(
+                0,
+                PCWSTR::from_raw(parent_id.as_ptr()),
+                None,
+            )
+        }?;
+
+        Ok(Box::new(ObjectIdIterator{ com_iter }))
+    }
+
+    fn properties(&self, object_id: &U16CStr, properties_to_fetch: &[crate::PROPERTYKEY]) -> Result<DeviceValues, MtpError> {
+        let props_to_read: IPortableDeviceKeyCollection = unsafe {
+            CoCreateInstance(
+                &PortableDeviceKeyCollection as *const GUID,
+                None,
+                CLSCTX_ALL
+            )
+        }?;
+        for prop_to_fetch in properties_to_fetch {
+            unsafe{ props_to_read.Add(prop_to_fetch as *const _)}?;
+        }
+
+        let properties = unsafe{ self.com_content.Properties() }?;
+        let com_values = unsafe{ properties.GetValues(
+                PCWSTR::from_raw(object_id.as_ptr()),
+                &props_to_read,
+            )
+        }?;
+        values::from_com(&com_values)
+    }
+
+    fn set_properties(&self, object_id: &U16CStr, values: &DeviceValues) -> Result<Vec<Result<(), MtpError>>, MtpError> {
+        let com_values = values::to_com(values)?;
+        let properties = unsafe{ self.com_content.Properties() }?;
+        let results = unsafe{ properties.SetValues(
+                PCWSTR::from_raw(object_id.as_ptr()),
+                &com_values,
+            )
+        }?;
+
+        // Each written key is given an HRESULT
+        for_each_property(values, |key, _| {
+            let hr = unsafe{ results.GetErrorValue(key as *const _) }?;
+            if hr != S_OK {
+                return Err(MtpError::from(windows::core::Error::from(hr)));
+            }
+            Ok(())
+        })
+    }
+
+    fn create_object(&self, properties: &DeviceValues) -> Result<U16CString, MtpError> {
+        let com_properties = values::to_com(properties)?;
+        let mut created_object_id = PWSTR::null();
+        unsafe{ self.com_content.CreateObjectWithPropertiesOnly(
+            &com_properties,
+            &mut created_object_id as *mut _,
+        )}?;
+
+        let owned_id = unsafe{ U16CString::from_ptr_str(created_object_id.as_ptr()) };
+        unsafe{
+            CoTaskMemFree(Some(created_object_id.as_ptr() as *const _))
+        };
+
+        Ok(owned_id)
+    }
+
+    fn create_object_with_data(&self, properties: &DeviceValues) -> Result<(Box<dyn WriteStreamBackend>, u32), MtpError> {
+        let (stream, optimal_transfer_size) = self
+            .create_raw_write_stream(properties)?
+            .ok_or(MtpError::UnableToCreateStream)?;
+        Ok((Box::new(ComStream(stream)), optimal_transfer_size))
+    }
+
+    fn open_read_stream(&self, object_id: &U16CStr) -> Result<(Box<dyn ReadStreamBackend>, u32), MtpError> {
+        let (stream, optimal_transfer_size) = self
+            .open_raw_stream(object_id, STGM_READ)?
+            .ok_or(MtpError::UnableToCreateStream)?;
+        Ok((Box::new(ComStream(stream)), optimal_transfer_size))
+    }
+
+    fn delete(&self, object_ids: &[&U16CStr], recursive: bool) -> Result<Vec<Result<(), MtpError>>, MtpError> {
+        let objects_to_delete = make_propvariant_collection(object_ids)?;
+
+        let options = if recursive { PORTABLE_DEVICE_DELETE_WITH_RECURSION } else { PORTABLE_DEVICE_DELETE_NO_RECURSION };
+        let mut result_status: Option<IPortableDevicePropVariantCollection> = None;
+        // Called through the vtable, as the windows-rs wrapper turns `S_FALSE` (i.e. some objects failed) into a success
+        let call_result = unsafe{
+            (Interface::vtable(&self.com_content).Delete)(
+                Interface::as_raw(&self.com_content),
+                options.0 as u32,
+                Interface::as_raw(&objects_to_delete),
+                &mut result_status as *mut _ as *mut _,
+            )
+        };
+
+        per_object_results(call_result, result_status, object_ids.len())
+    }
+
+    fn move_objects(&self, object_ids: &[&U16CStr], destination_folder_id: &U16CStr) -> Result<Vec<Result<(), MtpError>>, MtpError> {
+        let objects_to_move = make_propvariant_collection(object_ids)?;
+
+        let dest = PCWSTR::from_raw(destination_folder_id.as_ptr());
+        let mut result_status: Option<IPortableDevicePropVariantCollection> = None;
+        // See `delete` about the vtable
+        let call_result = unsafe{
+            (Interface::vtable(&self.com_content).Move)(
+                Interface::as_raw(&self.com_content),
+                Interface::as_raw(&objects_to_move),
+                dest,
+                &mut result_status as *mut _ as *mut _,
+            )
+        };
+
+        per_object_results(call_result, result_status, object_ids.len())
+    }
+
+    /// Events are sent by WPD, from one of its own threads
+    fn subscribe(&self, sink: EventSink) -> Result<Subscription, MtpError> {
+        let callback = event_callback(sink);
+        let cookie = unsafe{ self.com_device.Advise(0, &callback, None) }?;
+        let owned_cookie = unsafe{ U16CString::from_ptr_str(cookie.as_ptr()) };
+        unsafe{ CoTaskMemFree(Some(cookie.as_ptr() as *const _)) };
+
+        let com_device = self.com_device.clone();
+        Ok(Subscription::new(move || {
+            let _ = unsafe{ com_device.Unadvise(PCWSTR::from_raw(owned_cookie.as_ptr())) };
+        }))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+struct ObjectIdIterator {
+    com_iter: IEnumPortableDeviceObjectIDs,
+}
+
+impl Iterator for ObjectIdIterator {
+    type Item = U16CString;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut out = [PWSTR::null()];
+        let mut requested: u32 = 1;
+        unsafe{ self.com_iter.Next(&mut out, &mut requested as *mut u32) }.ok().ok()?;
+        if requested != 1 {
+            return None;
+        }
+
+        let single_child = out.first()?;  // cannot return None, `out` is a 1-item array
+        let child_widestring = unsafe{ U16CString::from_ptr_str(single_child.as_ptr()) };
+        unsafe{ CoTaskMemFree(Some(single_child.as_ptr() as *const _)) };
+
+        Some(child_widestring)
+    }
+}
+
+fn make_propvariant_collection(object_ids: &[&U16CStr]) -> Result<IPortableDevicePropVariantCollection, MtpError> {
+    let collection: IPortableDevicePropVariantCollection = unsafe {
+        CoCreateInstance(
+            &PortableDevicePropVariantCollection as *const GUID,
+            None,
+            CLSCTX_ALL
+        )
+    }?;
+
+    for object_id in object_ids {
+        let id_as_propvariant = unsafe{ init_propvariant_from_string(object_id) };
+        // `Add` copies the value, so `id_as_propvariant` does not have to outlive the collection
+        unsafe{ collection.Add(&id_as_propvariant as *const _) }?;
+    }
+
+    Ok(collection)
+}
+
+/// Parse the status collection returned by `Delete` and `Move`, that holds an HRESULT for every object.
+///
+/// These calls return `S_FALSE` (or an error) as soon as one object failed, so their own result only tells the outcome of a single object,
+/// when there is no usable status collection.
+fn per_object_results(
+    call_result: HRESULT,
+    result_status: Option<IPortableDevicePropVariantCollection>,
+    object_count: usize,
+) -> Result<Vec<Result<(), MtpError>>, MtpError> {
+    let statuses = match &result_status {
+        Some(result_status) => read_statuses(result_status)?,
+        None => Vec::new(),
+    };
+    if statuses.len() != object_count {
+        call_result.ok()?;
+        if object_count == 1 && call_result == S_OK {
+            return Ok(vec![Ok(())]);
+        }
+        return Err(MtpError::Backend(format!("Expected {} statuses, got {}", object_count, statuses.len())));
+    }
+
+    Ok(statuses.into_iter().map(|status| status.ok().map_err(MtpError::from)).collect())
+}
+
+fn read_statuses(collection: &IPortableDevicePropVariantCollection) -> Result<Vec<HRESULT>, MtpError> {
+    let mut count = 0;
+    unsafe{ collection.GetCount(&mut count as *mut _) }?;
+
+    (0..count).map(|index| {
+        let mut status = PROPVARIANT::default();
+        unsafe{ collection.GetAt(index, &mut status as *mut _) }?;
+        // VT_ERROR values do not own any memory, there is nothing to clear
+        let status = unsafe{ &status.Anonymous.Anonymous };
+        if status.vt != VT_ERROR {
+            return Err(MtpError::InvalidProperty);
+        }
+        Ok(HRESULT(unsafe{ status.Anonymous.scode }))
+    }).collect()
+}
+
+/// Re-implementation of `InitPropVariantFromString`, which is missing in windows-rs.
+/// See https://github.com/microsoft/windows-rs/issues/976#issuecomment-878697273
+///
+/// # Safety
+///
+/// I'm too lazy to wrap to result with a 'a PhantomData, so for now, the result is only valid as long `data` is valid.
+unsafe fn init_propvariant_from_string(data: &U16CStr) -> PROPVARIANT {
+    windows::Win32::System::Com::StructuredStorage::PROPVARIANT{
+        Anonymous: windows::Win32::System::Com::StructuredStorage::PROPVARIANT_0 {
+            Anonymous: std::mem::ManuallyDrop::new(windows::Win32::System::Com::StructuredStorage::PROPVARIANT_0_0 {
+                vt: windows::Win32::System::Variant::VT_LPWSTR,
+                Anonymous: windows::Win32::System::Com::StructuredStorage::PROPVARIANT_0_0_0 {
+                    // The value is only read from
+                    pwszVal: PWSTR::from_raw(data.as_ptr() as *mut u16),
+                },
+                ..Default::default()
+            })
+        },
+    }
+}
@@ -0,0 +1,107 @@
+//! An `IPortableDeviceEventCallback` that forwards WPD events to an [`EventSink`]
+
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use windows::core::{ComInterface, IUnknown, IUnknown_Vtbl, Interface, GUID, HRESULT, PWSTR};
+use windows::Win32::Foundation::{E_NOINTERFACE, E_POINTER, S_OK};
+use windows::Win32::System::Com::CoTaskMemFree;
+use windows::Win32::UI::Shell::PropertiesSystem::PROPERTYKEY;
+use windows::Win32::Devices::PortableDevices::{
+    IPortableDeviceEventCallback, IPortableDeviceEventCallback_Vtbl, IPortableDeviceValues,
+    WPD_EVENT_PARAMETER_EVENT_ID, WPD_OBJECT_ID, WPD_OBJECT_PARENT_ID,
+    WPD_EVENT_OBJECT_ADDED, WPD_EVENT_OBJECT_REMOVED, WPD_EVENT_OBJECT_UPDATED, WPD_EVENT_DEVICE_REMOVED, WPD_EVENT_STORAGE_FORMAT,
+};
+use widestring::U16CString;
+
+use crate::device::{DeviceEvent, EventSink};
+use crate::object::ObjectId;
+
+/// The COM object handed over to `IPortableDevice::Advise`.
+///
+/// The `windows` crate can only implement COM interfaces with its `implement` feature, hence this hand-written one.
+#[repr(C)]
+struct EventCallback {
+    vtable: *const IPortableDeviceEventCallback_Vtbl,
+    ref_count: AtomicU32,
+    sink: EventSink,
+}
+
+static VTABLE: IPortableDeviceEventCallback_Vtbl = IPortableDeviceEventCallback_Vtbl {
+    base__: IUnknown_Vtbl {
+        QueryInterface: query_interface,
+        AddRef: add_ref,
+        Release: release,
+    },
+    OnEvent: on_event,
+};
+
+/// Create a callback that sends events to `sink`
+pub(crate) fn event_callback(sink: EventSink) -> IPortableDeviceEventCallback {
+    let callback = Box::new(EventCallback{ vtable: &VTABLE, ref_count: AtomicU32::new(1), sink });
+    // The interface takes over the reference the callback has been created with
+    unsafe{ IPortableDeviceEventCallback::from_raw(Box::into_raw(callback) as *mut c_void) }
+}
+
+unsafe extern "system" fn query_interface(this: *mut c_void, iid: *const GUID, interface: *mut *mut c_void) -> HRESULT {
+    if iid.is_null() || interface.is_null() {
+        return E_POINTER;
+    }
+    if *iid == IUnknown::IID || *iid == IPortableDeviceEventCallback::IID {
+        add_ref(this);
+        *interface = this;
+        S_OK
+    } else {
+        *interface = std::ptr::null_mut();
+        E_NOINTERFACE
+    }
+}
+
+unsafe extern "system" fn add_ref(this: *mut c_void) -> u32 {
+    let callback = &*(this as *const EventCallback);
+    callback.ref_count.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+unsafe extern "system" fn release(this: *mut c_void) -> u32 {
+    let remaining = {
+        let callback = &*(this as *const EventCallback);
+        callback.ref_count.fetch_sub(1, Ordering::Release) - 1
+    };
+    if remaining == 0 {
+        std::sync::atomic::fence(Ordering::Acquire);
+        drop(Box::from_raw(this as *mut EventCallback));
+    }
+    remaining
+}
+
+unsafe extern "system" fn on_event(this: *mut c_void, event_parameters: *mut c_void) -> HRESULT {
+    let callback = &*(this as *const EventCallback);
+    let Some(parameters) = IPortableDeviceValues::from_raw_borrowed(&event_parameters) else {
+        return E_POINTER;
+    };
+    if let Some(event) = device_event(parameters) {
+        callback.sink.send(event);
+    }
+    S_OK
+}
+
+/// Events that are not part of [`DeviceEvent`] (e.g. transfer requests) are ignored
+fn device_event(parameters: &IPortableDeviceValues) -> Option<DeviceEvent> {
+    let event_id = unsafe{ parameters.GetGuidValue(&WPD_EVENT_PARAMETER_EVENT_ID) }.ok()?;
+    let object_id = string_value(parameters, &WPD_OBJECT_ID);
+    match event_id {
+        WPD_EVENT_OBJECT_ADDED => Some(DeviceEvent::ObjectAdded{ id: object_id?, parent: string_value(parameters, &WPD_OBJECT_PARENT_ID) }),
+        WPD_EVENT_OBJECT_REMOVED => Some(DeviceEvent::ObjectRemoved{ id: object_id? }),
+        WPD_EVENT_OBJECT_UPDATED => Some(DeviceEvent::ObjectUpdated{ id: object_id? }),
+        WPD_EVENT_DEVICE_REMOVED => Some(DeviceEvent::DeviceRemoved),
+        WPD_EVENT_STORAGE_FORMAT => Some(DeviceEvent::StorageChanged{ id: object_id }),
+        _ => None,
+    }
+}
+
+fn string_value(parameters: &IPortableDeviceValues, key: &PROPERTYKEY) -> Option<ObjectId> {
+    let value: PWSTR = unsafe{ parameters.GetStringValue(key) }.ok()?;
+    let owned_value = unsafe{ U16CString::from_ptr_str(value.as_ptr()) };
+    unsafe{ CoTaskMemFree(Some(value.as_ptr() as *const _)) };
+    Some(ObjectId::from(owned_value))
+}
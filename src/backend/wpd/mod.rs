@@ -0,0 +1,227 @@
+//! Backend over the [Windows Portable Devices COM API](https://learn.microsoft.com/en-us/windows/win32/wpd_sdk/programming-guide)
+//!
+//! This is the default backend on Windows, and the only one that is registered by [`crate::Provider::new`] there.
+
+use std::any::Any;
+use std::ffi::c_void;
+use std::rc::Rc;
+
+use windows::core::{GUID, PWSTR, PCWSTR};
+use windows::Win32::System::Com::{CoInitializeEx, CoCreateInstance, CoTaskMemFree, CLSCTX_ALL, COINIT_DISABLE_OLE1DDE, COINIT_MULTITHREADED};
+use windows::Win32::Devices::PortableDevices::{IPortableDeviceManager, IPortableDevice, PortableDeviceFTM, GUID_DEVINTERFACE_WPD};
+use windows::Win32::Devices::DeviceAndDriverInstallation::{
+    CM_Register_Notification, CM_Unregister_Notification, CM_NOTIFY_FILTER, CM_NOTIFY_FILTER_TYPE_DEVICEINTERFACE, CM_NOTIFY_ACTION,
+    CM_NOTIFY_ACTION_DEVICEINTERFACEARRIVAL, CM_NOTIFY_ACTION_DEVICEINTERFACEREMOVAL, CM_NOTIFY_EVENT_DATA, HCMNOTIFICATION, CR_SUCCESS,
+};
+use windows::Win32::Foundation::ERROR_SUCCESS;
+use widestring::U16CString;
+
+use crate::backend::{ProviderBackend, DeviceBackend, OpenedDeviceBackend, ContentBackend, CapabilitiesBackend};
+use crate::device::{BasicDevice, Subscription};
+use crate::device::device_values::AppIdentifiers;
+use crate::error::MtpError;
+use crate::watcher::WatchSink;
+
+mod content;
+pub use content::WpdContent;
+
+mod capabilities;
+pub use capabilities::WpdCapabilities;
+
+mod events;
+mod stream;
+pub(crate) mod values;
+
+/// Lists devices known to Windows
+pub struct WpdProvider {}
+
+impl WpdProvider {
+    /// This internally inits the underlying Windows API.
+    pub fn new() -> Result<Self, MtpError> {
+        unsafe {
+            CoInitializeEx(
+                None,
+                COINIT_MULTITHREADED
+                | COINIT_DISABLE_OLE1DDE, // Setting this flag avoids some overhead associated with Object Linking and Embedding (OLE) 1.0, an obsolete technology. (see https://learn.microsoft.com/en-us/windows/win32/learnwin32/initializing-the-com-library)
+            )?;
+        }
+
+        Ok(Self{})
+    }
+}
+
+impl ProviderBackend for WpdProvider {
+    fn enumerate_devices(&self) -> Result<Vec<BasicDevice>, MtpError> {
+        let device_mgr: IPortableDeviceManager = unsafe {
+            CoCreateInstance(
+                &windows::Win32::Devices::PortableDevices::PortableDeviceManager as *const GUID,
+                None,
+                CLSCTX_ALL,
+            )
+        }?;
+
+        // How many devices are there?
+        let mut dev_count: u32 = 0;
+        unsafe {
+            device_mgr.RefreshDeviceList()?;
+            device_mgr.GetDevices(
+                std::ptr::null_mut(),
+                &mut dev_count as *mut _
+            )?;
+        }
+
+        if dev_count == 0 {
+            return Ok(Vec::new());
+        }
+
+        // Get their IDs
+        let mut dev_ids: Vec<PWSTR> = Vec::with_capacity(dev_count as usize);
+        let mut fetched_devices: u32 = dev_count;
+        unsafe {
+            device_mgr.GetDevices(
+                dev_ids.as_mut_ptr(),
+                &mut fetched_devices as *mut _,
+            )
+        }?;
+        if fetched_devices != dev_count {
+            return Err(MtpError::ChangedConditions);
+        }
+        unsafe { dev_ids.set_len(dev_count as usize) };
+
+        // Build a Rust result type
+        let mut devices = Vec::new();
+        for dev_id in &dev_ids {
+            let dev_id_const = PCWSTR::from_raw(dev_id.as_ptr());
+            let friendly_name = get_friendly_name(&device_mgr, dev_id_const)?;
+            let string_id = U16CString::from_vec_truncate(unsafe{ dev_id.as_wide() });
+            let backend = Rc::new(WpdDevice{ device_id: string_id.clone() });
+            devices.push(BasicDevice::new(string_id, friendly_name, backend));
+        }
+
+        // Free memory allocated by the COM API
+        for dev_id in dev_ids {
+            unsafe{ CoTaskMemFree(Some(dev_id.as_ptr() as *const _)) };
+        }
+
+        Ok(devices)
+    }
+
+    /// Arrivals and removals of WPD device interfaces are reported by the configuration manager
+    fn watch(&self, sink: WatchSink) -> Result<Subscription, MtpError> {
+        let mut filter = CM_NOTIFY_FILTER{
+            cbSize: std::mem::size_of::<CM_NOTIFY_FILTER>() as u32,
+            FilterType: CM_NOTIFY_FILTER_TYPE_DEVICEINTERFACE,
+            ..Default::default()
+        };
+        filter.u.DeviceInterface.ClassGuid = GUID_DEVINTERFACE_WPD;
+
+        let context = Box::into_raw(Box::new(sink));
+        let mut notification = HCMNOTIFICATION::default();
+        let result = unsafe{ CM_Register_Notification(&filter, Some(context as *const c_void), Some(on_device_change), &mut notification) };
+        if result != CR_SUCCESS {
+            drop(unsafe{ Box::from_raw(context) });
+            return Err(MtpError::Backend(format!("Unable to register for device notifications (CONFIGRET {})", result.0)));
+        }
+
+        Ok(Subscription::new(move || {
+            // This waits for the callbacks that are running, so that the sink can be freed afterwards
+            unsafe{ CM_Unregister_Notification(notification) };
+            drop(unsafe{ Box::from_raw(context) });
+        }))
+    }
+}
+
+/// Called by the configuration manager, from one of its own threads
+unsafe extern "system" fn on_device_change(_notification: HCMNOTIFICATION, context: *const c_void, action: CM_NOTIFY_ACTION, _event_data: *const CM_NOTIFY_EVENT_DATA, _event_data_size: u32) -> u32 {
+    if action == CM_NOTIFY_ACTION_DEVICEINTERFACEARRIVAL || action == CM_NOTIFY_ACTION_DEVICEINTERFACEREMOVAL {
+        let sink = &*(context as *const WatchSink);
+        sink.notify();
+    }
+    ERROR_SUCCESS.0
+}
+
+fn get_friendly_name(mgr: &IPortableDeviceManager, dev_id: PCWSTR) -> Result<String, MtpError> {
+    // How long is the name?
+    let mut required_len: u32 = 0;
+    unsafe {
+        mgr.GetDeviceFriendlyName(
+            dev_id,
+            PWSTR::null(),
+            &mut required_len as *mut u32,
+        )
+    }?;
+
+    if required_len == 0 {
+        return Ok(String::new());
+    }
+
+    let mut friendly_name: Vec<u16> = Vec::with_capacity(required_len as usize);
+    let p_friendly_name = PWSTR::from_raw(friendly_name.as_mut_ptr());
+    let mut retrieved_len: u32 = required_len;
+    unsafe {
+        mgr.GetDeviceFriendlyName(
+            dev_id,
+            p_friendly_name,
+            &mut retrieved_len as *mut u32,
+        )
+    }?;
+    if retrieved_len != required_len {
+        return Err(MtpError::ChangedConditions);
+    }
+    unsafe { friendly_name.set_len(required_len as usize) };
+
+    Ok(unsafe { p_friendly_name.to_string() }?)
+}
+
+/// A device known to Windows, identified by its PnP device ID
+pub struct WpdDevice {
+    device_id: U16CString,
+}
+
+impl DeviceBackend for WpdDevice {
+    fn open(&self, app_identifiers: &AppIdentifiers) -> Result<Box<dyn OpenedDeviceBackend>, MtpError> {
+        // Fill out information about your application, so the device knows
+        // who they are speaking to.
+        let device_values = values::make_values_for_open_device(app_identifiers)?;
+
+        let com_device: IPortableDevice = unsafe {
+            CoCreateInstance(
+                &PortableDeviceFTM as *const GUID,
+                None,
+                CLSCTX_ALL
+            )
+        }?;
+
+        unsafe { com_device.Open(PCWSTR::from_raw(self.device_id.as_ptr()), &device_values) }?;
+
+        Ok(Box::new(WpdOpenedDevice{ com_device }))
+    }
+}
+
+/// A device that has been opened through the WPD API
+pub struct WpdOpenedDevice {
+    com_device: IPortableDevice,
+}
+
+impl WpdOpenedDevice {
+    /// Returns the underlying COM object
+    pub fn com_object(&self) -> &IPortableDevice {
+        &self.com_device
+    }
+}
+
+impl OpenedDeviceBackend for WpdOpenedDevice {
+    fn content(&self) -> Result<Rc<dyn ContentBackend>, MtpError> {
+        let com_content = unsafe { self.com_device.Content() }?;
+        Ok(Rc::new(WpdContent::new(com_content, self.com_device.clone())))
+    }
+
+    fn capabilities(&self) -> Result<Rc<dyn CapabilitiesBackend>, MtpError> {
+        let com_capabilities = unsafe { self.com_device.Capabilities() }?;
+        Ok(Rc::new(WpdCapabilities::new(com_capabilities)))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
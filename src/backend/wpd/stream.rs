@@ -0,0 +1,115 @@
+//! Adapters so that COM streams implement `std::io::Read` and `std::io::Write`
+
+use std::ffi::c_void;
+use std::io::{Read, SeekFrom, Write};
+
+use windows::core::ComInterface;
+use windows::Win32::System::Com::{IStream, STGC_DEFAULT, STREAM_SEEK_SET, STREAM_SEEK_CUR, STREAM_SEEK_END, CoTaskMemFree};
+use windows::Win32::Foundation::{S_OK, S_FALSE};
+use windows::Win32::Devices::PortableDevices::IPortableDeviceDataStream;
+use widestring::U16CString;
+
+use crate::backend::{ReadStreamBackend, WriteStreamBackend};
+use crate::error::MtpError;
+
+/// A wrapper around a COM [`IStream`](windows::Win32::System::Com::IStream)
+pub(crate) struct ComStream(pub(crate) IStream);
+
+impl Read for ComStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let requested_bytes = match buf.len().try_into() {
+            Ok(b) => b,
+            Err(_) => return Err(std::io::Error::other("Requested too many bytes to read")),
+        };
+
+        let mut bytes_read: u32 = 0;
+        let res = unsafe{
+            self.0.Read(
+                buf.as_mut_ptr() as *mut c_void,
+                requested_bytes,
+                Some(&mut bytes_read as *mut u32),
+            )
+        };
+
+        match res {
+            // regular case
+            S_OK => Ok(bytes_read as usize),
+
+            // EOF reached
+            S_FALSE => Ok(bytes_read as usize),
+
+            // Other error, that keeps its HRESULT (see `MtpError::kind`)
+            err => Err(std::io::Error::other(MtpError::from(windows::core::Error::from(err)))),
+        }
+    }
+}
+
+impl ReadStreamBackend for ComStream {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        let (offset, origin) = match position {
+            SeekFrom::Start(offset) => (offset as i64, STREAM_SEEK_SET),
+            SeekFrom::Current(offset) => (offset, STREAM_SEEK_CUR),
+            SeekFrom::End(offset) => (offset, STREAM_SEEK_END),
+        };
+        let mut new_position: u64 = 0;
+        // Drivers that cannot seek return E_NOTIMPL, which is classified as `ErrorKind::Unsupported`
+        unsafe{ self.0.Seek(offset, origin, Some(&mut new_position as *mut u64)) }
+            .map_err(|err| std::io::Error::other(MtpError::from(err)))?;
+        Ok(new_position)
+    }
+}
+
+impl Write for ComStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let requested_bytes = match buf.len().try_into() {
+            Ok(b) => b,
+            Err(_) => return Err(std::io::Error::other("Requested too many bytes to write")),
+        };
+
+        let mut bytes_written: u32 = 0;
+        let res = unsafe{
+            self.0.Write(
+                buf.as_ptr() as *const c_void,
+                requested_bytes,
+                Some(&mut bytes_written as *mut u32),
+            )
+        };
+
+        match res {
+            // regular case
+            S_OK => Ok(bytes_written as usize),
+
+            // Other error, that keeps its HRESULT (see `MtpError::kind`)
+            err => Err(std::io::Error::other(MtpError::from(windows::core::Error::from(err)))),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        // Data is only sent to the device when committing, see `WriteStreamBackend::commit`
+        Ok(())
+    }
+}
+
+impl WriteStreamBackend for ComStream {
+    /// Call the COM `Commit` API
+    fn commit(&mut self) -> Result<(), MtpError> {
+        Ok(unsafe{ self.0.Commit(STGC_DEFAULT) }?)
+    }
+
+    /// Call the COM `Cancel` API of data streams, or `Revert` for other streams
+    fn abort(&mut self) -> Result<(), MtpError> {
+        match self.0.cast::<IPortableDeviceDataStream>() {
+            Ok(data_stream) => Ok(unsafe{ data_stream.Cancel() }?),
+            Err(_) => Ok(unsafe{ self.0.Revert() }?),
+        }
+    }
+
+    /// Streams returned by `CreateObjectWithPropertiesAndData` know the ID of the object they created
+    fn object_id(&self) -> Option<U16CString> {
+        let data_stream: IPortableDeviceDataStream = self.0.cast().ok()?;
+        let object_id = unsafe{ data_stream.GetObjectID() }.ok()?;
+        let owned_id = unsafe{ U16CString::from_ptr_str(object_id.as_ptr()) };
+        unsafe{ CoTaskMemFree(Some(object_id.as_ptr() as *const _)) };
+        Some(owned_id)
+    }
+}
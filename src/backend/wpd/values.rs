@@ -0,0 +1,149 @@
+//! Conversions between [`DeviceValues`] and COM `IPortableDeviceValues`
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use windows::core::{GUID, PCWSTR};
+use windows::Win32::Foundation::BOOL;
+use windows::Win32::System::Com::{CoCreateInstance, CLSCTX_ALL};
+use windows::Win32::System::Com::StructuredStorage::{PropVariantClear, PROPVARIANT, PROPVARIANT_0, PROPVARIANT_0_0, PROPVARIANT_0_0_0};
+use windows::Win32::System::Variant::{VT_BOOL, VT_CLSID, VT_DATE, VT_I4, VT_INT, VT_LPWSTR, VT_R4, VT_UI4, VT_UI8, VT_UINT};
+use windows::Win32::Storage::FileSystem::SECURITY_IMPERSONATION;
+use windows::Win32::Devices::PortableDevices::{
+    PortableDeviceValues, IPortableDeviceValues,
+    WPD_CLIENT_NAME,
+    WPD_CLIENT_MAJOR_VERSION,
+    WPD_CLIENT_MINOR_VERSION,
+    WPD_CLIENT_REVISION,
+    WPD_CLIENT_SECURITY_QUALITY_OF_SERVICE,
+};
+use widestring::U16CString;
+
+use crate::device::device_values::{AppIdentifiers, DeviceValues, PropertyValue};
+use crate::error::MtpError;
+
+const SECONDS_PER_DAY: f64 = 86_400.0;
+const DAYS_BETWEEN_1899_AND_1970: f64 = 25_569.0;
+
+pub(crate) fn new_com_values() -> Result<IPortableDeviceValues, MtpError> {
+    Ok(unsafe {
+        CoCreateInstance(
+            &PortableDeviceValues as *const GUID,
+            None,
+            CLSCTX_ALL
+        )
+    }?)
+}
+
+/// Create a IPortableDeviceValues instance with suggestions from https://learn.microsoft.com/en-us/windows/win32/wpd_sdk/specifying-client-information
+pub(crate) fn make_values_for_open_device(current_app_identifiers: &AppIdentifiers) -> Result<IPortableDeviceValues, MtpError> {
+    let device_values = new_com_values()?;
+
+    // At a minimum, your application should provide a string containing its name, a major version number, a minor version number, and a revision number. These are the fields supplied by the sample application.
+    // See https://learn.microsoft.com/en-us/windows/win32/wpd_sdk/specifying-client-information
+
+    let current_app_name_wide = U16CString::from_str_truncate(&current_app_identifiers.app_name);
+    let pcwstr_current_app_name = PCWSTR::from_raw(current_app_name_wide.as_ptr());
+
+    unsafe{ device_values.SetStringValue(&WPD_CLIENT_NAME as *const _, pcwstr_current_app_name) }?;
+    unsafe{ device_values.SetUnsignedIntegerValue(&WPD_CLIENT_MAJOR_VERSION as *const _, current_app_identifiers.app_major) }?;
+    unsafe{ device_values.SetUnsignedIntegerValue(&WPD_CLIENT_MINOR_VERSION as *const _, current_app_identifiers.app_minor) }?;
+    unsafe{ device_values.SetUnsignedIntegerValue(&WPD_CLIENT_REVISION as *const _, current_app_identifiers.app_patch) }?;
+    // Some device drivers need to impersonate the caller in order to function correctly.  Since our application does not
+    // need to restrict its identity, specify SECURITY_IMPERSONATION so that we work with all devices.
+    // See https://learn.microsoft.com/en-us/windows/win32/wpd_sdk/specifying-client-information
+    unsafe{ device_values.SetUnsignedIntegerValue(&WPD_CLIENT_SECURITY_QUALITY_OF_SERVICE as *const _, SECURITY_IMPERSONATION.0) }?;
+
+    Ok(device_values)
+}
+
+/// Build a COM collection out of Rust values
+pub(crate) fn to_com(values: &DeviceValues) -> Result<IPortableDeviceValues, MtpError> {
+    let com_values = new_com_values()?;
+
+    for (key, value) in values.iter() {
+        let key = key as *const _;
+        match value {
+            PropertyValue::String(s) => unsafe{ com_values.SetStringValue(key, PCWSTR::from_raw(s.as_ptr())) }?,
+            PropertyValue::U32(v) => unsafe{ com_values.SetUnsignedIntegerValue(key, *v) }?,
+            PropertyValue::I32(v) => unsafe{ com_values.SetSignedIntegerValue(key, *v) }?,
+            PropertyValue::U64(v) => unsafe{ com_values.SetUnsignedLargeIntegerValue(key, *v) }?,
+            PropertyValue::F32(v) => unsafe{ com_values.SetFloatValue(key, *v) }?,
+            PropertyValue::Guid(v) => unsafe{ com_values.SetGuidValue(key, v as *const _) }?,
+            PropertyValue::Bool(v) => unsafe{ com_values.SetBoolValue(key, BOOL::from(*v)) }?,
+            PropertyValue::Date(v) => {
+                let prop_variant = PROPVARIANT{
+                    Anonymous: PROPVARIANT_0 {
+                        Anonymous: std::mem::ManuallyDrop::new(PROPVARIANT_0_0 {
+                            vt: VT_DATE,
+                            Anonymous: PROPVARIANT_0_0_0 { date: system_time_to_variant_date(*v) },
+                            ..Default::default()
+                        })
+                    },
+                };
+                unsafe{ com_values.SetValue(key, &prop_variant as *const _) }?
+            },
+        }
+    }
+
+    Ok(com_values)
+}
+
+/// Read every value of a COM collection.
+///
+/// Values whose type is not supported (or that hold an error, as WPD does for properties it could not read) are skipped.
+pub(crate) fn from_com(com_values: &IPortableDeviceValues) -> Result<DeviceValues, MtpError> {
+    let mut count: u32 = 0;
+    unsafe{ com_values.GetCount(&mut count as *mut u32) }?;
+
+    let mut values = DeviceValues::new();
+    for index in 0..count {
+        let mut key = crate::PROPERTYKEY::default();
+        let mut prop_variant = PROPVARIANT::default();
+        unsafe{ com_values.GetAt(index, &mut key as *mut _, &mut prop_variant as *mut _) }?;
+
+        if let Some(value) = unsafe{ from_propvariant(&prop_variant) } {
+            values.set(key, value);
+        }
+        unsafe{ PropVariantClear(&mut prop_variant as *mut _) }?;
+    }
+
+    Ok(values)
+}
+
+/// # Safety
+///
+/// `prop_variant` must be a valid, initialized PROPVARIANT
+pub(crate) unsafe fn from_propvariant(prop_variant: &PROPVARIANT) -> Option<PropertyValue> {
+    let inner = &prop_variant.Anonymous.Anonymous;
+    let value = match inner.vt {
+        VT_LPWSTR => PropertyValue::String(U16CString::from_ptr_str(inner.Anonymous.pwszVal.as_ptr())),
+        VT_UI4 | VT_UINT => PropertyValue::U32(inner.Anonymous.ulVal),
+        VT_I4 | VT_INT => PropertyValue::I32(inner.Anonymous.lVal),
+        VT_UI8 => PropertyValue::U64(inner.Anonymous.uhVal),
+        VT_R4 => PropertyValue::F32(inner.Anonymous.fltVal),
+        VT_CLSID => PropertyValue::Guid(*inner.Anonymous.puuid.as_ref()?),
+        VT_BOOL => PropertyValue::Bool(inner.Anonymous.boolVal.as_bool()),
+        VT_DATE => PropertyValue::Date(variant_date_to_system_time(inner.Anonymous.date)),
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn variant_date_to_system_time(vt_date: f64) -> SystemTime {
+    let days_since_unix_epoch = vt_date - DAYS_BETWEEN_1899_AND_1970;
+    let seconds_since_unix_epoch = days_since_unix_epoch * SECONDS_PER_DAY;
+
+    if seconds_since_unix_epoch >= 0.0 {
+        UNIX_EPOCH + Duration::from_secs_f64(seconds_since_unix_epoch)
+    } else {
+        UNIX_EPOCH - Duration::from_secs_f64(-seconds_since_unix_epoch)
+    }
+}
+
+fn system_time_to_variant_date(time: SystemTime) -> f64 {
+    let seconds_since_unix_epoch = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs_f64(),
+        Err(err) => -err.duration().as_secs_f64(),
+    };
+    seconds_since_unix_epoch / SECONDS_PER_DAY + DAYS_BETWEEN_1899_AND_1970
+}
@@ -0,0 +1,164 @@
+//! What a device supports: commands, functional categories, content types, formats and properties
+
+use std::rc::Rc;
+
+use windows::core::GUID;
+use windows::Win32::Devices::PortableDevices::{
+    WPD_FUNCTIONAL_CATEGORY_DEVICE, WPD_FUNCTIONAL_CATEGORY_STORAGE, WPD_FUNCTIONAL_CATEGORY_STILL_IMAGE_CAPTURE,
+    WPD_FUNCTIONAL_CATEGORY_AUDIO_CAPTURE, WPD_FUNCTIONAL_CATEGORY_VIDEO_CAPTURE, WPD_FUNCTIONAL_CATEGORY_SMS,
+    WPD_FUNCTIONAL_CATEGORY_RENDERING_INFORMATION, WPD_FUNCTIONAL_CATEGORY_NETWORK_CONFIGURATION,
+};
+
+use crate::backend::CapabilitiesBackend;
+use crate::device::device_values::PropertyValue;
+use crate::error::MtpError;
+use crate::object::ObjectType;
+
+/// A group of features of a device, each of them being implemented by functional objects (e.g. every storage is a functional object of the [`FunctionalCategory::Storage`] category)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionalCategory {
+    Device,
+    Storage,
+    StillImageCapture,
+    AudioCapture,
+    VideoCapture,
+    Sms,
+    RenderingInformation,
+    NetworkConfiguration,
+    /// A category this crate has no name for, e.g. one that is specific to a vendor
+    Other(GUID),
+}
+
+impl FunctionalCategory {
+    pub fn from_guid(guid: GUID) -> Self {
+        match guid {
+            WPD_FUNCTIONAL_CATEGORY_DEVICE                => Self::Device,
+            WPD_FUNCTIONAL_CATEGORY_STORAGE               => Self::Storage,
+            WPD_FUNCTIONAL_CATEGORY_STILL_IMAGE_CAPTURE   => Self::StillImageCapture,
+            WPD_FUNCTIONAL_CATEGORY_AUDIO_CAPTURE         => Self::AudioCapture,
+            WPD_FUNCTIONAL_CATEGORY_VIDEO_CAPTURE         => Self::VideoCapture,
+            WPD_FUNCTIONAL_CATEGORY_SMS                   => Self::Sms,
+            WPD_FUNCTIONAL_CATEGORY_RENDERING_INFORMATION => Self::RenderingInformation,
+            WPD_FUNCTIONAL_CATEGORY_NETWORK_CONFIGURATION => Self::NetworkConfiguration,
+            other => Self::Other(other),
+        }
+    }
+
+    pub fn as_guid(&self) -> GUID {
+        match self {
+            Self::Device               => WPD_FUNCTIONAL_CATEGORY_DEVICE,
+            Self::Storage              => WPD_FUNCTIONAL_CATEGORY_STORAGE,
+            Self::StillImageCapture    => WPD_FUNCTIONAL_CATEGORY_STILL_IMAGE_CAPTURE,
+            Self::AudioCapture         => WPD_FUNCTIONAL_CATEGORY_AUDIO_CAPTURE,
+            Self::VideoCapture         => WPD_FUNCTIONAL_CATEGORY_VIDEO_CAPTURE,
+            Self::Sms                  => WPD_FUNCTIONAL_CATEGORY_SMS,
+            Self::RenderingInformation => WPD_FUNCTIONAL_CATEGORY_RENDERING_INFORMATION,
+            Self::NetworkConfiguration => WPD_FUNCTIONAL_CATEGORY_NETWORK_CONFIGURATION,
+            Self::Other(guid)          => *guid,
+        }
+    }
+}
+
+/// The values a property accepts
+#[derive(Debug, Clone, PartialEq)]
+pub enum AllowedValues {
+    /// The device does not tell
+    Any,
+    Range{ min: PropertyValue, max: PropertyValue, step: PropertyValue },
+    Enumeration(Vec<PropertyValue>),
+    RegularExpression(String),
+    /// The value is the ID of another object
+    ObjectId,
+}
+
+/// How a property of objects of a given format behaves, as returned by [`Capabilities::property_description`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyDescription {
+    pub writable: bool,
+    /// Whether the property can be removed from an object
+    pub can_delete: bool,
+    pub default_value: Option<PropertyValue>,
+    pub allowed_values: AllowedValues,
+}
+
+impl PropertyDescription {
+    pub fn is_read_only(&self) -> bool {
+        !self.writable
+    }
+}
+
+/// What a device supports, as returned by [`Device::capabilities`](crate::device::Device::capabilities)
+///
+/// Commands, formats and properties are designated by their WPD GUIDs and `PROPERTYKEY`s
+/// (e.g. [`WPD_COMMAND_OBJECT_MANAGEMENT_MOVE_OBJECTS`](crate::PortableDevices::WPD_COMMAND_OBJECT_MANAGEMENT_MOVE_OBJECTS),
+/// [`WPD_OBJECT_FORMAT_FLAC`](crate::PortableDevices::WPD_OBJECT_FORMAT_FLAC) or [`WPD_OBJECT_NAME`](crate::PortableDevices::WPD_OBJECT_NAME)), whatever the backend.
+///
+/// ```no_run
+/// use winmtp::PortableDevices::WPD_OBJECT_FORMAT_FLAC;
+/// # let provider = winmtp::Provider::new().unwrap();
+/// # let basic_device = &provider.enumerate_devices().unwrap()[0];
+/// # let app_identifiers = winmtp::make_current_app_identifiers!();
+/// let device = basic_device.open(&app_identifiers, false).unwrap();
+/// if !device.capabilities().unwrap().supports_format(&WPD_OBJECT_FORMAT_FLAC).unwrap() {
+///     println!("This device would not play FLAC files");
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Capabilities {
+    backend: Rc<dyn CapabilitiesBackend>,
+}
+
+impl Capabilities {
+    pub(crate) fn new(backend: Rc<dyn CapabilitiesBackend>) -> Self {
+        Self{ backend }
+    }
+
+    /// The WPD commands (e.g. `WPD_COMMAND_OBJECT_MANAGEMENT_MOVE_OBJECTS`) the device supports
+    pub fn supported_commands(&self) -> Result<Vec<crate::PROPERTYKEY>, MtpError> {
+        self.backend.supported_commands()
+    }
+
+    pub fn supports_command(&self, command: &crate::PROPERTYKEY) -> Result<bool, MtpError> {
+        Ok(self.supported_commands()?.contains(command))
+    }
+
+    pub fn functional_categories(&self) -> Result<Vec<FunctionalCategory>, MtpError> {
+        Ok(self.backend.functional_categories()?.into_iter().map(FunctionalCategory::from_guid).collect())
+    }
+
+    /// The types of objects the functional objects of a category can hold, e.g. the types of the files a storage accepts
+    pub fn content_types(&self, category: FunctionalCategory) -> Result<Vec<ObjectType>, MtpError> {
+        Ok(self.backend.content_types(&category.as_guid())?.into_iter().map(ObjectType::from_guid).collect())
+    }
+
+    /// The formats (e.g. `WPD_OBJECT_FORMAT_MP3`) objects of a type can have
+    pub fn formats(&self, content_type: ObjectType) -> Result<Vec<GUID>, MtpError> {
+        self.backend.formats(&content_type.as_guid())
+    }
+
+    /// Whether objects of this format can be stored on the device, whatever their type
+    pub fn supports_format(&self, format: &GUID) -> Result<bool, MtpError> {
+        for content_type in self.backend.content_types(&WPD_FUNCTIONAL_CATEGORY_STORAGE)? {
+            if self.backend.formats(&content_type)?.contains(format) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// The properties objects of this format can have
+    pub fn format_properties(&self, format: &GUID) -> Result<Vec<crate::PROPERTYKEY>, MtpError> {
+        self.backend.format_properties(format)
+    }
+
+    /// How a property of objects of this format behaves (whether it is writable, the values it accepts...)
+    pub fn property_description(&self, format: &GUID, property: &crate::PROPERTYKEY) -> Result<PropertyDescription, MtpError> {
+        self.backend.property_description(format, property)
+    }
+}
+
+impl std::fmt::Debug for Capabilities {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Capabilities").finish_non_exhaustive()
+    }
+}
@@ -1,58 +1,242 @@
 //! Access to content of a device
 
-use windows::Win32::System::Com::{CoCreateInstance, CLSCTX_ALL};
-use windows::core::{PCWSTR, GUID};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use windows::Win32::Devices::PortableDevices::{
-    IPortableDeviceContent, IPortableDeviceKeyCollection, PortableDeviceKeyCollection, WPD_OBJECT_NAME,
-    WPD_OBJECT_CONTENT_TYPE, WPD_OBJECT_ORIGINAL_FILE_NAME, WPD_DEVICE_OBJECT_ID
+    WPD_OBJECT_NAME, WPD_OBJECT_CONTENT_TYPE, WPD_OBJECT_ORIGINAL_FILE_NAME, WPD_OBJECT_CONTAINER_FUNCTIONAL_OBJECT_ID,
+    WPD_FUNCTIONAL_OBJECT_CATEGORY, WPD_FUNCTIONAL_CATEGORY_STORAGE,
 };
-use widestring::{U16CString, U16CStr};
+use widestring::U16CStr;
+
+use crate::backend::ContentBackend;
+use crate::object::{Object, ObjectId, ObjectIdRef, ObjectMetadata, ObjectType};
+use crate::device::Storage;
+use crate::device::device_values::{make_values_for_create_folder, DeviceValues};
+use crate::error::{ErrorKind, MtpError};
+
+/// What happened to one of the objects of a batch operation (see [`Content::delete_many`] and [`Content::move_many`])
+#[derive(Debug)]
+pub struct ObjectOutcome {
+    pub object_id: ObjectId,
+    pub result: Result<(), MtpError>,
+}
+
+impl ObjectOutcome {
+    pub fn is_ok(&self) -> bool {
+        self.result.is_ok()
+    }
+
+    /// Why this object failed, if it did
+    pub fn error_kind(&self) -> Option<ErrorKind> {
+        self.result.as_ref().err().map(MtpError::kind)
+    }
+}
+
+/// What happened to one of the properties written by [`Content::set_properties`]
+#[derive(Debug)]
+pub struct PropertyOutcome {
+    pub key: crate::PROPERTYKEY,
+    pub result: Result<(), MtpError>,
+}
+
+impl PropertyOutcome {
+    pub fn is_ok(&self) -> bool {
+        self.result.is_ok()
+    }
 
-use crate::object::{Object, ObjectType};
-use crate::device::device_values::DeviceValues;
+    /// Why the device rejected this property, if it did
+    pub fn error_kind(&self) -> Option<ErrorKind> {
+        self.result.as_ref().err().map(MtpError::kind)
+    }
+}
 
 #[derive(Debug, Clone)]
 /// Abstraction over the content of a device
 pub struct Content{
-    com_content: IPortableDeviceContent,
+    backend: Rc<dyn ContentBackend>,
     case_sensitive_fs: bool,
+    /// What [`Self::detect_case_sensitivity`] found or [`Self::set_storage_case_sensitive_fs`] set, by storage ID. This is shared by every clone, so that objects benefit from it.
+    storage_case_sensitivity: Rc<RefCell<HashMap<ObjectId, bool>>>,
+    /// The storages objects belong to (`None` for objects outside of storages), as found by [`Self::case_sensitive_fs_for`]. This is shared by every clone too.
+    object_storages: Rc<RefCell<HashMap<ObjectId, Option<ObjectId>>>>,
 }
 
 impl Content {
-    pub(crate) fn new(com_content: IPortableDeviceContent, case_sensitive_fs: bool) -> Self {
-        Self{ com_content, case_sensitive_fs }
+    pub(crate) fn new(backend: Rc<dyn ContentBackend>, case_sensitive_fs: bool) -> Self {
+        Self{
+            backend,
+            case_sensitive_fs,
+            storage_case_sensitivity: Rc::new(RefCell::new(HashMap::new())),
+            object_storages: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    pub(crate) fn backend(&self) -> &dyn ContentBackend {
+        self.backend.as_ref()
     }
 
     /// Retrieve the inner COM object, in case one wants to call a method for which there is no wrapper in this crate
-    pub fn com_object(&self) -> &IPortableDeviceContent {
-        &self.com_content
+    ///
+    /// This returns `None` if this content is not handled by the [WPD backend](crate::backend::wpd).
+    #[cfg(windows)]
+    pub fn com_object(&self) -> Option<&windows::Win32::Devices::PortableDevices::IPortableDeviceContent> {
+        self.backend
+            .as_any()
+            .downcast_ref::<crate::backend::wpd::WpdContent>()
+            .map(|content| content.com_object())
     }
 
+    /// Whether paths are case-sensitive, as given to [`BasicDevice::open`](crate::device::BasicDevice::open)
+    ///
+    /// See [`Self::case_sensitive_fs_for`] for what is used for the objects of a given storage.
     pub fn case_sensitive_fs(&self) -> bool {
         self.case_sensitive_fs
     }
 
+    /// Whether paths are case-sensitive in the storage an object belongs to
+    ///
+    /// This is what has been set for this storage (see [`Self::set_storage_case_sensitive_fs`] and [`Self::detect_case_sensitivity`]),
+    /// or [`Self::case_sensitive_fs`] for storages nothing has been set for.<br/>
+    /// Once something has been set for a storage, this asks the device which storage the object belongs to, the first time it is called for this object.
+    pub fn case_sensitive_fs_for(&self, object_id: &ObjectIdRef) -> Result<bool, MtpError> {
+        if self.storage_case_sensitivity.borrow().is_empty() {
+            return Ok(self.case_sensitive_fs);
+        }
+        let storage_id = self.storage_of(object_id)?;
+        Ok(storage_id
+            .and_then(|storage_id| self.storage_case_sensitivity.borrow().get(&storage_id).copied())
+            .unwrap_or(self.case_sensitive_fs))
+    }
+
+    /// The storage an object belongs to, or `None` for objects that are not in a storage (e.g. the device root)
+    fn storage_of(&self, object_id: &ObjectIdRef) -> Result<Option<ObjectId>, MtpError> {
+        if self.storage_case_sensitivity.borrow().contains_key(object_id) {
+            return Ok(Some(object_id.to_owned()));
+        }
+        if let Some(storage_id) = self.object_storages.borrow().get(object_id) {
+            return Ok(storage_id.clone());
+        }
+        let storage_id = self.properties(object_id, &[WPD_OBJECT_CONTAINER_FUNCTIONAL_OBJECT_ID])?
+            .get_string(&WPD_OBJECT_CONTAINER_FUNCTIONAL_OBJECT_ID)
+            .ok()
+            .filter(|storage_id| !storage_id.is_empty())
+            .map(ObjectId::from);
+        self.object_storages.borrow_mut().insert(object_id.to_owned(), storage_id.clone());
+        Ok(storage_id)
+    }
+
+    /// Set whether paths are case-sensitive on a storage, e.g. on an SD card of a device whose internal storage is case-sensitive
+    ///
+    /// This overrides what has been given to [`BasicDevice::open`](crate::device::BasicDevice::open) or found by [`Self::detect_case_sensitivity`],
+    /// for every object of this storage, including the ones of other clones of this `Content`.
+    pub fn set_storage_case_sensitive_fs(&self, storage_id: &ObjectIdRef, case_sensitive: bool) {
+        self.storage_case_sensitivity.borrow_mut().insert(storage_id.to_owned(), case_sensitive);
+    }
+
+    /// Find out whether paths are case-sensitive on a storage, and use this for its objects from now on (see [`Self::case_sensitive_fs_for`])
+    ///
+    /// The filesystem type of the storage is used if it tells (see [`Storage::case_sensitivity_hint`]).
+    /// Otherwise, this creates a uniquely named folder at the root of the storage, then another one whose name only differs by its case,
+    /// checks whether they collide, and deletes them. Devices that refuse the second folder without telling why are only deemed case-insensitive
+    /// if they accept a third one, whose name is not a variant of the first one; other failures are returned.<br/>
+    /// The answer is cached, so that only the first call for a storage may touch the device. If some has been set with [`Self::set_storage_case_sensitive_fs`], it is returned as is.
+    pub fn detect_case_sensitivity(&self, storage: &Storage) -> Result<bool, MtpError> {
+        if let Some(case_sensitive) = self.storage_case_sensitivity.borrow().get(storage.id()) {
+            return Ok(*case_sensitive);
+        }
+
+        let case_sensitive = match storage.case_sensitivity_hint() {
+            Some(case_sensitive) => case_sensitive,
+            None if storage.is_read_only() => return Err(MtpError::AccessDenied("Cannot probe a read-only storage".to_string())),
+            None => self.probe_case_sensitivity(storage.id())?,
+        };
+        self.storage_case_sensitivity.borrow_mut().insert(storage.id().to_owned(), case_sensitive);
+        Ok(case_sensitive)
+    }
+
+    fn probe_case_sensitivity(&self, storage_id: &ObjectIdRef) -> Result<bool, MtpError> {
+        let unique = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos());
+        let name = format!(".winmtp-case-probe-{}-{:x}", std::process::id(), unique);
+        let create_folder = |name: &str| self.backend.create_object(&make_values_for_create_folder(storage_id.as_ucstr(), OsStr::new(name)));
+
+        let mut probe_ids = vec![create_folder(&name)?];
+        let case_sensitive = match create_folder(&name.to_uppercase()) {
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => Ok(false),
+            // MTP has no specific response code for collisions, devices usually answer a mere `GeneralError`.
+            // This is only a collision if a folder whose name differs otherwise can be created.
+            Err(err) if err.kind() == ErrorKind::Other => match create_folder(&format!("{}-control", name)) {
+                Ok(control_id) => {
+                    probe_ids.push(control_id);
+                    Ok(false)
+                },
+                Err(_) => Err(err),
+            },
+            Err(err) => Err(err),
+            Ok(other_id) if other_id == probe_ids[0] => Ok(false),
+            Ok(other_id) => {
+                probe_ids.push(other_id);
+                // The device may have accepted both folders, but only made one
+                self.backend.children(storage_id.as_ucstr())
+                    .map(|children| children.filter(|child_id| probe_ids.contains(child_id)).count() == probe_ids.len())
+            },
+        };
+
+        let probe_ids: Vec<&U16CStr> = probe_ids.iter().map(|probe_id| probe_id.as_ucstr()).collect();
+        let deletions = self.backend.delete(&probe_ids, false);
+        let case_sensitive = case_sensitive?;
+        for deletion in deletions? {
+            match deletion {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+                _ => {},
+            }
+        }
+        Ok(case_sensitive)
+    }
+
     /// Get the root object of the current device
-    pub fn root(&self) -> crate::WindowsResult<Object> {
-        self.object_by_id(unsafe{ U16CString::from_ptr_str(WPD_DEVICE_OBJECT_ID.as_ptr()) })
+    pub fn root(&self) -> Result<Object, MtpError> {
+        self.object_by_id(ObjectId::device_root())
     }
 
     /// List all functional objects for this device
-    pub fn functional_objects(&self) -> crate::WindowsResult<Vec<Object>> {
+    pub fn functional_objects(&self) -> Result<Vec<Object>, MtpError> {
         Ok(self.root()?
             .children()?
             .filter(|child| child.object_type() == ObjectType::FunctionalObject)
             .collect())
     }
 
+    /// List the storages of this device (e.g. its internal memory and its SD card), with their capacity and free space
+    pub fn storages(&self) -> Result<Vec<Storage>, MtpError> {
+        let mut storages = Vec::new();
+        for object in self.functional_objects()? {
+            // Functional objects whose category is unknown are assumed to be storages
+            let is_storage = object.properties(&[WPD_FUNCTIONAL_OBJECT_CATEGORY])?
+                .get_guid(&WPD_FUNCTIONAL_OBJECT_CATEGORY)
+                .map_or(true, |category| category == WPD_FUNCTIONAL_CATEGORY_STORAGE);
+            if is_storage {
+                storages.push(Storage::new(object)?);
+            }
+        }
+        Ok(storages)
+    }
+
     /// Get an MTP object given its MTP object ID
-    pub fn object_by_id(&self, object_id: U16CString) -> crate::WindowsResult<Object> {
+    pub fn object_by_id(&self, object_id: ObjectId) -> Result<Object, MtpError> {
         // Get the display name, type and the original filename when the device exposes it.
-        let basic_properties = self.properties(
-            &object_id,
-            &[WPD_OBJECT_NAME, WPD_OBJECT_CONTENT_TYPE, WPD_OBJECT_ORIGINAL_FILE_NAME]
-        )?;
+        let basic_properties = self.properties(&object_id, &Self::BASIC_PROPERTIES)?;
+        self.object_from_properties(object_id, &basic_properties)
+    }
+
+    /// The properties every [`Object`] is built from
+    pub(crate) const BASIC_PROPERTIES: [crate::PROPERTYKEY; 3] = [WPD_OBJECT_NAME, WPD_OBJECT_CONTENT_TYPE, WPD_OBJECT_ORIGINAL_FILE_NAME];
 
+    /// Build an object out of its [`Self::BASIC_PROPERTIES`]
+    pub(crate) fn object_from_properties(&self, object_id: ObjectId, basic_properties: &DeviceValues) -> Result<Object, MtpError> {
         let name = basic_properties.get_string(&WPD_OBJECT_NAME)?;
         let original_file_name = basic_properties.get_string(&WPD_OBJECT_ORIGINAL_FILE_NAME).ok();
         let ty_guid = basic_properties.get_guid(&WPD_OBJECT_CONTENT_TYPE)?;
@@ -64,23 +248,64 @@ impl Content {
     /// Get a list of requested metadata about an object.
     ///
     /// Example of valid properties are listed on [Microsoft's documentation](https://learn.microsoft.com/en-gb/windows/win32/wpd_sdk/object-properties).
-    pub fn properties(&self, object_id: &U16CStr, properties_to_fetch: &[crate::PROPERTYKEY]) -> crate::WindowsResult<DeviceValues> {
-        let props_to_read: IPortableDeviceKeyCollection = unsafe {
-            CoCreateInstance(
-                &PortableDeviceKeyCollection as *const GUID,
-                None,
-                CLSCTX_ALL
-            )
-        }?;
-        for prop_to_fetch in properties_to_fetch {
-            unsafe{ props_to_read.Add(prop_to_fetch as *const _)}?;
+    pub fn properties(&self, object_id: &ObjectIdRef, properties_to_fetch: &[crate::PROPERTYKEY]) -> Result<DeviceValues, MtpError> {
+        self.backend.properties(object_id.as_ucstr(), properties_to_fetch)
+    }
+
+    /// Get the usual metadata of an object (size, dates, etc.), in a single call to the device
+    ///
+    /// Use [`Self::properties`] for less usual properties.
+    pub fn metadata(&self, object_id: &ObjectIdRef) -> Result<ObjectMetadata, MtpError> {
+        ObjectMetadata::from_values(&self.properties(object_id, &ObjectMetadata::PROPERTIES)?)
+    }
+
+    /// Write some properties of an object
+    ///
+    /// Devices may accept some properties and reject others: this returns an outcome for every property, in the same order as `values`.
+    /// An error is only returned if the operation failed as a whole (e.g. the object does not exist).
+    pub fn set_properties(&self, object_id: &ObjectIdRef, values: &DeviceValues) -> Result<Vec<PropertyOutcome>, MtpError> {
+        let results = self.backend.set_properties(object_id.as_ucstr(), values)?;
+        if results.len() != values.len() {
+            return Err(MtpError::Backend(format!("Expected {} outcomes, got {}", values.len(), results.len())));
         }
+        Ok(values.iter()
+            .zip(results)
+            .map(|((key, _), result)| PropertyOutcome{ key: *key, result })
+            .collect())
+    }
+
+    /// Delete many objects, in as few device calls as the backend allows
+    ///
+    /// Folders are only deleted if `recursive` is `true`.<br/>
+    /// This returns an outcome for every object, in the same order as `object_ids`, so that partial failures can be told apart.
+    /// An error is only returned if the operation failed as a whole (e.g. the device has been disconnected).
+    pub fn delete_many(&self, object_ids: &[&ObjectIdRef], recursive: bool) -> Result<Vec<ObjectOutcome>, MtpError> {
+        let results = self.backend.delete(&platform_ids(object_ids), recursive)?;
+        outcomes(object_ids, results)
+    }
+
+    /// Move many objects into a folder, in as few device calls as the backend allows
+    ///
+    /// See [`Self::delete_many`] about the returned outcomes.
+    pub fn move_many(&self, object_ids: &[&ObjectIdRef], destination_folder_id: &ObjectIdRef) -> Result<Vec<ObjectOutcome>, MtpError> {
+        // Objects (and their descendants) may move to another storage
+        self.object_storages.borrow_mut().clear();
+        let results = self.backend.move_objects(&platform_ids(object_ids), destination_folder_id.as_ucstr())?;
+        outcomes(object_ids, results)
+    }
+}
+
+fn platform_ids<'a>(object_ids: &[&'a ObjectIdRef]) -> Vec<&'a U16CStr> {
+    object_ids.iter().map(|object_id| object_id.as_ucstr()).collect()
+}
 
-        let properties = unsafe{ self.com_content.Properties() }?;
-        unsafe{ properties.GetValues(
-                PCWSTR::from_raw(object_id.as_ptr()),
-                &props_to_read,
-            )
-        }.map(|dv| DeviceValues::new(dv))
+/// Every object must have an outcome, backends that do not tell them all are wrong
+fn outcomes(object_ids: &[&ObjectIdRef], results: Vec<Result<(), MtpError>>) -> Result<Vec<ObjectOutcome>, MtpError> {
+    if results.len() != object_ids.len() {
+        return Err(MtpError::Backend(format!("Expected {} outcomes, got {}", object_ids.len(), results.len())));
     }
+    Ok(object_ids.iter()
+        .zip(results)
+        .map(|(object_id, result)| ObjectOutcome{ object_id: (*object_id).to_owned(), result })
+        .collect())
 }
@@ -1,27 +1,21 @@
 use std::ffi::OsStr;
 use std::time::SystemTime;
 
-use windows::core::{GUID, PCWSTR};
-use windows::Win32::Foundation::E_FAIL;
-use windows::Win32::System::Com::{CoCreateInstance, CLSCTX_ALL, CoTaskMemFree};
-use windows::Win32::System::Variant::VT_DATE;
-use windows::Win32::Storage::FileSystem::SECURITY_IMPERSONATION;
+use windows::core::GUID;
 use windows::Win32::Devices::PortableDevices::{
-    PortableDeviceValues, IPortableDeviceValues,
-    WPD_CLIENT_NAME,
-    WPD_CLIENT_MAJOR_VERSION,
-    WPD_CLIENT_MINOR_VERSION,
-    WPD_CLIENT_REVISION,
-    WPD_CLIENT_SECURITY_QUALITY_OF_SERVICE,
     WPD_OBJECT_NAME,
     WPD_OBJECT_PARENT_ID,
     WPD_OBJECT_CONTENT_TYPE,
     WPD_CONTENT_TYPE_FOLDER,
     WPD_OBJECT_SIZE,
     WPD_OBJECT_ORIGINAL_FILE_NAME,
+    WPD_OBJECT_FORMAT,
 };
 use widestring::{U16CStr, U16CString};
 
+use crate::error::MtpError;
+use crate::object::ObjectFormat;
+
 /// Identifies the current application
 ///
 /// This is required by the Windows drivers
@@ -51,144 +45,208 @@ macro_rules! make_current_app_identifiers {
     };
 }
 
-/// Create a IPortableDeviceValues instance with suggestions from https://learn.microsoft.com/en-us/windows/win32/wpd_sdk/specifying-client-information
-pub(crate) fn make_values_for_open_device(current_app_identifiers: &AppIdentifiers) -> crate::WindowsResult<IPortableDeviceValues> {
-    let device_values: IPortableDeviceValues = unsafe {
-        CoCreateInstance(
-            &PortableDeviceValues as *const GUID,
-            None,
-            CLSCTX_ALL
-        )
-    }?;
-
-    // At a minimum, your application should provide a string containing its name, a major version number, a minor version number, and a revision number. These are the fields supplied by the sample application.
-    // See https://learn.microsoft.com/en-us/windows/win32/wpd_sdk/specifying-client-information
-
-    let current_app_name_wide = U16CString::from_str_truncate(&current_app_identifiers.app_name);
-    let pcwstr_current_app_name = PCWSTR::from_raw(current_app_name_wide.as_ptr());
-
-    unsafe{ device_values.SetStringValue(&WPD_CLIENT_NAME as *const _, pcwstr_current_app_name) }?;
-    unsafe{ device_values.SetUnsignedIntegerValue(&WPD_CLIENT_MAJOR_VERSION as *const _, current_app_identifiers.app_major) }?;
-    unsafe{ device_values.SetUnsignedIntegerValue(&WPD_CLIENT_MINOR_VERSION as *const _, current_app_identifiers.app_minor) }?;
-    unsafe{ device_values.SetUnsignedIntegerValue(&WPD_CLIENT_REVISION as *const _, current_app_identifiers.app_patch) }?;
-    // Some device drivers need to impersonate the caller in order to function correctly.  Since our application does not
-    // need to restrict its identity, specify SECURITY_IMPERSONATION so that we work with all devices.
-    // See https://learn.microsoft.com/en-us/windows/win32/wpd_sdk/specifying-client-information
-    unsafe{ device_values.SetUnsignedIntegerValue(&WPD_CLIENT_SECURITY_QUALITY_OF_SERVICE as *const _, SECURITY_IMPERSONATION.0) }?;
-
-    Ok(device_values)
+pub(crate) fn make_values_for_create_folder(parent_id: &U16CStr, folder_name: &OsStr) -> DeviceValues {
+    let folder_name_wide = U16CString::from_os_str_truncate(folder_name);
+
+    let mut device_values = DeviceValues::new();
+    device_values.set(WPD_OBJECT_PARENT_ID, PropertyValue::String(parent_id.to_ucstring()));
+    device_values.set(WPD_OBJECT_NAME, PropertyValue::String(folder_name_wide.clone()));
+    device_values.set(WPD_OBJECT_ORIGINAL_FILE_NAME, PropertyValue::String(folder_name_wide));
+    device_values.set(WPD_OBJECT_CONTENT_TYPE, PropertyValue::Guid(WPD_CONTENT_TYPE_FOLDER));
+    device_values
 }
 
-pub(crate) fn make_values_for_create_folder(parent_id: &U16CStr, folder_name: &OsStr) -> crate::WindowsResult<IPortableDeviceValues> {
-    let device_values: IPortableDeviceValues = unsafe {
-        CoCreateInstance(
-            &PortableDeviceValues as *const GUID,
-            None,
-            CLSCTX_ALL
-        )
-    }?;
+/// Android devices happily work without a name, content type or format, but media players and some cameras then misclassify or reject the file.
+/// Hence these are always set, as Microsoft code samples do.
+pub(crate) fn make_values_for_create_file(parent_id: &U16CStr, file_name: &OsStr, file_size: u64, format: ObjectFormat) -> DeviceValues {
+    let file_name_wide = U16CString::from_os_str_truncate(file_name);
 
-    let folder_name_wide = U16CString::from_os_str_truncate(folder_name);
-    let pcwstr_folder_name = PCWSTR::from_raw(folder_name_wide.as_ptr());
+    let mut device_values = DeviceValues::new();
+    device_values.set(WPD_OBJECT_PARENT_ID, PropertyValue::String(parent_id.to_ucstring()));
+    device_values.set(WPD_OBJECT_SIZE, PropertyValue::U64(file_size));
+    device_values.set(WPD_OBJECT_NAME, PropertyValue::String(file_name_wide.clone()));
+    device_values.set(WPD_OBJECT_ORIGINAL_FILE_NAME, PropertyValue::String(file_name_wide));
+    device_values.set(WPD_OBJECT_CONTENT_TYPE, PropertyValue::Guid(format.object_type().as_guid()));
+    device_values.set(WPD_OBJECT_FORMAT, PropertyValue::Guid(format.as_guid()));
+    device_values
+}
 
-    unsafe{ device_values.SetStringValue(&WPD_OBJECT_PARENT_ID as *const _, PCWSTR::from_raw(parent_id.as_ptr())) }?;
-    unsafe{ device_values.SetStringValue(&WPD_OBJECT_NAME as *const _, pcwstr_folder_name) }?;
-    unsafe{ device_values.SetStringValue(&WPD_OBJECT_ORIGINAL_FILE_NAME as *const _, pcwstr_folder_name) }?;
-    unsafe{ device_values.SetGuidValue(&WPD_OBJECT_CONTENT_TYPE as *const _, &WPD_CONTENT_TYPE_FOLDER as *const _) }?;
 
-    Ok(device_values)
+/// The value of a single object property
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    String(U16CString),
+    U32(u32),
+    I32(i32),
+    U64(u64),
+    F32(f32),
+    Guid(GUID),
+    Bool(bool),
+    Date(SystemTime),
 }
 
-pub(crate) fn make_values_for_create_file(parent_id: &U16CStr, file_name: &OsStr, file_size: u64) -> crate::WindowsResult<IPortableDeviceValues> {
-    let device_values: IPortableDeviceValues = unsafe {
-        CoCreateInstance(
-            &PortableDeviceValues as *const GUID,
-            None,
-            CLSCTX_ALL
-        )
-    }?;
+/// A set of object properties, keyed by their `PROPERTYKEY`.
+///
+/// This is the backend-independent counterpart of [`IPortableDeviceValues`](https://learn.microsoft.com/en-us/windows/win32/wpd_sdk/iportabledevicevalues)
+#[derive(Debug, Clone, Default)]
+pub struct DeviceValues(Vec<(crate::PROPERTYKEY, PropertyValue)>);
 
-    let file_name_wide = U16CString::from_os_str_truncate(file_name);
-    let pcwstr_file_name = PCWSTR::from_raw(file_name_wide.as_ptr());
-    unsafe{ device_values.SetStringValue(&WPD_OBJECT_PARENT_ID as *const _, PCWSTR::from_raw(parent_id.as_ptr())) }?;
-    unsafe{ device_values.SetUnsignedLargeIntegerValue(&WPD_OBJECT_SIZE as *const _, file_size) }?;
-    unsafe{ device_values.SetStringValue(&WPD_OBJECT_ORIGINAL_FILE_NAME as *const _, PCWSTR::from_raw(pcwstr_file_name.as_ptr())) }?;
-
-    // Microsoft code samples suggest we should also populate
-    // * WPD_OBJECT_NAME
-    // * WPD_OBJECT_CONTENT_TYPE
-    // * WPD_OBJECT_FORMAT
-    // But experience shows Android device happily work without these values.
-
-    Ok(device_values)
-}
+impl DeviceValues {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Set a value, replacing any previous value for the same key
+    pub fn set(&mut self, key: crate::PROPERTYKEY, value: PropertyValue) {
+        match self.0.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, existing)) => *existing = value,
+            None => self.0.push((key, value)),
+        }
+    }
 
+    /// Builder-style counterpart of [`Self::set`]
+    ///
+    /// ```
+    /// use winmtp::PortableDevices::{WPD_OBJECT_ISHIDDEN, WPD_OBJECT_DATE_MODIFIED};
+    /// use winmtp::device::device_values::{DeviceValues, PropertyValue};
+    ///
+    /// let values = DeviceValues::new()
+    ///     .with(WPD_OBJECT_ISHIDDEN, PropertyValue::Bool(true))
+    ///     .with(WPD_OBJECT_DATE_MODIFIED, PropertyValue::Date(std::time::SystemTime::now()));
+    /// assert_eq!(values.len(), 2);
+    /// ```
+    pub fn with(mut self, key: crate::PROPERTYKEY, value: PropertyValue) -> Self {
+        self.set(key, value);
+        self
+    }
 
-/// A wrapper over [`IPortableDeviceValues`](https://learn.microsoft.com/en-us/windows/win32/wpd_sdk/iportabledevicevalues)
-pub struct DeviceValues(IPortableDeviceValues);
+    /// Remove a value, and return it
+    pub fn remove(&mut self, key: &crate::PROPERTYKEY) -> Option<PropertyValue> {
+        let index = self.0.iter().position(|(k, _)| k == key)?;
+        Some(self.0.remove(index).1)
+    }
 
-impl DeviceValues {
-    pub fn new(values: IPortableDeviceValues) -> Self {
-        Self(values)
+    /// Set a string value
+    pub fn set_string(&mut self, key: crate::PROPERTYKEY, value: &U16CStr) {
+        self.set(key, PropertyValue::String(value.to_ucstring()))
+    }
+
+    /// Set a uint value
+    pub fn set_u32(&mut self, key: crate::PROPERTYKEY, value: u32) {
+        self.set(key, PropertyValue::U32(value))
+    }
+
+    /// Set an int value
+    pub fn set_i32(&mut self, key: crate::PROPERTYKEY, value: i32) {
+        self.set(key, PropertyValue::I32(value))
+    }
+
+    /// Set a u64 value
+    pub fn set_u64(&mut self, key: crate::PROPERTYKEY, value: u64) {
+        self.set(key, PropertyValue::U64(value))
+    }
+
+    /// Set a float value
+    pub fn set_f32(&mut self, key: crate::PROPERTYKEY, value: f32) {
+        self.set(key, PropertyValue::F32(value))
+    }
+
+    /// Set a GUID value
+    pub fn set_guid(&mut self, key: crate::PROPERTYKEY, value: GUID) {
+        self.set(key, PropertyValue::Guid(value))
+    }
+
+    /// Set a bool value
+    pub fn set_bool(&mut self, key: crate::PROPERTYKEY, value: bool) {
+        self.set(key, PropertyValue::Bool(value))
+    }
+
+    /// Set a DATE value
+    pub fn set_date(&mut self, key: crate::PROPERTYKEY, value: SystemTime) {
+        self.set(key, PropertyValue::Date(value))
+    }
+
+    /// Retrieve a value, whatever its type
+    pub fn get(&self, key: &crate::PROPERTYKEY) -> Option<&PropertyValue> {
+        self.0.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    /// Iterate over every (key, value) pair
+    pub fn iter(&self) -> impl Iterator<Item = &(crate::PROPERTYKEY, PropertyValue)> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
     }
 
     /// Retrieve a string value
-    pub fn get_string(&self, key: &crate::PROPERTYKEY) -> crate::WindowsResult<U16CString> {
-        let pwstr = unsafe{ self.0.GetStringValue(key as *const _) }?;
-        let result = U16CString::from_vec_truncate(unsafe{ pwstr.as_wide() });
-        unsafe{ CoTaskMemFree(Some(pwstr.as_ptr() as *const _)) };
-        Ok(result)
+    pub fn get_string(&self, key: &crate::PROPERTYKEY) -> Result<U16CString, MtpError> {
+        match self.get(key) {
+            Some(PropertyValue::String(s)) => Ok(s.clone()),
+            _ => Err(MtpError::InvalidProperty),
+        }
     }
 
     /// Retrieve a uint value
-    pub fn get_u32(&self, key: &crate::PROPERTYKEY) -> crate::WindowsResult<u32> {
-        unsafe{ self.0.GetUnsignedIntegerValue(key as *const _) }
+    pub fn get_u32(&self, key: &crate::PROPERTYKEY) -> Result<u32, MtpError> {
+        match self.get(key) {
+            Some(PropertyValue::U32(v)) => Ok(*v),
+            Some(PropertyValue::U64(v)) => u32::try_from(*v).map_err(|_| MtpError::InvalidProperty),
+            _ => Err(MtpError::InvalidProperty),
+        }
     }
 
     /// Retrieve an int value
-    pub fn get_i32(&self, key: &crate::PROPERTYKEY) -> crate::WindowsResult<i32> {
-        unsafe{ self.0.GetSignedIntegerValue(key as *const _) }
+    pub fn get_i32(&self, key: &crate::PROPERTYKEY) -> Result<i32, MtpError> {
+        match self.get(key) {
+            Some(PropertyValue::I32(v)) => Ok(*v),
+            _ => Err(MtpError::InvalidProperty),
+        }
     }
 
     /// Retrieve a u64 value
-    pub fn get_u64(&self, key: &crate::PROPERTYKEY) -> crate::WindowsResult<u64> {
-        unsafe{ self.0.GetUnsignedLargeIntegerValue(key as *const _) }
+    pub fn get_u64(&self, key: &crate::PROPERTYKEY) -> Result<u64, MtpError> {
+        match self.get(key) {
+            Some(PropertyValue::U64(v)) => Ok(*v),
+            Some(PropertyValue::U32(v)) => Ok(*v as u64),
+            _ => Err(MtpError::InvalidProperty),
+        }
     }
 
     /// Retrieve a float value
-    pub fn get_f32(&self, key: &crate::PROPERTYKEY) -> crate::WindowsResult<f32> {
-        unsafe{ self.0.GetFloatValue(key as *const _) }
+    pub fn get_f32(&self, key: &crate::PROPERTYKEY) -> Result<f32, MtpError> {
+        match self.get(key) {
+            Some(PropertyValue::F32(v)) => Ok(*v),
+            _ => Err(MtpError::InvalidProperty),
+        }
     }
 
     /// Retrieve a GUID value
-    pub fn get_guid(&self, key: &crate::PROPERTYKEY) -> crate::WindowsResult<GUID> {
-        unsafe{ self.0.GetGuidValue(key as *const _) }
+    pub fn get_guid(&self, key: &crate::PROPERTYKEY) -> Result<GUID, MtpError> {
+        match self.get(key) {
+            Some(PropertyValue::Guid(v)) => Ok(*v),
+            _ => Err(MtpError::InvalidProperty),
+        }
     }
 
     /// Retrieve a bool value
-    pub fn get_bool(&self, key: &crate::PROPERTYKEY) -> crate::WindowsResult<bool> {
-        unsafe{ self.0.GetBoolValue(key as *const _) }.map(|b| b.as_bool())
+    pub fn get_bool(&self, key: &crate::PROPERTYKEY) -> Result<bool, MtpError> {
+        match self.get(key) {
+            Some(PropertyValue::Bool(v)) => Ok(*v),
+            _ => Err(MtpError::InvalidProperty),
+        }
     }
 
     /// Retrieve a DATE value
-    pub fn get_date(&self, key: &crate::PROPERTYKEY) -> crate::WindowsResult<SystemTime> {
-        const SECONDS_PER_DAY: f64 = 86_400.0;
-        const DAYS_BETWEEN_1899_AND_1970: f64 = 25_569.0;
-
-        let prop_variant =  unsafe{ self.0.GetValue(key as *const _) }?;
-
-        let inner = &unsafe { prop_variant.Anonymous.Anonymous };
-        if inner.vt != VT_DATE {
-            return Err(crate::WindowsError::from(E_FAIL));
+    pub fn get_date(&self, key: &crate::PROPERTYKEY) -> Result<SystemTime, MtpError> {
+        match self.get(key) {
+            Some(PropertyValue::Date(v)) => Ok(*v),
+            _ => Err(MtpError::InvalidProperty),
         }
-
-        let vt_date = unsafe { inner.Anonymous.date };
-
-        let days_since_unix_epoch = vt_date - DAYS_BETWEEN_1899_AND_1970;
-        let seconds_since_unix_epoch = days_since_unix_epoch * SECONDS_PER_DAY;
-
-        Ok(std::time::UNIX_EPOCH + std::time::Duration::from_secs_f64(seconds_since_unix_epoch))
     }
-
-    // TODO: we may add some more types here in the future
 }
@@ -0,0 +1,75 @@
+//! Notifications of what happens on a device
+
+use std::sync::{Arc, Mutex};
+
+use crate::object::ObjectId;
+
+/// Something that happened on a device, as reported to [`Device::subscribe`](crate::device::Device::subscribe)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceEvent {
+    /// An object has been created (e.g. a photo has just been taken). Not every backend knows its parent.
+    ObjectAdded{ id: ObjectId, parent: Option<ObjectId> },
+    ObjectRemoved{ id: ObjectId },
+    /// The properties or the content of an object have changed. This includes renames and moves.
+    ObjectUpdated{ id: ObjectId },
+    /// The device has been unplugged, or the connection to it has been lost. No event follows this one.
+    DeviceRemoved,
+    /// A storage has been added, removed or formatted, or its info (e.g. its free space) has changed. Not every backend knows which one.
+    StorageChanged{ id: Option<ObjectId> },
+}
+
+/// Where backends send the events of a device
+///
+/// Clones share the same callback. Backends may send events from another thread.
+#[derive(Clone)]
+pub struct EventSink(Arc<Mutex<dyn FnMut(DeviceEvent) + Send>>);
+
+impl EventSink {
+    pub fn new<F>(callback: F) -> Self
+    where F: FnMut(DeviceEvent) + Send + 'static
+    {
+        Self(Arc::new(Mutex::new(callback)))
+    }
+
+    pub fn send(&self, event: DeviceEvent) {
+        // A callback that panicked once is not called anymore
+        if let Ok(mut callback) = self.0.lock() {
+            callback(event)
+        }
+    }
+}
+
+impl std::fmt::Debug for EventSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventSink").finish_non_exhaustive()
+    }
+}
+
+/// Keeps events coming, until it is dropped
+#[must_use = "events stop as soon as the subscription is dropped"]
+pub struct Subscription {
+    unsubscribe: Option<Box<dyn FnOnce()>>,
+}
+
+impl Subscription {
+    /// Create a subscription. This is meant to be called by [`crate::backend::ContentBackend`]s, with what stops sending events.
+    pub fn new<F>(unsubscribe: F) -> Self
+    where F: FnOnce() + 'static
+    {
+        Self{ unsubscribe: Some(Box::new(unsubscribe)) }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(unsubscribe) = self.unsubscribe.take() {
+            unsubscribe()
+        }
+    }
+}
+
+impl std::fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Subscription").finish_non_exhaustive()
+    }
+}
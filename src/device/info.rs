@@ -0,0 +1,119 @@
+//! Info about a device itself (rather than about its content)
+
+use windows::Win32::Devices::PortableDevices::{
+    WPD_DEVICE_MANUFACTURER, WPD_DEVICE_MODEL, WPD_DEVICE_SERIAL_NUMBER, WPD_DEVICE_FIRMWARE_VERSION,
+    WPD_DEVICE_PROTOCOL, WPD_DEVICE_TYPE, WPD_DEVICE_POWER_SOURCE, WPD_DEVICE_POWER_LEVEL,
+};
+
+use crate::device::device_values::DeviceValues;
+
+/// What a device is, as it tells it
+///
+/// There is no dedicated type for e-readers: they usually are [`DeviceType::Generic`] or [`DeviceType::MediaPlayer`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    Generic,
+    Camera,
+    MediaPlayer,
+    Phone,
+    Video,
+    PersonalInformationManager,
+    AudioRecorder,
+}
+
+impl DeviceType {
+    /// WPD and MTP use the same values
+    pub fn from_u32(value: u32) -> Self {
+        match value {
+            1 => DeviceType::Camera,
+            2 => DeviceType::MediaPlayer,
+            3 => DeviceType::Phone,
+            4 => DeviceType::Video,
+            5 => DeviceType::PersonalInformationManager,
+            6 => DeviceType::AudioRecorder,
+            _ => DeviceType::Generic,
+        }
+    }
+
+    pub fn as_u32(&self) -> u32 {
+        match self {
+            DeviceType::Generic => 0,
+            DeviceType::Camera => 1,
+            DeviceType::MediaPlayer => 2,
+            DeviceType::Phone => 3,
+            DeviceType::Video => 4,
+            DeviceType::PersonalInformationManager => 5,
+            DeviceType::AudioRecorder => 6,
+        }
+    }
+}
+
+/// What a device is currently powered by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerSource {
+    Battery,
+    /// E.g. the USB cable
+    External,
+}
+
+impl PowerSource {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(PowerSource::Battery),
+            1 => Some(PowerSource::External),
+            _ => None,
+        }
+    }
+}
+
+/// Info about a device, as returned by [`Device::info`](crate::device::Device::info)
+///
+/// This is a snapshot: e.g. the battery level is not updated afterwards.<br/>
+/// Fields are `None` when the device does not tell them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    /// This tells physical devices apart, even those of the same model
+    pub serial_number: Option<String>,
+    pub firmware_version: Option<String>,
+    /// E.g. `"MTP: 1.00"`
+    pub protocol: Option<String>,
+    pub device_type: Option<DeviceType>,
+    pub power_source: Option<PowerSource>,
+    /// The battery level, in percents
+    pub battery_level: Option<u32>,
+}
+
+impl DeviceInfo {
+    /// The properties of the device object [`Device::info`](crate::device::Device::info) reads
+    pub const PROPERTIES: [crate::PROPERTYKEY; 8] = [
+        WPD_DEVICE_MANUFACTURER,
+        WPD_DEVICE_MODEL,
+        WPD_DEVICE_SERIAL_NUMBER,
+        WPD_DEVICE_FIRMWARE_VERSION,
+        WPD_DEVICE_PROTOCOL,
+        WPD_DEVICE_TYPE,
+        WPD_DEVICE_POWER_SOURCE,
+        WPD_DEVICE_POWER_LEVEL,
+    ];
+
+    pub(crate) fn from_values(values: &DeviceValues) -> Self {
+        let string = |key| values.get_string(key).ok().filter(|value| !value.is_empty()).map(|value| value.to_string_lossy());
+        Self{
+            manufacturer: string(&WPD_DEVICE_MANUFACTURER),
+            model: string(&WPD_DEVICE_MODEL),
+            serial_number: string(&WPD_DEVICE_SERIAL_NUMBER),
+            firmware_version: string(&WPD_DEVICE_FIRMWARE_VERSION),
+            protocol: string(&WPD_DEVICE_PROTOCOL),
+            device_type: values.get_u32(&WPD_DEVICE_TYPE).ok().map(DeviceType::from_u32),
+            power_source: values.get_u32(&WPD_DEVICE_POWER_SOURCE).ok().and_then(PowerSource::from_u32),
+            battery_level: values.get_u32(&WPD_DEVICE_POWER_LEVEL).ok().map(|level| level.min(100)),
+        }
+    }
+
+    /// Whether the battery level is known to be below `percent`. Devices that do not tell, or that are not on battery, are assumed to be fine.
+    pub fn is_battery_below(&self, percent: u32) -> bool {
+        self.power_source != Some(PowerSource::External) && self.battery_level.is_some_and(|level| level < percent)
+    }
+}
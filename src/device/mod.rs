@@ -1,14 +1,29 @@
-use windows::Win32::System::Com::{CoCreateInstance, CLSCTX_ALL};
-use windows::core::{GUID, PCWSTR} ;
-use windows::Win32::Devices::PortableDevices::{PortableDeviceFTM, IPortableDevice};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver};
+
 use widestring::U16CString;
 
+use crate::backend::{DeviceBackend, OpenedDeviceBackend};
 use crate::device::device_values::AppIdentifiers;
+use crate::error::MtpError;
+use crate::object::ObjectId;
 
 pub mod device_values;
 
 mod content;
-pub use content::Content;
+pub use content::{Content, ObjectOutcome, PropertyOutcome};
+
+mod events;
+pub use events::{DeviceEvent, EventSink, Subscription};
+
+mod storage;
+pub use storage::{Storage, StorageAccess, StorageType};
+
+mod info;
+pub use info::{DeviceInfo, DeviceType, PowerSource};
+
+mod capabilities;
+pub use capabilities::{AllowedValues, Capabilities, FunctionalCategory, PropertyDescription};
 
 /// Basic info about an MTP device
 ///
@@ -17,15 +32,17 @@ pub use content::Content;
 pub struct BasicDevice {
     device_id: U16CString,
     friendly_name: String,
+    backend: Rc<dyn DeviceBackend>,
 }
 
 impl BasicDevice {
-    pub(crate) fn new(device_id: U16CString, friendly_name: String) -> Self {
-        Self{ device_id, friendly_name }
+    /// Create a device. This is meant to be called by [`crate::backend::ProviderBackend`]s.
+    pub fn new(device_id: U16CString, friendly_name: String, backend: Rc<dyn DeviceBackend>) -> Self {
+        Self{ device_id, friendly_name, backend }
     }
 
     pub fn device_id(&self) -> String {
-        self.device_id.to_string_lossy() // We trust Windows for not providing invalid UTF-16 characters
+        self.device_id.to_string_lossy() // We trust backends for not providing invalid UTF-16 characters
     }
 
     pub fn friendly_name(&self) -> &str {
@@ -38,23 +55,13 @@ impl BasicDevice {
     /// Some devices (e.g. ones that are backed by a FAT filesystem) use case-insensitive paths. In this case, you want to set `case_sensitive` to false.
     /// Otherwise, you would often get `Err`s, e.g. when you try to create or replace a file (or folder) with a similar name but different casing.<br/>
     /// Unfortunately, the Windows API does not look to be able to give this info.
-    pub fn open(&self, app_identifiers: &AppIdentifiers, case_sensitive_fs: bool) -> crate::WindowsResult<Device> {
-        // Fill out information about your application, so the device knows
-        // who they are speaking to.
-        let device_values = device_values::make_values_for_open_device(app_identifiers)?;
-
-        let com_device: IPortableDevice = unsafe {
-            CoCreateInstance(
-                &PortableDeviceFTM as *const GUID,
-                None,
-                CLSCTX_ALL
-            )
-        }?;
-
-        unsafe { com_device.Open(PCWSTR::from_raw(self.device_id.as_ptr()), &device_values) }.unwrap();
+    /// This is only a default: storages may differ (e.g. a FAT SD card in a phone), see [`Content::set_storage_case_sensitive_fs`].
+    /// [`Content::detect_case_sensitivity`] can find it out for a given storage, at the cost of creating and deleting a couple of folders.
+    pub fn open(&self, app_identifiers: &AppIdentifiers, case_sensitive_fs: bool) -> Result<Device, MtpError> {
+        let backend = self.backend.open(app_identifiers)?;
 
         Ok(Device{
-            com_device,
+            backend,
             case_sensitive_fs,
         })
     }
@@ -62,20 +69,81 @@ impl BasicDevice {
 
 /// An MTP device that as been opened
 pub struct Device {
-    com_device: IPortableDevice,
+    backend: Box<dyn OpenedDeviceBackend>,
     case_sensitive_fs: bool,
 }
 
 impl Device {
-    /// Returns the underlying COM object
+    /// Returns the underlying COM object, if this device is handled by the [WPD backend](crate::backend::wpd)
     ///
     /// This is useful in case you want to call a function that has no Rust wrapper (yet?) in this crate
-    pub fn raw_device(&self) -> &IPortableDevice {
-        &self.com_device
+    #[cfg(windows)]
+    pub fn raw_device(&self) -> Option<&windows::Win32::Devices::PortableDevices::IPortableDevice> {
+        self.backend
+            .as_any()
+            .downcast_ref::<crate::backend::wpd::WpdOpenedDevice>()
+            .map(|device| device.com_object())
+    }
+
+    pub fn content(&self) -> Result<Content, MtpError> {
+        let content_backend = self.backend.content()?;
+        Ok(Content::new(content_backend, self.case_sensitive_fs))
+    }
+
+    /// Get what this device supports: commands, content types, formats, properties...
+    ///
+    /// This returns an error if the backend of this device cannot tell.
+    pub fn capabilities(&self) -> Result<Capabilities, MtpError> {
+        Ok(Capabilities::new(self.backend.capabilities()?))
+    }
+
+    /// Read the info of this device: its manufacturer, model, serial number, battery level...
+    ///
+    /// ```no_run
+    /// # let provider = winmtp::Provider::new().unwrap();
+    /// # let basic_device = &provider.enumerate_devices().unwrap()[0];
+    /// # let app_identifiers = winmtp::make_current_app_identifiers!();
+    /// let device = basic_device.open(&app_identifiers, false).unwrap();
+    /// let info = device.info().unwrap();
+    /// if info.is_battery_below(20) {
+    ///     println!("Please charge your {} first", info.model.unwrap_or_default());
+    /// }
+    /// ```
+    pub fn info(&self) -> Result<DeviceInfo, MtpError> {
+        let values = self.backend.content()?.properties(ObjectId::device_root().as_ucstr(), &DeviceInfo::PROPERTIES)?;
+        Ok(DeviceInfo::from_values(&values))
+    }
+
+    /// Have `callback` called with every event of this device (objects added, removed or updated, device removed...), until the returned guard is dropped.
+    ///
+    /// `callback` may be called from another thread, hence the `Send` bound. It should return quickly, as it may delay the next events.<br/>
+    /// This returns an error if the backend of this device does not report events.
+    ///
+    /// ```no_run
+    /// use winmtp::device::DeviceEvent;
+    /// # let provider = winmtp::Provider::new().unwrap();
+    /// # let basic_device = &provider.enumerate_devices().unwrap()[0];
+    /// # let app_identifiers = winmtp::make_current_app_identifiers!();
+    /// let device = basic_device.open(&app_identifiers, false).unwrap();
+    /// let _subscription = device.subscribe(|event| {
+    ///     if let DeviceEvent::ObjectAdded{ id, .. } = event {
+    ///         println!("New object {}", id);
+    ///     }
+    /// }).unwrap();
+    /// ```
+    pub fn subscribe<F>(&self, callback: F) -> Result<Subscription, MtpError>
+    where F: FnMut(DeviceEvent) + Send + 'static
+    {
+        self.backend.content()?.subscribe(EventSink::new(callback))
     }
 
-    pub fn content(&self) -> crate::WindowsResult<Content> {
-        let com_content = unsafe { self.com_device.Content() }?;
-        Ok(Content::new(com_content, self.case_sensitive_fs))
+    /// Like [`Self::subscribe`], but events are sent to a channel
+    pub fn subscribe_channel(&self) -> Result<(Subscription, Receiver<DeviceEvent>), MtpError> {
+        let (sender, receiver) = mpsc::channel();
+        let subscription = self.subscribe(move |event| {
+            // The receiver may have been dropped before the subscription
+            let _ = sender.send(event);
+        })?;
+        Ok((subscription, receiver))
     }
 }
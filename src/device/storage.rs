@@ -0,0 +1,167 @@
+//! Storages of a device, and what they are made of
+
+use windows::Win32::Devices::PortableDevices::{
+    WPD_STORAGE_TYPE, WPD_STORAGE_FILE_SYSTEM_TYPE, WPD_STORAGE_ACCESS_CAPABILITY, WPD_STORAGE_CAPACITY,
+    WPD_STORAGE_FREE_SPACE_IN_BYTES, WPD_STORAGE_FREE_SPACE_IN_OBJECTS, WPD_STORAGE_DESCRIPTION, WPD_STORAGE_SERIAL_NUMBER,
+};
+use widestring::U16CStr;
+
+use crate::device::device_values::DeviceValues;
+use crate::error::MtpError;
+use crate::object::{Object, ObjectIdRef};
+
+/// The kind of medium of a storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageType {
+    Undefined,
+    FixedRom,
+    RemovableRom,
+    /// E.g. the internal memory of a phone
+    FixedRam,
+    /// E.g. an SD card
+    RemovableRam,
+}
+
+impl StorageType {
+    /// WPD and MTP use the same values
+    pub fn from_u32(value: u32) -> Self {
+        match value {
+            1 => StorageType::FixedRom,
+            2 => StorageType::RemovableRom,
+            3 => StorageType::FixedRam,
+            4 => StorageType::RemovableRam,
+            _ => StorageType::Undefined,
+        }
+    }
+
+    pub fn is_removable(&self) -> bool {
+        matches!(self, StorageType::RemovableRom | StorageType::RemovableRam)
+    }
+}
+
+/// What can be done to the content of a storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageAccess {
+    ReadWrite,
+    ReadOnlyWithoutObjectDeletion,
+    /// Objects cannot be created nor modified, but they can be deleted
+    ReadOnlyWithObjectDeletion,
+}
+
+impl StorageAccess {
+    /// WPD and MTP use the same values
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(StorageAccess::ReadWrite),
+            1 => Some(StorageAccess::ReadOnlyWithoutObjectDeletion),
+            2 => Some(StorageAccess::ReadOnlyWithObjectDeletion),
+            _ => None,
+        }
+    }
+}
+
+/// A storage of a device (e.g. its internal memory, or an SD card), as listed by [`Content::storages`](crate::device::Content::storages)
+///
+/// This is a snapshot: free space is not updated as objects are added, see [`Self::refresh`].<br/>
+/// Fields are `None` when the device does not tell them.
+#[derive(Debug, Clone)]
+pub struct Storage {
+    object: Object,
+    pub storage_type: StorageType,
+    /// E.g. `"FAT32"` for WPD devices, or `"Generic hierarchical"` for devices that are reached through MTP
+    pub filesystem_type: Option<String>,
+    pub access: Option<StorageAccess>,
+    /// The total size, in bytes
+    pub capacity: Option<u64>,
+    pub free_bytes: Option<u64>,
+    /// How many objects can still be created. Most devices do not limit this.
+    pub free_objects: Option<u64>,
+    pub description: Option<String>,
+    pub serial_number: Option<String>,
+}
+
+impl Storage {
+    /// The properties [`Self::refresh`] reads
+    pub const PROPERTIES: [crate::PROPERTYKEY; 8] = [
+        WPD_STORAGE_TYPE,
+        WPD_STORAGE_FILE_SYSTEM_TYPE,
+        WPD_STORAGE_ACCESS_CAPABILITY,
+        WPD_STORAGE_CAPACITY,
+        WPD_STORAGE_FREE_SPACE_IN_BYTES,
+        WPD_STORAGE_FREE_SPACE_IN_OBJECTS,
+        WPD_STORAGE_DESCRIPTION,
+        WPD_STORAGE_SERIAL_NUMBER,
+    ];
+
+    pub(crate) fn new(object: Object) -> Result<Self, MtpError> {
+        let values = object.properties(&Self::PROPERTIES)?;
+        Ok(Self::from_values(object, &values))
+    }
+
+    fn from_values(object: Object, values: &DeviceValues) -> Self {
+        let string = |key| values.get_string(key).ok().filter(|value| !value.is_empty()).map(|value| value.to_string_lossy());
+        Self{
+            object,
+            storage_type: values.get_u32(&WPD_STORAGE_TYPE).map(StorageType::from_u32).unwrap_or(StorageType::Undefined),
+            filesystem_type: string(&WPD_STORAGE_FILE_SYSTEM_TYPE),
+            access: values.get_u32(&WPD_STORAGE_ACCESS_CAPABILITY).ok().and_then(StorageAccess::from_u32),
+            capacity: values.get_u64(&WPD_STORAGE_CAPACITY).ok(),
+            free_bytes: values.get_u64(&WPD_STORAGE_FREE_SPACE_IN_BYTES).ok(),
+            free_objects: values.get_u64(&WPD_STORAGE_FREE_SPACE_IN_OBJECTS).ok(),
+            description: string(&WPD_STORAGE_DESCRIPTION),
+            serial_number: string(&WPD_STORAGE_SERIAL_NUMBER),
+        }
+    }
+
+    /// The functional object of this storage, e.g. to browse its content
+    pub fn object(&self) -> &Object {
+        &self.object
+    }
+
+    pub fn id(&self) -> &ObjectIdRef {
+        self.object.id()
+    }
+
+    pub fn name(&self) -> &U16CStr {
+        self.object.name()
+    }
+
+    /// Whether objects cannot be created nor modified in this storage. Storages that do not tell are assumed to be writable.
+    pub fn is_read_only(&self) -> bool {
+        matches!(self.access, Some(StorageAccess::ReadOnlyWithoutObjectDeletion | StorageAccess::ReadOnlyWithObjectDeletion))
+    }
+
+    /// Whether paths are case-sensitive on this storage, as far as its filesystem type tells (e.g. they are not on FAT filesystems)
+    ///
+    /// This is `None` when the filesystem type is unknown, or is not an actual filesystem, like the `"Generic hierarchical"` of MTP devices.
+    /// See [`Content::detect_case_sensitivity`](crate::device::Content::detect_case_sensitivity) to find out anyway.
+    pub fn case_sensitivity_hint(&self) -> Option<bool> {
+        match self.filesystem_type.as_deref()?.to_ascii_lowercase().as_str() {
+            "fat" | "fat12" | "fat16" | "fat32" | "vfat" | "exfat" | "ntfs" => Some(false),
+            "ext2" | "ext3" | "ext4" | "f2fs" | "btrfs" | "xfs" => Some(true),
+            _ => None,
+        }
+    }
+
+    /// Whether paths are case-sensitive on this storage (see [`Content::case_sensitive_fs_for`](crate::device::Content::case_sensitive_fs_for))
+    pub fn case_sensitive_fs(&self) -> Result<bool, MtpError> {
+        self.object.case_sensitive_fs()
+    }
+
+    /// Override whether paths are case-sensitive on this storage (see [`Content::set_storage_case_sensitive_fs`](crate::device::Content::set_storage_case_sensitive_fs))
+    pub fn set_case_sensitive_fs(&self, case_sensitive: bool) {
+        self.object.device_content().set_storage_case_sensitive_fs(self.id(), case_sensitive);
+    }
+
+    /// Whether a file of `size` bytes would fit. Storages that do not tell their free space are assumed to have enough.
+    pub fn has_room_for(&self, size: u64) -> bool {
+        self.free_bytes.is_none_or(|free_bytes| size <= free_bytes)
+    }
+
+    /// Read the info of this storage again, e.g. to get its current free space
+    pub fn refresh(&mut self) -> Result<(), MtpError> {
+        let values = self.object.properties(&Self::PROPERTIES)?;
+        *self = Self::from_values(self.object.clone(), &values);
+        Ok(())
+    }
+}
@@ -1,51 +1,254 @@
-#[derive(thiserror::Error, Debug)]
-pub enum MtpError {
-    #[error("Windows API error ({0})")]
-    Windows(#[from] crate::WindowsError),
-    #[error("Incoherent results from successive calls to Windows API")]
-    ChangedConditions,
-    #[error("Invalid UTF-16 string")]
-    Utf16Error(#[from] std::string::FromUtf16Error),
-}
+//! Errors returned by this crate
+//!
+//! Every fallible function returns an [`MtpError`]. Whatever the backend that raised it, an error can be classified with [`MtpError::kind`]
+//! (e.g. to tell a disconnected device from a missing file), and the code reported by the underlying API is kept (see [`MtpError::raw_code`]).
 
-#[derive(thiserror::Error, Debug)]
-pub enum ItemByPathError {
-    #[error("Windows API error ({0})")]
-    Windows(#[from] crate::WindowsError),
-    #[error("Path not found")]
+use std::io;
+
+use windows::core::HRESULT;
+use windows::Win32::Foundation::{
+    E_ABORT, E_ACCESSDENIED, E_INVALIDARG, E_NOTIMPL, E_POINTER, RPC_E_DISCONNECTED,
+    ERROR_ALREADY_EXISTS, ERROR_BAD_ARGUMENTS, ERROR_BUSY, ERROR_CANCELLED, ERROR_DEVICE_NOT_CONNECTED, ERROR_DEVICE_REMOVED,
+    ERROR_DEV_NOT_EXIST, ERROR_DISK_FULL, ERROR_FILE_EXISTS, ERROR_FILE_NOT_FOUND, ERROR_GEN_FAILURE, ERROR_HANDLE_DISK_FULL,
+    ERROR_INVALID_NAME, ERROR_NOT_FOUND, ERROR_NOT_READY, ERROR_NOT_SUPPORTED, ERROR_OPERATION_ABORTED, ERROR_PATH_NOT_FOUND,
+    ERROR_SHARING_VIOLATION, ERROR_TIMEOUT, ERROR_WRITE_PROTECT,
+};
+use windows::Win32::Devices::PortableDevices::{E_WPD_DEVICE_IS_HUNG, E_WPD_DEVICE_NOT_OPEN};
+
+use crate::protocol::codes::ResponseCode;
+
+/// What went wrong, regardless of the backend that reported it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The device has been disconnected, or cannot be reached anymore
+    DeviceGone,
+    AccessDenied,
     NotFound,
-    #[error("Got an absolute path, expected a relative path")]
-    AbsolutePath,
+    AlreadyExists,
+    StorageFull,
+    /// The device is busy with something else. Retrying later may help.
+    Busy,
+    Unsupported,
+    InvalidArgument,
+    Cancelled,
+    /// Anything else (e.g. a protocol violation, or an error this crate does not know how to classify)
+    Other,
 }
 
-#[derive(thiserror::Error, Debug)]
-pub enum OpenStreamError {
-    #[error("Windows API error ({0})")]
-    Windows(#[from] crate::WindowsError),
-    #[error("MTP API did not return any stream")] // Will probably never happen, as a Windows error would be raised before. But we never know
-    UnableToCreate,
+/// HRESULTs returned by WPD, and what they mean
+const HRESULT_KINDS: &[(HRESULT, ErrorKind)] = &[
+    (ERROR_DEVICE_NOT_CONNECTED.to_hresult(), ErrorKind::DeviceGone),
+    (ERROR_DEVICE_REMOVED.to_hresult(), ErrorKind::DeviceGone),
+    (ERROR_DEV_NOT_EXIST.to_hresult(), ErrorKind::DeviceGone),
+    (ERROR_NOT_READY.to_hresult(), ErrorKind::DeviceGone),
+    // This is what WPD usually reports when the USB cable has been unplugged
+    (ERROR_GEN_FAILURE.to_hresult(), ErrorKind::DeviceGone),
+    (RPC_E_DISCONNECTED, ErrorKind::DeviceGone),
+    (E_WPD_DEVICE_NOT_OPEN, ErrorKind::DeviceGone),
+    (E_ACCESSDENIED, ErrorKind::AccessDenied),
+    (ERROR_WRITE_PROTECT.to_hresult(), ErrorKind::AccessDenied),
+    (ERROR_FILE_NOT_FOUND.to_hresult(), ErrorKind::NotFound),
+    (ERROR_PATH_NOT_FOUND.to_hresult(), ErrorKind::NotFound),
+    (ERROR_NOT_FOUND.to_hresult(), ErrorKind::NotFound),
+    (ERROR_ALREADY_EXISTS.to_hresult(), ErrorKind::AlreadyExists),
+    (ERROR_FILE_EXISTS.to_hresult(), ErrorKind::AlreadyExists),
+    (ERROR_DISK_FULL.to_hresult(), ErrorKind::StorageFull),
+    (ERROR_HANDLE_DISK_FULL.to_hresult(), ErrorKind::StorageFull),
+    (ERROR_BUSY.to_hresult(), ErrorKind::Busy),
+    (ERROR_SHARING_VIOLATION.to_hresult(), ErrorKind::Busy),
+    (ERROR_TIMEOUT.to_hresult(), ErrorKind::Busy),
+    (E_WPD_DEVICE_IS_HUNG, ErrorKind::Busy),
+    (E_NOTIMPL, ErrorKind::Unsupported),
+    (ERROR_NOT_SUPPORTED.to_hresult(), ErrorKind::Unsupported),
+    // Same value as HRESULT_FROM_WIN32(ERROR_INVALID_PARAMETER)
+    (E_INVALIDARG, ErrorKind::InvalidArgument),
+    (E_POINTER, ErrorKind::InvalidArgument),
+    (ERROR_BAD_ARGUMENTS.to_hresult(), ErrorKind::InvalidArgument),
+    (ERROR_INVALID_NAME.to_hresult(), ErrorKind::InvalidArgument),
+    (ERROR_CANCELLED.to_hresult(), ErrorKind::Cancelled),
+    (ERROR_OPERATION_ABORTED.to_hresult(), ErrorKind::Cancelled),
+    (E_ABORT, ErrorKind::Cancelled),
+];
+
+impl ErrorKind {
+    /// Classify an HRESULT, as returned by Windows APIs
+    pub fn from_hresult(hresult: i32) -> Self {
+        HRESULT_KINDS.iter()
+            .find(|(known, _)| known.0 == hresult)
+            .map_or(Self::Other, |(_, kind)| *kind)
+    }
+
+    /// Classify an MTP response code
+    pub fn from_response_code(code: ResponseCode) -> Self {
+        match code {
+            ResponseCode::StoreNotAvailable => Self::DeviceGone,
+            ResponseCode::AccessDenied | ResponseCode::StoreReadOnly | ResponseCode::ObjectWriteProtected => Self::AccessDenied,
+            ResponseCode::InvalidObjectHandle | ResponseCode::InvalidStorageId | ResponseCode::InvalidParentObject | ResponseCode::InvalidObjectReference => Self::NotFound,
+            ResponseCode::StoreFull | ResponseCode::ObjectTooLarge => Self::StorageFull,
+            ResponseCode::DeviceBusy => Self::Busy,
+            ResponseCode::OperationNotSupported | ResponseCode::ParameterNotSupported | ResponseCode::DevicePropNotSupported
+            | ResponseCode::ObjectPropNotSupported | ResponseCode::GroupNotSupported | ResponseCode::SpecificationByFormatUnsupported
+            | ResponseCode::SpecificationOfDestinationUnsupported | ResponseCode::SpecificationByGroupUnsupported
+            | ResponseCode::SpecificationByDepthUnsupported => Self::Unsupported,
+            ResponseCode::InvalidParameter | ResponseCode::InvalidDataset | ResponseCode::InvalidObjectFormatCode | ResponseCode::InvalidCodeFormat
+            | ResponseCode::InvalidObjectPropCode | ResponseCode::InvalidObjectPropFormat | ResponseCode::InvalidObjectPropValue
+            | ResponseCode::InvalidDevicePropFormat | ResponseCode::InvalidDevicePropValue | ResponseCode::NoValidObjectInfo => Self::InvalidArgument,
+            ResponseCode::TransactionCancelled => Self::Cancelled,
+            _ => Self::Other,
+        }
+    }
+
+    /// Classify an I/O error, e.g. from a local file, or from the connection to the device
+    ///
+    /// The end of a stream is not deemed to be a disconnection, as it may be a truncated local file: see [`MtpError::from_connection`].
+    pub fn from_io_error(err: &io::Error) -> Self {
+        #[cfg(target_os = "linux")]
+        if let Some(libc::ENODEV | libc::ESHUTDOWN) = err.raw_os_error() {
+            return Self::DeviceGone;
+        }
+        #[cfg(windows)]
+        if let Some(code) = err.raw_os_error() {
+            let kind = Self::from_hresult(HRESULT::from_win32(code as u32).0);
+            if kind != Self::Other {
+                return kind;
+            }
+        }
+
+        match err.kind() {
+            io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::NotConnected | io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable => Self::DeviceGone,
+            io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem => Self::AccessDenied,
+            io::ErrorKind::NotFound => Self::NotFound,
+            io::ErrorKind::AlreadyExists => Self::AlreadyExists,
+            io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded | io::ErrorKind::FileTooLarge => Self::StorageFull,
+            io::ErrorKind::ResourceBusy | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Self::Busy,
+            io::ErrorKind::Unsupported => Self::Unsupported,
+            io::ErrorKind::InvalidInput | io::ErrorKind::InvalidFilename | io::ErrorKind::IsADirectory | io::ErrorKind::NotADirectory
+            | io::ErrorKind::DirectoryNotEmpty => Self::InvalidArgument,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// The code an error has been reported with, by the API or the device that raised it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RawErrorCode {
+    /// A Windows API error
+    HResult(i32),
+    /// An MTP response code sent by the device
+    Response(ResponseCode),
+    /// An OS error (`errno` on Unix)
+    Os(i32),
 }
 
 #[derive(thiserror::Error, Debug)]
-pub enum CreateFolderError {
+pub enum MtpError {
+    #[cfg(windows)]
     #[error("Windows API error ({0})")]
     Windows(#[from] crate::WindowsError),
-    #[error("There already is an object at this path")]
+    #[error("Incoherent results from successive calls to Windows API")]
+    ChangedConditions,
+    #[error("Invalid UTF-16 string")]
+    Utf16Error(#[from] std::string::FromUtf16Error),
+    #[error("Object not found")]
+    ObjectNotFound,
+    #[error("There already is an object with this name")]
     AlreadyExists,
+    #[error("Got an absolute path, expected a relative path")]
+    AbsolutePath,
     #[error("Path should be relative, without any parent (..) component")]
     NonRelativePath,
-}
-
-#[derive(thiserror::Error, Debug)]
-pub enum AddFileError {
-    #[error("Windows API error ({0})")]
-    Windows(#[from] crate::WindowsError),
-    #[error("std::io error ({0})")]
-    Std(#[from] std::io::Error),
     #[error("Invalid local file")]
     InvalidLocalFile,
-    #[error("A file already exists at this path")]
-    AlreadyExists,
+    #[error("Object name cannot be used as a local file name ({0})")]
+    UnsafeFileName(String),
+    #[error("Property is missing or has an unexpected type")]
+    InvalidProperty,
+    #[error("Access denied ({0})")]
+    AccessDenied(String),
+    #[error("Invalid argument ({0})")]
+    InvalidArgument(String),
+    #[error("Operation not supported by this backend")]
+    Unsupported,
+    #[error("Operation cancelled")]
+    Cancelled,
+    #[error("Transferred {actual} bytes, but the object size is {expected} bytes")]
+    SizeMismatch{ expected: u64, actual: u64 },
     #[error("MTP API did not return any stream")] // Will probably never happen, as a Windows error would be raised before. But we never know
-    UnableToCreate,
+    UnableToCreateStream,
+    #[error("Backend error ({0})")]
+    Backend(String),
+    #[error("MTP protocol error ({0})")]
+    Protocol(#[from] ProtocolError),
+    #[error("The device answered {0:?}")]
+    Response(ResponseCode),
+    #[error("I/O error ({0})")]
+    Io(#[from] io::Error),
+}
+
+impl MtpError {
+    /// What went wrong
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            #[cfg(windows)]
+            Self::Windows(err) => ErrorKind::from_hresult(err.code().0),
+            Self::ObjectNotFound => ErrorKind::NotFound,
+            Self::AlreadyExists => ErrorKind::AlreadyExists,
+            Self::AbsolutePath | Self::NonRelativePath | Self::InvalidLocalFile | Self::UnsafeFileName(_) | Self::InvalidProperty | Self::InvalidArgument(_) => ErrorKind::InvalidArgument,
+            Self::AccessDenied(_) => ErrorKind::AccessDenied,
+            Self::Unsupported => ErrorKind::Unsupported,
+            Self::Cancelled => ErrorKind::Cancelled,
+            Self::Response(code) => ErrorKind::from_response_code(*code),
+            Self::Io(err) => match err.get_ref().and_then(|inner| inner.downcast_ref::<MtpError>()) {
+                // E.g. a stream that failed because of a device error
+                Some(inner) => inner.kind(),
+                None => ErrorKind::from_io_error(err),
+            },
+            Self::ChangedConditions | Self::Utf16Error(_) | Self::SizeMismatch{ .. } | Self::UnableToCreateStream | Self::Backend(_) | Self::Protocol(_) => ErrorKind::Other,
+        }
+    }
+
+    /// An error of the connection to a device. Unlike the end of a local file, its end means that the device is gone.
+    pub fn from_connection(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => io::Error::new(io::ErrorKind::ConnectionAborted, err).into(),
+            _ => err.into(),
+        }
+    }
+
+    /// The code this error has been reported with, if any
+    pub fn raw_code(&self) -> Option<RawErrorCode> {
+        match self {
+            #[cfg(windows)]
+            Self::Windows(err) => Some(RawErrorCode::HResult(err.code().0)),
+            Self::Response(code) => Some(RawErrorCode::Response(*code)),
+            Self::Io(err) => match err.get_ref().and_then(|inner| inner.downcast_ref::<MtpError>()) {
+                Some(inner) => inner.raw_code(),
+                None => err.raw_os_error().map(RawErrorCode::Os),
+            },
+            _ => None,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    #[error("Unexpected end of data")]
+    Truncated,
+    #[error("Invalid container type {0}")]
+    InvalidContainerType(u16),
+    #[error("Invalid length {0}")]
+    InvalidLength(u32),
+    #[error("Invalid UTF-16 string")]
+    InvalidString,
+    #[error("Invalid date {0:?}")]
+    InvalidDate(String),
+    #[error("Unsupported data type {0:#06x}")]
+    UnsupportedDataType(u16),
+    #[error("Invalid property form {0:#04x}")]
+    InvalidForm(u8),
+    #[error("Invalid PTP/IP packet type {0}")]
+    InvalidPacketType(u32),
+    #[error("The responder refused the connection (reason {0:#x})")]
+    ConnectionRefused(u32),
 }
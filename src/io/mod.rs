@@ -1,19 +1,24 @@
-//! Adapters so that COM streams implement `std::io::Read` and `std::io::Write`
+//! Adapters so that backend streams implement `std::io::Read` and `std::io::Write`, and ways to follow or cancel transfers
 
-use std::ffi::c_void;
-use std::io::{Read, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 
-use windows::Win32::System::Com::{IStream, STGC_DEFAULT};
-use windows::Win32::Foundation::{S_OK, S_FALSE};
+use crate::backend::{ReadStreamBackend, WriteStreamBackend};
+use crate::error::MtpError;
 
-/// A wrapper around a COM [`IStream`](windows::Win32::System::Com::IStream) that implements `std::io::Read`
+mod progress;
+pub use progress::{Progress, ProgressObserver, CancellationToken};
+pub(crate) use progress::{copy_with_progress, AggregatedProgress};
+
+/// A stream to read data from an object, that implements `std::io::Read`
+///
+/// It also implements `std::io::Seek`, but seeking fails with [`std::io::ErrorKind::Unsupported`] on streams that cannot seek.
 pub struct ReadStream {
-    stream: IStream,
+    stream: Box<dyn ReadStreamBackend>,
     optimal_transfer_size: usize,
 }
 
 impl ReadStream {
-    pub fn new(stream: IStream, optimal_transfer_size: usize) -> Self {
+    pub fn new(stream: Box<dyn ReadStreamBackend>, optimal_transfer_size: usize) -> Self {
         Self{ stream, optimal_transfer_size }
     }
 
@@ -24,43 +29,24 @@ impl ReadStream {
 
 impl Read for ReadStream {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let requested_bytes = match buf.len().try_into() {
-            Ok(b) => b,
-            Err(_) => return Err(std::io::Error::new(std::io::ErrorKind::Other, "Requested too many bytes to read")),
-        };
-
-        let mut bytes_read: u32 = 0;
-        let res = unsafe{
-            self.stream.Read(
-                buf.as_mut_ptr() as *mut c_void,
-                requested_bytes,
-                Some(&mut bytes_read as *mut u32),
-            )
-        };
-
-        match res {
-            // regular case
-            S_OK => Ok(bytes_read as usize),
-
-            // EOF reached
-            S_FALSE => Ok(bytes_read as usize),
+        self.stream.read(buf)
+    }
+}
 
-            // Other error
-            err => Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Unexpected error {:?} when reading from a stream", err))),
-        }
+impl Seek for ReadStream {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        self.stream.seek(position)
     }
 }
 
-/// A wrapper around a COM [`IStream`](windows::Win32::System::Com::IStream) that implements `std::io::Write`
+/// A stream to write data into an object, that implements `std::io::Write`
 pub struct WriteStream {
-    stream: IStream,
+    stream: Box<dyn WriteStreamBackend>,
     optimal_transfer_size: usize,
 }
 
 impl WriteStream {
-    pub fn new(stream: IStream, optimal_transfer_size: usize) -> Self {
+    pub fn new(stream: Box<dyn WriteStreamBackend>, optimal_transfer_size: usize) -> Self {
         Self{ stream, optimal_transfer_size }
     }
 
@@ -68,42 +54,23 @@ impl WriteStream {
         self.optimal_transfer_size
     }
 
-    /// Call the COM `Commit` API
-    pub fn commit(&self) -> crate::WindowsResult<()> {
-        unsafe{ self.stream.Commit(STGC_DEFAULT) }
+    /// Finalize the transfer (this calls the COM `Commit` API on the WPD backend)
+    pub fn commit(&mut self) -> Result<(), MtpError> {
+        self.stream.commit()
+    }
+
+    /// Give up the transfer: the object will not be created on the device
+    pub fn abort(&mut self) -> Result<(), MtpError> {
+        self.stream.abort()
     }
 }
 
 impl Write for WriteStream {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        let requested_bytes = match buf.len().try_into() {
-            Ok(b) => b,
-            Err(_) => return Err(std::io::Error::new(std::io::ErrorKind::Other, "Requested too many bytes to read")),
-        };
-
-        let mut bytes_written: u32 = 0;
-        let res = unsafe{
-            self.stream.Write(
-                buf.as_ptr() as *const c_void,
-                requested_bytes,
-                Some(&mut bytes_written as *mut u32),
-            )
-        };
-
-        match res {
-            // regular case
-            S_OK => Ok(bytes_written as usize),
-
-            // Other error
-            err => Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Unexpected error {:?} when writing into a stream", err))),
-        }
+        self.stream.write(buf)
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        self.commit().map_err(|err| std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Unexpected error {:?} when flushing a stream", err)))
+        self.commit().map_err(std::io::Error::other)
     }
 }
@@ -0,0 +1,149 @@
+use std::io::{Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::error::MtpError;
+
+/// How far a transfer has gone, as reported to a [`ProgressObserver`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// The number of bytes transferred so far
+    pub bytes_done: u64,
+    /// The total number of bytes to transfer, if known
+    pub bytes_total: Option<u64>,
+    /// The time since the transfer started
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// The average transfer rate so far, in bytes per second
+    pub fn rate(&self) -> Option<f64> {
+        let seconds = self.elapsed.as_secs_f64();
+        (seconds > 0.0).then(|| self.bytes_done as f64 / seconds)
+    }
+
+    /// The estimated time until the transfer is done, based on the average rate so far
+    pub fn eta(&self) -> Option<Duration> {
+        let remaining = self.bytes_total?.saturating_sub(self.bytes_done);
+        if remaining == 0 {
+            return Some(Duration::ZERO);
+        }
+        let rate = self.rate().filter(|rate| *rate > 0.0)?;
+        Some(Duration::from_secs_f64(remaining as f64 / rate))
+    }
+
+    /// The fraction of the transfer that is done, between 0 and 1, if the total size is known
+    pub fn fraction(&self) -> Option<f64> {
+        match self.bytes_total? {
+            0 => Some(1.0),
+            total => Some(self.bytes_done as f64 / total as f64),
+        }
+    }
+}
+
+/// Something that is notified of the progress of a transfer
+///
+/// This is implemented for closures, e.g. `&mut |progress: &Progress| println!("{} bytes", progress.bytes_done)`
+pub trait ProgressObserver {
+    /// Called once when the transfer starts, then every time a chunk of data has been transferred
+    fn on_progress(&mut self, progress: &Progress);
+}
+
+impl<F: FnMut(&Progress)> ProgressObserver for F {
+    fn on_progress(&mut self, progress: &Progress) {
+        self(progress)
+    }
+}
+
+/// Used to cancel a transfer, possibly from another thread
+///
+/// Clones share the same state: cancelling one cancels them all.<br/>
+/// Cancelled transfers fail with [`MtpError::Cancelled`], and do not leave partial objects on the device.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request the cancellation of the transfers that use this token (or any of its clones)
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Turns the progress of every file of a bigger transfer (e.g. a folder tree) into the progress of the whole transfer
+pub(crate) struct AggregatedProgress<'a, 'o> {
+    observer: Option<&'a mut (dyn ProgressObserver + 'o)>,
+    bytes_before: u64,
+    bytes_total: u64,
+    start: Instant,
+}
+
+impl<'a, 'o> AggregatedProgress<'a, 'o> {
+    /// Also reports that the transfer is starting
+    pub(crate) fn new(observer: Option<&'a mut (dyn ProgressObserver + 'o)>, bytes_total: u64) -> Self {
+        let mut aggregated = Self{ observer, bytes_before: 0, bytes_total, start: Instant::now() };
+        aggregated.on_progress(&Progress{ bytes_done: 0, bytes_total: None, elapsed: Duration::ZERO });
+        aggregated
+    }
+
+    /// To be called once a file is done (or skipped), with its expected size
+    pub(crate) fn next_file(&mut self, size: u64) {
+        self.bytes_before += size;
+    }
+}
+
+impl ProgressObserver for AggregatedProgress<'_, '_> {
+    fn on_progress(&mut self, progress: &Progress) {
+        if let Some(observer) = self.observer.as_mut() {
+            observer.on_progress(&Progress{
+                bytes_done: self.bytes_before + progress.bytes_done,
+                bytes_total: Some(self.bytes_total),
+                elapsed: self.start.elapsed(),
+            });
+        }
+    }
+}
+
+/// Copy `reader` into `writer` chunk by chunk, reporting progress and checking for cancellation between chunks.
+///
+/// Returns the number of bytes copied.
+pub(crate) fn copy_with_progress(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    bytes_total: Option<u64>,
+    buffer_size: usize,
+    observer: &mut dyn ProgressObserver,
+    cancellation: &CancellationToken,
+) -> Result<u64, MtpError> {
+    let start = Instant::now();
+    let mut buffer = vec![0; buffer_size.max(1)];
+    let mut progress = Progress{ bytes_done: 0, bytes_total, elapsed: Duration::ZERO };
+    observer.on_progress(&progress);
+
+    loop {
+        if cancellation.is_cancelled() {
+            return Err(MtpError::Cancelled);
+        }
+        let read_bytes = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read_bytes) => read_bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        writer.write_all(&buffer[..read_bytes])?;
+
+        progress.bytes_done += read_bytes as u64;
+        progress.elapsed = start.elapsed();
+        observer.on_progress(&progress);
+    }
+
+    Ok(progress.bytes_done)
+}
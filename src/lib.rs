@@ -4,6 +4,7 @@
 //! It also provides [raw bindings over these COM APIs](https://microsoft.github.io/windows-docs-rs/doc/windows/Win32/Devices/PortableDevices/index.html).
 //!
 //! This create provides a safe Rust abstraction over this API.<br/>
+//! Every operation actually goes through a [backend](crate::backend). The WPD API is the default backend on Windows, other backends can be plugged in, so that this crate also builds (and can be tested) on other platforms.
 //!
 //! # What is MTP
 //!
@@ -24,20 +25,29 @@
 //!
 //! Some examples are provided in the `examples/` folder. The `tests/` folder also contains examples on how to use this library.
 
+pub mod backend;
 pub mod io;
 
 mod provider;
 pub use provider::Provider;
 
+mod watcher;
+pub use watcher::{DeviceChange, DeviceWatcher, WatchSink, DEFAULT_POLL_INTERVAL};
+
 pub mod device;
 pub mod object;
+pub mod protocol;
+pub mod responder;
+pub mod sync;
 pub mod utils;
 
 pub mod error;
 
 /// Re-exported from the windows-rs crate, because it is used in our public API.<br/>
+#[cfg(windows)]
 pub use windows::core::Result as WindowsResult;
 /// Re-exported from the windows-rs crate, because it is used in our public API.<br/>
+#[cfg(windows)]
 pub use windows::core::Error as WindowsError;
 /// Re-exported from the windows-rs crate, because it is used in our public API.<br/>
 pub use windows::Win32::Devices::PortableDevices;
@@ -1,32 +1,43 @@
 //! MTP object (can be a folder, a file, etc.)
 
-use std::io::{BufReader, BufWriter, Write};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, Components, Component};
 use std::iter::Peekable;
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
 
-use windows::core::{GUID, PWSTR, PCWSTR};
-use windows::Win32::System::Com::{CoCreateInstance, CoTaskMemFree, CLSCTX_ALL};
-use windows::Win32::System::Com::{IStream, STGM, STGM_READ};
-use windows::Win32::System::Com::StructuredStorage::PROPVARIANT;
 use windows::Win32::Devices::PortableDevices::{
-    PortableDevicePropVariantCollection, IPortableDeviceValues, IPortableDevicePropVariantCollection, IPortableDeviceContent,
-    PORTABLE_DEVICE_DELETE_WITH_RECURSION, PORTABLE_DEVICE_DELETE_NO_RECURSION, WPD_OBJECT_PARENT_ID, WPD_RESOURCE_DEFAULT,
+    WPD_OBJECT_PARENT_ID, WPD_OBJECT_NAME, WPD_OBJECT_ORIGINAL_FILE_NAME, WPD_OBJECT_DATE_MODIFIED, WPD_OBJECT_DATE_CREATED,
+    WPD_OBJECT_ISHIDDEN, WPD_OBJECT_SIZE,
 };
 use widestring::{U16CString, U16CStr};
 
-use crate::device::Content;
-use crate::device::device_values::{make_values_for_create_folder, make_values_for_create_file};
-use crate::error::{ItemByPathError, OpenStreamError, CreateFolderError, AddFileError};
-use crate::io::{ReadStream, WriteStream};
+use crate::backend::WriteStreamBackend;
+use crate::device::{Content, ObjectOutcome, PropertyOutcome};
+use crate::device::device_values::{DeviceValues, PropertyValue, make_values_for_create_folder, make_values_for_create_file};
+use crate::error::{MtpError, ErrorKind};
+use crate::io::{ReadStream, WriteStream, Progress, ProgressObserver, CancellationToken, copy_with_progress};
 use crate::utils::are_path_eq;
 
 mod object_id;
-pub use object_id::ObjectId;
+pub use object_id::{ObjectId, ObjectIdRef};
+
+mod object_metadata;
+pub use object_metadata::ObjectMetadata;
 
 mod object_type;
 pub use object_type::ObjectType;
 
+mod object_format;
+pub use object_format::ObjectFormat;
+
+mod pull_options;
+pub use pull_options::{OverwritePolicy, PullOptions};
+
+mod tree_transfer;
+pub use tree_transfer::{TreeOptions, TreeReport, TreeEntry, TreeOutcome, SkipReason, SymlinkPolicy};
+
 mod object_iterator;
 pub use object_iterator::ObjectIterator;
 
@@ -35,7 +46,7 @@ pub use object_iterator::ObjectIterator;
 pub struct Object {
     device_content: Content,
     /// The MTP ID of the object (e.g. "o2C")
-    id: U16CString,
+    id: ObjectId,
     /// The object display name (e.g. "PIC_001.jpg")
     name: U16CString,
     /// The original file name, as exposed by the device for file-like objects.
@@ -46,7 +57,7 @@ pub struct Object {
 impl Object {
     pub fn new(
         device_content: Content,
-        id: U16CString,
+        id: ObjectId,
         name: U16CString,
         original_file_name: Option<U16CString>,
         ty: ObjectType
@@ -58,7 +69,7 @@ impl Object {
         &self.device_content
     }
 
-    pub fn id(&self) -> &U16CStr {
+    pub fn id(&self) -> &ObjectIdRef {
         &self.id
     }
 
@@ -71,6 +82,16 @@ impl Object {
         self.original_file_name.as_deref()
     }
 
+    /// Whether paths are case-sensitive in the storage of this object (see [`Content::case_sensitive_fs_for`])
+    pub fn case_sensitive_fs(&self) -> Result<bool, MtpError> {
+        self.device_content.case_sensitive_fs_for(&self.id)
+    }
+
+    /// The case sensitivity children of this object inherit when walking down a path: every object of a storage has the same, but children of the device root are storages, that may have their own
+    fn inherited_case_sensitivity(&self, case_sensitive: bool) -> Option<bool> {
+        (self.id != ObjectId::device_root()).then_some(case_sensitive)
+    }
+
     pub fn object_type(&self) -> ObjectType {
         // TODO: lazy evaluation?
         self.ty
@@ -79,30 +100,96 @@ impl Object {
     /// Get a list of requested metadata about an object.
     ///
     /// See [`crate::device::Content::properties`].
-    pub fn properties(&self, properties_to_fetch: &[crate::PROPERTYKEY]) -> crate::WindowsResult<crate::device::device_values::DeviceValues> {
+    pub fn properties(&self, properties_to_fetch: &[crate::PROPERTYKEY]) -> Result<crate::device::device_values::DeviceValues, MtpError> {
         self.device_content.properties(&self.id, properties_to_fetch)
     }
 
-    pub fn parent_id(&self) -> crate::WindowsResult<U16CString> {
+    /// Get the usual metadata of this object (size, dates, etc.), in a single call to the device
+    ///
+    /// See [`crate::device::Content::metadata`].
+    pub fn metadata(&self) -> Result<ObjectMetadata, MtpError> {
+        self.device_content.metadata(&self.id)
+    }
+
+    /// Write some properties of this object
+    ///
+    /// See [`crate::device::Content::set_properties`] about the returned outcomes.
+    pub fn set_properties(&self, values: &DeviceValues) -> Result<Vec<PropertyOutcome>, MtpError> {
+        self.device_content.set_properties(&self.id, values)
+    }
+
+    /// Rename this object
+    ///
+    /// Both its display name and its original file name are written, some devices only accepting one of them.
+    /// The name of this `Object` is updated with the ones the device accepted.
+    pub fn rename(&mut self, new_name: &OsStr) -> Result<Vec<PropertyOutcome>, MtpError> {
+        let new_name = U16CString::from_os_str_truncate(new_name);
+        let values = DeviceValues::new()
+            .with(WPD_OBJECT_ORIGINAL_FILE_NAME, PropertyValue::String(new_name.clone()))
+            .with(WPD_OBJECT_NAME, PropertyValue::String(new_name.clone()));
+        let outcomes = self.set_properties(&values)?;
+        for outcome in outcomes.iter().filter(|outcome| outcome.is_ok()) {
+            if outcome.key == WPD_OBJECT_NAME {
+                self.name = new_name.clone();
+            } else {
+                self.original_file_name = Some(new_name.clone());
+            }
+        }
+        Ok(outcomes)
+    }
+
+    /// Set the modification date of this object
+    pub fn set_date_modified(&self, date: SystemTime) -> Result<Vec<PropertyOutcome>, MtpError> {
+        self.set_properties(&DeviceValues::new().with(WPD_OBJECT_DATE_MODIFIED, PropertyValue::Date(date)))
+    }
+
+    /// Set the creation date of this object
+    pub fn set_date_created(&self, date: SystemTime) -> Result<Vec<PropertyOutcome>, MtpError> {
+        self.set_properties(&DeviceValues::new().with(WPD_OBJECT_DATE_CREATED, PropertyValue::Date(date)))
+    }
+
+    /// Hide or unhide this object
+    pub fn set_hidden(&self, hidden: bool) -> Result<Vec<PropertyOutcome>, MtpError> {
+        self.set_properties(&DeviceValues::new().with(WPD_OBJECT_ISHIDDEN, PropertyValue::Bool(hidden)))
+    }
+
+    pub fn parent_id(&self) -> Result<ObjectId, MtpError> {
         let parent_id_props = self.device_content.properties(&self.id, &[WPD_OBJECT_PARENT_ID])?;
-        parent_id_props.get_string(&WPD_OBJECT_PARENT_ID)
+        parent_id_props.get_string(&WPD_OBJECT_PARENT_ID).map(ObjectId::from)
     }
 
     /// Returns an iterator to list every children of the current object (including sub-folders)
-    pub fn children(&self) -> crate::WindowsResult<ObjectIterator<'_>> {
-        let com_iter = unsafe{
-            self.device_content.com_object().EnumObjects(
-                0,
-                PCWSTR::from_raw(self.id.as_ptr()),
-                None,
-            )
-        }?;
+    pub fn children(&self) -> Result<ObjectIterator<'_>, MtpError> {
+        let child_ids = self.device_content.backend().children(self.id.as_ucstr())?;
+        Ok(ObjectIterator::new(&self.device_content, child_ids))
+    }
 
-        Ok(ObjectIterator::new(&self.device_content, com_iter))
+    /// List every children of the current object, along with some of their properties
+    ///
+    /// This is the same as calling [`Self::properties`] on every item of [`Self::children`], but backends can read the properties of every child at once,
+    /// e.g. MTP devices that support `GetObjectPropList` are asked for them in a single transaction.
+    pub fn children_with_properties(&self, properties_to_fetch: &[crate::PROPERTYKEY]) -> Result<Vec<(Object, DeviceValues)>, MtpError> {
+        let mut keys = Content::BASIC_PROPERTIES.to_vec();
+        keys.extend(properties_to_fetch.iter().filter(|key| !Content::BASIC_PROPERTIES.contains(key)));
+        let children = self.device_content.backend().children_properties(self.id.as_ucstr(), &keys)?;
+        Ok(children
+            .into_iter()
+            // Like `children`, skip objects that cannot be described
+            .filter_map(|(child_id, values)| {
+                let child = self.device_content.object_from_properties(child_id.into(), &values).ok()?;
+                let mut requested_values = DeviceValues::new();
+                for key in properties_to_fetch {
+                    if let Some(value) = values.get(key) {
+                        requested_values.set(*key, value.clone());
+                    }
+                }
+                Some((child, requested_values))
+            })
+            .collect())
     }
 
     /// Returns an iterator that only lists folders within this object
-    pub fn sub_folders(&self) -> crate::WindowsResult<impl Iterator<Item = Object> + '_> {
+    pub fn sub_folders(&self) -> Result<impl Iterator<Item = Object> + '_, MtpError> {
         self.children().map(|children| children.filter(|obj| obj.object_type() == ObjectType::Folder))
     }
 
@@ -111,29 +198,34 @@ impl Object {
     /// This function looks for a sub-item with the right name, then iteratively does so for the matching child.<br/>
     /// This is quite expensive. Depending on your use-cases, you may want to cache some "parent folders" that you will want to access often.<br/>
     /// Note that caching however defeats the purpose of MTP, which is supposed to _not_ use any cache, so that it guarantees there is no race between concurrent accesses to the same medium.
-    pub fn object_by_path(&self, relative_path: &Path) -> Result<Object, ItemByPathError> {
+    pub fn object_by_path(&self, relative_path: &Path) -> Result<Object, MtpError> {
         let mut comps = relative_path.components().peekable();
-        self.object_by_components(&mut comps)
+        self.object_by_components(&mut comps, None)
     }
 
-    fn object_by_components(&self, comps: &mut Peekable<Components>) -> Result<Object, ItemByPathError> {
+    /// `case_sensitive` is `None` when it is not known yet for this object
+    fn object_by_components(&self, comps: &mut Peekable<Components>, case_sensitive: Option<bool>) -> Result<Object, MtpError> {
         match comps.next() {
             Some(Component::Normal(haystack)) => {
+                let case_sensitive = match case_sensitive {
+                    Some(case_sensitive) => case_sensitive,
+                    None => self.case_sensitive_fs()?,
+                };
                 let candidate = self
                     .children()?
                     .find(|obj|
-                        are_path_eq(obj.name(), haystack, self.device_content.case_sensitive_fs())
+                        are_path_eq(obj.name(), haystack, case_sensitive)
                         || obj.original_file_name().is_some_and(|original_file_name|
-                            are_path_eq(original_file_name, haystack, self.device_content.case_sensitive_fs())
+                            are_path_eq(original_file_name, haystack, case_sensitive)
                         )
                     )
-                    .ok_or(ItemByPathError::NotFound)?;
+                    .ok_or(MtpError::ObjectNotFound)?;
 
-                object_by_components_last_stage(candidate, comps)
+                object_by_components_last_stage(candidate, comps, self.inherited_case_sensitivity(case_sensitive))
             },
 
             Some(Component::CurDir) => {
-                object_by_components_last_stage(self.clone(), comps)
+                object_by_components_last_stage(self.clone(), comps, case_sensitive)
             },
 
             Some(Component::ParentDir) => {
@@ -141,88 +233,96 @@ impl Object {
                     .device_content
                     .object_by_id(self.parent_id()?)?;
 
-                object_by_components_last_stage(candidate, comps)
+                object_by_components_last_stage(candidate, comps, None)
             }
 
             Some(Component::Prefix(_)) |
             Some(Component::RootDir) =>
-                Err(ItemByPathError::AbsolutePath),
+                Err(MtpError::AbsolutePath),
 
-            None => Err(ItemByPathError::NotFound)
+            None => Err(MtpError::ObjectNotFound)
         }
     }
 
     /// Opens a COM [`IStream`](windows::Win32::System::Com::IStream) to this object, suitable for reading.
     ///
-    /// An error will be returned if the required operation does not make sense (e.g. get a stream to a folder).
+    /// An error will be returned if the required operation does not make sense (e.g. get a stream to a folder),
+    /// or if this object is not handled by the [WPD backend](crate::backend::wpd).
     ///
     /// Also returns the optimal transfer buffer size (in bytes) for this transfer, as stated by the Microsoft API.
     ///
     /// See also [`Self::open_read_stream`].
-    pub fn open_raw_stream(&self, stream_mode: STGM) -> Result<(IStream, u32), OpenStreamError> {
-        let resources = unsafe{ self.device_content.com_object().Transfer()? };
-
-        let mut stream = None;
-        let mut optimal_transfer_size_bytes: u32 = 0;
-        unsafe{ resources.GetStream(
-            PCWSTR::from_raw(self.id.as_ptr()),
-            &WPD_RESOURCE_DEFAULT as *const _,  // We are transferring the default resource (which is the entire object's data)
-            stream_mode.0,
-            &mut optimal_transfer_size_bytes as *mut u32,
-            &mut stream as *mut Option<IStream>,
-        )}?;
-
-        match stream {
-            None => Err(OpenStreamError::UnableToCreate),
-            Some(s) => Ok((s, optimal_transfer_size_bytes)),
-        }
+    #[cfg(windows)]
+    pub fn open_raw_stream(&self, stream_mode: windows::Win32::System::Com::STGM) -> Result<(windows::Win32::System::Com::IStream, u32), MtpError> {
+        let wpd_content = self.wpd_content().ok_or(MtpError::Unsupported)?;
+        wpd_content.open_raw_stream(self.id.as_ucstr(), stream_mode)?.ok_or(MtpError::UnableToCreateStream)
     }
 
-    /// The same as [`Self::open_raw_stream`], but wrapped into a [`crate::io::ReadStream`] for more added Rust magic.
+    /// Opens a stream to this object, suitable for reading, wrapped into a [`crate::io::ReadStream`] for more added Rust magic.
+    ///
+    /// An error will be returned if the required operation does not make sense (e.g. get a stream to a folder).<br/>
+    /// The stream can also seek, when the backend supports it (see also [`Self::read_range`]).
     ///
     /// # Example
-    /// ```
-    /// # use widestring::{u16cstr, U16CString};
+    /// ```no_run
+    /// # use winmtp::object::ObjectId;
     /// # let provider = winmtp::Provider::new().unwrap();
     /// # let basic_device = &provider.enumerate_devices().unwrap()[0];
     /// # let app_identifiers = winmtp::make_current_app_identifiers!();
     /// # let device = basic_device.open(&app_identifiers, false).unwrap();
-    /// # let some_id = U16CString::from(u16cstr!("some_id"));
+    /// # let some_id: ObjectId = "some_id".parse().unwrap();
     /// let object = device.content().unwrap().object_by_id(some_id).unwrap();
     /// let mut input_stream = object.open_read_stream().unwrap();
     /// let mut output_file = std::fs::File::create("pulled-from-device.dat").unwrap();
     /// std::io::copy(&mut input_stream, &mut output_file).unwrap();
     /// ```
-    pub fn open_read_stream(&self) -> Result<BufReader<ReadStream>, OpenStreamError> {
-        let (stream, optimal_transfer_size) = self.open_raw_stream(STGM_READ)?;
+    pub fn open_read_stream(&self) -> Result<BufReader<ReadStream>, MtpError> {
+        let (stream, optimal_transfer_size) = self.device_content.backend().open_read_stream(self.id.as_ucstr())?;
         let read_stream = ReadStream::new(stream, optimal_transfer_size as usize);
         // Reader this reader is a slow process. Let's wrap it in a buffered reader for optimal perfs.
         // (There is no obvious reason for the capacity to be the same as the transfer size, but let's use it anyway)
         Ok(BufReader::with_capacity(optimal_transfer_size as usize, read_stream))
     }
 
+    /// Read at most `length` bytes of this object, starting at `offset`. Fewer bytes are returned when the end of the object is reached.
+    ///
+    /// Unlike seeking into [`Self::open_read_stream`], this only transfers the requested bytes whenever the device supports partial reads.
+    pub fn read_range(&self, offset: u64, length: usize) -> Result<Vec<u8>, MtpError> {
+        self.device_content.backend().read_range(self.id.as_ucstr(), offset, length)
+    }
+
     /// Open a COM [`IStream`](windows::Win32::System::Com::IStream) to create a file in the current object.
     ///
-    /// The current object is expected to be a folder-like object.
+    /// The current object is expected to be a folder-like object, handled by the [WPD backend](crate::backend::wpd).
     /// The file does not exist until the stream is committed.
     /// The MTP protocol requires to know the size of the created file before starting the transfer.
     ///
     /// This function returns the optimal transfer buffer size (in bytes) for this transfer, as stated by the Microsoft API.
     ///
+    /// The format (and content type) of the file is only guessed from its extension, as its content is not known yet.
+    ///
     /// See also [`Self::create_write_stream`].
-    pub fn create_raw_write_stream(&self, file_name: &OsStr, file_size: u64, allow_overwrite: bool) -> Result<(IStream, u32), AddFileError> {
+    #[cfg(windows)]
+    pub fn create_raw_write_stream(&self, file_name: &OsStr, file_size: u64, allow_overwrite: bool) -> Result<(windows::Win32::System::Com::IStream, u32), MtpError> {
+        let wpd_content = self.wpd_content().ok_or(MtpError::Unsupported)?;
         self.remove_existing_file_if_needed(file_name, allow_overwrite)?;
-        let file_properties = make_values_for_create_file(&self.id, file_name, file_size)?;
-        make_dest_raw_stream(self.device_content.com_object(), &file_properties)
+        let file_properties = make_values_for_create_file(self.id.as_ucstr(), file_name, file_size, ObjectFormat::detect(file_name, &[]));
+        wpd_content.create_raw_write_stream(&file_properties)?.ok_or(MtpError::UnableToCreateStream)
     }
 
-    /// The same as [`Self::open_raw_write_stream`], but wrapped into a [`crate::io::WriteStream`] for more added Rust magic.
+    /// Create a file in the current object, and return a [`crate::io::WriteStream`] to write its content.
     ///
-    /// The returned stream must be committed, either by calling `flush()` (which calls COM `Commit`) or `commit()`
+    /// The current object is expected to be a folder-like object.
+    /// The file does not exist until the stream is committed.
+    /// The MTP protocol requires to know the size of the created file before starting the transfer.
+    ///
+    /// The returned stream must be committed, either by calling `flush()` (which commits the transfer) or `commit()`
     /// on the inner stream.
     ///
+    /// The format (and content type) of the file is guessed from its extension. See [`Self::create_write_stream_with_format`] to choose it.
+    ///
     /// # Example
-    /// ```
+    /// ```no_run
     /// # let provider = winmtp::Provider::new().unwrap();
     /// # let basic_device = &provider.enumerate_devices().unwrap()[0];
     /// # let app_identifiers = winmtp::make_current_app_identifiers!();
@@ -233,180 +333,383 @@ impl Object {
     /// output_stream.write_all(b"hello").unwrap();
     /// output_stream.flush().unwrap();
     /// ```
-    pub fn create_write_stream(&self, file_name: &OsStr, file_size: u64, allow_overwrite: bool) -> Result<BufWriter<WriteStream>, AddFileError> {
-        let (stream, optimal_transfer_size) = self.create_raw_write_stream(file_name, file_size, allow_overwrite)?;
-        let write_stream = WriteStream::new(stream, optimal_transfer_size as usize);
-        Ok(BufWriter::with_capacity(optimal_transfer_size as usize, write_stream))
+    pub fn create_write_stream(&self, file_name: &OsStr, file_size: u64, allow_overwrite: bool) -> Result<BufWriter<WriteStream>, MtpError> {
+        self.create_write_stream_with_format(file_name, file_size, ObjectFormat::detect(file_name, &[]), allow_overwrite)
+    }
+
+    /// Like [`Self::create_write_stream`], for a file of a given format, e.g. one that has been detected by [`ObjectFormat::detect`] out of its first bytes
+    pub fn create_write_stream_with_format(&self, file_name: &OsStr, file_size: u64, format: ObjectFormat, allow_overwrite: bool) -> Result<BufWriter<WriteStream>, MtpError> {
+        let write_stream = self.create_unbuffered_write_stream(file_name, file_size, format, allow_overwrite)?;
+        Ok(BufWriter::with_capacity(write_stream.optimal_transfer_size(), write_stream))
+    }
+
+    /// An existing file is only replaced once the new one has been committed: until then, the new one is written under a temporary name.
+    fn create_unbuffered_write_stream(&self, file_name: &OsStr, file_size: u64, format: ObjectFormat, allow_overwrite: bool) -> Result<WriteStream, MtpError> {
+        let existing_file = match self.object_by_path(Path::new(file_name)) {
+            Ok(existing_file) if allow_overwrite => Some(existing_file),
+            Ok(_existing_file) => return Err(MtpError::AlreadyExists),
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+
+        let Some(existing_file) = existing_file else {
+            let file_properties = make_values_for_create_file(self.id.as_ucstr(), file_name, file_size, format);
+            let (stream, optimal_transfer_size) = self.device_content.backend().create_object_with_data(&file_properties)?;
+            return Ok(WriteStream::new(stream, optimal_transfer_size as usize));
+        };
+        let temp_name = temp_file_name(file_name);
+        let file_properties = make_values_for_create_file(self.id.as_ucstr(), &temp_name, file_size, format);
+        let (stream, optimal_transfer_size) = self.device_content.backend().create_object_with_data(&file_properties)?;
+        let replacing_stream = ReplacingWriteStream{
+            stream,
+            folder: self.clone(),
+            temp_name,
+            file_name: file_name.to_os_string(),
+            existing_file,
+            created_id: None,
+        };
+        Ok(WriteStream::new(Box::new(replacing_stream), optimal_transfer_size as usize))
     }
 
     /// Create a subfolder, and return its object ID
     ///
-    /// If a folder with the same name already exists ("same name" depends on the chosen case-folding mode), an `CreateFolderError::AlreadyExists` error will be returned.
+    /// If a folder with the same name already exists ("same name" depends on the chosen case-folding mode), an [`MtpError::AlreadyExists`] error will be returned.
     ///
     /// See also [`Self::create_subfolder_recursive`]
-    pub fn create_subfolder(&self, folder_name: &OsStr) -> Result<U16CString, CreateFolderError> {
-        // Check if such an item already exist (otherwise, `CreateObjectWithPropertiesOnly` would return an unhelpful "Unspecified error ")
-        if let Ok(_existing_item) = self.object_by_path(Path::new(folder_name)) {
-            return Err(CreateFolderError::AlreadyExists)
+    pub fn create_subfolder(&self, folder_name: &OsStr) -> Result<ObjectId, MtpError> {
+        // Check if such an item already exist (otherwise, WPD `CreateObjectWithPropertiesOnly` would return an unhelpful "Unspecified error ")
+        match self.object_by_path(Path::new(folder_name)) {
+            Ok(_existing_item) => return Err(MtpError::AlreadyExists),
+            Err(err) if err.kind() == ErrorKind::NotFound => {},
+            Err(err) => return Err(err),
         }
 
-        let folder_properties = make_values_for_create_folder(&self.id, folder_name)?;
-        let mut created_object_id = PWSTR::null();
-        unsafe{ self.device_content.com_object().CreateObjectWithPropertiesOnly(
-            &folder_properties,
-            &mut created_object_id as *mut _,
-        )}?;
-
-        let owned_id = unsafe{ U16CString::from_ptr_str(created_object_id.as_ptr()) };
-        unsafe{
-            CoTaskMemFree(Some(created_object_id.as_ptr() as *const _))
-        };
-
-        Ok(owned_id)
+        let folder_properties = make_values_for_create_folder(self.id.as_ucstr(), folder_name);
+        self.device_content.backend().create_object(&folder_properties).map(ObjectId::from)
     }
 
     /// Create a path of folders, creating intermediate folders if needed
-    pub fn create_subfolder_recursive(&self, folder_path: &Path) -> Result<(), CreateFolderError> {
+    pub fn create_subfolder_recursive(&self, folder_path: &Path) -> Result<(), MtpError> {
         let comps = folder_path.components();
-        self.create_subfolder_recursive_inner(comps)
+        self.create_subfolder_recursive_inner(comps, None)
     }
 
-    fn create_subfolder_recursive_inner(&self, mut remaining_components: Components) -> Result<(), CreateFolderError> {
+    fn create_subfolder_recursive_inner(&self, mut remaining_components: Components, case_sensitive: Option<bool>) -> Result<(), MtpError> {
         match remaining_components.next() {
             None => {},
             Some(Component::Normal(dir)) => {
-                match self.sub_folders()?.find(|f| are_path_eq(f.name(), dir, self.device_content().case_sensitive_fs())) {
+                let case_sensitive = match case_sensitive {
+                    Some(case_sensitive) => case_sensitive,
+                    None => self.case_sensitive_fs()?,
+                };
+                match self.sub_folders()?.find(|f| are_path_eq(f.name(), dir, case_sensitive)) {
                     Some(already_exists) => {
-                        already_exists.create_subfolder_recursive_inner(remaining_components)?;
+                        already_exists.create_subfolder_recursive_inner(remaining_components, self.inherited_case_sensitivity(case_sensitive))?;
                     },
                     None => {
                         let created_folder_id = self.create_subfolder(dir)?;
                         let created_folder = self.device_content.object_by_id(created_folder_id)?;
-                        created_folder.create_subfolder_recursive_inner(remaining_components)?;
+                        created_folder.create_subfolder_recursive_inner(remaining_components, self.inherited_case_sensitivity(case_sensitive))?;
                     }
                 }
             },
-            _ => return Err(CreateFolderError::NonRelativePath),
+            _ => return Err(MtpError::NonRelativePath),
         }
 
         Ok(())
     }
 
     /// Add a file into the current directory
-    pub fn push_file(&self, local_file: &Path, allow_overwrite: bool) -> Result<(), AddFileError> {
-        let file_name = local_file.file_name().ok_or(AddFileError::InvalidLocalFile)?;
-        let file_size = local_file.metadata()?.len();
-        let mut dest_writer = self.create_write_stream(file_name, file_size, allow_overwrite)?;
+    ///
+    /// See [`Self::push_file_with_progress`] to follow or cancel the transfer.
+    pub fn push_file(&self, local_file: &Path, allow_overwrite: bool) -> Result<(), MtpError> {
+        self.push_file_with_progress(local_file, allow_overwrite, &mut |_: &Progress| {}, &CancellationToken::new())
+    }
 
+    /// Add a file into the current directory, reporting the progress of the transfer to `progress`
+    ///
+    /// If `cancellation` is cancelled during the transfer, this returns an [`MtpError::Cancelled`] error, and the file is not created on the device.<br/>
+    /// When `allow_overwrite` is set, an existing file is only replaced once the transfer has succeeded, so that it is kept if the transfer fails or is cancelled.
+    ///
+    /// The format (and content type) of the file is guessed from its extension, or else from its first bytes (see [`ObjectFormat::detect`]).
+    /// See [`Self::push_file_with_format`] to choose it.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use std::path::Path;
+    /// use winmtp::io::{CancellationToken, Progress};
+    /// # let provider = winmtp::Provider::new().unwrap();
+    /// # let basic_device = &provider.enumerate_devices().unwrap()[0];
+    /// # let app_identifiers = winmtp::make_current_app_identifiers!();
+    /// # let device = basic_device.open(&app_identifiers, false).unwrap();
+    /// let destination_folder = device.content().unwrap().root().unwrap();
+    /// let mut print_progress = |progress: &Progress| println!("{}/{:?} bytes, ETA {:?}", progress.bytes_done, progress.bytes_total, progress.eta());
+    /// destination_folder.push_file_with_progress(Path::new("file_to_upload.txt"), false, &mut print_progress, &CancellationToken::new()).unwrap();
+    /// ```
+    pub fn push_file_with_progress(&self, local_file: &Path, allow_overwrite: bool, progress: &mut dyn ProgressObserver, cancellation: &CancellationToken) -> Result<(), MtpError> {
+        let file_name = local_file.file_name().ok_or(MtpError::InvalidLocalFile)?;
         let mut source_reader = std::fs::File::open(local_file)?;
-        std::io::copy(&mut source_reader, &mut dest_writer)?;
-
-        dest_writer.flush()?;
+        let file_size = source_reader.metadata()?.len();
+        let mut magic_bytes = Vec::with_capacity(ObjectFormat::MAGIC_BYTES_LEN);
+        Read::by_ref(&mut source_reader).take(ObjectFormat::MAGIC_BYTES_LEN as u64).read_to_end(&mut magic_bytes)?;
+        source_reader.seek(SeekFrom::Start(0))?;
+        let format = ObjectFormat::detect(file_name, &magic_bytes);
+        self.push_with_progress(file_name, &mut source_reader, file_size, format, allow_overwrite, progress, cancellation)
+    }
 
-        Ok(())
+    /// Like [`Self::push_file_with_progress`], for a file of a given format, whatever its name and content
+    pub fn push_file_with_format(&self, local_file: &Path, format: ObjectFormat, allow_overwrite: bool, progress: &mut dyn ProgressObserver, cancellation: &CancellationToken) -> Result<(), MtpError> {
+        let file_name = local_file.file_name().ok_or(MtpError::InvalidLocalFile)?;
+        let mut source_reader = std::fs::File::open(local_file)?;
+        let file_size = source_reader.metadata()?.len();
+        self.push_with_progress(file_name, &mut source_reader, file_size, format, allow_overwrite, progress, cancellation)
     }
 
     /// Add a file into the current directory
-    pub fn push_data(&self, file_name: &OsStr, data: &[u8], allow_overwrite: bool) -> Result<(), AddFileError> {
-        let file_size = data.len() as u64;
-        let mut dest_writer = self.create_write_stream(file_name, file_size, allow_overwrite)?;
+    ///
+    /// See [`Self::push_data_with_progress`] to follow or cancel the transfer.
+    pub fn push_data(&self, file_name: &OsStr, data: &[u8], allow_overwrite: bool) -> Result<(), MtpError> {
+        self.push_data_with_progress(file_name, data, allow_overwrite, &mut |_: &Progress| {}, &CancellationToken::new())
+    }
 
-        let mut source_reader = std::io::BufReader::new(data);
-        std::io::copy(&mut source_reader, &mut dest_writer)?;
+    /// Add a file into the current directory, reporting the progress of the transfer to `progress`
+    ///
+    /// See [`Self::push_file_with_progress`] about cancellation.
+    pub fn push_data_with_progress(&self, file_name: &OsStr, data: &[u8], allow_overwrite: bool, progress: &mut dyn ProgressObserver, cancellation: &CancellationToken) -> Result<(), MtpError> {
+        let mut source_reader = data;
+        self.push_with_progress(file_name, &mut source_reader, data.len() as u64, ObjectFormat::detect(file_name, data), allow_overwrite, progress, cancellation)
+    }
 
-        dest_writer.flush()?;
+    #[allow(clippy::too_many_arguments)]
+    fn push_with_progress(&self, file_name: &OsStr, source_reader: &mut dyn Read, file_size: u64, format: ObjectFormat, allow_overwrite: bool, progress: &mut dyn ProgressObserver, cancellation: &CancellationToken) -> Result<(), MtpError> {
+        let mut dest_writer = self.create_unbuffered_write_stream(file_name, file_size, format, allow_overwrite)?;
+        let buffer_size = dest_writer.optimal_transfer_size();
+        match copy_with_progress(source_reader, &mut dest_writer, Some(file_size), buffer_size, progress, cancellation) {
+            Ok(_) => dest_writer.commit(),
+            Err(err) => {
+                // The original error matters more than a failure to clean up
+                let _ = dest_writer.abort();
+                Err(err)
+            },
+        }
+    }
 
-        Ok(())
+    /// Download the content of this object into `destination`, reporting the progress of the transfer to `progress`
+    ///
+    /// If `cancellation` is cancelled during the transfer, this returns an [`MtpError::Cancelled`] error, and `destination` may have received only part of the content.<br/>
+    /// Returns the number of bytes that have been downloaded.
+    pub fn pull_with_progress(&self, destination: &mut dyn Write, progress: &mut dyn ProgressObserver, cancellation: &CancellationToken) -> Result<u64, MtpError> {
+        let bytes_total = self.properties(&[WPD_OBJECT_SIZE])?.get_u64(&WPD_OBJECT_SIZE).ok();
+        self.pull_into(destination, bytes_total, progress, cancellation)
     }
 
-    fn remove_existing_file_if_needed(&self, file_name: &OsStr, allow_overwrite: bool) -> Result<(), AddFileError> {
-        if let Ok(mut existing_file) = self.object_by_path(Path::new(file_name)) {
-            if allow_overwrite {
-                existing_file.delete(false)?;
-            } else {
-                return Err(AddFileError::AlreadyExists);
-            }
+    fn pull_into(&self, destination: &mut dyn Write, bytes_total: Option<u64>, progress: &mut dyn ProgressObserver, cancellation: &CancellationToken) -> Result<u64, MtpError> {
+        let (stream, optimal_transfer_size) = self.device_content.backend().open_read_stream(self.id.as_ucstr())?;
+        let mut source_reader = ReadStream::new(stream, optimal_transfer_size as usize);
+        copy_with_progress(&mut source_reader, destination, bytes_total, optimal_transfer_size as usize, progress, cancellation)
+    }
+
+    /// Download this object into a local file, and return the number of bytes that have been downloaded
+    ///
+    /// The content is first written into a temporary file next to `destination`, that is only renamed to `destination` once the transfer
+    /// has succeeded. Hence, `destination` is never left half-written, even if the transfer fails or is cancelled.<br/>
+    /// The transfer fails with [`MtpError::SizeMismatch`] if the device sent a different number of bytes than its `WPD_OBJECT_SIZE`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use std::path::Path;
+    /// use winmtp::object::{OverwritePolicy, PullOptions};
+    /// # let provider = winmtp::Provider::new().unwrap();
+    /// # let basic_device = &provider.enumerate_devices().unwrap()[0];
+    /// # let app_identifiers = winmtp::make_current_app_identifiers!();
+    /// # let device = basic_device.open(&app_identifiers, false).unwrap();
+    /// let object = device.content().unwrap().root().unwrap().object_by_path(Path::new("Internal storage/DCIM/IMG_0001.JPG")).unwrap();
+    /// object.pull_to_file(Path::new("IMG_0001.JPG"), PullOptions::new().overwrite(OverwritePolicy::Overwrite)).unwrap();
+    /// ```
+    pub fn pull_to_file(&self, destination: &Path, options: PullOptions) -> Result<u64, MtpError> {
+        let PullOptions{ overwrite, preserve_timestamps, progress, cancellation } = options;
+        let file_name = destination.file_name().ok_or(MtpError::InvalidLocalFile)?;
+        match std::fs::symlink_metadata(destination) {
+            Ok(existing) if existing.is_dir() => return Err(MtpError::InvalidLocalFile),
+            Ok(_existing) if overwrite == OverwritePolicy::Refuse => return Err(MtpError::AlreadyExists),
+            Ok(_existing) => {},
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {},
+            Err(err) => return Err(err.into()),
+        }
+
+        let metadata = self.metadata()?;
+        let temp_path = destination.with_file_name(temp_file_name(file_name));
+
+        let mut no_progress = |_: &Progress| {};
+        let progress = progress.unwrap_or(&mut no_progress);
+        let result = std::fs::File::create(&temp_path)
+            .map_err(MtpError::from)
+            .and_then(|mut temp_file| {
+                let pulled_size = self.pull_into(&mut temp_file, metadata.size, progress, &cancellation)?;
+                if let Some(expected) = metadata.size {
+                    if expected != pulled_size {
+                        return Err(MtpError::SizeMismatch{ expected, actual: pulled_size });
+                    }
+                }
+                if preserve_timestamps {
+                    set_file_times(&temp_file, &metadata)?;
+                }
+                temp_file.sync_all()?;
+                Ok(pulled_size)
+            })
+            .and_then(|pulled_size| {
+                match overwrite {
+                    OverwritePolicy::Overwrite => std::fs::rename(&temp_path, destination)?,
+                    // `destination` may have been created during the transfer
+                    OverwritePolicy::Refuse => rename_without_replacing(&temp_path, destination)?,
+                }
+                Ok(pulled_size)
+            });
+
+        if result.is_err() {
+            // The original error matters more than a failure to clean up
+            let _ = std::fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    #[cfg(windows)]
+    fn remove_existing_file_if_needed(&self, file_name: &OsStr, allow_overwrite: bool) -> Result<(), MtpError> {
+        match self.object_by_path(Path::new(file_name)) {
+            Ok(existing_file) if allow_overwrite => existing_file.delete(false),
+            Ok(_existing_file) => Err(MtpError::AlreadyExists),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err),
         }
-        Ok(())
     }
 
     /// Delete an object
     ///
-    /// If this is a folder, you must set `recursive` to `true`, otherwise this would return an error.
-    pub fn delete(&mut self, recursive: bool) -> crate::WindowsResult<()> {
-        let id_as_propvariant = unsafe{ init_propvariant_from_string(&mut self.id) };
-
-        let objects_to_delete: IPortableDevicePropVariantCollection = unsafe {
-            CoCreateInstance(
-                &PortableDevicePropVariantCollection as *const GUID,
-                None,
-                CLSCTX_ALL
-            )
-        }.unwrap();
-        unsafe{ objects_to_delete.Add(&id_as_propvariant as *const _) }.unwrap();
-
-        let options = if recursive { PORTABLE_DEVICE_DELETE_WITH_RECURSION } else { PORTABLE_DEVICE_DELETE_NO_RECURSION };
-        let mut result_status = None;
-        unsafe{
-            self.device_content.com_object().Delete(
-                options.0 as u32,
-                &objects_to_delete,
-                &mut result_status as *mut _,
-            )
-        }.unwrap();
-
-        Ok(())
+    /// If this is a folder, you must set `recursive` to `true`, otherwise this would return an error.<br/>
+    /// See [`Content::delete_many`] to delete many objects at once.
+    pub fn delete(&self, recursive: bool) -> Result<(), MtpError> {
+        single_outcome(self.device_content.delete_many(&[&self.id], recursive)?)
     }
 
     /// Move an object that is already on the device to a new folder
-    pub fn move_to(&mut self, new_folder_id: &U16CStr) -> crate::WindowsResult<()>  {
-        let id_as_propvariant = unsafe{ init_propvariant_from_string(&mut self.id) };
-
-        let objects_to_move: IPortableDevicePropVariantCollection = unsafe {
-            CoCreateInstance(
-                &PortableDevicePropVariantCollection as *const GUID,
-                None,
-                CLSCTX_ALL
-            )
-        }.unwrap();
-        unsafe{ objects_to_move.Add(&id_as_propvariant as *const _) }.unwrap();
-
-        let dest = PCWSTR::from_raw(new_folder_id.as_ptr());
-        let mut result_status = None;
-        unsafe{
-            self.device_content.com_object().Move(
-                &objects_to_move,
-                dest,
-                &mut result_status as *mut _,
-            )
-        }.unwrap();
+    ///
+    /// See [`Content::move_many`] to move many objects at once.
+    pub fn move_to(&self, new_folder_id: &ObjectIdRef) -> Result<(), MtpError> {
+        single_outcome(self.device_content.move_many(&[&self.id], new_folder_id)?)
+    }
 
-        Ok(())
+    #[cfg(windows)]
+    fn wpd_content(&self) -> Option<&crate::backend::wpd::WpdContent> {
+        self.device_content.backend().as_any().downcast_ref()
     }
 }
 
+/// A name to write a file under until it is complete, next to its final name `file_name`
+fn temp_file_name(file_name: &OsStr) -> OsString {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{}.{}.part", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+    temp_name
+}
 
-/// Re-implementation of `InitPropVariantFromString`, which is missing in windows-rs.
-/// See https://github.com/microsoft/windows-rs/issues/976#issuecomment-878697273
-///
-/// # Safety
+/// A stream that creates a file under a temporary name, and only replaces an existing file with it once it is committed.
 ///
-/// I'm too lazy to wrap to result with a 'a PhantomData, so for now, the result is only valid as long `data` is valid.
-unsafe fn init_propvariant_from_string(data: &mut U16CStr) -> PROPVARIANT {
-    windows::Win32::System::Com::StructuredStorage::PROPVARIANT{
-        Anonymous: windows::Win32::System::Com::StructuredStorage::PROPVARIANT_0 {
-            Anonymous: std::mem::ManuallyDrop::new(windows::Win32::System::Com::StructuredStorage::PROPVARIANT_0_0 {
-                vt: windows::Win32::System::Variant::VT_LPWSTR,
-                Anonymous: windows::Win32::System::Com::StructuredStorage::PROPVARIANT_0_0_0 {
-                    pwszVal: PWSTR::from_raw(data.as_mut_ptr()),
-                },
-                ..Default::default()
-            })
+/// Hence, the existing file is left untouched if the transfer fails or is cancelled.
+struct ReplacingWriteStream {
+    stream: Box<dyn WriteStreamBackend>,
+    folder: Object,
+    temp_name: OsString,
+    file_name: OsString,
+    existing_file: Object,
+    created_id: Option<U16CString>,
+}
+
+impl ReplacingWriteStream {
+    fn replace_existing_file(&self, created_file: &mut Object) -> Result<(), MtpError> {
+        if let Err(err) = self.existing_file.delete(false) {
+            // Keep the existing file rather than two of them
+            let _ = created_file.delete(false);
+            return Err(err);
+        }
+        // Some devices only accept one of the names
+        let outcomes = created_file.rename(&self.file_name)?;
+        match outcomes.into_iter().find(|outcome| outcome.is_ok()) {
+            Some(_renamed) => Ok(()),
+            None => Err(MtpError::Backend(format!("Unable to rename {:?} to {:?}", self.temp_name, self.file_name))),
+        }
+    }
+}
+
+impl Write for ReplacingWriteStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl WriteStreamBackend for ReplacingWriteStream {
+    fn commit(&mut self) -> Result<(), MtpError> {
+        self.stream.commit()?;
+        let mut created_file = match self.stream.object_id() {
+            Some(created_id) => self.folder.device_content.object_by_id(created_id.into())?,
+            None => self.folder.object_by_path(Path::new(&self.temp_name))?,
+        };
+        self.replace_existing_file(&mut created_file)?;
+        self.created_id = Some(created_file.id.into_ucstring());
+        Ok(())
+    }
+
+    fn abort(&mut self) -> Result<(), MtpError> {
+        self.stream.abort()
+    }
+
+    fn object_id(&self) -> Option<U16CString> {
+        self.created_id.clone().or_else(|| self.stream.object_id())
+    }
+}
+
+/// Rename a local file, unless its new path already exists
+fn rename_without_replacing(from: &Path, to: &Path) -> Result<(), MtpError> {
+    match std::fs::hard_link(from, to) {
+        Ok(()) => {
+            // `to` is complete anyway
+            let _ = std::fs::remove_file(from);
+            Ok(())
         },
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => Err(MtpError::AlreadyExists),
+        // Some filesystems do not support hard links
+        Err(_) if std::fs::symlink_metadata(to).is_ok() => Err(MtpError::AlreadyExists),
+        Err(_) => Ok(std::fs::rename(from, to)?),
     }
 }
 
-fn object_by_components_last_stage(candidate: Object, next_components: &mut Peekable<Components>) -> Result<Object, ItemByPathError> {
+fn set_file_times(file: &std::fs::File, metadata: &ObjectMetadata) -> Result<(), MtpError> {
+    let mut times = std::fs::FileTimes::new();
+    if let Some(date_modified) = metadata.date_modified {
+        times = times.set_modified(date_modified);
+    }
+    // Only Windows lets the creation date of a file be set
+    #[cfg(windows)]
+    if let Some(date_created) = metadata.date_created {
+        use std::os::windows::fs::FileTimesExt;
+        times = times.set_created(date_created);
+    }
+    file.set_times(times)?;
+    Ok(())
+}
+
+fn single_outcome(outcomes: Vec<ObjectOutcome>) -> Result<(), MtpError> {
+    outcomes.into_iter()
+        .next()
+        .ok_or_else(|| MtpError::Backend("No outcome for the object".to_string()))?
+        .result
+}
+
+fn object_by_components_last_stage(candidate: Object, next_components: &mut Peekable<Components>, case_sensitive: Option<bool>) -> Result<Object, MtpError> {
     match next_components.peek() {
         None => {
             // We've reached the end of the required path
@@ -414,21 +717,7 @@ fn object_by_components_last_stage(candidate: Object, next_components: &mut Peek
             Ok(candidate)
         },
         Some(_) => {
-            candidate.object_by_components(next_components)
+            candidate.object_by_components(next_components, case_sensitive)
         }
     }
 }
-
-fn make_dest_raw_stream(com_object: &IPortableDeviceContent, file_properties: &IPortableDeviceValues) -> Result<(IStream, u32), AddFileError> {
-    let mut write_stream = None;
-    let mut optimal_write_buffer_size = 0;
-    unsafe{ com_object.CreateObjectWithPropertiesAndData(
-        file_properties,
-        &mut write_stream as *mut _,
-        &mut optimal_write_buffer_size,
-        &mut PWSTR::null() as *mut PWSTR,
-    )}?;
-
-    let write_stream = write_stream.ok_or(AddFileError::UnableToCreate)?;
-    Ok((write_stream, optimal_write_buffer_size))
-}
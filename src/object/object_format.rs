@@ -0,0 +1,257 @@
+use std::ffi::OsStr;
+use std::path::Path;
+
+use windows::core::GUID;
+use windows::Win32::Devices::PortableDevices::{
+    WPD_OBJECT_FORMAT_UNSPECIFIED, WPD_OBJECT_FORMAT_PROPERTIES_ONLY, WPD_OBJECT_FORMAT_TEXT, WPD_OBJECT_FORMAT_HTML,
+    WPD_OBJECT_FORMAT_XML, WPD_OBJECT_FORMAT_MICROSOFT_WORD, WPD_OBJECT_FORMAT_MICROSOFT_EXCEL,
+    WPD_OBJECT_FORMAT_MICROSOFT_POWERPOINT, WPD_OBJECT_FORMAT_MP3, WPD_OBJECT_FORMAT_WAVE, WPD_OBJECT_FORMAT_WMA,
+    WPD_OBJECT_FORMAT_AAC, WPD_OBJECT_FORMAT_M4A, WPD_OBJECT_FORMAT_FLAC, WPD_OBJECT_FORMAT_OGG, WPD_OBJECT_FORMAT_AIFF,
+    WPD_OBJECT_FORMAT_AMR, WPD_OBJECT_FORMAT_MP4, WPD_OBJECT_FORMAT_3GP, WPD_OBJECT_FORMAT_AVI, WPD_OBJECT_FORMAT_WMV,
+    WPD_OBJECT_FORMAT_MPEG, WPD_OBJECT_FORMAT_ASF, WPD_OBJECT_FORMAT_MKV, WPD_OBJECT_FORMAT_EXIF, WPD_OBJECT_FORMAT_PNG,
+    WPD_OBJECT_FORMAT_GIF, WPD_OBJECT_FORMAT_BMP, WPD_OBJECT_FORMAT_TIFF, WPD_OBJECT_FORMAT_M3UPLAYLIST,
+    WPD_OBJECT_FORMAT_PLSPLAYLIST, WPD_OBJECT_FORMAT_WPLPLAYLIST, WPD_OBJECT_FORMAT_VCARD2, WPD_OBJECT_FORMAT_ICALENDAR,
+};
+
+use crate::object::ObjectType;
+use crate::protocol::codes::ObjectFormatCode;
+
+/// The format of an object (its `WPD_OBJECT_FORMAT`), which tells how its data is encoded
+///
+/// Its [`ObjectType`] is rather what the object is about (e.g. an audio track, whatever its format).
+///
+/// Some formats (e.g. [`ObjectFormat::Epub`] and [`ObjectFormat::Pdf`]) have no WPD GUID: devices get them as unspecified documents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjectFormat {
+    Unspecified,
+    /// Objects that have no data, e.g. folders
+    PropertiesOnly,
+    Text,
+    Html,
+    Xml,
+    Pdf,
+    Epub,
+    MsWord,
+    MsExcel,
+    MsPowerpoint,
+    Mp3,
+    Wav,
+    Wma,
+    Aac,
+    M4a,
+    Flac,
+    Ogg,
+    Aiff,
+    Amr,
+    Mp4,
+    ThreeGp,
+    Avi,
+    Wmv,
+    Mpeg,
+    Asf,
+    Mkv,
+    /// JPEG images, as taken by cameras
+    Jpeg,
+    Png,
+    Gif,
+    Bmp,
+    Tiff,
+    M3uPlaylist,
+    PlsPlaylist,
+    WplPlaylist,
+    VCard,
+    ICalendar,
+    /// A format this crate has no name for
+    Other(GUID),
+}
+
+impl ObjectFormat {
+    /// How many bytes at the start of a file [`Self::from_magic_bytes`] may need
+    pub const MAGIC_BYTES_LEN: usize = 64;
+
+    pub fn from_guid(guid: GUID) -> Self {
+        match guid {
+            WPD_OBJECT_FORMAT_UNSPECIFIED           => Self::Unspecified,
+            WPD_OBJECT_FORMAT_PROPERTIES_ONLY       => Self::PropertiesOnly,
+            WPD_OBJECT_FORMAT_TEXT                  => Self::Text,
+            WPD_OBJECT_FORMAT_HTML                  => Self::Html,
+            WPD_OBJECT_FORMAT_XML                   => Self::Xml,
+            WPD_OBJECT_FORMAT_MICROSOFT_WORD        => Self::MsWord,
+            WPD_OBJECT_FORMAT_MICROSOFT_EXCEL       => Self::MsExcel,
+            WPD_OBJECT_FORMAT_MICROSOFT_POWERPOINT  => Self::MsPowerpoint,
+            WPD_OBJECT_FORMAT_MP3                   => Self::Mp3,
+            WPD_OBJECT_FORMAT_WAVE                  => Self::Wav,
+            WPD_OBJECT_FORMAT_WMA                   => Self::Wma,
+            WPD_OBJECT_FORMAT_AAC                   => Self::Aac,
+            WPD_OBJECT_FORMAT_M4A                   => Self::M4a,
+            WPD_OBJECT_FORMAT_FLAC                  => Self::Flac,
+            WPD_OBJECT_FORMAT_OGG                   => Self::Ogg,
+            WPD_OBJECT_FORMAT_AIFF                  => Self::Aiff,
+            WPD_OBJECT_FORMAT_AMR                   => Self::Amr,
+            WPD_OBJECT_FORMAT_MP4                   => Self::Mp4,
+            WPD_OBJECT_FORMAT_3GP                   => Self::ThreeGp,
+            WPD_OBJECT_FORMAT_AVI                   => Self::Avi,
+            WPD_OBJECT_FORMAT_WMV                   => Self::Wmv,
+            WPD_OBJECT_FORMAT_MPEG                  => Self::Mpeg,
+            WPD_OBJECT_FORMAT_ASF                   => Self::Asf,
+            WPD_OBJECT_FORMAT_MKV                   => Self::Mkv,
+            WPD_OBJECT_FORMAT_EXIF                  => Self::Jpeg,
+            WPD_OBJECT_FORMAT_PNG                   => Self::Png,
+            WPD_OBJECT_FORMAT_GIF                   => Self::Gif,
+            WPD_OBJECT_FORMAT_BMP                   => Self::Bmp,
+            WPD_OBJECT_FORMAT_TIFF                  => Self::Tiff,
+            WPD_OBJECT_FORMAT_M3UPLAYLIST           => Self::M3uPlaylist,
+            WPD_OBJECT_FORMAT_PLSPLAYLIST           => Self::PlsPlaylist,
+            WPD_OBJECT_FORMAT_WPLPLAYLIST           => Self::WplPlaylist,
+            WPD_OBJECT_FORMAT_VCARD2                => Self::VCard,
+            WPD_OBJECT_FORMAT_ICALENDAR             => Self::ICalendar,
+            other => Self::Other(other),
+        }
+    }
+
+    pub fn as_guid(&self) -> GUID {
+        match self {
+            Self::Unspecified     => WPD_OBJECT_FORMAT_UNSPECIFIED,
+            Self::PropertiesOnly  => WPD_OBJECT_FORMAT_PROPERTIES_ONLY,
+            Self::Text            => WPD_OBJECT_FORMAT_TEXT,
+            Self::Html            => WPD_OBJECT_FORMAT_HTML,
+            Self::Xml             => WPD_OBJECT_FORMAT_XML,
+            Self::Pdf             => WPD_OBJECT_FORMAT_UNSPECIFIED,
+            Self::Epub            => WPD_OBJECT_FORMAT_UNSPECIFIED,
+            Self::MsWord          => WPD_OBJECT_FORMAT_MICROSOFT_WORD,
+            Self::MsExcel         => WPD_OBJECT_FORMAT_MICROSOFT_EXCEL,
+            Self::MsPowerpoint    => WPD_OBJECT_FORMAT_MICROSOFT_POWERPOINT,
+            Self::Mp3             => WPD_OBJECT_FORMAT_MP3,
+            Self::Wav             => WPD_OBJECT_FORMAT_WAVE,
+            Self::Wma             => WPD_OBJECT_FORMAT_WMA,
+            Self::Aac             => WPD_OBJECT_FORMAT_AAC,
+            Self::M4a             => WPD_OBJECT_FORMAT_M4A,
+            Self::Flac            => WPD_OBJECT_FORMAT_FLAC,
+            Self::Ogg             => WPD_OBJECT_FORMAT_OGG,
+            Self::Aiff            => WPD_OBJECT_FORMAT_AIFF,
+            Self::Amr             => WPD_OBJECT_FORMAT_AMR,
+            Self::Mp4             => WPD_OBJECT_FORMAT_MP4,
+            Self::ThreeGp         => WPD_OBJECT_FORMAT_3GP,
+            Self::Avi             => WPD_OBJECT_FORMAT_AVI,
+            Self::Wmv             => WPD_OBJECT_FORMAT_WMV,
+            Self::Mpeg            => WPD_OBJECT_FORMAT_MPEG,
+            Self::Asf             => WPD_OBJECT_FORMAT_ASF,
+            Self::Mkv             => WPD_OBJECT_FORMAT_MKV,
+            Self::Jpeg            => WPD_OBJECT_FORMAT_EXIF,
+            Self::Png             => WPD_OBJECT_FORMAT_PNG,
+            Self::Gif             => WPD_OBJECT_FORMAT_GIF,
+            Self::Bmp             => WPD_OBJECT_FORMAT_BMP,
+            Self::Tiff            => WPD_OBJECT_FORMAT_TIFF,
+            Self::M3uPlaylist     => WPD_OBJECT_FORMAT_M3UPLAYLIST,
+            Self::PlsPlaylist     => WPD_OBJECT_FORMAT_PLSPLAYLIST,
+            Self::WplPlaylist     => WPD_OBJECT_FORMAT_WPLPLAYLIST,
+            Self::VCard           => WPD_OBJECT_FORMAT_VCARD2,
+            Self::ICalendar       => WPD_OBJECT_FORMAT_ICALENDAR,
+            Self::Other(guid)     => *guid,
+        }
+    }
+
+    /// The content type objects of this format usually have (e.g. [`ObjectType::Audio`] for [`ObjectFormat::Flac`])
+    pub fn object_type(&self) -> ObjectType {
+        match self {
+            Self::Unspecified => ObjectType::GenericFile,
+            Self::PropertiesOnly => ObjectType::Folder,
+            Self::Text | Self::Html | Self::Xml | Self::Pdf | Self::Epub | Self::MsWord | Self::MsExcel | Self::MsPowerpoint => ObjectType::Document,
+            Self::Mp3 | Self::Wav | Self::Wma | Self::Aac | Self::M4a | Self::Flac | Self::Ogg | Self::Aiff | Self::Amr => ObjectType::Audio,
+            Self::Mp4 | Self::ThreeGp | Self::Avi | Self::Wmv | Self::Mpeg | Self::Asf | Self::Mkv => ObjectType::Video,
+            Self::Jpeg | Self::Png | Self::Gif | Self::Bmp | Self::Tiff => ObjectType::Image,
+            Self::M3uPlaylist | Self::PlsPlaylist | Self::WplPlaylist => ObjectType::Playlist,
+            Self::VCard => ObjectType::Contact,
+            Self::ICalendar => ObjectType::Calendar,
+            // MTP formats have a known content type
+            Self::Other(guid) => ObjectFormatCode::from_guid(*guid).map_or(ObjectType::GenericFile, |code| code.object_type()),
+        }
+    }
+
+    /// Guess the format of a file from its extension (case-insensitive)
+    pub fn from_extension(extension: &OsStr) -> Option<Self> {
+        let extension = extension.to_str()?.to_ascii_lowercase();
+        Some(match extension.as_str() {
+            "txt" => Self::Text,
+            "htm" | "html" => Self::Html,
+            "xml" => Self::Xml,
+            "pdf" => Self::Pdf,
+            "epub" => Self::Epub,
+            "doc" => Self::MsWord,
+            "xls" => Self::MsExcel,
+            "ppt" => Self::MsPowerpoint,
+            "mp3" => Self::Mp3,
+            "wav" => Self::Wav,
+            "wma" => Self::Wma,
+            "aac" => Self::Aac,
+            "m4a" => Self::M4a,
+            "flac" => Self::Flac,
+            "ogg" | "oga" | "opus" => Self::Ogg,
+            "aif" | "aiff" => Self::Aiff,
+            "amr" => Self::Amr,
+            "mp4" | "m4v" => Self::Mp4,
+            "3gp" => Self::ThreeGp,
+            "avi" => Self::Avi,
+            "wmv" => Self::Wmv,
+            "mpg" | "mpeg" => Self::Mpeg,
+            "asf" => Self::Asf,
+            "mkv" => Self::Mkv,
+            "jpg" | "jpeg" => Self::Jpeg,
+            "png" => Self::Png,
+            "gif" => Self::Gif,
+            "bmp" => Self::Bmp,
+            "tif" | "tiff" => Self::Tiff,
+            "m3u" | "m3u8" => Self::M3uPlaylist,
+            "pls" => Self::PlsPlaylist,
+            "wpl" => Self::WplPlaylist,
+            "vcf" => Self::VCard,
+            "ics" => Self::ICalendar,
+            _ => return None,
+        })
+    }
+
+    /// Guess the format of a file from its first bytes (at most [`Self::MAGIC_BYTES_LEN`] of them are needed)
+    pub fn from_magic_bytes(data: &[u8]) -> Option<Self> {
+        let at = |offset: usize, magic: &[u8]| data.get(offset..offset + magic.len()) == Some(magic);
+        Some(match data {
+            _ if at(0, b"%PDF-") => Self::Pdf,
+            // EPUBs are ZIP files, that start with an uncompressed "mimetype" file
+            _ if at(0, b"PK\x03\x04") && at(30, b"mimetypeapplication/epub+zip") => Self::Epub,
+            _ if at(0, b"ID3") => Self::Mp3,
+            // An MPEG audio frame sync, for MP3 files without ID3 tags
+            [0xFF, second, ..] if second & 0xE6 == 0xE2 => Self::Mp3,
+            _ if at(0, b"RIFF") && at(8, b"WAVE") => Self::Wav,
+            _ if at(0, b"RIFF") && at(8, b"AVI ") => Self::Avi,
+            _ if at(0, b"FORM") && at(8, b"AIFF") => Self::Aiff,
+            _ if at(0, b"fLaC") => Self::Flac,
+            _ if at(0, b"OggS") => Self::Ogg,
+            _ if at(0, b"#!AMR") => Self::Amr,
+            _ if at(4, b"ftypM4A") => Self::M4a,
+            _ if at(4, b"ftyp3gp") => Self::ThreeGp,
+            _ if at(4, b"ftyp") => Self::Mp4,
+            _ if at(0, &[0x30, 0x26, 0xB2, 0x75, 0x8E, 0x66, 0xCF, 0x11]) => Self::Asf,
+            _ if at(0, &[0x1A, 0x45, 0xDF, 0xA3]) => Self::Mkv,
+            _ if at(0, &[0x00, 0x00, 0x01, 0xBA]) => Self::Mpeg,
+            _ if at(0, &[0xFF, 0xD8, 0xFF]) => Self::Jpeg,
+            _ if at(0, b"\x89PNG\r\n\x1A\n") => Self::Png,
+            _ if at(0, b"GIF87a") || at(0, b"GIF89a") => Self::Gif,
+            _ if at(0, b"II*\0") || at(0, b"MM\0*") => Self::Tiff,
+            _ if at(0, b"#EXTM3U") => Self::M3uPlaylist,
+            _ if at(0, b"[playlist]") => Self::PlsPlaylist,
+            _ if at(0, b"BEGIN:VCARD") => Self::VCard,
+            _ if at(0, b"BEGIN:VCALENDAR") => Self::ICalendar,
+            _ if at(0, b"<?xml") => Self::Xml,
+            _ => return None,
+        })
+    }
+
+    /// Guess the format of a file from its name, or else from its first bytes (`data` can be empty when they are not known yet)
+    ///
+    /// The extension comes first, as it tells apart formats that share the same container (e.g. WMA and WMV files are both ASF files).
+    pub fn detect(file_name: &OsStr, data: &[u8]) -> Self {
+        Path::new(file_name).extension()
+            .and_then(Self::from_extension)
+            .or_else(|| Self::from_magic_bytes(data))
+            .unwrap_or(Self::Unspecified)
+    }
+}
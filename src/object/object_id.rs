@@ -1,10 +1,201 @@
-use windows::core::PCWSTR;
+use std::borrow::Borrow;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::str::FromStr;
 
-#[derive(Debug)]
-pub struct ObjectId(pub PCWSTR);
+use serde::{Deserialize, Serialize};
+use windows::Win32::Devices::PortableDevices::WPD_DEVICE_OBJECT_ID;
+use widestring::{U16CStr, U16CString};
 
-impl<'a> std::convert::From<&'a ObjectId> for &'a PCWSTR {
-    fn from(val: &'a ObjectId) -> Self {
-        &val.0
+use crate::error::MtpError;
+
+/// The ID of an object, as assigned by the device (e.g. `"o2C"`)
+///
+/// IDs are only valid for a given device, and devices may assign new IDs to objects across sessions
+/// (see [`WPD_OBJECT_PERSISTENT_UNIQUE_ID`](crate::PortableDevices::WPD_OBJECT_PERSISTENT_UNIQUE_ID) for IDs that are stable).
+///
+/// They are (de)serialized as strings.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct ObjectId(U16CString);
+
+/// A borrowed [`ObjectId`], just like `&str` is a borrowed `String`
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct ObjectIdRef(U16CStr);
+
+impl ObjectId {
+    /// The ID of the root object of every device, whose children are the functional objects (e.g. the storages)
+    pub fn device_root() -> Self {
+        Self(unsafe{ U16CString::from_ptr_str(WPD_DEVICE_OBJECT_ID.as_ptr()) })
+    }
+
+    /// The platform representation of this ID
+    pub fn into_ucstring(self) -> U16CString {
+        self.0
+    }
+}
+
+impl ObjectIdRef {
+    /// Wrap the platform representation of an ID
+    pub fn from_ucstr(id: &U16CStr) -> &Self {
+        // Safety: `ObjectIdRef` is a `repr(transparent)` wrapper around `U16CStr`
+        unsafe{ &*(id as *const U16CStr as *const Self) }
+    }
+
+    /// The platform representation of this ID
+    pub fn as_ucstr(&self) -> &U16CStr {
+        &self.0
+    }
+
+    /// The ID as a string, with invalid UTF-16 replaced
+    pub fn to_string_lossy(&self) -> String {
+        self.0.to_string_lossy()
+    }
+}
+
+impl Deref for ObjectId {
+    type Target = ObjectIdRef;
+
+    fn deref(&self) -> &ObjectIdRef {
+        ObjectIdRef::from_ucstr(&self.0)
+    }
+}
+
+impl Borrow<ObjectIdRef> for ObjectId {
+    fn borrow(&self) -> &ObjectIdRef {
+        self
+    }
+}
+
+impl AsRef<ObjectIdRef> for ObjectId {
+    fn as_ref(&self) -> &ObjectIdRef {
+        self
+    }
+}
+
+impl AsRef<ObjectIdRef> for ObjectIdRef {
+    fn as_ref(&self) -> &ObjectIdRef {
+        self
+    }
+}
+
+impl ToOwned for ObjectIdRef {
+    type Owned = ObjectId;
+
+    fn to_owned(&self) -> ObjectId {
+        ObjectId(self.0.to_ucstring())
+    }
+}
+
+// `ObjectId` and `ObjectIdRef` must hash the same, since `ObjectId: Borrow<ObjectIdRef>`
+impl Hash for ObjectIdRef {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_slice().hash(state)
+    }
+}
+
+impl Hash for ObjectId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl PartialEq<ObjectIdRef> for ObjectId {
+    fn eq(&self, other: &ObjectIdRef) -> bool {
+        **self == *other
+    }
+}
+
+impl PartialEq<ObjectId> for ObjectIdRef {
+    fn eq(&self, other: &ObjectId) -> bool {
+        *self == **other
+    }
+}
+
+impl PartialEq<&ObjectIdRef> for ObjectId {
+    fn eq(&self, other: &&ObjectIdRef) -> bool {
+        **self == **other
+    }
+}
+
+impl PartialEq<ObjectId> for &ObjectIdRef {
+    fn eq(&self, other: &ObjectId) -> bool {
+        **self == **other
+    }
+}
+
+impl From<U16CString> for ObjectId {
+    fn from(id: U16CString) -> Self {
+        Self(id)
+    }
+}
+
+impl From<&U16CStr> for ObjectId {
+    fn from(id: &U16CStr) -> Self {
+        Self(id.to_ucstring())
+    }
+}
+
+impl From<&ObjectIdRef> for ObjectId {
+    fn from(id: &ObjectIdRef) -> Self {
+        id.to_owned()
+    }
+}
+
+impl From<ObjectId> for U16CString {
+    fn from(id: ObjectId) -> Self {
+        id.0
+    }
+}
+
+impl From<ObjectId> for String {
+    fn from(id: ObjectId) -> Self {
+        id.to_string_lossy()
+    }
+}
+
+impl TryFrom<String> for ObjectId {
+    type Error = MtpError;
+
+    fn try_from(id: String) -> Result<Self, MtpError> {
+        id.parse()
+    }
+}
+
+impl FromStr for ObjectId {
+    type Err = MtpError;
+
+    fn from_str(id: &str) -> Result<Self, MtpError> {
+        // `U16CString::from_str` would accept (and drop) a trailing null character
+        if id.contains('\0') {
+            return Err(MtpError::InvalidArgument("Object IDs cannot contain null characters".to_string()));
+        }
+        Ok(Self(U16CString::from_str_truncate(id)))
+    }
+}
+
+impl fmt::Display for ObjectIdRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_string_lossy())
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl fmt::Debug for ObjectIdRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.to_string_lossy(), f)
+    }
+}
+
+impl fmt::Debug for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
     }
 }
@@ -0,0 +1,88 @@
+use std::time::SystemTime;
+
+use windows::Win32::Devices::PortableDevices::{
+    WPD_OBJECT_NAME, WPD_OBJECT_ORIGINAL_FILE_NAME, WPD_OBJECT_CONTENT_TYPE, WPD_OBJECT_FORMAT, WPD_OBJECT_SIZE,
+    WPD_OBJECT_PARENT_ID, WPD_OBJECT_PERSISTENT_UNIQUE_ID, WPD_OBJECT_DATE_CREATED, WPD_OBJECT_DATE_MODIFIED,
+    WPD_OBJECT_DATE_AUTHORED, WPD_OBJECT_ISHIDDEN, WPD_OBJECT_ISSYSTEM, WPD_OBJECT_CAN_DELETE, WPD_OBJECT_NON_CONSUMABLE,
+};
+use widestring::U16CString;
+
+use crate::device::device_values::DeviceValues;
+use crate::error::MtpError;
+use crate::object::{ObjectFormat, ObjectId, ObjectType};
+use crate::protocol::mapping::{MTP_OBJECT_PROTECTION_STATUS, PROTECTION_READ_ONLY, PROTECTION_READ_ONLY_DATA};
+
+/// The usual metadata of an object, fetched at once (see [`Object::metadata`](crate::object::Object::metadata))
+///
+/// Fields are `None` when the device does not expose them for this object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectMetadata {
+    /// The object display name (e.g. "PIC_001.jpg")
+    pub name: U16CString,
+    pub original_file_name: Option<U16CString>,
+    pub content_type: ObjectType,
+    pub format: Option<ObjectFormat>,
+    /// The size in bytes. Folders usually have none.
+    pub size: Option<u64>,
+    /// `None` for the device root
+    pub parent_id: Option<ObjectId>,
+    /// An ID that remains the same across sessions, unlike [`ObjectId`]s
+    pub persistent_unique_id: Option<U16CString>,
+    pub date_created: Option<SystemTime>,
+    pub date_modified: Option<SystemTime>,
+    pub date_authored: Option<SystemTime>,
+    pub is_hidden: Option<bool>,
+    pub is_system: Option<bool>,
+    /// Whether the content of the object cannot be modified
+    pub is_read_only: Option<bool>,
+    pub can_delete: Option<bool>,
+    /// Whether the object is meant to be used by the device only (e.g. a ringtone), rather than be played or displayed
+    pub is_non_consumable: Option<bool>,
+}
+
+impl ObjectMetadata {
+    /// The properties [`Self::from_values`] reads
+    pub const PROPERTIES: [crate::PROPERTYKEY; 15] = [
+        WPD_OBJECT_NAME,
+        WPD_OBJECT_ORIGINAL_FILE_NAME,
+        WPD_OBJECT_CONTENT_TYPE,
+        WPD_OBJECT_FORMAT,
+        WPD_OBJECT_SIZE,
+        WPD_OBJECT_PARENT_ID,
+        WPD_OBJECT_PERSISTENT_UNIQUE_ID,
+        WPD_OBJECT_DATE_CREATED,
+        WPD_OBJECT_DATE_MODIFIED,
+        WPD_OBJECT_DATE_AUTHORED,
+        WPD_OBJECT_ISHIDDEN,
+        WPD_OBJECT_ISSYSTEM,
+        WPD_OBJECT_CAN_DELETE,
+        WPD_OBJECT_NON_CONSUMABLE,
+        MTP_OBJECT_PROTECTION_STATUS,
+    ];
+
+    /// Build the metadata out of the values of [`Self::PROPERTIES`]
+    ///
+    /// Only the name and the content type are required.
+    pub fn from_values(values: &DeviceValues) -> Result<Self, MtpError> {
+        let protection_status = values.get_u32(&MTP_OBJECT_PROTECTION_STATUS).ok();
+        Ok(Self{
+            name: values.get_string(&WPD_OBJECT_NAME)?,
+            original_file_name: values.get_string(&WPD_OBJECT_ORIGINAL_FILE_NAME).ok(),
+            content_type: ObjectType::from_guid(values.get_guid(&WPD_OBJECT_CONTENT_TYPE)?),
+            format: values.get_guid(&WPD_OBJECT_FORMAT).ok().map(ObjectFormat::from_guid),
+            size: values.get_u64(&WPD_OBJECT_SIZE).ok(),
+            // The device root has an empty parent ID
+            parent_id: values.get_string(&WPD_OBJECT_PARENT_ID).ok().filter(|id| !id.is_empty()).map(ObjectId::from),
+            persistent_unique_id: values.get_string(&WPD_OBJECT_PERSISTENT_UNIQUE_ID).ok(),
+            date_created: values.get_date(&WPD_OBJECT_DATE_CREATED).ok(),
+            date_modified: values.get_date(&WPD_OBJECT_DATE_MODIFIED).ok(),
+            date_authored: values.get_date(&WPD_OBJECT_DATE_AUTHORED).ok(),
+            is_hidden: values.get_bool(&WPD_OBJECT_ISHIDDEN).ok(),
+            is_system: values.get_bool(&WPD_OBJECT_ISSYSTEM).ok(),
+            is_read_only: protection_status.map(|status| status == PROTECTION_READ_ONLY || status == PROTECTION_READ_ONLY_DATA),
+            can_delete: values.get_bool(&WPD_OBJECT_CAN_DELETE).ok()
+                .or(protection_status.map(|status| status != PROTECTION_READ_ONLY)),
+            is_non_consumable: values.get_bool(&WPD_OBJECT_NON_CONSUMABLE).ok(),
+        })
+    }
+}
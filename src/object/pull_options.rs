@@ -0,0 +1,79 @@
+use crate::io::{CancellationToken, ProgressObserver};
+
+/// What to do when the destination of a transfer already exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverwritePolicy {
+    /// Fail with [`MtpError::AlreadyExists`](crate::error::MtpError::AlreadyExists)
+    #[default]
+    Refuse,
+    /// Replace the existing file
+    Overwrite,
+}
+
+/// Options of [`Object::pull_to_file`](crate::object::Object::pull_to_file)
+///
+/// ```
+/// use winmtp::io::{CancellationToken, Progress};
+/// use winmtp::object::{OverwritePolicy, PullOptions};
+///
+/// let mut print_progress = |progress: &Progress| println!("{} bytes", progress.bytes_done);
+/// let options = PullOptions::new()
+///     .overwrite(OverwritePolicy::Overwrite)
+///     .progress(&mut print_progress)
+///     .cancellation(CancellationToken::new());
+/// ```
+pub struct PullOptions<'a> {
+    pub(crate) overwrite: OverwritePolicy,
+    pub(crate) preserve_timestamps: bool,
+    pub(crate) progress: Option<&'a mut dyn ProgressObserver>,
+    pub(crate) cancellation: CancellationToken,
+}
+
+impl<'a> PullOptions<'a> {
+    /// Refuse to overwrite, preserve timestamps, no progress report, and no way to cancel
+    pub fn new() -> Self {
+        Self{
+            overwrite: OverwritePolicy::default(),
+            preserve_timestamps: true,
+            progress: None,
+            cancellation: CancellationToken::new(),
+        }
+    }
+
+    pub fn overwrite(mut self, overwrite: OverwritePolicy) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Whether the local file gets the modification (and, on Windows, creation) dates of the object
+    pub fn preserve_timestamps(mut self, preserve_timestamps: bool) -> Self {
+        self.preserve_timestamps = preserve_timestamps;
+        self
+    }
+
+    pub fn progress(mut self, progress: &'a mut dyn ProgressObserver) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    pub fn cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+}
+
+impl Default for PullOptions<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for PullOptions<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PullOptions")
+            .field("overwrite", &self.overwrite)
+            .field("preserve_timestamps", &self.preserve_timestamps)
+            .field("cancellation", &self.cancellation)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,451 @@
+//! Copies of whole folder trees, see [`Object::pull_tree`] and [`Object::push_tree`]
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use windows::Win32::Devices::PortableDevices::WPD_OBJECT_SIZE;
+
+use crate::error::{ErrorKind, MtpError};
+use crate::io::{AggregatedProgress, CancellationToken, ProgressObserver};
+use crate::object::{Object, ObjectType, OverwritePolicy, PullOptions};
+use crate::utils::{glob_match, join_object_name};
+
+/// What to do with local symbolic links, when pushing a tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    /// Do not copy them, and report them as skipped
+    #[default]
+    Skip,
+    /// Copy the file or the folder they point to (links that would loop are skipped)
+    Follow,
+}
+
+/// Options of [`Object::pull_tree`] and [`Object::push_tree`]
+///
+/// Filters are glob patterns (see [`glob_match`]), matched against paths relative to the copied folder, using `/` as a separator.
+/// They are case-insensitive, so that `*.jpg` also matches `IMG_0001.JPG`.
+/// Files are copied if they match any `include` pattern (or if there is none), unless they match an `exclude` pattern.
+/// Folders that match an `exclude` pattern are not copied at all.
+///
+/// ```
+/// use winmtp::object::{OverwritePolicy, TreeOptions};
+///
+/// let options = TreeOptions::new()
+///     .include("*.jpg")
+///     .include("*.mp4")
+///     .exclude(".thumbnails")
+///     .overwrite(OverwritePolicy::Overwrite);
+/// ```
+pub struct TreeOptions<'a> {
+    includes: Vec<String>,
+    excludes: Vec<String>,
+    overwrite: OverwritePolicy,
+    symlinks: SymlinkPolicy,
+    preserve_timestamps: bool,
+    progress: Option<&'a mut dyn ProgressObserver>,
+    cancellation: CancellationToken,
+}
+
+impl<'a> TreeOptions<'a> {
+    /// Copy everything, skip existing files and symbolic links, preserve timestamps, no progress report, and no way to cancel
+    pub fn new() -> Self {
+        Self{
+            includes: Vec::new(),
+            excludes: Vec::new(),
+            overwrite: OverwritePolicy::default(),
+            symlinks: SymlinkPolicy::default(),
+            preserve_timestamps: true,
+            progress: None,
+            cancellation: CancellationToken::new(),
+        }
+    }
+
+    pub fn include(mut self, pattern: &str) -> Self {
+        self.includes.push(pattern.to_string());
+        self
+    }
+
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.excludes.push(pattern.to_string());
+        self
+    }
+
+    /// With [`OverwritePolicy::Refuse`], files that already exist are reported as [`SkipReason::AlreadyExists`]
+    pub fn overwrite(mut self, overwrite: OverwritePolicy) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    pub fn symlinks(mut self, symlinks: SymlinkPolicy) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// Whether pulled files get the dates of the objects (see [`PullOptions::preserve_timestamps`])
+    pub fn preserve_timestamps(mut self, preserve_timestamps: bool) -> Self {
+        self.preserve_timestamps = preserve_timestamps;
+        self
+    }
+
+    /// Progress is reported for the whole tree, not file by file
+    pub fn progress(mut self, progress: &'a mut dyn ProgressObserver) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    pub fn cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    fn is_excluded(&self, relative_path: &Path) -> bool {
+        let path = slash_path(relative_path);
+        self.excludes.iter().any(|pattern| glob_match(pattern, &path, false))
+    }
+
+    fn is_included(&self, relative_path: &Path) -> bool {
+        let path = slash_path(relative_path);
+        self.includes.is_empty() || self.includes.iter().any(|pattern| glob_match(pattern, &path, false))
+    }
+}
+
+impl Default for TreeOptions<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for TreeOptions<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TreeOptions")
+            .field("includes", &self.includes)
+            .field("excludes", &self.excludes)
+            .field("overwrite", &self.overwrite)
+            .field("symlinks", &self.symlinks)
+            .field("preserve_timestamps", &self.preserve_timestamps)
+            .field("cancellation", &self.cancellation)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Why an entry has not been copied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Because of the include or exclude filters
+    Excluded,
+    /// The destination already exists, and the overwrite policy is [`OverwritePolicy::Refuse`]
+    AlreadyExists,
+    /// A local symbolic link, skipped because of the [`SymlinkPolicy`]
+    Symlink,
+}
+
+/// What happened to an entry of a tree
+#[derive(Debug)]
+pub enum TreeOutcome {
+    /// The file has been copied, or the folder has been created (or already existed)
+    Copied{ bytes: u64 },
+    Skipped(SkipReason),
+    Failed(MtpError),
+}
+
+/// An entry of a [`TreeReport`]
+#[derive(Debug)]
+pub struct TreeEntry {
+    /// The path of the entry, relative to the copied folder
+    pub path: PathBuf,
+    pub is_folder: bool,
+    pub outcome: TreeOutcome,
+}
+
+/// What [`Object::pull_tree`] and [`Object::push_tree`] did, entry by entry
+#[derive(Debug, Default)]
+pub struct TreeReport {
+    pub entries: Vec<TreeEntry>,
+}
+
+impl TreeReport {
+    pub fn copied(&self) -> impl Iterator<Item = &TreeEntry> {
+        self.entries.iter().filter(|entry| matches!(entry.outcome, TreeOutcome::Copied{ .. }))
+    }
+
+    pub fn skipped(&self) -> impl Iterator<Item = &TreeEntry> {
+        self.entries.iter().filter(|entry| matches!(entry.outcome, TreeOutcome::Skipped(_)))
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &TreeEntry> {
+        self.entries.iter().filter(|entry| matches!(entry.outcome, TreeOutcome::Failed(_)))
+    }
+
+    /// The number of bytes of the copied files
+    pub fn bytes_copied(&self) -> u64 {
+        self.entries.iter()
+            .map(|entry| match entry.outcome {
+                TreeOutcome::Copied{ bytes } => bytes,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// Whether every entry has been copied or deliberately skipped
+    pub fn is_success(&self) -> bool {
+        self.failed().next().is_none()
+    }
+
+    fn push(&mut self, path: PathBuf, is_folder: bool, outcome: TreeOutcome) {
+        self.entries.push(TreeEntry{ path, is_folder, outcome });
+    }
+}
+
+/// A file to copy, found while walking a tree
+struct PlannedFile<T> {
+    relative_path: PathBuf,
+    source: T,
+    size: u64,
+}
+
+/// Errors that stop the whole copy, rather than just failing an entry
+fn is_fatal(err: &MtpError) -> bool {
+    matches!(err.kind(), ErrorKind::DeviceGone | ErrorKind::Cancelled)
+}
+
+fn slash_path(relative_path: &Path) -> String {
+    relative_path.iter().map(|component| component.to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+impl Object {
+    /// Download this folder and everything it contains into `local_dir`, which is created if needed
+    ///
+    /// Failures that only affect an entry are listed in the returned report, and do not prevent the other entries from being copied.
+    /// An error is only returned if the whole copy failed, e.g. if the device is gone, or if the copy has been cancelled.<br/>
+    /// Files are downloaded with [`Self::pull_to_file`], hence a failed or cancelled copy never leaves half-written files.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use std::path::Path;
+    /// use winmtp::object::TreeOptions;
+    /// # let provider = winmtp::Provider::new().unwrap();
+    /// # let basic_device = &provider.enumerate_devices().unwrap()[0];
+    /// # let app_identifiers = winmtp::make_current_app_identifiers!();
+    /// # let device = basic_device.open(&app_identifiers, false).unwrap();
+    /// let dcim = device.content().unwrap().root().unwrap().object_by_path(Path::new("Internal storage/DCIM")).unwrap();
+    /// let report = dcim.pull_tree(Path::new("phone-backup"), TreeOptions::new().exclude(".thumbnails")).unwrap();
+    /// for entry in report.failed() {
+    ///     println!("Failed to back up {}: {:?}", entry.path.display(), entry.outcome);
+    /// }
+    /// ```
+    pub fn pull_tree(&self, local_dir: &Path, mut options: TreeOptions) -> Result<TreeReport, MtpError> {
+        let mut report = TreeReport::default();
+        let mut folders = Vec::new();
+        let mut files = Vec::new();
+        self.plan_pull(PathBuf::new(), &options, &mut folders, &mut files, &mut report)?;
+
+        std::fs::create_dir_all(local_dir)?;
+        for relative_path in folders {
+            let outcome = match std::fs::create_dir_all(local_dir.join(&relative_path)) {
+                Ok(()) => TreeOutcome::Copied{ bytes: 0 },
+                Err(err) => TreeOutcome::Failed(err.into()),
+            };
+            report.push(relative_path, true, outcome);
+        }
+
+        let bytes_total = files.iter().map(|file: &PlannedFile<Object>| file.size).sum();
+        let mut tree_progress = AggregatedProgress::new(options.progress.take(), bytes_total);
+        for file in files {
+            if options.cancellation.is_cancelled() {
+                return Err(MtpError::Cancelled);
+            }
+            let pull_options = PullOptions::new()
+                .overwrite(options.overwrite)
+                .preserve_timestamps(options.preserve_timestamps)
+                .progress(&mut tree_progress)
+                .cancellation(options.cancellation.clone());
+            let outcome = match file.source.pull_to_file(&local_dir.join(&file.relative_path), pull_options) {
+                Ok(bytes) => TreeOutcome::Copied{ bytes },
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => TreeOutcome::Skipped(SkipReason::AlreadyExists),
+                Err(err) if is_fatal(&err) => return Err(err),
+                Err(err) => TreeOutcome::Failed(err),
+            };
+            tree_progress.next_file(file.size);
+            report.push(file.relative_path, false, outcome);
+        }
+        Ok(report)
+    }
+
+    fn plan_pull(&self, relative_dir: PathBuf, options: &TreeOptions, folders: &mut Vec<PathBuf>, files: &mut Vec<PlannedFile<Object>>, report: &mut TreeReport) -> Result<(), MtpError> {
+        let mut children: Vec<Object> = self.children()?.collect();
+        children.sort_by_key(|child| child.name().to_string_lossy());
+        for child in children {
+            let name = child.original_file_name().unwrap_or(child.name()).to_os_string();
+            let is_folder = matches!(child.object_type(), ObjectType::Folder | ObjectType::FunctionalObject);
+            let relative_path = match join_object_name(&relative_dir, &name) {
+                Ok(relative_path) => relative_path,
+                Err((reported_path, err)) => {
+                    report.push(reported_path, is_folder, TreeOutcome::Failed(err));
+                    continue;
+                },
+            };
+            if options.is_excluded(&relative_path) || (!is_folder && !options.is_included(&relative_path)) {
+                report.push(relative_path, is_folder, TreeOutcome::Skipped(SkipReason::Excluded));
+                continue;
+            }
+
+            if is_folder {
+                // Parents must come before their children
+                let index = folders.len();
+                match child.plan_pull(relative_path.clone(), options, folders, files, report) {
+                    Ok(()) => folders.insert(index, relative_path),
+                    Err(err) if is_fatal(&err) => return Err(err),
+                    Err(err) => report.push(relative_path, true, TreeOutcome::Failed(err)),
+                }
+            } else {
+                match child.properties(&[WPD_OBJECT_SIZE]) {
+                    Ok(values) => files.push(PlannedFile{ relative_path, size: values.get_u64(&WPD_OBJECT_SIZE).unwrap_or(0), source: child }),
+                    Err(err) if is_fatal(&err) => return Err(err),
+                    Err(err) => report.push(relative_path, false, TreeOutcome::Failed(err)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Upload `local_dir` and everything it contains into this folder
+    ///
+    /// The content of `local_dir` is copied, not `local_dir` itself. Sub-folders are created on the device if needed.<br/>
+    /// See [`Self::pull_tree`] about the returned report. Files are uploaded with [`Self::push_file_with_progress`],
+    /// hence a failed or cancelled copy never leaves partial objects on the device.
+    pub fn push_tree(&self, local_dir: &Path, mut options: TreeOptions) -> Result<TreeReport, MtpError> {
+        let mut report = TreeReport::default();
+        let mut folders = Vec::new();
+        let mut files = Vec::new();
+        let mut visited = vec![std::fs::canonicalize(local_dir)?];
+        plan_push(local_dir, PathBuf::new(), &options, &mut visited, &mut folders, &mut files, &mut report)?;
+
+        let bytes_total = files.iter().map(|file: &PlannedFile<PathBuf>| file.size).sum();
+        let mut tree_progress = AggregatedProgress::new(options.progress.take(), bytes_total);
+
+        // Device folders, by relative path. Their parents always come first.
+        let mut device_folders: Vec<(PathBuf, Result<Object, ()>)> = vec![(PathBuf::new(), Ok(self.clone()))];
+        for relative_path in folders {
+            // Entries of folders whose parent could not be created are not reported again
+            let Some(parent) = device_folder(&device_folders, relative_path.parent().unwrap_or(Path::new(""))) else {
+                device_folders.push((relative_path, Err(())));
+                continue;
+            };
+            let folder = match parent.find_or_create_subfolder(relative_path.file_name().unwrap_or_default()) {
+                Ok(folder) => {
+                    report.push(relative_path.clone(), true, TreeOutcome::Copied{ bytes: 0 });
+                    Ok(folder)
+                },
+                Err(err) if is_fatal(&err) => return Err(err),
+                Err(err) => {
+                    report.push(relative_path.clone(), true, TreeOutcome::Failed(err));
+                    Err(())
+                },
+            };
+            device_folders.push((relative_path, folder));
+        }
+
+        for file in files {
+            if options.cancellation.is_cancelled() {
+                return Err(MtpError::Cancelled);
+            }
+            let Some(parent) = device_folder(&device_folders, file.relative_path.parent().unwrap_or(Path::new(""))) else {
+                continue;
+            };
+            let allow_overwrite = options.overwrite == OverwritePolicy::Overwrite;
+            let outcome = match parent.push_file_with_progress(&file.source, allow_overwrite, &mut tree_progress, &options.cancellation) {
+                Ok(()) => TreeOutcome::Copied{ bytes: file.size },
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => TreeOutcome::Skipped(SkipReason::AlreadyExists),
+                Err(err) if is_fatal(&err) => return Err(err),
+                Err(err) => TreeOutcome::Failed(err),
+            };
+            tree_progress.next_file(file.size);
+            report.push(file.relative_path, false, outcome);
+        }
+        Ok(report)
+    }
+
+    fn find_or_create_subfolder(&self, folder_name: &std::ffi::OsStr) -> Result<Object, MtpError> {
+        match self.object_by_path(Path::new(folder_name)) {
+            Ok(existing) if matches!(existing.object_type(), ObjectType::Folder | ObjectType::FunctionalObject) => Ok(existing),
+            Ok(_existing) => Err(MtpError::AlreadyExists),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                let created_id = self.create_subfolder(folder_name)?;
+                self.device_content.object_by_id(created_id)
+            },
+            Err(err) => Err(err),
+        }
+    }
+}
+
+fn device_folder<'f>(device_folders: &'f [(PathBuf, Result<Object, ()>)], relative_path: &Path) -> Option<&'f Object> {
+    device_folders.iter()
+        .find(|(path, _)| path == relative_path)
+        .and_then(|(_, folder)| folder.as_ref().ok())
+}
+
+fn plan_push(dir: &Path, relative_dir: PathBuf, options: &TreeOptions, visited: &mut Vec<PathBuf>, folders: &mut Vec<PathBuf>, files: &mut Vec<PlannedFile<PathBuf>>, report: &mut TreeReport) -> Result<(), MtpError> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name: OsString = entry.file_name();
+        let relative_path = relative_dir.join(&name);
+        let local_path = entry.path();
+
+        let mut metadata = match std::fs::symlink_metadata(&local_path) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                report.push(relative_path, false, TreeOutcome::Failed(err.into()));
+                continue;
+            },
+        };
+        if metadata.file_type().is_symlink() {
+            let target_metadata = match options.symlinks {
+                SymlinkPolicy::Skip => None,
+                // Dangling links are skipped as well
+                SymlinkPolicy::Follow => std::fs::metadata(&local_path).ok(),
+            };
+            match target_metadata {
+                Some(target_metadata) => metadata = target_metadata,
+                None => {
+                    report.push(relative_path, false, TreeOutcome::Skipped(SkipReason::Symlink));
+                    continue;
+                },
+            }
+        }
+
+        let is_folder = metadata.is_dir();
+        if options.is_excluded(&relative_path) || (!is_folder && !options.is_included(&relative_path)) {
+            report.push(relative_path, is_folder, TreeOutcome::Skipped(SkipReason::Excluded));
+            continue;
+        }
+
+        if is_folder {
+            let canonical_path = match std::fs::canonicalize(&local_path) {
+                Ok(canonical_path) => canonical_path,
+                Err(err) => {
+                    report.push(relative_path, true, TreeOutcome::Failed(err.into()));
+                    continue;
+                },
+            };
+            if visited.contains(&canonical_path) {
+                // A link to one of its own parents
+                report.push(relative_path, true, TreeOutcome::Skipped(SkipReason::Symlink));
+                continue;
+            }
+            visited.push(canonical_path);
+            // Parents are created before their children
+            let folder_index = folders.len();
+            folders.push(relative_path.clone());
+            if let Err(err) = plan_push(&local_path, relative_path.clone(), options, visited, folders, files, report) {
+                // A folder that cannot be read is not created
+                folders.truncate(folder_index);
+                report.push(relative_path, true, TreeOutcome::Failed(err));
+            }
+            visited.pop();
+        } else {
+            files.push(PlannedFile{ relative_path, source: local_path, size: metadata.len() });
+        }
+    }
+    Ok(())
+}
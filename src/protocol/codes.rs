@@ -0,0 +1,315 @@
+//! Operation, response, event, format and property codes defined by the PTP and MTP specifications
+
+macro_rules! code_enum {
+    ($(#[$meta:meta])* $name:ident { $($(#[$variant_meta:meta])* $variant:ident = $value:literal,)* }) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($(#[$variant_meta])* $variant,)*
+            /// A code that is not known to this crate (e.g. a vendor extension)
+            Other(u16),
+        }
+
+        impl $name {
+            pub fn from_u16(code: u16) -> Self {
+                match code {
+                    $($value => Self::$variant,)*
+                    other => Self::Other(other),
+                }
+            }
+
+            pub fn as_u16(&self) -> u16 {
+                match self {
+                    $(Self::$variant => $value,)*
+                    Self::Other(code) => *code,
+                }
+            }
+        }
+    };
+}
+
+code_enum!(
+    /// Operations an initiator can request
+    OperationCode {
+        GetDeviceInfo = 0x1001,
+        OpenSession = 0x1002,
+        CloseSession = 0x1003,
+        GetStorageIds = 0x1004,
+        GetStorageInfo = 0x1005,
+        GetNumObjects = 0x1006,
+        GetObjectHandles = 0x1007,
+        GetObjectInfo = 0x1008,
+        GetObject = 0x1009,
+        GetThumb = 0x100A,
+        DeleteObject = 0x100B,
+        SendObjectInfo = 0x100C,
+        SendObject = 0x100D,
+        InitiateCapture = 0x100E,
+        FormatStore = 0x100F,
+        ResetDevice = 0x1010,
+        SelfTest = 0x1011,
+        SetObjectProtection = 0x1012,
+        PowerDown = 0x1013,
+        GetDevicePropDesc = 0x1014,
+        GetDevicePropValue = 0x1015,
+        SetDevicePropValue = 0x1016,
+        ResetDevicePropValue = 0x1017,
+        TerminateOpenCapture = 0x1018,
+        MoveObject = 0x1019,
+        CopyObject = 0x101A,
+        GetPartialObject = 0x101B,
+        InitiateOpenCapture = 0x101C,
+        /// Android extension
+        GetPartialObject64 = 0x95C1,
+        /// Android extension
+        SendPartialObject = 0x95C2,
+        /// Android extension
+        TruncateObject = 0x95C3,
+        /// Android extension
+        BeginEditObject = 0x95C4,
+        /// Android extension
+        EndEditObject = 0x95C5,
+        GetObjectPropsSupported = 0x9801,
+        GetObjectPropDesc = 0x9802,
+        GetObjectPropValue = 0x9803,
+        SetObjectPropValue = 0x9804,
+        GetObjectPropList = 0x9805,
+        SetObjectPropList = 0x9806,
+        GetInterdependentPropDesc = 0x9807,
+        SendObjectPropList = 0x9808,
+        GetObjectReferences = 0x9810,
+        SetObjectReferences = 0x9811,
+    }
+);
+
+code_enum!(
+    /// Results of operations
+    ResponseCode {
+        Ok = 0x2001,
+        GeneralError = 0x2002,
+        SessionNotOpen = 0x2003,
+        InvalidTransactionId = 0x2004,
+        OperationNotSupported = 0x2005,
+        ParameterNotSupported = 0x2006,
+        IncompleteTransfer = 0x2007,
+        InvalidStorageId = 0x2008,
+        InvalidObjectHandle = 0x2009,
+        DevicePropNotSupported = 0x200A,
+        InvalidObjectFormatCode = 0x200B,
+        StoreFull = 0x200C,
+        ObjectWriteProtected = 0x200D,
+        StoreReadOnly = 0x200E,
+        AccessDenied = 0x200F,
+        NoThumbnailPresent = 0x2010,
+        SelfTestFailed = 0x2011,
+        PartialDeletion = 0x2012,
+        StoreNotAvailable = 0x2013,
+        SpecificationByFormatUnsupported = 0x2014,
+        NoValidObjectInfo = 0x2015,
+        InvalidCodeFormat = 0x2016,
+        UnknownVendorCode = 0x2017,
+        CaptureAlreadyTerminated = 0x2018,
+        DeviceBusy = 0x2019,
+        InvalidParentObject = 0x201A,
+        InvalidDevicePropFormat = 0x201B,
+        InvalidDevicePropValue = 0x201C,
+        InvalidParameter = 0x201D,
+        SessionAlreadyOpen = 0x201E,
+        TransactionCancelled = 0x201F,
+        SpecificationOfDestinationUnsupported = 0x2020,
+        InvalidObjectPropCode = 0xA801,
+        InvalidObjectPropFormat = 0xA802,
+        InvalidObjectPropValue = 0xA803,
+        InvalidObjectReference = 0xA804,
+        GroupNotSupported = 0xA805,
+        InvalidDataset = 0xA806,
+        SpecificationByGroupUnsupported = 0xA807,
+        SpecificationByDepthUnsupported = 0xA808,
+        ObjectTooLarge = 0xA809,
+        ObjectPropNotSupported = 0xA80A,
+    }
+);
+
+code_enum!(
+    /// Events a responder can send
+    EventCode {
+        CancelTransaction = 0x4001,
+        ObjectAdded = 0x4002,
+        ObjectRemoved = 0x4003,
+        StoreAdded = 0x4004,
+        StoreRemoved = 0x4005,
+        DevicePropChanged = 0x4006,
+        ObjectInfoChanged = 0x4007,
+        DeviceInfoChanged = 0x4008,
+        RequestObjectTransfer = 0x4009,
+        StoreFull = 0x400A,
+        DeviceReset = 0x400B,
+        StorageInfoChanged = 0x400C,
+        CaptureComplete = 0x400D,
+        UnreportedStatus = 0x400E,
+        ObjectPropChanged = 0xC801,
+        ObjectPropDescChanged = 0xC802,
+        ObjectReferencesChanged = 0xC803,
+    }
+);
+
+code_enum!(
+    /// Formats of objects
+    ObjectFormatCode {
+        Undefined = 0x3000,
+        /// Folders are "associations" in PTP parlance
+        Association = 0x3001,
+        Script = 0x3002,
+        Executable = 0x3003,
+        Text = 0x3004,
+        Html = 0x3005,
+        Dpof = 0x3006,
+        Aiff = 0x3007,
+        Wav = 0x3008,
+        Mp3 = 0x3009,
+        Avi = 0x300A,
+        Mpeg = 0x300B,
+        Asf = 0x300C,
+        UndefinedImage = 0x3800,
+        ExifJpeg = 0x3801,
+        TiffEp = 0x3802,
+        FlashPix = 0x3803,
+        Bmp = 0x3804,
+        Ciff = 0x3805,
+        Gif = 0x3807,
+        Jfif = 0x3808,
+        Pcd = 0x3809,
+        Pict = 0x380A,
+        Png = 0x380B,
+        Tiff = 0x380D,
+        TiffIt = 0x380E,
+        Jp2 = 0x380F,
+        Jpx = 0x3810,
+        UndefinedFirmware = 0xB802,
+        WindowsImageFormat = 0xB881,
+        UndefinedAudio = 0xB900,
+        Wma = 0xB901,
+        Ogg = 0xB902,
+        Aac = 0xB903,
+        Audible = 0xB904,
+        Flac = 0xB906,
+        UndefinedVideo = 0xB980,
+        Wmv = 0xB981,
+        Mp4Container = 0xB982,
+        Mp2 = 0xB983,
+        ThreeGpContainer = 0xB984,
+        UndefinedCollection = 0xBA00,
+        AbstractMultimediaAlbum = 0xBA01,
+        AbstractImageAlbum = 0xBA02,
+        AbstractAudioAlbum = 0xBA03,
+        AbstractVideoAlbum = 0xBA04,
+        AbstractAvPlaylist = 0xBA05,
+        AbstractContactGroup = 0xBA06,
+        AbstractMessageFolder = 0xBA07,
+        AbstractChapteredProduction = 0xBA08,
+        AbstractAudioPlaylist = 0xBA09,
+        AbstractVideoPlaylist = 0xBA0A,
+        AbstractMediacast = 0xBA0B,
+        WplPlaylist = 0xBA10,
+        M3uPlaylist = 0xBA11,
+        MplPlaylist = 0xBA12,
+        AsxPlaylist = 0xBA13,
+        PlsPlaylist = 0xBA14,
+        UndefinedDocument = 0xBA80,
+        AbstractDocument = 0xBA81,
+        XmlDocument = 0xBA82,
+        MsWordDocument = 0xBA83,
+        MhtCompiledHtmlDocument = 0xBA84,
+        MsExcelSpreadsheet = 0xBA85,
+        MsPowerpointPresentation = 0xBA86,
+        UndefinedMessage = 0xBB00,
+        AbstractMessage = 0xBB01,
+        UndefinedContact = 0xBB80,
+        AbstractContact = 0xBB81,
+        VCard2 = 0xBB82,
+    }
+);
+
+code_enum!(
+    /// Object properties (MTP only)
+    ObjectPropertyCode {
+        StorageId = 0xDC01,
+        ObjectFormat = 0xDC02,
+        ProtectionStatus = 0xDC03,
+        ObjectSize = 0xDC04,
+        AssociationType = 0xDC05,
+        AssociationDesc = 0xDC06,
+        ObjectFileName = 0xDC07,
+        DateCreated = 0xDC08,
+        DateModified = 0xDC09,
+        Keywords = 0xDC0A,
+        ParentObject = 0xDC0B,
+        AllowedFolderContents = 0xDC0C,
+        Hidden = 0xDC0D,
+        SystemObject = 0xDC0E,
+        PersistentUniqueObjectIdentifier = 0xDC41,
+        SyncId = 0xDC42,
+        PropertyBag = 0xDC43,
+        Name = 0xDC44,
+        CreatedBy = 0xDC45,
+        Artist = 0xDC46,
+        DateAuthored = 0xDC47,
+        Description = 0xDC48,
+        UrlReference = 0xDC49,
+        LanguageLocale = 0xDC4A,
+        CopyrightInformation = 0xDC4B,
+        Source = 0xDC4C,
+        OriginLocation = 0xDC4D,
+        DateAdded = 0xDC4E,
+        NonConsumable = 0xDC4F,
+        CorruptOrUnplayable = 0xDC50,
+        ProducerSerialNumber = 0xDC51,
+    }
+);
+
+code_enum!(
+    /// Device properties
+    DevicePropertyCode {
+        BatteryLevel = 0x5001,
+        DeviceFriendlyName = 0xD402,
+        PerceivedDeviceType = 0xD407,
+    }
+);
+
+code_enum!(
+    /// Types of values found in datasets and properties
+    DataType {
+        Undefined = 0x0000,
+        Int8 = 0x0001,
+        Uint8 = 0x0002,
+        Int16 = 0x0003,
+        Uint16 = 0x0004,
+        Int32 = 0x0005,
+        Uint32 = 0x0006,
+        Int64 = 0x0007,
+        Uint64 = 0x0008,
+        Int128 = 0x0009,
+        Uint128 = 0x000A,
+        ArrayInt8 = 0x4001,
+        ArrayUint8 = 0x4002,
+        ArrayInt16 = 0x4003,
+        ArrayUint16 = 0x4004,
+        ArrayInt32 = 0x4005,
+        ArrayUint32 = 0x4006,
+        ArrayInt64 = 0x4007,
+        ArrayUint64 = 0x4008,
+        ArrayInt128 = 0x4009,
+        ArrayUint128 = 0x400A,
+        String = 0xFFFF,
+    }
+);
+
+impl DataType {
+    /// For array types, the type of their elements
+    pub fn element_type(&self) -> Option<DataType> {
+        match self.as_u16() {
+            code @ 0x4001..=0x400A => Some(DataType::from_u16(code & 0x00FF)),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! Generic containers, that wrap every command, data phase, response and event
+
+use std::io::{self, Read, Write};
+
+use crate::error::ProtocolError;
+use crate::protocol::codes::{OperationCode, ResponseCode, EventCode};
+use crate::protocol::data::{Reader, Writer};
+
+/// The kind of a container
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContainerType {
+    Command,
+    Data,
+    Response,
+    Event,
+}
+
+impl ContainerType {
+    pub fn from_u16(value: u16) -> Result<Self, ProtocolError> {
+        match value {
+            1 => Ok(Self::Command),
+            2 => Ok(Self::Data),
+            3 => Ok(Self::Response),
+            4 => Ok(Self::Event),
+            other => Err(ProtocolError::InvalidContainerType(other)),
+        }
+    }
+
+    pub fn as_u16(&self) -> u16 {
+        match self {
+            Self::Command => 1,
+            Self::Data => 2,
+            Self::Response => 3,
+            Self::Event => 4,
+        }
+    }
+}
+
+/// The fixed-size header of every container
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContainerHeader {
+    /// Total length of the container, including this header, or [`Self::UNKNOWN_LENGTH`] for containers that do not fit in 32 bits
+    pub length: u32,
+    pub container_type: ContainerType,
+    /// Operation, response or event code, depending on the container type
+    pub code: u16,
+    pub transaction_id: u32,
+}
+
+impl ContainerHeader {
+    pub const LEN: usize = 12;
+
+    /// The length of data phases of 4 GiB or more, that only end with a short USB packet
+    pub const UNKNOWN_LENGTH: u32 = 0xFFFFFFFF;
+
+    /// The header of a data phase, whose payload is sent separately
+    pub fn data(operation: OperationCode, transaction_id: u32, payload_len: u64) -> Self {
+        Self{
+            length: u32::try_from(payload_len.saturating_add(Self::LEN as u64)).unwrap_or(Self::UNKNOWN_LENGTH),
+            container_type: ContainerType::Data,
+            code: operation.as_u16(),
+            transaction_id,
+        }
+    }
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut writer = Writer::new();
+        writer.put_u32(self.length);
+        writer.put_u16(self.container_type.as_u16());
+        writer.put_u16(self.code);
+        writer.put_u32(self.transaction_id);
+
+        let mut header = [0; Self::LEN];
+        header.copy_from_slice(&writer.into_bytes());
+        header
+    }
+
+    /// Decode the header at the start of `bytes`. Trailing bytes (e.g. the payload) are ignored.
+    pub fn decode(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        let mut reader = Reader::new(bytes);
+        let length = reader.get_u32()?;
+        let container_type = ContainerType::from_u16(reader.get_u16()?)?;
+        let code = reader.get_u16()?;
+        let transaction_id = reader.get_u32()?;
+        if (length as usize) < Self::LEN {
+            return Err(ProtocolError::InvalidLength(length));
+        }
+        Ok(Self{ length, container_type, code, transaction_id })
+    }
+
+    /// Read a header from a byte stream
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut header_bytes = [0; Self::LEN];
+        reader.read_exact(&mut header_bytes)?;
+        Self::decode(&header_bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Length of the payload that follows this header. This is meaningless for containers of [unknown length](Self::UNKNOWN_LENGTH),
+    /// and 0 for (invalid) headers that are shorter than themselves.
+    pub fn payload_len(&self) -> usize {
+        (self.length as usize).saturating_sub(Self::LEN)
+    }
+}
+
+/// A whole container, with its payload
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Container {
+    pub container_type: ContainerType,
+    pub code: u16,
+    pub transaction_id: u32,
+    /// Parameters (for commands, responses and events) or data (for data phases)
+    pub payload: Vec<u8>,
+}
+
+impl Container {
+    /// Commands, responses and events have at most 5 parameters
+    pub const MAX_PARAMS: usize = 5;
+
+    pub fn command(operation: OperationCode, transaction_id: u32, params: &[u32]) -> Self {
+        Self::with_params(ContainerType::Command, operation.as_u16(), transaction_id, params)
+    }
+
+    pub fn data(operation: OperationCode, transaction_id: u32, data: Vec<u8>) -> Self {
+        Self{ container_type: ContainerType::Data, code: operation.as_u16(), transaction_id, payload: data }
+    }
+
+    pub fn response(response: ResponseCode, transaction_id: u32, params: &[u32]) -> Self {
+        Self::with_params(ContainerType::Response, response.as_u16(), transaction_id, params)
+    }
+
+    pub fn event(event: EventCode, transaction_id: u32, params: &[u32]) -> Self {
+        Self::with_params(ContainerType::Event, event.as_u16(), transaction_id, params)
+    }
+
+    fn with_params(container_type: ContainerType, code: u16, transaction_id: u32, params: &[u32]) -> Self {
+        let mut writer = Writer::new();
+        for param in params.iter().take(Self::MAX_PARAMS) {
+            writer.put_u32(*param);
+        }
+        Self{ container_type, code, transaction_id, payload: writer.into_bytes() }
+    }
+
+    /// Parse the payload as a list of parameters
+    pub fn params(&self) -> Result<Vec<u32>, ProtocolError> {
+        if !self.payload.len().is_multiple_of(4) || self.payload.len() > 4 * Self::MAX_PARAMS {
+            return Err(ProtocolError::InvalidLength(self.payload.len() as u32));
+        }
+        let mut reader = Reader::new(&self.payload);
+        (0..self.payload.len() / 4).map(|_| reader.get_u32()).collect()
+    }
+
+    /// Get a parameter, or 0 if it is missing (this is what the specification mandates for omitted parameters)
+    pub fn param(&self, index: usize) -> u32 {
+        self.params()
+            .ok()
+            .and_then(|params| params.get(index).copied())
+            .unwrap_or(0)
+    }
+
+    pub fn operation_code(&self) -> OperationCode {
+        OperationCode::from_u16(self.code)
+    }
+
+    pub fn response_code(&self) -> ResponseCode {
+        ResponseCode::from_u16(self.code)
+    }
+
+    pub fn event_code(&self) -> EventCode {
+        EventCode::from_u16(self.code)
+    }
+
+    pub fn header(&self) -> ContainerHeader {
+        ContainerHeader{
+            length: u32::try_from(ContainerHeader::LEN + self.payload.len()).unwrap_or(ContainerHeader::UNKNOWN_LENGTH),
+            container_type: self.container_type,
+            code: self.code,
+            transaction_id: self.transaction_id,
+        }
+    }
+
+    /// A container with the fields of `header`, whose payload has been read separately
+    pub fn from_header(header: &ContainerHeader, payload: Vec<u8>) -> Self {
+        Self{ container_type: header.container_type, code: header.code, transaction_id: header.transaction_id, payload }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(ContainerHeader::LEN + self.payload.len());
+        bytes.extend_from_slice(&self.header().encode());
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+
+    /// Decode a container, that must span the whole `bytes`
+    pub fn decode(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        let header = ContainerHeader::decode(bytes)?;
+        let spans_bytes = match header.length {
+            ContainerHeader::UNKNOWN_LENGTH => bytes.len() >= ContainerHeader::UNKNOWN_LENGTH as usize,
+            length => length as usize == bytes.len(),
+        };
+        if !spans_bytes {
+            return Err(ProtocolError::InvalidLength(header.length));
+        }
+        Ok(Self{
+            container_type: header.container_type,
+            code: header.code,
+            transaction_id: header.transaction_id,
+            payload: bytes[ContainerHeader::LEN..].to_vec(),
+        })
+    }
+
+    /// Read a container from a byte stream in which containers are simply concatenated (e.g. a socket or a pipe)
+    ///
+    /// The payload buffer grows as data arrives, so that a bogus length does not allocate more than what the stream actually holds.<br/>
+    /// Containers of [unknown length](ContainerHeader::UNKNOWN_LENGTH) are rejected, as nothing would tell where they end.
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let header = ContainerHeader::read_from(reader)?;
+        Self::read_payload_from(&header, reader)
+    }
+
+    /// Read the payload of a container whose header has already been read, like [`Self::read_from`] does
+    pub fn read_payload_from<R: Read>(header: &ContainerHeader, reader: &mut R) -> io::Result<Self> {
+        if header.length == ContainerHeader::UNKNOWN_LENGTH {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, ProtocolError::InvalidLength(header.length)));
+        }
+
+        let mut payload = Vec::new();
+        reader.take(header.payload_len() as u64).read_to_end(&mut payload)?;
+        if payload.len() != header.payload_len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, ProtocolError::Truncated));
+        }
+        Ok(Self::from_header(header, payload))
+    }
+
+    /// The reverse of [`Self::read_from`]. Containers of 4 GiB or more are rejected, as they could not be read back.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        if self.header().length == ContainerHeader::UNKNOWN_LENGTH {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, ProtocolError::InvalidLength(ContainerHeader::UNKNOWN_LENGTH)));
+        }
+        writer.write_all(&self.encode())
+    }
+}
@@ -0,0 +1,261 @@
+//! Encoding and decoding of the primitive types used in MTP datasets
+//!
+//! Every integer is little-endian.<br/>
+//! Strings are made of a one-byte length (in UTF-16 code units, including the null terminator), followed by the UTF-16LE, null-terminated, characters.
+//! Empty strings are a single zero byte.<br/>
+//! Arrays are made of a 4-byte element count, followed by the elements.
+
+use crate::error::ProtocolError;
+use crate::protocol::codes::DataType;
+
+/// A typed value, as found in properties and property descriptions
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    I8(i8),
+    U8(u8),
+    I16(i16),
+    U16(u16),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    U64(u64),
+    I128(i128),
+    U128(u128),
+    /// The elements of an array all have the same type
+    Array(Vec<Value>),
+    Str(String),
+}
+
+impl Value {
+    /// Returns this value as an unsigned integer, if it is an integer that fits
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::I8(v) => u64::try_from(*v).ok(),
+            Value::U8(v) => Some(*v as u64),
+            Value::I16(v) => u64::try_from(*v).ok(),
+            Value::U16(v) => Some(*v as u64),
+            Value::I32(v) => u64::try_from(*v).ok(),
+            Value::U32(v) => Some(*v as u64),
+            Value::I64(v) => u64::try_from(*v).ok(),
+            Value::U64(v) => Some(*v),
+            Value::I128(v) => u64::try_from(*v).ok(),
+            Value::U128(v) => u64::try_from(*v).ok(),
+            Value::Array(_) | Value::Str(_) => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// Serializes values into a byte buffer
+#[derive(Debug, Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn put_u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn put_u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_u128(&mut self, value: u128) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Write an MTP string. Strings that are too long for MTP (more than 254 UTF-16 code units) are truncated.
+    pub fn put_string(&mut self, value: &str) {
+        if value.is_empty() {
+            self.put_u8(0);
+            return;
+        }
+
+        // Strings hold at most 255 units, including the terminating null. Longer ones are truncated, without splitting surrogate pairs.
+        let mut units = Vec::new();
+        for c in value.chars() {
+            if units.len() + c.len_utf16() > 254 {
+                break;
+            }
+            units.extend_from_slice(c.encode_utf16(&mut [0; 2]));
+        }
+        units.push(0);
+        self.put_u8(units.len() as u8);
+        for unit in units {
+            self.put_u16(unit);
+        }
+    }
+
+    pub fn put_u16_array(&mut self, values: &[u16]) {
+        self.put_u32(values.len() as u32);
+        for value in values {
+            self.put_u16(*value);
+        }
+    }
+
+    pub fn put_u32_array(&mut self, values: &[u32]) {
+        self.put_u32(values.len() as u32);
+        for value in values {
+            self.put_u32(*value);
+        }
+    }
+
+    /// Write a typed value. Its type is not written, as it is always known from the context.
+    pub fn put_value(&mut self, value: &Value) {
+        match value {
+            Value::I8(v) => self.put_bytes(&v.to_le_bytes()),
+            Value::U8(v) => self.put_u8(*v),
+            Value::I16(v) => self.put_bytes(&v.to_le_bytes()),
+            Value::U16(v) => self.put_u16(*v),
+            Value::I32(v) => self.put_bytes(&v.to_le_bytes()),
+            Value::U32(v) => self.put_u32(*v),
+            Value::I64(v) => self.put_bytes(&v.to_le_bytes()),
+            Value::U64(v) => self.put_u64(*v),
+            Value::I128(v) => self.put_bytes(&v.to_le_bytes()),
+            Value::U128(v) => self.put_u128(*v),
+            Value::Array(values) => {
+                self.put_u32(values.len() as u32);
+                for value in values {
+                    self.put_value(value);
+                }
+            },
+            Value::Str(s) => self.put_string(s),
+        }
+    }
+}
+
+/// Deserializes values from a byte buffer
+#[derive(Debug)]
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self{ buf, pos: 0 }
+    }
+
+    /// How many bytes have not been read yet
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    pub fn get_bytes(&mut self, len: usize) -> Result<&'a [u8], ProtocolError> {
+        if self.remaining() < len {
+            return Err(ProtocolError::Truncated);
+        }
+        let bytes = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn get_array<const N: usize>(&mut self) -> Result<[u8; N], ProtocolError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.get_bytes(N)?);
+        Ok(array)
+    }
+
+    pub fn get_u8(&mut self) -> Result<u8, ProtocolError> {
+        Ok(self.get_bytes(1)?[0])
+    }
+
+    pub fn get_u16(&mut self) -> Result<u16, ProtocolError> {
+        self.get_array().map(u16::from_le_bytes)
+    }
+
+    pub fn get_u32(&mut self) -> Result<u32, ProtocolError> {
+        self.get_array().map(u32::from_le_bytes)
+    }
+
+    pub fn get_u64(&mut self) -> Result<u64, ProtocolError> {
+        self.get_array().map(u64::from_le_bytes)
+    }
+
+    pub fn get_u128(&mut self) -> Result<u128, ProtocolError> {
+        self.get_array().map(u128::from_le_bytes)
+    }
+
+    pub fn get_string(&mut self) -> Result<String, ProtocolError> {
+        let len = self.get_u8()? as usize;
+        let mut units = Vec::with_capacity(len);
+        for _ in 0..len {
+            units.push(self.get_u16()?);
+        }
+        // Strip the null terminator (some devices omit it)
+        if units.last() == Some(&0) {
+            units.pop();
+        }
+        String::from_utf16(&units).map_err(|_| ProtocolError::InvalidString)
+    }
+
+    pub fn get_u16_array(&mut self) -> Result<Vec<u16>, ProtocolError> {
+        let count = self.get_count(2)?;
+        (0..count).map(|_| self.get_u16()).collect()
+    }
+
+    pub fn get_u32_array(&mut self) -> Result<Vec<u32>, ProtocolError> {
+        let count = self.get_count(4)?;
+        (0..count).map(|_| self.get_u32()).collect()
+    }
+
+    /// Read an element count, making sure the buffer is large enough for that many elements
+    fn get_count(&mut self, element_size: usize) -> Result<usize, ProtocolError> {
+        let count = self.get_u32()? as usize;
+        if count.saturating_mul(element_size) > self.remaining() {
+            return Err(ProtocolError::Truncated);
+        }
+        Ok(count)
+    }
+
+    /// Read a value of a given type
+    pub fn get_value(&mut self, data_type: DataType) -> Result<Value, ProtocolError> {
+        if let Some(element_type) = data_type.element_type() {
+            let count = self.get_count(1)?;
+            let values = (0..count).map(|_| self.get_value(element_type)).collect::<Result<_, _>>()?;
+            return Ok(Value::Array(values));
+        }
+
+        Ok(match data_type {
+            DataType::Int8 => Value::I8(i8::from_le_bytes(self.get_array()?)),
+            DataType::Uint8 => Value::U8(self.get_u8()?),
+            DataType::Int16 => Value::I16(i16::from_le_bytes(self.get_array()?)),
+            DataType::Uint16 => Value::U16(self.get_u16()?),
+            DataType::Int32 => Value::I32(i32::from_le_bytes(self.get_array()?)),
+            DataType::Uint32 => Value::U32(self.get_u32()?),
+            DataType::Int64 => Value::I64(i64::from_le_bytes(self.get_array()?)),
+            DataType::Uint64 => Value::U64(self.get_u64()?),
+            DataType::Int128 => Value::I128(i128::from_le_bytes(self.get_array()?)),
+            DataType::Uint128 => Value::U128(self.get_u128()?),
+            DataType::String => Value::Str(self.get_string()?),
+            other => return Err(ProtocolError::UnsupportedDataType(other.as_u16())),
+        })
+    }
+}
@@ -0,0 +1,358 @@
+//! Datasets exchanged during data phases
+
+use crate::error::ProtocolError;
+use crate::protocol::codes::{DataType, DevicePropertyCode, ObjectFormatCode, ObjectPropertyCode};
+use crate::protocol::data::{Reader, Writer, Value};
+
+/// Dataset returned by `GetDeviceInfo`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub standard_version: u16,
+    pub vendor_extension_id: u32,
+    pub vendor_extension_version: u16,
+    pub vendor_extension_desc: String,
+    pub functional_mode: u16,
+    pub operations_supported: Vec<u16>,
+    pub events_supported: Vec<u16>,
+    pub device_properties_supported: Vec<u16>,
+    pub capture_formats: Vec<u16>,
+    pub playback_formats: Vec<u16>,
+    pub manufacturer: String,
+    pub model: String,
+    pub device_version: String,
+    pub serial_number: String,
+}
+
+impl DeviceInfo {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.put_u16(self.standard_version);
+        writer.put_u32(self.vendor_extension_id);
+        writer.put_u16(self.vendor_extension_version);
+        writer.put_string(&self.vendor_extension_desc);
+        writer.put_u16(self.functional_mode);
+        writer.put_u16_array(&self.operations_supported);
+        writer.put_u16_array(&self.events_supported);
+        writer.put_u16_array(&self.device_properties_supported);
+        writer.put_u16_array(&self.capture_formats);
+        writer.put_u16_array(&self.playback_formats);
+        writer.put_string(&self.manufacturer);
+        writer.put_string(&self.model);
+        writer.put_string(&self.device_version);
+        writer.put_string(&self.serial_number);
+        writer.into_bytes()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        let mut reader = Reader::new(bytes);
+        Ok(Self{
+            standard_version: reader.get_u16()?,
+            vendor_extension_id: reader.get_u32()?,
+            vendor_extension_version: reader.get_u16()?,
+            vendor_extension_desc: reader.get_string()?,
+            functional_mode: reader.get_u16()?,
+            operations_supported: reader.get_u16_array()?,
+            events_supported: reader.get_u16_array()?,
+            device_properties_supported: reader.get_u16_array()?,
+            capture_formats: reader.get_u16_array()?,
+            playback_formats: reader.get_u16_array()?,
+            manufacturer: reader.get_string()?,
+            model: reader.get_string()?,
+            device_version: reader.get_string()?,
+            serial_number: reader.get_string()?,
+        })
+    }
+}
+
+/// Dataset returned by `GetStorageInfo`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StorageInfo {
+    /// 1: fixed ROM, 2: removable ROM, 3: fixed RAM, 4: removable RAM
+    pub storage_type: u16,
+    /// 1: generic flat, 2: generic hierarchical, 3: DCF
+    pub filesystem_type: u16,
+    /// 0: read-write, 1: read-only without object deletion, 2: read-only with object deletion
+    pub access_capability: u16,
+    pub max_capacity: u64,
+    pub free_space_in_bytes: u64,
+    pub free_space_in_objects: u32,
+    pub storage_description: String,
+    pub volume_identifier: String,
+}
+
+impl StorageInfo {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.put_u16(self.storage_type);
+        writer.put_u16(self.filesystem_type);
+        writer.put_u16(self.access_capability);
+        writer.put_u64(self.max_capacity);
+        writer.put_u64(self.free_space_in_bytes);
+        writer.put_u32(self.free_space_in_objects);
+        writer.put_string(&self.storage_description);
+        writer.put_string(&self.volume_identifier);
+        writer.into_bytes()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        let mut reader = Reader::new(bytes);
+        Ok(Self{
+            storage_type: reader.get_u16()?,
+            filesystem_type: reader.get_u16()?,
+            access_capability: reader.get_u16()?,
+            max_capacity: reader.get_u64()?,
+            free_space_in_bytes: reader.get_u64()?,
+            free_space_in_objects: reader.get_u32()?,
+            storage_description: reader.get_string()?,
+            volume_identifier: reader.get_string()?,
+        })
+    }
+}
+
+/// Dataset returned by `GetObjectInfo`, and sent by `SendObjectInfo`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ObjectInfo {
+    pub storage_id: u32,
+    pub object_format: ObjectFormatCode,
+    pub protection_status: u16,
+    /// The size of the object, or `0xFFFFFFFF` for objects larger than 4GB
+    pub object_compressed_size: u32,
+    pub thumb_format: u16,
+    pub thumb_compressed_size: u32,
+    pub thumb_pix_width: u32,
+    pub thumb_pix_height: u32,
+    pub image_pix_width: u32,
+    pub image_pix_height: u32,
+    pub image_bit_depth: u32,
+    /// The handle of the parent object, `0` for objects at the root of a storage
+    pub parent_object: u32,
+    /// 1 for "generic folders"
+    pub association_type: u16,
+    pub association_desc: u32,
+    pub sequence_number: u32,
+    pub filename: String,
+    /// See [`crate::protocol::datetime`]
+    pub date_created: String,
+    pub date_modified: String,
+    pub keywords: String,
+}
+
+impl Default for ObjectInfo {
+    fn default() -> Self {
+        Self{
+            storage_id: 0,
+            object_format: ObjectFormatCode::Undefined,
+            protection_status: 0,
+            object_compressed_size: 0,
+            thumb_format: 0,
+            thumb_compressed_size: 0,
+            thumb_pix_width: 0,
+            thumb_pix_height: 0,
+            image_pix_width: 0,
+            image_pix_height: 0,
+            image_bit_depth: 0,
+            parent_object: 0,
+            association_type: 0,
+            association_desc: 0,
+            sequence_number: 0,
+            filename: String::new(),
+            date_created: String::new(),
+            date_modified: String::new(),
+            keywords: String::new(),
+        }
+    }
+}
+
+impl ObjectInfo {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.put_u32(self.storage_id);
+        writer.put_u16(self.object_format.as_u16());
+        writer.put_u16(self.protection_status);
+        writer.put_u32(self.object_compressed_size);
+        writer.put_u16(self.thumb_format);
+        writer.put_u32(self.thumb_compressed_size);
+        writer.put_u32(self.thumb_pix_width);
+        writer.put_u32(self.thumb_pix_height);
+        writer.put_u32(self.image_pix_width);
+        writer.put_u32(self.image_pix_height);
+        writer.put_u32(self.image_bit_depth);
+        writer.put_u32(self.parent_object);
+        writer.put_u16(self.association_type);
+        writer.put_u32(self.association_desc);
+        writer.put_u32(self.sequence_number);
+        writer.put_string(&self.filename);
+        writer.put_string(&self.date_created);
+        writer.put_string(&self.date_modified);
+        writer.put_string(&self.keywords);
+        writer.into_bytes()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        let mut reader = Reader::new(bytes);
+        Ok(Self{
+            storage_id: reader.get_u32()?,
+            object_format: ObjectFormatCode::from_u16(reader.get_u16()?),
+            protection_status: reader.get_u16()?,
+            object_compressed_size: reader.get_u32()?,
+            thumb_format: reader.get_u16()?,
+            thumb_compressed_size: reader.get_u32()?,
+            thumb_pix_width: reader.get_u32()?,
+            thumb_pix_height: reader.get_u32()?,
+            image_pix_width: reader.get_u32()?,
+            image_pix_height: reader.get_u32()?,
+            image_bit_depth: reader.get_u32()?,
+            parent_object: reader.get_u32()?,
+            association_type: reader.get_u16()?,
+            association_desc: reader.get_u32()?,
+            sequence_number: reader.get_u32()?,
+            filename: reader.get_string()?,
+            // Some devices stop right after the file name
+            date_created: if reader.remaining() > 0 { reader.get_string()? } else { String::new() },
+            date_modified: if reader.remaining() > 0 { reader.get_string()? } else { String::new() },
+            keywords: if reader.remaining() > 0 { reader.get_string()? } else { String::new() },
+        })
+    }
+}
+
+/// The allowed values of a property
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PropertyForm {
+    None,
+    Range{ min: Value, max: Value, step: Value },
+    Enumeration(Vec<Value>),
+    DateTime,
+    FixedLengthArray(u16),
+    RegularExpression(String),
+    ByteArray,
+    LongString,
+}
+
+impl PropertyForm {
+    fn flag(&self) -> u8 {
+        match self {
+            Self::None => 0x00,
+            Self::Range{ .. } => 0x01,
+            Self::Enumeration(_) => 0x02,
+            Self::DateTime => 0x03,
+            Self::FixedLengthArray(_) => 0x04,
+            Self::RegularExpression(_) => 0x05,
+            Self::ByteArray => 0x06,
+            Self::LongString => 0xFF,
+        }
+    }
+
+    fn encode(&self, writer: &mut Writer) {
+        writer.put_u8(self.flag());
+        match self {
+            PropertyForm::Range{ min, max, step } => {
+                writer.put_value(min);
+                writer.put_value(max);
+                writer.put_value(step);
+            },
+            PropertyForm::Enumeration(values) => {
+                writer.put_u16(values.len() as u16);
+                for value in values {
+                    writer.put_value(value);
+                }
+            },
+            PropertyForm::FixedLengthArray(len) => writer.put_u16(*len),
+            PropertyForm::RegularExpression(regex) => writer.put_string(regex),
+            PropertyForm::None | PropertyForm::DateTime | PropertyForm::ByteArray | PropertyForm::LongString => {},
+        }
+    }
+
+    fn decode(reader: &mut Reader, data_type: DataType) -> Result<Self, ProtocolError> {
+        Ok(match reader.get_u8()? {
+            0x00 => PropertyForm::None,
+            0x01 => PropertyForm::Range{
+                min: reader.get_value(data_type)?,
+                max: reader.get_value(data_type)?,
+                step: reader.get_value(data_type)?,
+            },
+            0x02 => {
+                let count = reader.get_u16()?;
+                let values = (0..count).map(|_| reader.get_value(data_type)).collect::<Result<_, _>>()?;
+                PropertyForm::Enumeration(values)
+            },
+            0x03 => PropertyForm::DateTime,
+            0x04 => PropertyForm::FixedLengthArray(reader.get_u16()?),
+            0x05 => PropertyForm::RegularExpression(reader.get_string()?),
+            0x06 => PropertyForm::ByteArray,
+            0xFF => PropertyForm::LongString,
+            other => return Err(ProtocolError::InvalidForm(other)),
+        })
+    }
+}
+
+/// Dataset returned by `GetObjectPropDesc`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ObjectPropDesc {
+    pub property_code: ObjectPropertyCode,
+    pub data_type: DataType,
+    pub writable: bool,
+    pub default_value: Value,
+    pub group_code: u32,
+    pub form: PropertyForm,
+}
+
+impl ObjectPropDesc {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.put_u16(self.property_code.as_u16());
+        writer.put_u16(self.data_type.as_u16());
+        writer.put_u8(self.writable as u8);
+        writer.put_value(&self.default_value);
+        writer.put_u32(self.group_code);
+        self.form.encode(&mut writer);
+        writer.into_bytes()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        let mut reader = Reader::new(bytes);
+        let property_code = ObjectPropertyCode::from_u16(reader.get_u16()?);
+        let data_type = DataType::from_u16(reader.get_u16()?);
+        let writable = reader.get_u8()? != 0;
+        let default_value = reader.get_value(data_type)?;
+        let group_code = reader.get_u32()?;
+        let form = PropertyForm::decode(&mut reader, data_type)?;
+
+        Ok(Self{ property_code, data_type, writable, default_value, group_code, form })
+    }
+}
+
+/// Dataset returned by `GetDevicePropDesc`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DevicePropDesc {
+    pub property_code: DevicePropertyCode,
+    pub data_type: DataType,
+    pub writable: bool,
+    pub factory_default_value: Value,
+    pub current_value: Value,
+    pub form: PropertyForm,
+}
+
+impl DevicePropDesc {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.put_u16(self.property_code.as_u16());
+        writer.put_u16(self.data_type.as_u16());
+        writer.put_u8(self.writable as u8);
+        writer.put_value(&self.factory_default_value);
+        writer.put_value(&self.current_value);
+        self.form.encode(&mut writer);
+        writer.into_bytes()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        let mut reader = Reader::new(bytes);
+        let property_code = DevicePropertyCode::from_u16(reader.get_u16()?);
+        let data_type = DataType::from_u16(reader.get_u16()?);
+        let writable = reader.get_u8()? != 0;
+        let factory_default_value = reader.get_value(data_type)?;
+        let current_value = reader.get_value(data_type)?;
+        let form = PropertyForm::decode(&mut reader, data_type)?;
+
+        Ok(Self{ property_code, data_type, writable, factory_default_value, current_value, form })
+    }
+}
@@ -0,0 +1,102 @@
+//! Dates, that MTP represents as `YYYYMMDDThhmmss[.s][Z|±hhmm]` strings
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::ProtocolError;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Format a date, in UTC
+pub fn format_datetime(time: SystemTime) -> String {
+    let seconds = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(err) => -(err.duration().as_secs_f64().ceil() as i64),
+    };
+    let days = seconds.div_euclid(SECONDS_PER_DAY);
+    let seconds_of_day = seconds.rem_euclid(SECONDS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}",
+        year, month, day,
+        seconds_of_day / 3600, (seconds_of_day / 60) % 60, seconds_of_day % 60,
+    )
+}
+
+/// Parse a date.
+///
+/// Dates without any time zone are assumed to be UTC. Tenths of seconds are ignored.
+pub fn parse_datetime(text: &str) -> Result<SystemTime, ProtocolError> {
+    let invalid = || ProtocolError::InvalidDate(text.to_string());
+    let number = |range: std::ops::Range<usize>| -> Result<i64, ProtocolError> {
+        let digits = text.get(range).ok_or_else(invalid)?;
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        digits.parse().map_err(|_| invalid())
+    };
+
+    if text.as_bytes().get(8) != Some(&b'T') {
+        return Err(invalid());
+    }
+    let (year, month, day) = (number(0..4)?, number(4..6)?, number(6..8)?);
+    let (hour, minute, second) = (number(9..11)?, number(11..13)?, number(13..15)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return Err(invalid());
+    }
+    // Days past the end of the month (e.g. February 31st) would end up in the next month
+    let days = days_from_civil(year, month, day);
+    if civil_from_days(days) != (year, month, day) {
+        return Err(invalid());
+    }
+
+    let mut rest = &text[15..];
+    if let Some(tenths) = rest.strip_prefix('.') {
+        rest = tenths.trim_start_matches(|c: char| c.is_ascii_digit());
+    }
+    let offset_seconds = match rest.as_bytes().first() {
+        None | Some(b'Z') => 0,
+        Some(sign @ (b'+' | b'-')) => {
+            let offset: i64 = rest.get(1..5).filter(|s| s.bytes().all(|b| b.is_ascii_digit())).ok_or_else(invalid)?.parse().map_err(|_| invalid())?;
+            let offset_seconds = (offset / 100) * 3600 + (offset % 100) * 60;
+            if *sign == b'+' { offset_seconds } else { -offset_seconds }
+        },
+        Some(_) => return Err(invalid()),
+    };
+
+    let seconds = days * SECONDS_PER_DAY
+        + hour * 3600 + minute * 60 + second
+        - offset_seconds;
+
+    // Not every platform can represent every year (e.g. Windows cannot go before 1601)
+    let time = if seconds >= 0 {
+        UNIX_EPOCH.checked_add(Duration::from_secs(seconds as u64))
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::from_secs(seconds.unsigned_abs()))
+    };
+    time.ok_or_else(invalid)
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date (see <http://howardhinnant.github.io/date_algorithms.html>)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Inverse of [`days_from_civil`]
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,245 @@
+//! Correspondence between MTP protocol concepts and the ones this crate exposes
+//!
+//! * MTP designates objects with numeric handles and storages with numeric IDs, while this crate (like WPD) uses string IDs.
+//!   Like the Windows MTP driver, storage `0x10001` is designated as `s10001`, and object `0x2C` as `o2C`.
+//! * MTP object formats map onto [`ObjectType`]s and onto WPD `WPD_OBJECT_FORMAT_*` GUIDs.
+//! * MTP object properties map onto WPD `WPD_OBJECT_*` properties. `ProtectionStatus`, that WPD has no property for, maps onto [`MTP_OBJECT_PROTECTION_STATUS`].
+//! * MTP events map onto [`DeviceEvent`]s.
+//! * MTP filesystem types map onto the strings of [`WPD_STORAGE_FILE_SYSTEM_TYPE`](crate::PortableDevices::WPD_STORAGE_FILE_SYSTEM_TYPE).
+
+use windows::core::GUID;
+use windows::Win32::Devices::PortableDevices::{
+    WPD_OBJECT_FORMAT, WPD_OBJECT_PARENT_ID, WPD_OBJECT_NAME, WPD_OBJECT_ORIGINAL_FILE_NAME, WPD_OBJECT_SIZE,
+    WPD_OBJECT_DATE_CREATED, WPD_OBJECT_DATE_MODIFIED, WPD_OBJECT_DATE_AUTHORED, WPD_OBJECT_PERSISTENT_UNIQUE_ID,
+    WPD_OBJECT_ISHIDDEN, WPD_OBJECT_ISSYSTEM, WPD_OBJECT_NON_CONSUMABLE, WPD_OBJECT_KEYWORDS, WPD_OBJECT_SYNC_ID,
+    WPD_OBJECT_CONTAINER_FUNCTIONAL_OBJECT_ID,
+};
+use widestring::{U16CStr, U16CString};
+
+use crate::device::DeviceEvent;
+use crate::device::device_values::PropertyValue;
+use crate::object::ObjectType;
+use crate::protocol::codes::{EventCode, ObjectFormatCode, ObjectPropertyCode};
+use crate::protocol::container::Container;
+use crate::protocol::data::Value;
+use crate::protocol::datetime::{format_datetime, parse_datetime};
+
+/// The MTP `ProtectionStatus` of an object (a `U32` value)
+///
+/// WPD has no such property. Like the Windows MTP driver does for MTP properties it has no WPD counterpart for,
+/// it belongs to the MTP vendor extension property set, and its PID is the MTP property code.
+pub const MTP_OBJECT_PROTECTION_STATUS: crate::PROPERTYKEY = crate::PROPERTYKEY{
+    fmtid: GUID::from_u128(0x4d545058_4fce_4578_95c8_8698a9bc0f49),
+    pid: 0xDC03,
+};
+
+/// `ProtectionStatus` of objects that are not protected
+pub const PROTECTION_NONE: u32 = 0x0000;
+/// The object can be neither modified nor deleted
+pub const PROTECTION_READ_ONLY: u32 = 0x0001;
+/// The data of the object cannot be modified, but the object can be deleted, and its properties can be modified
+pub const PROTECTION_READ_ONLY_DATA: u32 = 0x8002;
+
+/// The object ID of an object handle
+pub fn object_id_from_handle(handle: u32) -> U16CString {
+    U16CString::from_str_truncate(format!("o{:X}", handle))
+}
+
+/// The object handle of an object ID, if this is an object ID that has been built by [`object_id_from_handle`]
+pub fn handle_from_object_id(object_id: &U16CStr) -> Option<u32> {
+    parse_prefixed_hex(object_id, 'o')
+}
+
+/// The object ID of a storage ID
+pub fn object_id_from_storage_id(storage_id: u32) -> U16CString {
+    U16CString::from_str_truncate(format!("s{:X}", storage_id))
+}
+
+/// The storage ID of an object ID, if this is an object ID that has been built by [`object_id_from_storage_id`]
+pub fn storage_id_from_object_id(object_id: &U16CStr) -> Option<u32> {
+    parse_prefixed_hex(object_id, 's')
+}
+
+/// The names of the MTP filesystem types, starting at `0x0001`
+const FILESYSTEM_TYPES: [&str; 3] = ["Generic flat", "Generic hierarchical", "DCF"];
+
+/// The name of an MTP filesystem type (e.g. `"DCF"` for `0x0003`), `None` for undefined and unknown ones
+pub fn filesystem_type_name(filesystem_type: u16) -> Option<&'static str> {
+    FILESYSTEM_TYPES.get((filesystem_type as usize).checked_sub(1)?).copied()
+}
+
+/// The MTP filesystem type that has this name. Unknown names (e.g. `"FAT32"`) are generic hierarchical filesystems.
+pub fn filesystem_type_code(name: &str) -> u16 {
+    FILESYSTEM_TYPES.iter().position(|known| *known == name).map_or(0x0002, |index| index as u16 + 1)
+}
+
+/// The [`DeviceEvent`] an MTP event container maps onto, if any
+pub fn device_event(container: &Container) -> Option<DeviceEvent> {
+    let param = container.param(0);
+    let storage_id = (param != 0 && param != 0xFFFF_FFFF).then(|| object_id_from_storage_id(param).into());
+    match container.event_code() {
+        EventCode::ObjectAdded => Some(DeviceEvent::ObjectAdded{ id: object_id_from_handle(param).into(), parent: None }),
+        EventCode::ObjectRemoved => Some(DeviceEvent::ObjectRemoved{ id: object_id_from_handle(param).into() }),
+        EventCode::ObjectInfoChanged | EventCode::ObjectPropChanged => Some(DeviceEvent::ObjectUpdated{ id: object_id_from_handle(param).into() }),
+        EventCode::StoreAdded | EventCode::StoreRemoved | EventCode::StoreFull | EventCode::StorageInfoChanged => Some(DeviceEvent::StorageChanged{ id: storage_id }),
+        _ => None,
+    }
+}
+
+fn parse_prefixed_hex(object_id: &U16CStr, prefix: char) -> Option<u32> {
+    let text = object_id.to_string().ok()?;
+    let digits = text.strip_prefix(prefix)?;
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(digits, 16).ok()
+}
+
+/// WPD format GUIDs are `XXXX0000-AE6C-4804-98BA-C57B46965FE7`, where `XXXX` is the MTP format code
+const FORMAT_GUID_SUFFIX: u128 = 0x0000_0000_ae6c_4804_98ba_c57b_4696_5fe7;
+const FORMAT_GUID_SUFFIX_MASK: u128 = 0x0000_ffff_ffff_ffff_ffff_ffff_ffff_ffff;
+
+impl ObjectFormatCode {
+    /// The matching WPD format GUID (e.g. [`WPD_OBJECT_FORMAT_MP3`](crate::PortableDevices::WPD_OBJECT_FORMAT_MP3) for [`ObjectFormatCode::Mp3`])
+    pub fn as_guid(&self) -> GUID {
+        GUID::from_u128(((self.as_u16() as u128) << 112) | FORMAT_GUID_SUFFIX)
+    }
+
+    /// The reverse of [`Self::as_guid`]
+    pub fn from_guid(guid: GUID) -> Option<Self> {
+        let value = guid.to_u128();
+        if value & FORMAT_GUID_SUFFIX_MASK != FORMAT_GUID_SUFFIX {
+            return None;
+        }
+        Some(Self::from_u16((value >> 112) as u16))
+    }
+
+    /// The kind of content objects of this format usually hold
+    pub fn object_type(&self) -> ObjectType {
+        match self {
+            Self::Association => ObjectType::Folder,
+
+            Self::Aiff | Self::Wav | Self::Mp3 | Self::UndefinedAudio | Self::Wma | Self::Ogg | Self::Aac |
+            Self::Audible | Self::Flac => ObjectType::Audio,
+
+            Self::Avi | Self::Mpeg | Self::Asf | Self::UndefinedVideo | Self::Wmv | Self::Mp4Container |
+            Self::Mp2 | Self::ThreeGpContainer => ObjectType::Video,
+
+            Self::UndefinedImage | Self::ExifJpeg | Self::TiffEp | Self::FlashPix | Self::Bmp | Self::Ciff |
+            Self::Gif | Self::Jfif | Self::Pcd | Self::Pict | Self::Png | Self::Tiff | Self::TiffIt | Self::Jp2 |
+            Self::Jpx | Self::WindowsImageFormat => ObjectType::Image,
+
+            Self::AbstractAvPlaylist | Self::AbstractAudioPlaylist | Self::AbstractVideoPlaylist |
+            Self::WplPlaylist | Self::M3uPlaylist | Self::MplPlaylist | Self::AsxPlaylist |
+            Self::PlsPlaylist => ObjectType::Playlist,
+
+            Self::AbstractMultimediaAlbum => ObjectType::MixedContentAlbum,
+            Self::AbstractImageAlbum => ObjectType::ImageAlbum,
+            Self::AbstractAudioAlbum => ObjectType::AudioAlbum,
+            Self::AbstractVideoAlbum => ObjectType::VideoAlbum,
+            Self::AbstractContactGroup => ObjectType::ContactGroup,
+            Self::AbstractMediacast => ObjectType::MediaCast,
+            Self::AbstractChapteredProduction => ObjectType::Section,
+
+            Self::Text | Self::Html | Self::UndefinedDocument | Self::AbstractDocument | Self::XmlDocument |
+            Self::MsWordDocument | Self::MhtCompiledHtmlDocument | Self::MsExcelSpreadsheet |
+            Self::MsPowerpointPresentation => ObjectType::Document,
+
+            Self::UndefinedMessage | Self::AbstractMessage | Self::AbstractMessageFolder => ObjectType::GenericMessage,
+            Self::UndefinedContact | Self::AbstractContact | Self::VCard2 => ObjectType::Contact,
+            Self::Script | Self::Executable | Self::UndefinedFirmware => ObjectType::Program,
+
+            Self::Undefined | Self::Dpof | Self::UndefinedCollection | Self::Other(_) => ObjectType::GenericFile,
+        }
+    }
+}
+
+impl ObjectPropertyCode {
+    /// The matching WPD property, if any
+    pub fn property_key(&self) -> Option<crate::PROPERTYKEY> {
+        Some(match self {
+            Self::StorageId => WPD_OBJECT_CONTAINER_FUNCTIONAL_OBJECT_ID,
+            Self::ObjectFormat => WPD_OBJECT_FORMAT,
+            Self::ProtectionStatus => MTP_OBJECT_PROTECTION_STATUS,
+            Self::ObjectSize => WPD_OBJECT_SIZE,
+            Self::ObjectFileName => WPD_OBJECT_ORIGINAL_FILE_NAME,
+            Self::DateCreated => WPD_OBJECT_DATE_CREATED,
+            Self::DateModified => WPD_OBJECT_DATE_MODIFIED,
+            Self::DateAuthored => WPD_OBJECT_DATE_AUTHORED,
+            Self::Keywords => WPD_OBJECT_KEYWORDS,
+            Self::ParentObject => WPD_OBJECT_PARENT_ID,
+            Self::Hidden => WPD_OBJECT_ISHIDDEN,
+            Self::SystemObject => WPD_OBJECT_ISSYSTEM,
+            Self::PersistentUniqueObjectIdentifier => WPD_OBJECT_PERSISTENT_UNIQUE_ID,
+            Self::SyncId => WPD_OBJECT_SYNC_ID,
+            Self::Name => WPD_OBJECT_NAME,
+            Self::NonConsumable => WPD_OBJECT_NON_CONSUMABLE,
+            _ => return None,
+        })
+    }
+
+    /// The reverse of [`Self::property_key`]
+    pub fn from_property_key(key: &crate::PROPERTYKEY) -> Option<Self> {
+        PROPERTIES_WITH_KEYS.iter().copied().find(|code| code.property_key().as_ref() == Some(key))
+    }
+
+    /// Convert an MTP value of this property into a WPD-like value
+    ///
+    /// Objects at the root of a storage have a `ParentObject` of 0, which cannot be converted without knowing their storage: this returns `None` in this case.
+    pub fn to_property_value(&self, value: &Value) -> Option<PropertyValue> {
+        Some(match self {
+            Self::StorageId => PropertyValue::String(object_id_from_storage_id(value.as_u64()? as u32)),
+            Self::ParentObject => match value.as_u64()? {
+                0 => return None,
+                handle => PropertyValue::String(object_id_from_handle(handle as u32)),
+            },
+            Self::ObjectFormat => PropertyValue::Guid(ObjectFormatCode::from_u16(value.as_u64()? as u16).as_guid()),
+            Self::ObjectSize => PropertyValue::U64(value.as_u64()?),
+            Self::ProtectionStatus => PropertyValue::U32(value.as_u64()? as u32),
+            Self::DateCreated | Self::DateModified | Self::DateAuthored => PropertyValue::Date(parse_datetime(value.as_str()?).ok()?),
+            Self::Hidden | Self::SystemObject | Self::NonConsumable => PropertyValue::Bool(value.as_u64()? != 0),
+            Self::PersistentUniqueObjectIdentifier => match value {
+                Value::U128(uid) => PropertyValue::String(U16CString::from_str_truncate(format!("{:032X}", uid))),
+                other => PropertyValue::String(U16CString::from_str_truncate(other.as_str()?)),
+            },
+            _ => PropertyValue::String(U16CString::from_str_truncate(value.as_str()?)),
+        })
+    }
+
+    /// Convert a WPD-like value of this property into an MTP value
+    pub fn to_value(&self, value: &PropertyValue) -> Option<Value> {
+        Some(match (self, value) {
+            (Self::StorageId, PropertyValue::String(id)) => Value::U32(storage_id_from_object_id(id)?),
+            // Storages are not MTP objects, their children have no parent. IDs that are neither are invalid.
+            (Self::ParentObject, PropertyValue::String(id)) => Value::U32(handle_from_object_id(id).or_else(|| storage_id_from_object_id(id).map(|_| 0))?),
+            (Self::ObjectFormat, PropertyValue::Guid(guid)) => Value::U16(ObjectFormatCode::from_guid(*guid)?.as_u16()),
+            (Self::ObjectSize, PropertyValue::U64(size)) => Value::U64(*size),
+            (Self::ObjectSize, PropertyValue::U32(size)) => Value::U64(*size as u64),
+            (Self::ProtectionStatus, PropertyValue::U32(status)) => Value::U16(*status as u16),
+            (Self::DateCreated | Self::DateModified | Self::DateAuthored, PropertyValue::Date(date)) => Value::Str(format_datetime(*date)),
+            (Self::Hidden | Self::SystemObject | Self::NonConsumable, PropertyValue::Bool(b)) => Value::U16(*b as u16),
+            (Self::PersistentUniqueObjectIdentifier, PropertyValue::String(uid)) => Value::U128(u128::from_str_radix(&uid.to_string().ok()?, 16).ok()?),
+            (_, PropertyValue::String(s)) => Value::Str(s.to_string().ok()?),
+            _ => return None,
+        })
+    }
+}
+
+const PROPERTIES_WITH_KEYS: [ObjectPropertyCode; 16] = [
+    ObjectPropertyCode::StorageId,
+    ObjectPropertyCode::ObjectFormat,
+    ObjectPropertyCode::ProtectionStatus,
+    ObjectPropertyCode::ObjectSize,
+    ObjectPropertyCode::ObjectFileName,
+    ObjectPropertyCode::DateCreated,
+    ObjectPropertyCode::DateModified,
+    ObjectPropertyCode::DateAuthored,
+    ObjectPropertyCode::Keywords,
+    ObjectPropertyCode::ParentObject,
+    ObjectPropertyCode::Hidden,
+    ObjectPropertyCode::SystemObject,
+    ObjectPropertyCode::PersistentUniqueObjectIdentifier,
+    ObjectPropertyCode::SyncId,
+    ObjectPropertyCode::Name,
+    ObjectPropertyCode::NonConsumable,
+];
@@ -0,0 +1,27 @@
+//! Platform-independent encoding and decoding of the PTP/MTP protocol
+//!
+//! This implements the [MTP specification](https://www.usb.org/document-library/media-transfer-protocol-v11-spec-and-mtp-v11-adopters-agreement)
+//! (and the part of PTP it is built upon) at the byte level, regardless of the transport (USB, TCP/IP...).<br/>
+//! This is the foundation of backends that do not rely on the Windows API, and can be handy to inspect MTP traffic.
+//!
+//! ```
+//! use winmtp::protocol::codes::OperationCode;
+//! use winmtp::protocol::container::{Container, ContainerType};
+//!
+//! let open_session = Container::command(OperationCode::OpenSession, 0, &[1]);
+//! let bytes = open_session.encode();
+//! assert_eq!(bytes, [0x10, 0, 0, 0, 0x01, 0x00, 0x02, 0x10, 0, 0, 0, 0, 0x01, 0, 0, 0]);
+//!
+//! let decoded = Container::decode(&bytes).unwrap();
+//! assert_eq!(decoded.container_type, ContainerType::Command);
+//! assert_eq!(decoded.operation_code(), OperationCode::OpenSession);
+//! assert_eq!(decoded.params().unwrap(), [1]);
+//! ```
+
+pub mod codes;
+pub mod container;
+pub mod data;
+pub mod datasets;
+pub mod datetime;
+pub mod mapping;
+pub mod ptpip;
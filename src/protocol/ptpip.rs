@@ -0,0 +1,274 @@
+//! Packets of PTP/IP, i.e. PTP over TCP/IP (see the CIPA DC-X005 standard)
+//!
+//! An initiator opens two TCP connections to the responder (usually on port [`DEFAULT_PORT`]):
+//! * the command channel, that starts with an [`Packet::InitCommandRequest`] / [`Packet::InitCommandAck`] handshake,
+//!   then carries operation requests, data phases and responses,
+//! * the event channel, that starts with an [`Packet::InitEventRequest`] / [`Packet::InitEventAck`] handshake, then carries events.
+//!
+//! Every packet is made of a 4-byte length (including the header), a 4-byte packet type, then a type-specific payload.
+//! Unlike in MTP datasets, strings are plain null-terminated UTF-16LE strings, without any length prefix.
+
+use std::io::{self, Read, Write};
+
+use crate::error::ProtocolError;
+use crate::protocol::codes::{OperationCode, ResponseCode};
+use crate::protocol::container::{Container, ContainerType};
+use crate::protocol::data::{Reader, Writer};
+
+/// The TCP port PTP/IP responders listen on
+pub const DEFAULT_PORT: u16 = 15740;
+
+/// The only protocol version this crate speaks (1.0)
+pub const PROTOCOL_VERSION: u32 = 0x0001_0000;
+
+/// Length of the header of every packet (length and type)
+pub const HEADER_LEN: usize = 8;
+
+/// Value of the data phase field of operation requests
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataPhase {
+    /// No data phase, or a data phase from the responder to the initiator
+    NoDataOrDataIn,
+    /// A data phase from the initiator to the responder
+    DataOut,
+    Unknown(u32),
+}
+
+impl DataPhase {
+    pub fn from_u32(value: u32) -> Self {
+        match value {
+            1 => Self::NoDataOrDataIn,
+            2 => Self::DataOut,
+            other => Self::Unknown(other),
+        }
+    }
+
+    pub fn as_u32(&self) -> u32 {
+        match self {
+            Self::NoDataOrDataIn => 1,
+            Self::DataOut => 2,
+            Self::Unknown(value) => *value,
+        }
+    }
+}
+
+/// A PTP/IP packet
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Packet {
+    InitCommandRequest{ guid: [u8; 16], friendly_name: String, protocol_version: u32 },
+    InitCommandAck{ connection_number: u32, guid: [u8; 16], friendly_name: String, protocol_version: u32 },
+    InitEventRequest{ connection_number: u32 },
+    InitEventAck,
+    InitFail{ reason: u32 },
+    OperationRequest{ data_phase: DataPhase, operation: u16, transaction_id: u32, params: Vec<u32> },
+    OperationResponse{ response: u16, transaction_id: u32, params: Vec<u32> },
+    Event{ event: u16, transaction_id: u32, params: Vec<u32> },
+    /// Starts a data phase. `total_length` is `u64::MAX` when it is not known in advance.
+    StartData{ transaction_id: u32, total_length: u64 },
+    Data{ transaction_id: u32, payload: Vec<u8> },
+    Cancel{ transaction_id: u32 },
+    /// Ends a data phase. It can carry the last bytes of the data.
+    EndData{ transaction_id: u32, payload: Vec<u8> },
+    ProbeRequest,
+    ProbeResponse,
+}
+
+impl Packet {
+    pub fn packet_type(&self) -> u32 {
+        match self {
+            Self::InitCommandRequest{ .. } => 1,
+            Self::InitCommandAck{ .. } => 2,
+            Self::InitEventRequest{ .. } => 3,
+            Self::InitEventAck => 4,
+            Self::InitFail{ .. } => 5,
+            Self::OperationRequest{ .. } => 6,
+            Self::OperationResponse{ .. } => 7,
+            Self::Event{ .. } => 8,
+            Self::StartData{ .. } => 9,
+            Self::Data{ .. } => 10,
+            Self::Cancel{ .. } => 11,
+            Self::EndData{ .. } => 12,
+            Self::ProbeRequest => 13,
+            Self::ProbeResponse => 14,
+        }
+    }
+
+    /// The operation request of a command container
+    pub fn operation_request(command: &Container, data_phase: DataPhase) -> Result<Self, ProtocolError> {
+        Ok(Self::OperationRequest{
+            data_phase,
+            operation: command.code,
+            transaction_id: command.transaction_id,
+            params: command.params()?,
+        })
+    }
+
+    /// The packet of a response or event container
+    pub fn from_container(container: &Container) -> Result<Self, ProtocolError> {
+        match container.container_type {
+            ContainerType::Response => Ok(Self::OperationResponse{ response: container.code, transaction_id: container.transaction_id, params: container.params()? }),
+            ContainerType::Event => Ok(Self::Event{ event: container.code, transaction_id: container.transaction_id, params: container.params()? }),
+            ContainerType::Command => Self::operation_request(container, DataPhase::NoDataOrDataIn),
+            ContainerType::Data => Err(ProtocolError::InvalidContainerType(container.container_type.as_u16())),
+        }
+    }
+
+    /// The container of an operation request, response or event packet
+    pub fn to_container(&self) -> Option<Container> {
+        match self {
+            Self::OperationRequest{ operation, transaction_id, params, .. } => Some(Container::command(OperationCode::from_u16(*operation), *transaction_id, params)),
+            Self::OperationResponse{ response, transaction_id, params } => Some(Container::response(ResponseCode::from_u16(*response), *transaction_id, params)),
+            Self::Event{ event, transaction_id, params } => Some(Container::event(crate::protocol::codes::EventCode::from_u16(*event), *transaction_id, params)),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Writer::new();
+        match self {
+            Self::InitCommandRequest{ guid, friendly_name, protocol_version } => {
+                payload.put_bytes(guid);
+                put_plain_string(&mut payload, friendly_name);
+                payload.put_u32(*protocol_version);
+            },
+            Self::InitCommandAck{ connection_number, guid, friendly_name, protocol_version } => {
+                payload.put_u32(*connection_number);
+                payload.put_bytes(guid);
+                put_plain_string(&mut payload, friendly_name);
+                payload.put_u32(*protocol_version);
+            },
+            Self::InitEventRequest{ connection_number } => payload.put_u32(*connection_number),
+            Self::InitFail{ reason } => payload.put_u32(*reason),
+            Self::OperationRequest{ data_phase, operation, transaction_id, params } => {
+                payload.put_u32(data_phase.as_u32());
+                payload.put_u16(*operation);
+                payload.put_u32(*transaction_id);
+                params.iter().for_each(|param| payload.put_u32(*param));
+            },
+            Self::OperationResponse{ response: code, transaction_id, params } | Self::Event{ event: code, transaction_id, params } => {
+                payload.put_u16(*code);
+                payload.put_u32(*transaction_id);
+                params.iter().for_each(|param| payload.put_u32(*param));
+            },
+            Self::StartData{ transaction_id, total_length } => {
+                payload.put_u32(*transaction_id);
+                payload.put_u64(*total_length);
+            },
+            Self::Data{ transaction_id, payload: data } | Self::EndData{ transaction_id, payload: data } => {
+                payload.put_u32(*transaction_id);
+                payload.put_bytes(data);
+            },
+            Self::Cancel{ transaction_id } => payload.put_u32(*transaction_id),
+            Self::InitEventAck | Self::ProbeRequest | Self::ProbeResponse => {},
+        }
+        let payload = payload.into_bytes();
+
+        let mut writer = Writer::new();
+        writer.put_u32((HEADER_LEN + payload.len()) as u32);
+        writer.put_u32(self.packet_type());
+        writer.put_bytes(&payload);
+        writer.into_bytes()
+    }
+
+    /// Decode a packet, that must span the whole `bytes`
+    pub fn decode(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        let mut reader = Reader::new(bytes);
+        let length = reader.get_u32()?;
+        if length as usize != bytes.len() || (length as usize) < HEADER_LEN {
+            return Err(ProtocolError::InvalidLength(length));
+        }
+        let packet_type = reader.get_u32()?;
+        let params = |reader: &mut Reader| -> Result<Vec<u32>, ProtocolError> {
+            if !reader.remaining().is_multiple_of(4) {
+                return Err(ProtocolError::InvalidLength(length));
+            }
+            (0..reader.remaining() / 4).map(|_| reader.get_u32()).collect()
+        };
+
+        let packet = match packet_type {
+            1 => Self::InitCommandRequest{
+                guid: get_guid(&mut reader)?,
+                friendly_name: get_plain_string(&mut reader)?,
+                protocol_version: reader.get_u32()?,
+            },
+            2 => Self::InitCommandAck{
+                connection_number: reader.get_u32()?,
+                guid: get_guid(&mut reader)?,
+                friendly_name: get_plain_string(&mut reader)?,
+                protocol_version: reader.get_u32()?,
+            },
+            3 => Self::InitEventRequest{ connection_number: reader.get_u32()? },
+            4 => Self::InitEventAck,
+            5 => Self::InitFail{ reason: reader.get_u32()? },
+            6 => Self::OperationRequest{
+                data_phase: DataPhase::from_u32(reader.get_u32()?),
+                operation: reader.get_u16()?,
+                transaction_id: reader.get_u32()?,
+                params: params(&mut reader)?,
+            },
+            7 => Self::OperationResponse{ response: reader.get_u16()?, transaction_id: reader.get_u32()?, params: params(&mut reader)? },
+            8 => Self::Event{ event: reader.get_u16()?, transaction_id: reader.get_u32()?, params: params(&mut reader)? },
+            9 => Self::StartData{ transaction_id: reader.get_u32()?, total_length: reader.get_u64()? },
+            10 => Self::Data{ transaction_id: reader.get_u32()?, payload: reader.get_bytes(reader.remaining())?.to_vec() },
+            11 => Self::Cancel{ transaction_id: reader.get_u32()? },
+            12 => Self::EndData{ transaction_id: reader.get_u32()?, payload: reader.get_bytes(reader.remaining())?.to_vec() },
+            13 => Self::ProbeRequest,
+            14 => Self::ProbeResponse,
+            other => return Err(ProtocolError::InvalidPacketType(other)),
+        };
+        Ok(packet)
+    }
+
+    /// Read a packet from a TCP stream
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        Self::read_from_limited(reader, u32::MAX as usize)
+    }
+
+    /// Read a packet from a TCP stream, and reject it before reading its payload if it is longer than `max_length` bytes (header included).
+    ///
+    /// The buffer grows as the packet is read, so that a bogus length does not allocate much by itself.
+    pub fn read_from_limited<R: Read>(reader: &mut R, max_length: usize) -> io::Result<Self> {
+        let mut length_bytes = [0; 4];
+        reader.read_exact(&mut length_bytes)?;
+        let length = u32::from_le_bytes(length_bytes) as usize;
+        if length < HEADER_LEN || length > max_length {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, ProtocolError::InvalidLength(length as u32)));
+        }
+
+        let mut bytes = length_bytes.to_vec();
+        reader.take((length - 4) as u64).read_to_end(&mut bytes)?;
+        if bytes.len() < length {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, ProtocolError::Truncated));
+        }
+        Self::decode(&bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// The reverse of [`Self::read_from`]
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.encode())
+    }
+}
+
+fn put_plain_string(writer: &mut Writer, value: &str) {
+    for unit in value.encode_utf16() {
+        writer.put_u16(unit);
+    }
+    writer.put_u16(0);
+}
+
+fn get_plain_string(reader: &mut Reader) -> Result<String, ProtocolError> {
+    let mut units = Vec::new();
+    loop {
+        match reader.get_u16()? {
+            0 => break,
+            unit => units.push(unit),
+        }
+    }
+    String::from_utf16(&units).map_err(|_| ProtocolError::InvalidString)
+}
+
+fn get_guid(reader: &mut Reader) -> Result<[u8; 16], ProtocolError> {
+    let mut guid = [0; 16];
+    guid.copy_from_slice(reader.get_bytes(16)?);
+    Ok(guid)
+}
@@ -1,113 +1,68 @@
-use windows::core::{GUID, PWSTR, PCWSTR} ;
-use windows::Win32::System::Com::{CoInitializeEx, CoCreateInstance, CoTaskMemFree, CLSCTX_ALL, COINIT_DISABLE_OLE1DDE, COINIT_MULTITHREADED};
-use windows::Win32::Devices::PortableDevices::IPortableDeviceManager;
-use widestring::U16CString;
+use std::rc::Rc;
 
+use crate::backend::ProviderBackend;
 use crate::device::BasicDevice;
 use crate::error::MtpError;
+use crate::watcher::DeviceWatcher;
 
-pub struct Provider {}
+pub struct Provider {
+    backends: Vec<Rc<dyn ProviderBackend>>,
+}
 
 impl Provider {
     /// Entry point of this crate.
     ///
-    /// It internally inits the underlying Windows API.
-    pub fn new() -> crate::WindowsResult<Self> {
-        unsafe {
-            CoInitializeEx(
-                None,
-                COINIT_MULTITHREADED
-                | COINIT_DISABLE_OLE1DDE, // Setting this flag avoids some overhead associated with Object Linking and Embedding (OLE) 1.0, an obsolete technology. (see https://learn.microsoft.com/en-us/windows/win32/learnwin32/initializing-the-com-library)
-            )?;
-        }
-
-        Ok(Self{})
-    }
+    /// On Windows, it internally inits the underlying Windows API, and registers the [WPD backend](crate::backend::wpd).<br/>
+    /// On Linux, it registers the [USB backend](crate::backend::usb).<br/>
+    /// On other platforms, no backend is registered by default, see [`Self::add_backend`].
+    pub fn new() -> Result<Self, MtpError> {
+        #[allow(unused_mut)]
+        let mut provider = Self::empty();
 
-    pub fn enumerate_devices(&self) -> Result<Vec<BasicDevice>, MtpError> {
-        let device_mgr: IPortableDeviceManager = unsafe {
-            CoCreateInstance(
-                &windows::Win32::Devices::PortableDevices::PortableDeviceManager as *const GUID,
-                None,
-                CLSCTX_ALL,
-            )
-        }?;
+        #[cfg(windows)]
+        provider.add_backend(Rc::new(crate::backend::wpd::WpdProvider::new()?));
+        #[cfg(target_os = "linux")]
+        provider.add_backend(Rc::new(crate::backend::usb::UsbProvider::new()));
 
-        // How many devices are there?
-        let mut dev_count: u32 = 0;
-        unsafe {
-            device_mgr.RefreshDeviceList()?;
-            device_mgr.GetDevices(
-                std::ptr::null_mut(),
-                &mut dev_count as *mut _
-            )?;
-        }
+        Ok(provider)
+    }
 
-        if dev_count == 0 {
-            return Ok(Vec::new());
-        }
+    /// Create a provider that has no backend
+    pub fn empty() -> Self {
+        Self{ backends: Vec::new() }
+    }
 
-        // Get their IDs
-        let mut dev_ids: Vec<PWSTR> = Vec::with_capacity(dev_count as usize);
-        let mut fetched_devices: u32 = dev_count;
-        unsafe {
-            device_mgr.GetDevices(
-                dev_ids.as_mut_ptr(),
-                &mut fetched_devices as *mut _,
-            )
-        }?;
-        if fetched_devices != dev_count {
-            return Err(MtpError::ChangedConditions);
-        }
-        unsafe { dev_ids.set_len(dev_count as usize) };
+    /// Register an additional backend. Its devices will be listed by [`Self::enumerate_devices`].
+    pub fn add_backend(&mut self, backend: Rc<dyn ProviderBackend>) {
+        self.backends.push(backend);
+    }
 
-        // Build a Rust result type
+    /// List devices of every registered backend
+    pub fn enumerate_devices(&self) -> Result<Vec<BasicDevice>, MtpError> {
         let mut devices = Vec::new();
-        for dev_id in &dev_ids {
-            let dev_id_const = PCWSTR::from_raw(dev_id.as_ptr());
-            let friendly_name = get_friendly_name(&device_mgr, dev_id_const)?;
-            let string_id = U16CString::from_vec_truncate(unsafe{ dev_id.as_wide() });
-            devices.push(BasicDevice::new(string_id, friendly_name));
-        }
-
-        // Free memory allocated by the COM API
-        for dev_id in dev_ids {
-            unsafe{ CoTaskMemFree(Some(dev_id.as_ptr() as *const _)) };
+        for backend in &self.backends {
+            devices.extend(backend.enumerate_devices()?);
         }
-
         Ok(devices)
     }
-}
-
-fn get_friendly_name(mgr: &IPortableDeviceManager, dev_id: PCWSTR) -> Result<String, MtpError> {
-    // How long is the name?
-    let mut required_len: u32 = 0;
-    unsafe {
-        mgr.GetDeviceFriendlyName(
-            dev_id,
-            PWSTR::null(),
-            &mut required_len as *mut u32,
-        )
-    }?;
 
-    if required_len == 0 {
-        return Ok(String::new());
-    }
-
-    let mut friendly_name: Vec<u16> = Vec::with_capacity(required_len as usize);
-    let p_friendly_name = PWSTR::from_raw(friendly_name.as_mut_ptr());
-    let mut retrieved_len: u32 = required_len;
-    unsafe {
-        mgr.GetDeviceFriendlyName(
-            dev_id,
-            p_friendly_name,
-            &mut retrieved_len as *mut u32,
-        )
-    }?;
-    if retrieved_len != required_len {
-        return Err(MtpError::ChangedConditions);
+    /// Be told when devices are connected or disconnected
+    ///
+    /// The watcher first reports every device that is currently connected, as [`DeviceChange::Connected`](crate::DeviceChange::Connected).
+    /// Backends that can tell when their devices change (e.g. WPD) are only enumerated again when they do, the other ones are polled.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), winmtp::error::MtpError> {
+    /// let provider = winmtp::Provider::new()?;
+    /// for change in provider.watch_devices()? {
+    ///     if let winmtp::DeviceChange::Connected(device) = change? {
+    ///         println!("{} has been plugged in", device.friendly_name());
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn watch_devices(&self) -> Result<DeviceWatcher, MtpError> {
+        DeviceWatcher::new(&self.backends)
     }
-    unsafe { friendly_name.set_len(required_len as usize) };
-
-    Ok(unsafe { p_friendly_name.to_string() }?)
 }
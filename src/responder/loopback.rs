@@ -0,0 +1,115 @@
+//! In-process connection between the [`MtpDevice`] backend and a [`Responder`]
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{Cursor, Read};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use widestring::U16CString;
+
+use crate::backend::mtp::{EventSource, MtpDevice, Transport};
+use crate::device::BasicDevice;
+use crate::error::MtpError;
+use crate::protocol::codes::OperationCode;
+use crate::protocol::container::{Container, ContainerType};
+use crate::responder::Responder;
+
+/// A [`Transport`] that hands containers over to a [`Responder`] living in the same thread
+///
+/// Data phases are handed over once they have been sent entirely.
+/// Events are delivered after the transaction that was being processed when they happened.
+pub struct LoopbackTransport {
+    responder: Rc<RefCell<Responder>>,
+    replies: VecDeque<Container>,
+    /// The data phase being sent
+    outgoing: Option<Container>,
+    /// The payload of the data phase being received
+    incoming: Cursor<Vec<u8>>,
+    event_senders: Vec<Sender<Container>>,
+}
+
+impl LoopbackTransport {
+    pub fn new(responder: Rc<RefCell<Responder>>) -> Self {
+        Self{ responder, replies: VecDeque::new(), outgoing: None, incoming: Cursor::default(), event_senders: Vec::new() }
+    }
+
+    fn process(&mut self, container: Container) {
+        let mut responder = self.responder.borrow_mut();
+        self.replies.extend(responder.process(container));
+        for event in responder.take_events() {
+            self.event_senders.retain(|event_sender| event_sender.send(event.clone()).is_ok());
+        }
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn send(&mut self, command: &Container) -> Result<(), MtpError> {
+        self.process(command.clone());
+        Ok(())
+    }
+
+    fn start_data(&mut self, operation: OperationCode, transaction_id: u32, _length: u64) -> Result<(), MtpError> {
+        self.outgoing = Some(Container::data(operation, transaction_id, Vec::new()));
+        Ok(())
+    }
+
+    fn send_data(&mut self, data: &[u8]) -> Result<(), MtpError> {
+        let outgoing = self.outgoing.as_mut().ok_or_else(|| MtpError::InvalidArgument("No data phase has been started".to_string()))?;
+        outgoing.payload.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn end_data(&mut self) -> Result<(), MtpError> {
+        let outgoing = self.outgoing.take().ok_or_else(|| MtpError::InvalidArgument("No data phase has been started".to_string()))?;
+        self.process(outgoing);
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Container, MtpError> {
+        let mut container = self.replies
+            .pop_front()
+            .ok_or_else(|| MtpError::Backend("The responder has nothing to send".to_string()))?;
+        if container.container_type == ContainerType::Data {
+            self.incoming = Cursor::new(std::mem::take(&mut container.payload));
+        }
+        Ok(container)
+    }
+
+    fn receive_data(&mut self, buf: &mut [u8]) -> Result<usize, MtpError> {
+        Ok(self.incoming.read(buf)?)
+    }
+
+    fn event_source(&mut self) -> Result<Box<dyn EventSource>, MtpError> {
+        let (event_sender, event_receiver) = mpsc::channel();
+        self.event_senders.push(event_sender);
+        Ok(Box::new(event_receiver))
+    }
+}
+
+/// The transport is gone once the receiver is disconnected
+impl EventSource for Receiver<Container> {
+    fn next_event(&mut self, timeout: Duration) -> Result<Option<Container>, MtpError> {
+        match self.recv_timeout(timeout) {
+            Ok(event) => Ok(Some(event)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(MtpError::Backend("The loopback transport is gone".to_string())),
+        }
+    }
+}
+
+impl Responder {
+    /// Make this responder reachable as a device, through the [`MtpDevice`] backend.
+    ///
+    /// Every time the device is opened, a new session is opened with this responder.
+    pub fn into_loopback_device(self, friendly_name: &str) -> BasicDevice {
+        let responder = Rc::new(RefCell::new(self));
+        let backend = MtpDevice::new(move || Ok(Box::new(LoopbackTransport::new(Rc::clone(&responder))) as Box<dyn Transport>));
+        BasicDevice::new(
+            U16CString::from_str_truncate(format!("loopback:{}", friendly_name)),
+            friendly_name.to_string(),
+            Rc::new(backend),
+        )
+    }
+}
@@ -0,0 +1,1144 @@
+//! The device side of the MTP protocol
+//!
+//! A [`Responder`] exposes the content of any [backend](crate::backend) to MTP initiators, e.g. an in-memory device
+//! ([`MemoryDevice`](crate::backend::memory::MemoryDevice)) or local folders ([`DirectoryDevice`](crate::backend::directory::DirectoryDevice)).
+//! This makes it possible to test initiators (including the backends of this crate) end-to-end, without any hardware.
+//!
+//! A responder only deals with [`Container`]s (see [`Responder::process`]). It can serve a byte stream, such as a socket, with [`Responder::serve`],
+//! serve PTP/IP initiators with [`Responder::serve_ptpip`], or be used in-process, through a [`LoopbackTransport`].
+//! While a session is open, the events of the backend (e.g. objects being added) are turned into MTP events (see [`Responder::take_events`]).
+//!
+//!
+//! ```
+//! use std::rc::Rc;
+//! use winmtp::backend::memory::MemoryDevice;
+//! use winmtp::responder::Responder;
+//!
+//! let device = MemoryDevice::new("Fake phone");
+//! let storage_id = device.add_storage("Internal shared storage");
+//! device.add_file(&storage_id, "notes.txt", b"hello").unwrap();
+//!
+//! let responder = Responder::new(Rc::new(device), "ACME", "Fake phone");
+//! let basic_device = responder.into_loopback_device("Emulated phone");
+//! let app_identifiers = winmtp::make_current_app_identifiers!();
+//! let content = basic_device.open(&app_identifiers, true).unwrap().content().unwrap();
+//! let notes = content.root().unwrap().object_by_path("Internal shared storage/notes.txt".as_ref()).unwrap();
+//! assert_eq!(notes.name().to_string_lossy(), "notes.txt");
+//! ```
+
+use std::collections::HashMap;
+use std::io::{self, Read, SeekFrom, Write};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver};
+
+use windows::Win32::Devices::PortableDevices::{
+    WPD_DEVICE_OBJECT_ID, WPD_OBJECT_PARENT_ID, WPD_OBJECT_NAME, WPD_OBJECT_ORIGINAL_FILE_NAME, WPD_OBJECT_CONTENT_TYPE,
+    WPD_OBJECT_FORMAT, WPD_OBJECT_SIZE, WPD_OBJECT_DATE_CREATED, WPD_OBJECT_DATE_MODIFIED, WPD_OBJECT_KEYWORDS,
+    WPD_OBJECT_ISHIDDEN, WPD_OBJECT_PERSISTENT_UNIQUE_ID,
+    WPD_FUNCTIONAL_OBJECT_CATEGORY, WPD_FUNCTIONAL_CATEGORY_STORAGE, WPD_CONTENT_TYPE_FOLDER,
+    WPD_STORAGE_TYPE, WPD_STORAGE_ACCESS_CAPABILITY, WPD_STORAGE_CAPACITY, WPD_STORAGE_FREE_SPACE_IN_BYTES,
+    WPD_STORAGE_FREE_SPACE_IN_OBJECTS, WPD_STORAGE_FILE_SYSTEM_TYPE, WPD_STORAGE_DESCRIPTION, WPD_STORAGE_SERIAL_NUMBER,
+    WPD_DEVICE_SERIAL_NUMBER, WPD_DEVICE_POWER_LEVEL, WPD_DEVICE_TYPE,
+};
+use widestring::{U16CStr, U16CString};
+
+use crate::backend::{ContentBackend, ReadStreamBackend, WriteStreamBackend};
+use crate::backend::mtp::ALL;
+use crate::device::{DeviceEvent, EventSink, Subscription};
+use crate::device::device_values::{DeviceValues, PropertyValue};
+use crate::error::{ErrorKind, MtpError, ProtocolError};
+use crate::protocol::codes::{OperationCode, ResponseCode, EventCode, ObjectFormatCode, ObjectPropertyCode, DevicePropertyCode, DataType};
+use crate::protocol::container::{Container, ContainerHeader, ContainerType};
+use crate::protocol::data::{Reader, Writer, Value};
+use crate::protocol::datasets::{DeviceInfo, StorageInfo, ObjectInfo, ObjectPropDesc, DevicePropDesc, PropertyForm};
+use crate::protocol::datetime::{format_datetime, parse_datetime};
+use crate::protocol::mapping::{MTP_OBJECT_PROTECTION_STATUS, filesystem_type_code};
+
+mod loopback;
+mod ptpip;
+pub use loopback::LoopbackTransport;
+
+/// The largest data phase that is gathered in memory, i.e. every data phase of initiators but the data of objects
+const MAX_BUFFERED_DATA_LEN: usize = 1024 * 1024;
+
+/// The size of the pieces the data of objects is streamed in
+const STREAM_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Storage IDs are assigned from this one (this is what most Android devices do)
+const FIRST_STORAGE_ID: u32 = 0x0001_0001;
+
+/// The events a [`Responder`] sends, if its backend reports them
+const SUPPORTED_EVENTS: [EventCode; 5] = [
+    EventCode::ObjectAdded,
+    EventCode::ObjectRemoved,
+    EventCode::ObjectInfoChanged,
+    EventCode::StoreAdded,
+    EventCode::StorageInfoChanged,
+];
+
+/// The operations a [`Responder`] supports
+const SUPPORTED_OPERATIONS: [OperationCode; 22] = [
+    OperationCode::GetDeviceInfo,
+    OperationCode::OpenSession,
+    OperationCode::CloseSession,
+    OperationCode::GetStorageIds,
+    OperationCode::GetStorageInfo,
+    OperationCode::GetNumObjects,
+    OperationCode::GetObjectHandles,
+    OperationCode::GetObjectInfo,
+    OperationCode::GetObject,
+    OperationCode::DeleteObject,
+    OperationCode::SendObjectInfo,
+    OperationCode::SendObject,
+    OperationCode::MoveObject,
+    OperationCode::GetObjectPropsSupported,
+    OperationCode::GetObjectPropDesc,
+    OperationCode::GetObjectPropValue,
+    OperationCode::SetObjectPropValue,
+    OperationCode::GetObjectPropList,
+    OperationCode::GetPartialObject,
+    OperationCode::GetPartialObject64,
+    OperationCode::GetDevicePropDesc,
+    OperationCode::GetDevicePropValue,
+];
+
+/// The device properties a [`Responder`] supports, with their data type, and the property of the device object they are read from.
+/// They are all read-only.
+const SUPPORTED_DEVICE_PROPERTIES: [(DevicePropertyCode, DataType, crate::PROPERTYKEY); 2] = [
+    (DevicePropertyCode::BatteryLevel, DataType::Uint8, WPD_DEVICE_POWER_LEVEL),
+    (DevicePropertyCode::PerceivedDeviceType, DataType::Uint32, WPD_DEVICE_TYPE),
+];
+
+/// The object properties a [`Responder`] supports, with their data type, and whether they are writable
+const SUPPORTED_PROPERTIES: [(ObjectPropertyCode, DataType, bool); 11] = [
+    (ObjectPropertyCode::StorageId, DataType::Uint32, false),
+    (ObjectPropertyCode::ObjectFormat, DataType::Uint16, false),
+    (ObjectPropertyCode::ProtectionStatus, DataType::Uint16, false),
+    (ObjectPropertyCode::ObjectSize, DataType::Uint64, false),
+    (ObjectPropertyCode::ObjectFileName, DataType::String, true),
+    (ObjectPropertyCode::DateCreated, DataType::String, true),
+    (ObjectPropertyCode::DateModified, DataType::String, true),
+    (ObjectPropertyCode::ParentObject, DataType::Uint32, false),
+    (ObjectPropertyCode::PersistentUniqueObjectIdentifier, DataType::Uint128, false),
+    (ObjectPropertyCode::Name, DataType::String, false),
+    (ObjectPropertyCode::Hidden, DataType::Uint16, true),
+];
+
+/// Operations that are followed by a data phase from the initiator
+const OPERATIONS_WITH_DATA: [OperationCode; 3] = [
+    OperationCode::SendObjectInfo,
+    OperationCode::SendObject,
+    OperationCode::SetObjectPropValue,
+];
+
+/// Properties needed to build the `ObjectInfo` dataset of an object
+const OBJECT_INFO_PROPERTIES: [crate::PROPERTYKEY; 10] = [
+    WPD_OBJECT_PARENT_ID,
+    WPD_OBJECT_ORIGINAL_FILE_NAME,
+    WPD_OBJECT_NAME,
+    WPD_OBJECT_FORMAT,
+    WPD_OBJECT_CONTENT_TYPE,
+    WPD_OBJECT_SIZE,
+    WPD_OBJECT_DATE_CREATED,
+    WPD_OBJECT_DATE_MODIFIED,
+    WPD_OBJECT_KEYWORDS,
+    MTP_OBJECT_PROTECTION_STATUS,
+];
+
+fn device_object_id() -> U16CString {
+    unsafe{ U16CString::from_ptr_str(WPD_DEVICE_OBJECT_ID.as_ptr()) }
+}
+
+/// Why an operation failed, as it is reported to the initiator
+struct Failure(ResponseCode);
+
+impl From<ResponseCode> for Failure {
+    fn from(code: ResponseCode) -> Self {
+        Self(code)
+    }
+}
+
+impl From<MtpError> for Failure {
+    fn from(err: MtpError) -> Self {
+        Self(match (&err, err.kind()) {
+            (MtpError::Response(code), _) => *code,
+            (MtpError::InvalidProperty, _) => ResponseCode::InvalidObjectPropValue,
+            (_, ErrorKind::NotFound) => ResponseCode::InvalidObjectHandle,
+            (_, ErrorKind::AccessDenied) => ResponseCode::AccessDenied,
+            (_, ErrorKind::StorageFull) => ResponseCode::StoreFull,
+            (_, ErrorKind::Busy) => ResponseCode::DeviceBusy,
+            (_, ErrorKind::Unsupported) => ResponseCode::OperationNotSupported,
+            (_, ErrorKind::InvalidArgument) => ResponseCode::InvalidParameter,
+            (_, ErrorKind::Cancelled) => ResponseCode::TransactionCancelled,
+            (_, ErrorKind::DeviceGone) => ResponseCode::StoreNotAvailable,
+            (_, ErrorKind::AlreadyExists | ErrorKind::Other) => ResponseCode::GeneralError,
+        })
+    }
+}
+
+impl From<crate::error::ProtocolError> for Failure {
+    fn from(_err: crate::error::ProtocolError) -> Self {
+        Self(ResponseCode::InvalidDataset)
+    }
+}
+
+/// What a successful operation sends back
+#[derive(Default)]
+struct Reply {
+    params: Vec<u32>,
+    data: Option<ReplyData>,
+}
+
+impl Reply {
+    fn params(params: &[u32]) -> Self {
+        Self{ params: params.to_vec(), data: None }
+    }
+
+    fn data(data: Vec<u8>) -> Self {
+        Self{ params: Vec::new(), data: Some(ReplyData::Bytes(data)) }
+    }
+}
+
+/// The payload of the data phase of a reply
+enum ReplyData {
+    Bytes(Vec<u8>),
+    /// The content of an object, that is streamed rather than read at once
+    Object{ stream: Box<dyn ReadStreamBackend>, length: u64 },
+}
+
+impl ReplyData {
+    fn len(&self) -> u64 {
+        match self {
+            Self::Bytes(bytes) => bytes.len() as u64,
+            Self::Object{ length, .. } => *length,
+        }
+    }
+}
+
+/// What is sent back to a command: a data phase, if any, then a response
+struct Answer {
+    operation: OperationCode,
+    transaction_id: u32,
+    data: Option<ReplyData>,
+    response: ResponseCode,
+    params: Vec<u32>,
+}
+
+impl Answer {
+    fn failure(operation: OperationCode, transaction_id: u32, code: ResponseCode) -> Self {
+        Self{ operation, transaction_id, data: None, response: code, params: Vec::new() }
+    }
+
+    /// Send the payload of the data phase, piece by piece, with `send`.
+    ///
+    /// Objects that turn out to be shorter than announced are padded with zeros, as the length of the data phase has already been sent,
+    /// and the transaction fails.
+    fn send_data(&mut self, send: &mut dyn FnMut(&[u8]) -> io::Result<()>) -> io::Result<()> {
+        let (mut stream, length) = match self.data.take() {
+            None => return Ok(()),
+            Some(ReplyData::Bytes(bytes)) if bytes.is_empty() => return Ok(()),
+            Some(ReplyData::Bytes(bytes)) => return send(&bytes),
+            Some(ReplyData::Object{ stream, length }) => (stream, length),
+        };
+
+        let mut buf = vec![0; STREAM_CHUNK_SIZE];
+        let mut left = length;
+        while left > 0 {
+            let max_len = buf.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+            let len = match stream.read(&mut buf[..max_len]) {
+                Ok(0) | Err(_) => break,
+                Ok(len) => len,
+            };
+            send(&buf[..len])?;
+            left -= len as u64;
+        }
+        if left > 0 {
+            self.response = ResponseCode::IncompleteTransfer;
+            self.params.clear();
+            buf.fill(0);
+            while left > 0 {
+                let len = buf.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+                send(&buf[..len])?;
+                left -= len as u64;
+            }
+        }
+        Ok(())
+    }
+
+    fn response(&self) -> Container {
+        Container::response(self.response, self.transaction_id, &self.params)
+    }
+
+    /// The containers to send back, with the whole payload of their data phase
+    fn into_containers(mut self) -> Vec<Container> {
+        let mut containers = Vec::new();
+        if self.data.is_some() {
+            let mut payload = Vec::new();
+            // Writing into memory cannot fail
+            let _ = self.send_data(&mut |bytes| {
+                payload.extend_from_slice(bytes);
+                Ok(())
+            });
+            containers.push(Container::data(self.operation, self.transaction_id, payload));
+        }
+        containers.push(self.response());
+        containers
+    }
+}
+
+/// An object announced by `SendObjectInfo`, that the next `SendObject` will create
+struct PendingObject {
+    handle: u32,
+    properties: DeviceValues,
+}
+
+/// A data phase the initiator is sending
+struct IncomingData {
+    operation: OperationCode,
+    transaction_id: u32,
+    kind: Incoming,
+}
+
+enum Incoming {
+    /// A dataset, or the value of a property, that is gathered in memory, for the command it belongs to
+    Buffered(Container, Vec<u8>),
+    /// The data of an object, that goes straight into the backend
+    Object(PendingObject, Box<dyn WriteStreamBackend>),
+    /// Data that is skipped, as the transaction has failed already
+    Rejected(ResponseCode),
+}
+
+/// The device side of MTP sessions, backed by a [`ContentBackend`]
+///
+/// Object handles are assigned the first time an object is listed, and remain valid as long as the responder lives, across sessions.
+pub struct Responder {
+    content: Rc<dyn ContentBackend>,
+    device_info: DeviceInfo,
+    session_id: Option<u32>,
+    /// Object IDs of the storages, the storage ID of `storage_object_ids[i]` being `FIRST_STORAGE_ID + i`
+    storage_object_ids: Vec<U16CString>,
+    /// Object IDs of the objects, the handle of `object_ids[i]` being `i + 1`. Handles that have been reserved by `SendObjectInfo` have no ID yet.
+    object_ids: Vec<Option<U16CString>>,
+    handles: HashMap<U16CString, u32>,
+    /// A command whose data phase has not been received yet
+    pending_command: Option<Container>,
+    pending_object: Option<PendingObject>,
+    /// The data phase that is being received
+    incoming: Option<IncomingData>,
+    /// The events of the backend, while a session is open
+    events: Option<(Subscription, Receiver<DeviceEvent>)>,
+}
+
+impl std::fmt::Debug for Responder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Responder")
+            .field("content", &self.content)
+            .field("session_id", &self.session_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Responder {
+    pub fn new(content: Rc<dyn ContentBackend>, manufacturer: &str, model: &str) -> Self {
+        let device_info = DeviceInfo{
+            standard_version: 100,
+            // Microsoft, i.e. MTP
+            vendor_extension_id: 6,
+            vendor_extension_version: 100,
+            vendor_extension_desc: "microsoft.com: 1.0;".to_string(),
+            functional_mode: 0,
+            operations_supported: SUPPORTED_OPERATIONS.iter().map(|operation| operation.as_u16()).collect(),
+            events_supported: SUPPORTED_EVENTS.iter().map(|event| event.as_u16()).collect(),
+            device_properties_supported: SUPPORTED_DEVICE_PROPERTIES.iter().map(|(code, _, _)| code.as_u16()).collect(),
+            capture_formats: Vec::new(),
+            playback_formats: vec![ObjectFormatCode::Undefined.as_u16(), ObjectFormatCode::Association.as_u16()],
+            manufacturer: manufacturer.to_string(),
+            model: model.to_string(),
+            device_version: env!("CARGO_PKG_VERSION").to_string(),
+            serial_number: content.properties(&device_object_id(), &[WPD_DEVICE_SERIAL_NUMBER])
+                .ok()
+                .and_then(|values| values.get_string(&WPD_DEVICE_SERIAL_NUMBER).ok().map(|serial| serial.to_string_lossy()))
+                .unwrap_or_default(),
+        };
+
+        Self{
+            content,
+            device_info,
+            session_id: None,
+            storage_object_ids: Vec::new(),
+            object_ids: Vec::new(),
+            handles: HashMap::new(),
+            pending_command: None,
+            pending_object: None,
+            incoming: None,
+            events: None,
+        }
+    }
+
+    pub fn device_info(&self) -> &DeviceInfo {
+        &self.device_info
+    }
+
+    /// Handle a container sent by the initiator, and return the containers to send back (a data phase and/or a response).
+    ///
+    /// Commands that expect a data phase from the initiator are only answered once their data phase has been received.<br/>
+    /// Whole containers are handled here, including the data of objects: [`Self::serve`] and [`Self::serve_ptpip`] stream them instead.
+    pub fn process(&mut self, container: Container) -> Vec<Container> {
+        let answer = match container.container_type {
+            ContainerType::Data => {
+                self.start_data(container.transaction_id, Some(container.payload.len() as u64));
+                self.receive_data(&container.payload);
+                Some(self.end_data(container.transaction_id))
+            },
+            _ => self.process_command(container),
+        };
+        answer.map(Answer::into_containers).unwrap_or_default()
+    }
+
+    /// Answer a command, unless it expects a data phase
+    fn process_command(&mut self, container: Container) -> Option<Answer> {
+        match container.container_type {
+            ContainerType::Command if OPERATIONS_WITH_DATA.contains(&container.operation_code()) => {
+                self.pending_command = Some(container);
+                None
+            },
+            ContainerType::Command => Some(self.answer(&container, None)),
+            // Initiators do not send anything else
+            _ => None,
+        }
+    }
+
+    /// Start receiving the data phase of the pending command. `length` is `None` when it is not known in advance.
+    fn start_data(&mut self, transaction_id: u32, length: Option<u64>) {
+        let command = self.pending_command.take().filter(|command| command.transaction_id == transaction_id);
+        let operation = command.as_ref().map_or(OperationCode::Other(0), Container::operation_code);
+        let kind = match command {
+            None => Incoming::Rejected(ResponseCode::GeneralError),
+            Some(_) if operation == OperationCode::SendObject => match self.start_object() {
+                Ok((pending_object, stream)) => Incoming::Object(pending_object, stream),
+                Err(Failure(code)) => Incoming::Rejected(code),
+            },
+            Some(_) if length.is_some_and(|length| length > MAX_BUFFERED_DATA_LEN as u64) => Incoming::Rejected(ResponseCode::InvalidDataset),
+            Some(command) => Incoming::Buffered(command, Vec::new()),
+        };
+        self.abort_incoming();
+        self.incoming = Some(IncomingData{ operation, transaction_id, kind });
+    }
+
+    /// Handle the next bytes of the data phase that is being received
+    fn receive_data(&mut self, data: &[u8]) {
+        let Some(incoming) = &mut self.incoming else {
+            return;
+        };
+        match &mut incoming.kind {
+            Incoming::Buffered(_, buffer) if buffer.len() + data.len() > MAX_BUFFERED_DATA_LEN => {
+                incoming.kind = Incoming::Rejected(ResponseCode::InvalidDataset);
+            },
+            Incoming::Buffered(_, buffer) => buffer.extend_from_slice(data),
+            Incoming::Object(_, stream) => {
+                if let Err(err) = stream.write_all(data) {
+                    let _ = stream.abort();
+                    incoming.kind = Incoming::Rejected(Failure::from(MtpError::from(err)).0);
+                }
+            },
+            Incoming::Rejected(_) => {},
+        }
+    }
+
+    /// Answer the command whose data phase has been received
+    fn end_data(&mut self, transaction_id: u32) -> Answer {
+        let Some(incoming) = self.incoming.take() else {
+            return Answer::failure(OperationCode::Other(0), transaction_id, ResponseCode::GeneralError);
+        };
+        match incoming.kind {
+            Incoming::Buffered(command, data) => self.answer(&command, Some(data)),
+            Incoming::Object(pending_object, stream) => match self.finish_object(pending_object, stream) {
+                Ok(()) => Answer{ operation: incoming.operation, transaction_id, data: None, response: ResponseCode::Ok, params: Vec::new() },
+                Err(Failure(code)) => Answer::failure(incoming.operation, transaction_id, code),
+            },
+            Incoming::Rejected(code) => Answer::failure(incoming.operation, incoming.transaction_id, code),
+        }
+    }
+
+    /// Give up the object that was being received, if any
+    fn abort_incoming(&mut self) {
+        if let Some(IncomingData{ kind: Incoming::Object(_, mut stream), .. }) = self.incoming.take() {
+            let _ = stream.abort();
+        }
+    }
+
+    /// Serve an initiator on a byte stream in which containers are simply concatenated (e.g. a socket), until the stream is closed.
+    ///
+    /// The data of objects is streamed, other containers are rejected when they are larger than needed, before anything is allocated for them.<br/>
+    /// The session is closed when the stream is, so that the next initiator can open its own.
+    pub fn serve<S: Read + Write>(&mut self, mut stream: S) -> Result<(), MtpError> {
+        let result = self.serve_stream(&mut stream);
+        self.end_session();
+        result
+    }
+
+    fn serve_stream<S: Read + Write>(&mut self, stream: &mut S) -> Result<(), MtpError> {
+        loop {
+            let header = match ContainerHeader::read_from(stream) {
+                Ok(header) => header,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(err) => return Err(err.into()),
+            };
+            let answer = match header.container_type {
+                // Nothing would tell where such data phases end
+                ContainerType::Data if header.length == ContainerHeader::UNKNOWN_LENGTH => {
+                    return Err(ProtocolError::InvalidLength(header.length).into());
+                },
+                ContainerType::Data => {
+                    self.start_data(header.transaction_id, Some(header.payload_len() as u64));
+                    let mut payload = Read::by_ref(stream).take(header.payload_len() as u64);
+                    let mut buf = vec![0; STREAM_CHUNK_SIZE.min(header.payload_len())];
+                    loop {
+                        match payload.read(&mut buf)? {
+                            0 => break,
+                            len => self.receive_data(&buf[..len]),
+                        }
+                    }
+                    if payload.limit() != 0 {
+                        return Err(ProtocolError::Truncated.into());
+                    }
+                    Some(self.end_data(header.transaction_id))
+                },
+                _ if header.payload_len() > 4 * Container::MAX_PARAMS => return Err(ProtocolError::InvalidLength(header.length).into()),
+                _ => self.process_command(Container::read_payload_from(&header, stream)?),
+            };
+
+            if let Some(mut answer) = answer {
+                if let Some(data) = &answer.data {
+                    let data_header = ContainerHeader::data(answer.operation, answer.transaction_id, data.len());
+                    if data_header.length == ContainerHeader::UNKNOWN_LENGTH {
+                        // Such data phases could not be read back either
+                        answer = Answer::failure(answer.operation, answer.transaction_id, ResponseCode::GeneralError);
+                    } else {
+                        stream.write_all(&data_header.encode())?;
+                        answer.send_data(&mut |bytes| stream.write_all(bytes))?;
+                    }
+                }
+                answer.response().write_to(stream)?;
+                stream.flush()?;
+            }
+            // There is no event channel on such streams
+            self.take_events();
+        }
+    }
+
+    /// Forget about the session of an initiator that went away
+    fn end_session(&mut self) {
+        self.session_id = None;
+        self.pending_command = None;
+        self.pending_object = None;
+        self.abort_incoming();
+        self.events = None;
+    }
+
+    /// Get the events to send to the initiator, about what has changed since the last call (including the changes the initiator made).
+    ///
+    /// Events are only collected while a session is open, and if the backend reports them.
+    pub fn take_events(&mut self) -> Vec<Container> {
+        let mut events: Vec<DeviceEvent> = match &self.events {
+            Some((_, receiver)) => receiver.try_iter().collect(),
+            None => return Vec::new(),
+        };
+        // E.g. an object whose properties are set one by one
+        events.dedup();
+        events.into_iter().filter_map(|event| self.event_container(event)).collect()
+    }
+
+    /// Objects the initiator has never been told about are not worth an event, unless they are new
+    fn event_container(&mut self, event: DeviceEvent) -> Option<Container> {
+        let (code, param) = match event {
+            DeviceEvent::ObjectAdded{ id, .. } => (EventCode::ObjectAdded, self.handle(id.as_ucstr())),
+            DeviceEvent::ObjectRemoved{ id } => (EventCode::ObjectRemoved, self.removed_handle(id.as_ucstr())?),
+            DeviceEvent::ObjectUpdated{ id } => (EventCode::ObjectInfoChanged, *self.handles.get(id.as_ucstr())?),
+            DeviceEvent::StorageChanged{ id: Some(id) } => match self.storage_id(id.as_ucstr()) {
+                Some(storage_id) => (EventCode::StorageInfoChanged, storage_id),
+                None => {
+                    self.refresh_storages().ok()?;
+                    (EventCode::StoreAdded, self.storage_id(id.as_ucstr())?)
+                },
+            },
+            DeviceEvent::StorageChanged{ id: None } => (EventCode::StorageInfoChanged, ALL),
+            DeviceEvent::DeviceRemoved => return None,
+        };
+        Some(Container::event(code, 0, &[param]))
+    }
+
+    fn answer(&mut self, command: &Container, data: Option<Vec<u8>>) -> Answer {
+        let operation = command.operation_code();
+        let transaction_id = command.transaction_id;
+        match self.run(operation, command, data) {
+            Ok(reply) => Answer{ operation, transaction_id, data: reply.data, response: ResponseCode::Ok, params: reply.params },
+            Err(Failure(code)) => Answer::failure(operation, transaction_id, code),
+        }
+    }
+
+    fn run(&mut self, operation: OperationCode, command: &Container, data: Option<Vec<u8>>) -> Result<Reply, Failure> {
+        let param = |index| command.param(index);
+
+        match operation {
+            OperationCode::GetDeviceInfo => return Ok(Reply::data(self.device_info.encode())),
+            OperationCode::OpenSession => return self.open_session(param(0)),
+            _ if self.session_id.is_none() => return Err(ResponseCode::SessionNotOpen.into()),
+            _ => {},
+        }
+
+        match operation {
+            OperationCode::CloseSession => {
+                self.session_id = None;
+                self.events = None;
+                Ok(Reply::default())
+            },
+            OperationCode::GetStorageIds => {
+                self.refresh_storages()?;
+                let storage_ids: Vec<u32> = (0..self.storage_object_ids.len() as u32).map(|index| FIRST_STORAGE_ID + index).collect();
+                let mut writer = Writer::new();
+                writer.put_u32_array(&storage_ids);
+                Ok(Reply::data(writer.into_bytes()))
+            },
+            OperationCode::GetStorageInfo => self.storage_info(param(0)),
+            OperationCode::GetNumObjects => {
+                let handles = self.object_handles(param(0), param(1), param(2))?;
+                Ok(Reply::params(&[handles.len() as u32]))
+            },
+            OperationCode::GetObjectHandles => {
+                let handles = self.object_handles(param(0), param(1), param(2))?;
+                let mut writer = Writer::new();
+                writer.put_u32_array(&handles);
+                Ok(Reply::data(writer.into_bytes()))
+            },
+            OperationCode::GetObjectInfo => {
+                let object_id = self.object_id(param(0))?;
+                Ok(Reply::data(self.object_info(&object_id)?.encode()))
+            },
+            OperationCode::GetObject => {
+                let object_id = self.object_id(param(0))?;
+                Ok(Reply{ params: Vec::new(), data: Some(self.object_data(&object_id)?) })
+            },
+            OperationCode::GetPartialObject => {
+                let object_id = self.object_id(param(0))?;
+                Ok(self.partial_object_data(&object_id, param(1) as u64, param(2))?)
+            },
+            OperationCode::GetPartialObject64 => {
+                let object_id = self.object_id(param(0))?;
+                let offset = (param(2) as u64) << 32 | param(1) as u64;
+                Ok(self.partial_object_data(&object_id, offset, param(3))?)
+            },
+            OperationCode::DeleteObject => {
+                if param(0) == ALL {
+                    return Err(ResponseCode::ParameterNotSupported.into());
+                }
+                let object_id = self.object_id(param(0))?;
+                self.content.delete(&[&object_id], true)?.into_iter().try_for_each(|result| result)?;
+                self.handles.remove(&object_id);
+                Ok(Reply::default())
+            },
+            OperationCode::SendObjectInfo => self.send_object_info(param(0), param(1), &data.unwrap_or_default()),
+            // Its data goes straight into the backend, see `start_object`
+            OperationCode::SendObject => Err(ResponseCode::NoValidObjectInfo.into()),
+            OperationCode::MoveObject => {
+                let object_id = self.object_id(param(0))?;
+                let destination_id = match param(2) {
+                    0 | ALL => self.storage_object_id(param(1))?,
+                    parent => self.object_id(parent)?,
+                };
+                self.content.move_objects(&[&object_id], &destination_id)?.into_iter().try_for_each(|result| result)?;
+                Ok(Reply::default())
+            },
+            OperationCode::GetObjectPropsSupported => {
+                let codes: Vec<u16> = SUPPORTED_PROPERTIES.iter().map(|(code, _, _)| code.as_u16()).collect();
+                let mut writer = Writer::new();
+                writer.put_u16_array(&codes);
+                Ok(Reply::data(writer.into_bytes()))
+            },
+            OperationCode::GetObjectPropDesc => {
+                let (property_code, data_type, writable) = supported_property(param(0))?;
+                let desc = ObjectPropDesc{
+                    property_code,
+                    data_type,
+                    writable,
+                    default_value: default_value(data_type),
+                    group_code: 0,
+                    form: match property_code {
+                        ObjectPropertyCode::DateCreated | ObjectPropertyCode::DateModified => PropertyForm::DateTime,
+                        _ => PropertyForm::None,
+                    },
+                };
+                Ok(Reply::data(desc.encode()))
+            },
+            OperationCode::GetObjectPropValue => {
+                let handle = param(0);
+                let object_id = self.object_id(handle)?;
+                let (property, _, _) = supported_property(param(1))?;
+                let info = self.object_info(&object_id)?;
+                let value = self.object_prop_value(handle, &object_id, &info, property)?;
+                let mut writer = Writer::new();
+                writer.put_value(&value);
+                Ok(Reply::data(writer.into_bytes()))
+            },
+            OperationCode::GetObjectPropList => self.object_prop_list(param(0), param(1), param(2), param(3), param(4)),
+            OperationCode::SetObjectPropValue => {
+                let object_id = self.object_id(param(0))?;
+                let (property, data_type, writable) = supported_property(param(1))?;
+                if !writable {
+                    return Err(ResponseCode::AccessDenied.into());
+                }
+                let value = Reader::new(&data.unwrap_or_default()).get_value(data_type)?;
+                let property_value = property.to_property_value(&value).ok_or(ResponseCode::InvalidObjectPropValue)?;
+                let mut values = DeviceValues::new();
+                if property == ObjectPropertyCode::ObjectFileName {
+                    // Devices display their file names
+                    values.set(WPD_OBJECT_NAME, property_value.clone());
+                }
+                values.set(property.property_key().ok_or(ResponseCode::ObjectPropNotSupported)?, property_value);
+                self.content.set_properties(&object_id, &values)?.into_iter().try_for_each(|result| result)?;
+                Ok(Reply::default())
+            },
+            OperationCode::GetDevicePropDesc => {
+                let (property_code, data_type, current_value) = self.device_prop_value(param(0))?;
+                let desc = DevicePropDesc{
+                    property_code,
+                    data_type,
+                    writable: false,
+                    factory_default_value: current_value.clone(),
+                    current_value,
+                    form: match property_code {
+                        DevicePropertyCode::BatteryLevel => PropertyForm::Range{ min: Value::U8(0), max: Value::U8(100), step: Value::U8(1) },
+                        _ => PropertyForm::None,
+                    },
+                };
+                Ok(Reply::data(desc.encode()))
+            },
+            OperationCode::GetDevicePropValue => {
+                let (_, _, value) = self.device_prop_value(param(0))?;
+                let mut writer = Writer::new();
+                writer.put_value(&value);
+                Ok(Reply::data(writer.into_bytes()))
+            },
+            _ => Err(ResponseCode::OperationNotSupported.into()),
+        }
+    }
+
+    /// Device properties are read from the device object of the backend, and are not supported when it does not have them
+    fn device_prop_value(&self, code: u32) -> Result<(DevicePropertyCode, DataType, Value), Failure> {
+        let code = DevicePropertyCode::from_u16(code as u16);
+        let (_, data_type, key) = SUPPORTED_DEVICE_PROPERTIES
+            .iter()
+            .find(|(supported_code, _, _)| *supported_code == code)
+            .ok_or(Failure(ResponseCode::DevicePropNotSupported))?;
+        let values = self.content.properties(&device_object_id(), &[*key])?;
+        let value = values.get_u32(key).map_err(|_| Failure(ResponseCode::DevicePropNotSupported))?;
+        let value = match data_type {
+            DataType::Uint8 => Value::U8(value.min(100) as u8),
+            _ => Value::U32(value),
+        };
+        Ok((code, *data_type, value))
+    }
+
+    fn open_session(&mut self, session_id: u32) -> Result<Reply, Failure> {
+        if session_id == 0 {
+            return Err(ResponseCode::InvalidParameter.into());
+        }
+        if self.session_id.is_some() {
+            return Err(ResponseCode::SessionAlreadyOpen.into());
+        }
+        self.refresh_storages()?;
+        self.session_id = Some(session_id);
+        let (sender, receiver) = mpsc::channel();
+        let sink = EventSink::new(move |event| {
+            let _ = sender.send(event);
+        });
+        // Backends that do not report events are served all the same
+        self.events = self.content.subscribe(sink).ok().map(|subscription| (subscription, receiver));
+        Ok(Reply::default())
+    }
+
+    /// Assign storage IDs to the storages that have appeared since the last call
+    fn refresh_storages(&mut self) -> Result<(), MtpError> {
+        for child_id in self.content.children(&device_object_id())? {
+            if self.storage_object_ids.contains(&child_id) {
+                continue;
+            }
+            let category = self.content.properties(&child_id, &[WPD_FUNCTIONAL_OBJECT_CATEGORY])?.get_guid(&WPD_FUNCTIONAL_OBJECT_CATEGORY);
+            if category.is_ok_and(|category| category == WPD_FUNCTIONAL_CATEGORY_STORAGE) {
+                self.storage_object_ids.push(child_id);
+            }
+        }
+        Ok(())
+    }
+
+    fn storage_object_id(&self, storage_id: u32) -> Result<U16CString, Failure> {
+        storage_id
+            .checked_sub(FIRST_STORAGE_ID)
+            .and_then(|index| self.storage_object_ids.get(index as usize))
+            .cloned()
+            .ok_or(Failure(ResponseCode::InvalidStorageId))
+    }
+
+    fn storage_id(&self, object_id: &U16CStr) -> Option<u32> {
+        self.storage_object_ids
+            .iter()
+            .position(|storage_object_id| storage_object_id.as_ucstr() == object_id)
+            .map(|index| FIRST_STORAGE_ID + index as u32)
+    }
+
+    fn object_id(&self, handle: u32) -> Result<U16CString, Failure> {
+        handle
+            .checked_sub(1)
+            .and_then(|index| self.object_ids.get(index as usize))
+            .cloned()
+            .flatten()
+            .ok_or(Failure(ResponseCode::InvalidObjectHandle))
+    }
+
+    /// The handle of an object that is gone, which may have been forgotten already, if the initiator deleted it
+    fn removed_handle(&mut self, object_id: &U16CStr) -> Option<u32> {
+        if let Some(handle) = self.handles.remove(object_id) {
+            return Some(handle);
+        }
+        let index = self.object_ids.iter().rposition(|known_id| known_id.as_deref() == Some(object_id))?;
+        Some(index as u32 + 1)
+    }
+
+    fn handle(&mut self, object_id: &U16CStr) -> u32 {
+        if let Some(handle) = self.handles.get(object_id) {
+            return *handle;
+        }
+        self.object_ids.push(Some(object_id.to_ucstring()));
+        let handle = self.object_ids.len() as u32;
+        self.handles.insert(object_id.to_ucstring(), handle);
+        handle
+    }
+
+    fn storage_info(&mut self, storage_id: u32) -> Result<Reply, Failure> {
+        let storage_object_id = self.storage_object_id(storage_id)?;
+        let values = self.content.properties(&storage_object_id, &[
+            WPD_OBJECT_NAME, WPD_STORAGE_TYPE, WPD_STORAGE_ACCESS_CAPABILITY, WPD_STORAGE_CAPACITY,
+            WPD_STORAGE_FREE_SPACE_IN_BYTES, WPD_STORAGE_FREE_SPACE_IN_OBJECTS, WPD_STORAGE_FILE_SYSTEM_TYPE,
+            WPD_STORAGE_DESCRIPTION, WPD_STORAGE_SERIAL_NUMBER,
+        ])?;
+        let string = |key| values.get_string(key).map(|s| s.to_string_lossy()).ok();
+
+        let info = StorageInfo{
+            // Fixed RAM
+            storage_type: values.get_u32(&WPD_STORAGE_TYPE).map(|ty| ty as u16).unwrap_or(3),
+            filesystem_type: string(&WPD_STORAGE_FILE_SYSTEM_TYPE).map_or(0x0002, |name| filesystem_type_code(&name)),
+            access_capability: values.get_u32(&WPD_STORAGE_ACCESS_CAPABILITY).map(|access| access as u16).unwrap_or(0),
+            max_capacity: values.get_u64(&WPD_STORAGE_CAPACITY).unwrap_or(0),
+            free_space_in_bytes: values.get_u64(&WPD_STORAGE_FREE_SPACE_IN_BYTES).unwrap_or(0),
+            free_space_in_objects: values.get_u64(&WPD_STORAGE_FREE_SPACE_IN_OBJECTS).map_or(u32::MAX, |free| free.min(u32::MAX as u64 - 1) as u32),
+            storage_description: string(&WPD_STORAGE_DESCRIPTION).or_else(|| string(&WPD_OBJECT_NAME)).unwrap_or_default(),
+            volume_identifier: string(&WPD_STORAGE_SERIAL_NUMBER).unwrap_or_default(),
+        };
+        Ok(Reply::data(info.encode()))
+    }
+
+    /// `parent` is [`ALL`] for the root of storages, and `0` for every object, whatever their depth
+    fn object_handles(&mut self, storage_id: u32, format: u32, parent: u32) -> Result<Vec<u32>, Failure> {
+        let mut object_ids = Vec::new();
+        if parent != 0 && parent != ALL {
+            let parent_id = self.object_id(parent)?;
+            object_ids.extend(self.content.children(&parent_id)?);
+        } else {
+            let storage_object_ids = match storage_id {
+                ALL => self.storage_object_ids.clone(),
+                storage_id => vec![self.storage_object_id(storage_id)?],
+            };
+            for storage_object_id in storage_object_ids {
+                if parent == ALL {
+                    object_ids.extend(self.content.children(&storage_object_id)?);
+                } else {
+                    self.collect_descendants(&storage_object_id, &mut object_ids)?;
+                }
+            }
+        }
+
+        let mut handles = Vec::with_capacity(object_ids.len());
+        for object_id in object_ids {
+            if format == 0 || self.object_format(&object_id)?.as_u16() as u32 == format {
+                handles.push(self.handle(&object_id));
+            }
+        }
+        Ok(handles)
+    }
+
+    fn collect_descendants(&self, object_id: &U16CStr, descendants: &mut Vec<U16CString>) -> Result<(), MtpError> {
+        for child_id in self.content.children(object_id)? {
+            self.collect_descendants(&child_id, descendants)?;
+            descendants.push(child_id);
+        }
+        Ok(())
+    }
+
+    fn object_format(&self, object_id: &U16CStr) -> Result<ObjectFormatCode, MtpError> {
+        let values = self.content.properties(object_id, &[WPD_OBJECT_FORMAT, WPD_OBJECT_CONTENT_TYPE])?;
+        Ok(format_of(&values))
+    }
+
+    /// The storage ID of an object, and the handle of its parent (`0` at the root of a storage), given the ID of its parent
+    fn location(&mut self, parent_id: &U16CStr) -> Result<(u32, u32), Failure> {
+        if let Some(storage_id) = self.storage_id(parent_id) {
+            return Ok((storage_id, 0));
+        }
+
+        let parent_handle = self.handle(parent_id);
+        let mut ancestor_id = parent_id.to_ucstring();
+        loop {
+            ancestor_id = self.content.properties(&ancestor_id, &[WPD_OBJECT_PARENT_ID])?.get_string(&WPD_OBJECT_PARENT_ID)?;
+            if let Some(storage_id) = self.storage_id(&ancestor_id) {
+                return Ok((storage_id, parent_handle));
+            }
+            if ancestor_id.is_empty() {
+                return Err(ResponseCode::GeneralError.into());
+            }
+        }
+    }
+
+    fn object_info(&mut self, object_id: &U16CStr) -> Result<ObjectInfo, Failure> {
+        let values = self.content.properties(object_id, &OBJECT_INFO_PROPERTIES)?;
+        let (storage_id, parent_object) = self.location(&values.get_string(&WPD_OBJECT_PARENT_ID)?)?;
+        let object_format = format_of(&values);
+        let filename = values.get_string(&WPD_OBJECT_ORIGINAL_FILE_NAME)
+            .or_else(|_| values.get_string(&WPD_OBJECT_NAME))?
+            .to_string_lossy();
+        let size = values.get_u64(&WPD_OBJECT_SIZE).unwrap_or(0);
+        let date = |key| values.get_date(key).map(format_datetime).unwrap_or_default();
+
+        Ok(ObjectInfo{
+            storage_id,
+            object_format,
+            object_compressed_size: u32::try_from(size).unwrap_or(u32::MAX),
+            parent_object,
+            // "Generic folder"
+            association_type: if object_format == ObjectFormatCode::Association { 1 } else { 0 },
+            filename,
+            date_created: date(&WPD_OBJECT_DATE_CREATED),
+            date_modified: date(&WPD_OBJECT_DATE_MODIFIED),
+            keywords: values.get_string(&WPD_OBJECT_KEYWORDS).map(|keywords| keywords.to_string_lossy()).unwrap_or_default(),
+            protection_status: values.get_u32(&MTP_OBJECT_PROTECTION_STATUS).map_or(0, |status| status as u16),
+            ..Default::default()
+        })
+    }
+
+    /// A range of the content of an object, whose length is chosen by the initiator, up to 4 GiB.
+    ///
+    /// It is streamed when the size of the object is known, and otherwise truncated to what can be buffered.
+    fn partial_object_data(&self, object_id: &U16CStr, offset: u64, length: u32) -> Result<Reply, MtpError> {
+        let Ok(size) = self.content.properties(object_id, &[WPD_OBJECT_SIZE])?.get_u64(&WPD_OBJECT_SIZE) else {
+            let sent = self.content.read_range(object_id, offset, (length as usize).min(MAX_BUFFERED_DATA_LEN))?;
+            return Ok(Reply{ params: vec![sent.len() as u32], data: Some(ReplyData::Bytes(sent)) });
+        };
+        let length = (length as u64).min(size.saturating_sub(offset));
+        let (mut stream, _optimal_transfer_size) = self.content.open_read_stream(object_id)?;
+        match stream.seek(SeekFrom::Start(offset)) {
+            Ok(_) => {},
+            // The beginning of streams that cannot seek is skipped instead
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => {
+                io::copy(&mut Read::by_ref(&mut stream).take(offset), &mut io::sink())?;
+            },
+            Err(err) => return Err(err.into()),
+        }
+        Ok(Reply{ params: vec![length as u32], data: Some(ReplyData::Object{ stream, length }) })
+    }
+
+    /// The content of an object, that is streamed when its size is known
+    fn object_data(&self, object_id: &U16CStr) -> Result<ReplyData, MtpError> {
+        let (mut stream, _optimal_transfer_size) = self.content.open_read_stream(object_id)?;
+        if let Ok(length) = self.content.properties(object_id, &[WPD_OBJECT_SIZE])?.get_u64(&WPD_OBJECT_SIZE) {
+            return Ok(ReplyData::Object{ stream, length });
+        }
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data)?;
+        Ok(ReplyData::Bytes(data))
+    }
+
+    fn object_prop_value(&mut self, handle: u32, object_id: &U16CStr, info: &ObjectInfo, property: ObjectPropertyCode) -> Result<Value, Failure> {
+        Ok(match property {
+            ObjectPropertyCode::StorageId => Value::U32(info.storage_id),
+            ObjectPropertyCode::ObjectFormat => Value::U16(info.object_format.as_u16()),
+            ObjectPropertyCode::ProtectionStatus => Value::U16(info.protection_status),
+            ObjectPropertyCode::ObjectSize => Value::U64(self.content.properties(object_id, &[WPD_OBJECT_SIZE])?.get_u64(&WPD_OBJECT_SIZE).unwrap_or(0)),
+            ObjectPropertyCode::ObjectFileName => Value::Str(info.filename.clone()),
+            ObjectPropertyCode::DateCreated => Value::Str(info.date_created.clone()),
+            ObjectPropertyCode::DateModified => Value::Str(info.date_modified.clone()),
+            ObjectPropertyCode::ParentObject => Value::U32(info.parent_object),
+            ObjectPropertyCode::PersistentUniqueObjectIdentifier => {
+                let values = self.content.properties(object_id, &[WPD_OBJECT_PERSISTENT_UNIQUE_ID])?;
+                values
+                    .get(&WPD_OBJECT_PERSISTENT_UNIQUE_ID)
+                    .and_then(|uid| property.to_value(uid))
+                    // Handles are as persistent as this responder is
+                    .unwrap_or(Value::U128(handle as u128))
+            },
+            ObjectPropertyCode::Name => {
+                let values = self.content.properties(object_id, &[WPD_OBJECT_NAME])?;
+                Value::Str(values.get_string(&WPD_OBJECT_NAME).map(|name| name.to_string_lossy()).unwrap_or_else(|_| info.filename.clone()))
+            },
+            ObjectPropertyCode::Hidden => {
+                let values = self.content.properties(object_id, &[WPD_OBJECT_ISHIDDEN])?;
+                Value::U16(values.get_bool(&WPD_OBJECT_ISHIDDEN).unwrap_or(false) as u16)
+            },
+            _ => return Err(ResponseCode::InvalidObjectPropCode.into()),
+        })
+    }
+
+    /// Only depths 0 (the object itself) and 1 (its children, or the objects at the root of every storage for handle 0) are supported, and properties cannot be designated by groups
+    fn object_prop_list(&mut self, handle: u32, format: u32, property: u32, group: u32, depth: u32) -> Result<Reply, Failure> {
+        let properties = match (property, group) {
+            (0, _) => return Err(ResponseCode::SpecificationByGroupUnsupported.into()),
+            (ALL, _) => SUPPORTED_PROPERTIES.iter().map(|(code, data_type, _)| (*code, *data_type)).collect(),
+            (code, _) => {
+                let (code, data_type, _) = supported_property(code)?;
+                vec![(code, data_type)]
+            },
+        };
+        let handles = match (handle, depth) {
+            (0 | ALL, 0) => return Err(ResponseCode::InvalidObjectHandle.into()),
+            (handle, 0) => {
+                self.object_id(handle)?;
+                vec![handle]
+            },
+            (0, 1) => self.object_handles(ALL, format, ALL)?,
+            (ALL, 1) => return Err(ResponseCode::InvalidObjectHandle.into()),
+            (handle, 1) => self.object_handles(ALL, format, handle)?,
+            _ => return Err(ResponseCode::SpecificationByDepthUnsupported.into()),
+        };
+
+        let mut elements = Vec::new();
+        for handle in handles {
+            let object_id = self.object_id(handle)?;
+            if format != 0 && depth == 0 && self.object_format(&object_id)?.as_u16() as u32 != format {
+                continue;
+            }
+            let info = self.object_info(&object_id)?;
+            for (property, data_type) in &properties {
+                elements.push((handle, *property, *data_type, self.object_prop_value(handle, &object_id, &info, *property)?));
+            }
+        }
+
+        let mut writer = Writer::new();
+        writer.put_u32(elements.len() as u32);
+        for (handle, property, data_type, value) in elements {
+            writer.put_u32(handle);
+            writer.put_u16(property.as_u16());
+            writer.put_u16(data_type.as_u16());
+            writer.put_value(&value);
+        }
+        Ok(Reply::data(writer.into_bytes()))
+    }
+
+    fn send_object_info(&mut self, storage_id: u32, parent: u32, dataset: &[u8]) -> Result<Reply, Failure> {
+        let info = ObjectInfo::decode(dataset)?;
+        let storage_id = match storage_id {
+            // The initiator lets the responder choose
+            0 => FIRST_STORAGE_ID,
+            storage_id => storage_id,
+        };
+        let parent_id = match parent {
+            0 | ALL => self.storage_object_id(storage_id)?,
+            parent => self.object_id(parent).map_err(|_| Failure(ResponseCode::InvalidParentObject))?,
+        };
+
+        let mut properties = DeviceValues::new();
+        properties.set(WPD_OBJECT_PARENT_ID, PropertyValue::String(parent_id));
+        properties.set(WPD_OBJECT_NAME, PropertyValue::String(U16CString::from_str_truncate(&info.filename)));
+        properties.set(WPD_OBJECT_ORIGINAL_FILE_NAME, PropertyValue::String(U16CString::from_str_truncate(&info.filename)));
+        if let Ok(date) = parse_datetime(&info.date_created) {
+            properties.set(WPD_OBJECT_DATE_CREATED, PropertyValue::Date(date));
+        }
+        if let Ok(date) = parse_datetime(&info.date_modified) {
+            properties.set(WPD_OBJECT_DATE_MODIFIED, PropertyValue::Date(date));
+        }
+
+        if info.object_format == ObjectFormatCode::Association {
+            properties.set(WPD_OBJECT_CONTENT_TYPE, PropertyValue::Guid(WPD_CONTENT_TYPE_FOLDER));
+            let object_id = self.content.create_object(&properties)?;
+            let handle = self.handle(&object_id);
+            return Ok(Reply::params(&[storage_id, parent, handle]));
+        }
+
+        if info.object_format != ObjectFormatCode::Undefined {
+            properties.set(WPD_OBJECT_FORMAT, PropertyValue::Guid(info.object_format.as_guid()));
+            properties.set(WPD_OBJECT_CONTENT_TYPE, PropertyValue::Guid(info.object_format.object_type().as_guid()));
+        }
+        // Larger sizes are not known in advance
+        if info.object_compressed_size != u32::MAX {
+            properties.set(WPD_OBJECT_SIZE, PropertyValue::U64(info.object_compressed_size as u64));
+        }
+
+        // The object is only created by `SendObject`, but its handle must be returned now
+        self.object_ids.push(None);
+        let handle = self.object_ids.len() as u32;
+        self.pending_object = Some(PendingObject{ handle, properties });
+        Ok(Reply::params(&[storage_id, parent, handle]))
+    }
+
+    /// Get ready to receive the data of the object announced by `SendObjectInfo`
+    fn start_object(&mut self) -> Result<(PendingObject, Box<dyn WriteStreamBackend>), Failure> {
+        if self.session_id.is_none() {
+            return Err(ResponseCode::SessionNotOpen.into());
+        }
+        let pending_object = self.pending_object.take().ok_or(ResponseCode::NoValidObjectInfo)?;
+        let (stream, _optimal_transfer_size) = self.content.create_object_with_data(&pending_object.properties)?;
+        Ok((pending_object, stream))
+    }
+
+    /// Create the object whose data has been received
+    fn finish_object(&mut self, pending_object: PendingObject, mut stream: Box<dyn WriteStreamBackend>) -> Result<(), Failure> {
+        stream.commit()?;
+
+        let object_id = match stream.object_id() {
+            Some(object_id) => object_id,
+            None => self.find_created_object(&pending_object.properties)?,
+        };
+        self.object_ids[pending_object.handle as usize - 1] = Some(object_id.clone());
+        self.handles.insert(object_id, pending_object.handle);
+        Ok(())
+    }
+
+    /// Find an object that has just been created, for backends that do not tell its ID
+    fn find_created_object(&self, properties: &DeviceValues) -> Result<U16CString, Failure> {
+        let parent_id = properties.get_string(&WPD_OBJECT_PARENT_ID)?;
+        let file_name = properties.get(&WPD_OBJECT_ORIGINAL_FILE_NAME);
+        for child_id in self.content.children(&parent_id)? {
+            let values = self.content.properties(&child_id, &[WPD_OBJECT_ORIGINAL_FILE_NAME])?;
+            if values.get(&WPD_OBJECT_ORIGINAL_FILE_NAME) == file_name {
+                return Ok(child_id);
+            }
+        }
+        Err(ResponseCode::GeneralError.into())
+    }
+}
+
+fn format_of(values: &DeviceValues) -> ObjectFormatCode {
+    if let Some(format) = values.get_guid(&WPD_OBJECT_FORMAT).ok().and_then(ObjectFormatCode::from_guid) {
+        return format;
+    }
+    match values.get_guid(&WPD_OBJECT_CONTENT_TYPE) {
+        Ok(content_type) if content_type == WPD_CONTENT_TYPE_FOLDER => ObjectFormatCode::Association,
+        _ => ObjectFormatCode::Undefined,
+    }
+}
+
+fn supported_property(code: u32) -> Result<(ObjectPropertyCode, DataType, bool), Failure> {
+    let code = ObjectPropertyCode::from_u16(code as u16);
+    SUPPORTED_PROPERTIES
+        .iter()
+        .copied()
+        .find(|(supported_code, _, _)| *supported_code == code)
+        .ok_or(Failure(ResponseCode::InvalidObjectPropCode))
+}
+
+fn default_value(data_type: DataType) -> Value {
+    match data_type {
+        DataType::Uint16 => Value::U16(0),
+        DataType::Uint32 => Value::U32(0),
+        DataType::Uint64 => Value::U64(0),
+        DataType::Uint128 => Value::U128(0),
+        _ => Value::Str(String::new()),
+    }
+}
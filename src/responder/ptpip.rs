@@ -0,0 +1,112 @@
+//! Serving PTP/IP initiators
+
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::error::MtpError;
+use crate::protocol::ptpip::{Packet, PROTOCOL_VERSION};
+use crate::responder::{Answer, Responder};
+
+/// How the responder introduces itself to initiators
+const RESPONDER_GUID: [u8; 16] = *b"winmtp-responder";
+
+/// The only connection a responder serves at a time
+const CONNECTION_NUMBER: u32 = 1;
+
+/// The longest packet that is accepted from initiators. Data phases may be longer, as they span several packets.
+const MAX_PACKET_LEN: usize = 16 * 1024 * 1024;
+
+/// Reason of an `InitFail` packet: the initiator did not start with the expected handshake
+const FAIL_REJECTED_INITIATOR: u32 = 1;
+
+impl Responder {
+    /// Accept a PTP/IP initiator on `listener` (its command and event channels), and serve it until it disconnects.
+    ///
+    /// Like [`Self::serve`], the session is closed when the initiator disconnects, so that this can be called again for the next initiator.
+    pub fn serve_ptpip(&mut self, listener: &TcpListener) -> Result<(), MtpError> {
+        let mut command_channel = accept(listener)?;
+        match Packet::read_from_limited(&mut command_channel, MAX_PACKET_LEN)? {
+            Packet::InitCommandRequest{ .. } => {},
+            other => {
+                Packet::InitFail{ reason: FAIL_REJECTED_INITIATOR }.write_to(&mut command_channel)?;
+                return Err(MtpError::Backend(format!("Unexpected PTP/IP packet {:?}", other)));
+            },
+        }
+        Packet::InitCommandAck{
+            connection_number: CONNECTION_NUMBER,
+            guid: RESPONDER_GUID,
+            friendly_name: self.device_info.model.clone(),
+            protocol_version: PROTOCOL_VERSION,
+        }.write_to(&mut command_channel)?;
+
+        let mut event_channel = accept(listener)?;
+        match Packet::read_from_limited(&mut event_channel, MAX_PACKET_LEN)? {
+            Packet::InitEventRequest{ connection_number: CONNECTION_NUMBER } => Packet::InitEventAck.write_to(&mut event_channel)?,
+            other => {
+                Packet::InitFail{ reason: FAIL_REJECTED_INITIATOR }.write_to(&mut event_channel)?;
+                return Err(MtpError::Backend(format!("Unexpected PTP/IP packet {:?}", other)));
+            },
+        }
+
+        let result = self.serve_ptpip_commands(&mut command_channel, &mut event_channel);
+        self.end_session();
+        result
+    }
+
+    /// Events are sent once the transaction that was being processed when they happened is over
+    fn serve_ptpip_commands(&mut self, command_channel: &mut TcpStream, event_channel: &mut TcpStream) -> Result<(), MtpError> {
+        loop {
+            let packet = match Packet::read_from_limited(command_channel, MAX_PACKET_LEN) {
+                Ok(packet) => packet,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(err) => return Err(err.into()),
+            };
+            let answer = match packet {
+                packet @ Packet::OperationRequest{ .. } => packet.to_container().and_then(|command| self.process_command(command)),
+                Packet::StartData{ transaction_id, total_length } => {
+                    // This is how initiators tell they do not know the length in advance
+                    let length = Some(total_length).filter(|&length| length != u64::MAX);
+                    self.start_data(transaction_id, length);
+                    continue;
+                },
+                Packet::Data{ payload, .. } => {
+                    self.receive_data(&payload);
+                    continue;
+                },
+                Packet::EndData{ transaction_id, payload } => {
+                    self.receive_data(&payload);
+                    Some(self.end_data(transaction_id))
+                },
+                // Nothing can be cancelled, since every transaction is completed before the next packet is read
+                Packet::Cancel{ .. } => continue,
+                other => return Err(MtpError::Backend(format!("Unexpected PTP/IP packet {:?}", other))),
+            };
+
+            if let Some(answer) = answer {
+                send_answer(command_channel, answer)?;
+            }
+            command_channel.flush()?;
+            for event in self.take_events() {
+                Packet::from_container(&event)?.write_to(event_channel)?;
+            }
+        }
+    }
+}
+
+fn accept(listener: &TcpListener) -> Result<TcpStream, MtpError> {
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+/// Send the data phase of an answer in `Data` packets, as it is read, then its response
+fn send_answer(command_channel: &mut TcpStream, mut answer: Answer) -> Result<(), MtpError> {
+    if let Some(data) = &answer.data {
+        let transaction_id = answer.transaction_id;
+        Packet::StartData{ transaction_id, total_length: data.len() }.write_to(command_channel)?;
+        answer.send_data(&mut |bytes| Packet::Data{ transaction_id, payload: bytes.to_vec() }.write_to(command_channel))?;
+        Packet::EndData{ transaction_id, payload: Vec::new() }.write_to(command_channel)?;
+    }
+    Packet::from_container(&answer.response())?.write_to(command_channel)?;
+    Ok(())
+}
@@ -0,0 +1,727 @@
+//! One-way synchronisation between a folder of a device and a local directory
+//!
+//! Synchronising is done in two steps:
+//! * [`plan`] compares both sides, and lists what is new, changed, deleted or unchanged, without modifying anything (this is a dry run)
+//! * [`SyncPlan::execute`] applies the plan, using the usual transfer functions (e.g. [`Object::pull_to_file`] and [`Object::push_file_with_progress`])
+//!
+//! [`sync`] does both at once.
+//!
+//! When pulling, a [`SyncState`] can remember what has been pulled, keyed by the persistent unique IDs of the objects.
+//! [`plan_with_state`] then tells new and changed objects without reading the whole local directory,
+//! and objects that have been renamed or moved on the device are moved locally, rather than pulled again.
+//!
+//! # Example
+//! ```no_run
+//! # use std::path::Path;
+//! use winmtp::sync::{SyncDirection, SyncMode, SyncOptions};
+//! # let provider = winmtp::Provider::new().unwrap();
+//! # let basic_device = &provider.enumerate_devices().unwrap()[0];
+//! # let app_identifiers = winmtp::make_current_app_identifiers!();
+//! # let device = basic_device.open(&app_identifiers, false).unwrap();
+//! let dcim = device.content().unwrap().root().unwrap().object_by_path(Path::new("Internal storage/DCIM")).unwrap();
+//! let options = SyncOptions::new().mode(SyncMode::Mirror).exclude(".thumbnails");
+//! let plan = winmtp::sync::plan(SyncDirection::Pull, &dcim, Path::new("/mnt/nas/phone"), &options).unwrap();
+//! println!("{} new files, {} changed files", plan.created().count(), plan.updated().count());
+//! let report = plan.execute(options).unwrap();
+//! for (path, err) in &report.failed {
+//!     println!("Failed to sync {}: {}", path.display(), err);
+//! }
+//! ```
+//!
+//! Incremental backups:
+//! ```no_run
+//! # use std::path::Path;
+//! use winmtp::sync::{SyncOptions, SyncState};
+//! # let provider = winmtp::Provider::new().unwrap();
+//! # let basic_device = &provider.enumerate_devices().unwrap()[0];
+//! # let app_identifiers = winmtp::make_current_app_identifiers!();
+//! # let device = basic_device.open(&app_identifiers, false).unwrap();
+//! let dcim = device.content().unwrap().root().unwrap().object_by_path(Path::new("Internal storage/DCIM")).unwrap();
+//! let state_file = Path::new("/mnt/nas/phone.sync-state.json");
+//! let mut state = SyncState::load(state_file).unwrap();
+//! let report = winmtp::sync::sync_with_state(&dcim, Path::new("/mnt/nas/phone"), &mut state, SyncOptions::new()).unwrap();
+//! println!("{} files moved on the phone", report.plan.moved().count());
+//! state.save(state_file).unwrap();
+//! ```
+
+mod state;
+pub use state::{StateEntry, SyncState};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use windows::Win32::Devices::PortableDevices::{WPD_OBJECT_SIZE, WPD_OBJECT_DATE_MODIFIED, WPD_OBJECT_PERSISTENT_UNIQUE_ID};
+
+use crate::error::{ErrorKind, MtpError};
+use crate::io::{AggregatedProgress, CancellationToken, ProgressObserver};
+use crate::object::{Object, ObjectType, OverwritePolicy, PullOptions};
+use crate::utils::{glob_match, join_object_name};
+
+/// Which side is the source, the other side being made like it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncDirection {
+    /// From the device to the local directory
+    Pull,
+    /// From the local directory to the device
+    Push,
+}
+
+/// What happens to entries that only exist at the destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncMode {
+    /// They are kept
+    #[default]
+    Additive,
+    /// They are deleted, so that the destination ends up being a copy of the source
+    Mirror,
+}
+
+/// How to tell whether a file that exists on both sides has changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComparePolicy {
+    /// Files have changed when their sizes differ
+    Size,
+    /// Files have changed when their sizes differ, or when the source has been modified after the destination.
+    ///
+    /// Dates that are less than `tolerance` apart are considered equal, because some file systems (and MTP) only have a coarse resolution.
+    /// Comparing the modification dates this way still works for devices that do not let the dates of pushed files be set.
+    SizeAndModified{ tolerance: Duration },
+}
+
+impl Default for ComparePolicy {
+    fn default() -> Self {
+        Self::SizeAndModified{ tolerance: Duration::from_secs(2) }
+    }
+}
+
+/// Options of [`plan`], [`SyncPlan::execute`] and [`sync`]
+///
+/// Excluded paths are glob patterns (see [`glob_match`]), matched case-insensitively against paths relative to the synchronised folders.
+/// Excluded entries are left untouched on both sides, even in [`SyncMode::Mirror`].
+pub struct SyncOptions<'a> {
+    mode: SyncMode,
+    compare: ComparePolicy,
+    excludes: Vec<String>,
+    dry_run: bool,
+    progress: Option<&'a mut dyn ProgressObserver>,
+    cancellation: CancellationToken,
+}
+
+impl<'a> SyncOptions<'a> {
+    /// Additive, compare sizes and modification dates, no progress report, and no way to cancel
+    pub fn new() -> Self {
+        Self{
+            mode: SyncMode::default(),
+            compare: ComparePolicy::default(),
+            excludes: Vec::new(),
+            dry_run: false,
+            progress: None,
+            cancellation: CancellationToken::new(),
+        }
+    }
+
+    pub fn mode(mut self, mode: SyncMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn compare(mut self, compare: ComparePolicy) -> Self {
+        self.compare = compare;
+        self
+    }
+
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.excludes.push(pattern.to_string());
+        self
+    }
+
+    /// With a dry run, [`sync`] only computes the plan, and does not modify anything
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Progress is reported for the whole synchronisation, not file by file
+    pub fn progress(mut self, progress: &'a mut dyn ProgressObserver) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    pub fn cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    fn is_excluded(&self, relative_path: &Path) -> bool {
+        let path = slash_path(relative_path);
+        self.excludes.iter().any(|pattern| glob_match(pattern, &path, false))
+    }
+}
+
+impl Default for SyncOptions<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for SyncOptions<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyncOptions")
+            .field("mode", &self.mode)
+            .field("compare", &self.compare)
+            .field("excludes", &self.excludes)
+            .field("dry_run", &self.dry_run)
+            .field("cancellation", &self.cancellation)
+            .finish_non_exhaustive()
+    }
+}
+
+/// What a [`SyncPlan`] does with an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncAction {
+    /// The entry only exists in the source, and will be copied
+    Create,
+    /// The entry exists on both sides but has changed, and will be copied again
+    Update,
+    /// The entry only exists at the destination, and will be deleted ([`SyncMode::Mirror`] only)
+    Delete,
+    /// The entry exists on both sides, and has not changed
+    Unchanged,
+    /// The object has been renamed or moved on the device since the last synchronisation, and its local copy will be moved as well
+    /// ([`plan_with_state`] only).
+    ///
+    /// If its content has changed too, its [size](SyncEntry::size) is set, and it will be pulled again.
+    Move,
+}
+
+/// An entry of a [`SyncPlan`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncEntry {
+    /// The path of the entry, relative to the synchronised folders
+    pub path: PathBuf,
+    pub is_folder: bool,
+    pub action: SyncAction,
+    /// The size of the source file, for entries to create, update, or move and update
+    pub size: Option<u64>,
+    /// Where the local copy of a [`SyncAction::Move`] entry currently is
+    pub moved_from: Option<PathBuf>,
+}
+
+/// The size and modification date of a file or a folder, on either side
+#[derive(Debug, Clone, Copy)]
+struct EntryState {
+    is_folder: bool,
+    size: Option<u64>,
+    modified: Option<SystemTime>,
+}
+
+/// The content of a device folder, by relative path
+#[derive(Debug, Default)]
+struct DeviceListing {
+    objects: HashMap<PathBuf, Object>,
+    entries: BTreeMap<PathBuf, EntryState>,
+    /// The keys of their records in a [`SyncState`]
+    keys: HashMap<PathBuf, String>,
+    /// Objects whose names cannot be used locally (e.g. `..`), that are left out of the plan, and reported as failed
+    rejected: Vec<(PathBuf, MtpError)>,
+}
+
+/// What [`plan`] found out, and that [`Self::execute`] can apply
+#[derive(Debug)]
+pub struct SyncPlan {
+    direction: SyncDirection,
+    device_folder: Object,
+    local_dir: PathBuf,
+    device: DeviceListing,
+    /// The keys of the records of the entries to delete, for plans made with a [`SyncState`]
+    deleted_keys: HashMap<PathBuf, String>,
+    /// The keys of the records of objects whose local copies are gone
+    forgotten_keys: Vec<String>,
+    /// Sorted by path, so that parents come before their children
+    pub entries: Vec<SyncEntry>,
+}
+
+/// What [`SyncPlan::execute`] did
+#[derive(Debug)]
+pub struct SyncReport {
+    pub plan: SyncPlan,
+    /// The paths of the entries that have been created, updated or deleted
+    pub done: Vec<PathBuf>,
+    pub failed: Vec<(PathBuf, MtpError)>,
+}
+
+impl SyncReport {
+    /// Whether every entry of the plan has been applied (this is `true` for dry runs that found nothing to do)
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty() && self.done.len() == self.plan.changes().count()
+    }
+}
+
+/// Compare `device_folder` with `local_dir` (that may not exist yet), and list what synchronising them would do
+pub fn plan(direction: SyncDirection, device_folder: &Object, local_dir: &Path, options: &SyncOptions) -> Result<SyncPlan, MtpError> {
+    let mut device = DeviceListing::default();
+    list_device(device_folder, PathBuf::new(), options, &mut device)?;
+    let mut local_entries = BTreeMap::new();
+    if local_dir.exists() {
+        list_local(local_dir, PathBuf::new(), options, &mut local_entries)?;
+    }
+
+    let (source, destination) = match direction {
+        SyncDirection::Pull => (&device.entries, &local_entries),
+        SyncDirection::Push => (&local_entries, &device.entries),
+    };
+    let mut entries = Vec::new();
+    for (path, source_state) in source {
+        let action = match destination.get(path) {
+            None => SyncAction::Create,
+            Some(destination_state) if has_changed(source_state, destination_state, options.compare) => SyncAction::Update,
+            Some(_) => SyncAction::Unchanged,
+        };
+        let size = match action {
+            SyncAction::Create | SyncAction::Update if !source_state.is_folder => Some(source_state.size.unwrap_or(0)),
+            _ => None,
+        };
+        entries.push(SyncEntry{ path: path.clone(), is_folder: source_state.is_folder, action, size, moved_from: None });
+    }
+    if options.mode == SyncMode::Mirror {
+        for (path, destination_state) in destination {
+            if !source.contains_key(path) {
+                entries.push(SyncEntry{ path: path.clone(), is_folder: destination_state.is_folder, action: SyncAction::Delete, size: None, moved_from: None });
+            }
+        }
+    }
+    entries.sort_by(|left, right| left.path.cmp(&right.path));
+
+    Ok(SyncPlan{
+        direction,
+        device_folder: device_folder.clone(),
+        local_dir: local_dir.to_path_buf(),
+        device,
+        deleted_keys: HashMap::new(),
+        forgotten_keys: Vec::new(),
+        entries,
+    })
+}
+
+/// Compare `device_folder` with what `state` remembers of a previous pull into `local_dir`, and list what pulling it again would do
+///
+/// Only the local copies of the objects that are known are looked at, and their contents are never read.
+/// The device folder is still listed entirely, as MTP cannot tell which folders have changed,
+/// but the properties of the children of each folder are read at once when the device allows it (see [`Object::children_with_properties`]).
+/// Objects that are not in `state` yet are compared with the local files at the same paths, as [`plan`] does.<br/>
+/// In [`SyncMode::Mirror`], the local copies of the objects that are gone from the device are deleted, but local files that have never been pulled are left alone.
+pub fn plan_with_state(device_folder: &Object, local_dir: &Path, state: &SyncState, options: &SyncOptions) -> Result<SyncPlan, MtpError> {
+    let mut device = DeviceListing::default();
+    list_device(device_folder, PathBuf::new(), options, &mut device)?;
+
+    let mut entries = Vec::new();
+    for (path, device_state) in &device.entries {
+        // Records may have been altered since the state has been loaded
+        let record = device.keys.get(path).and_then(|key| state.get(key)).filter(|record| record.has_valid_path());
+        let local_path = record.map(StateEntry::path).unwrap_or_else(|| path.clone());
+        let (action, moved_from, content_changed) = match (record, local_entry_state(&local_dir.join(&local_path))) {
+            (_, None) => (SyncAction::Create, None, true),
+            (None, Some(local_state)) => match has_changed(device_state, &local_state, options.compare) {
+                true => (SyncAction::Update, None, true),
+                false => (SyncAction::Unchanged, None, false),
+            },
+            (Some(record), Some(local_state)) => {
+                // The local copy may have been altered too
+                let content_changed = differs_from_record(device_state, record, options.compare)
+                    || local_state.is_folder != record.is_folder
+                    || (!local_state.is_folder && local_state.size != record.size);
+                if local_path != *path {
+                    (SyncAction::Move, Some(local_path), content_changed)
+                } else if content_changed {
+                    (SyncAction::Update, None, true)
+                } else {
+                    (SyncAction::Unchanged, None, false)
+                }
+            },
+        };
+        let size = (content_changed && !device_state.is_folder).then(|| device_state.size.unwrap_or(0));
+        entries.push(SyncEntry{ path: path.clone(), is_folder: device_state.is_folder, action, size, moved_from });
+    }
+
+    let seen_keys: HashSet<&str> = device.keys.values().map(String::as_str).collect();
+    let mut deleted_keys = HashMap::new();
+    let mut forgotten_keys = Vec::new();
+    for (key, record) in state.iter() {
+        if seen_keys.contains(key) || !record.has_valid_path() {
+            continue;
+        }
+        let path = record.path();
+        if device.entries.contains_key(&path) || local_entry_state(&local_dir.join(&path)).is_none() {
+            // Another object has taken its place, or its local copy is gone anyway
+            forgotten_keys.push(key.to_string());
+        } else if options.mode == SyncMode::Mirror && !options.is_excluded(&path) {
+            entries.push(SyncEntry{ path: path.clone(), is_folder: record.is_folder, action: SyncAction::Delete, size: None, moved_from: None });
+            deleted_keys.insert(path, key.to_string());
+        }
+    }
+    entries.sort_by(|left, right| left.path.cmp(&right.path));
+
+    Ok(SyncPlan{
+        direction: SyncDirection::Pull,
+        device_folder: device_folder.clone(),
+        local_dir: local_dir.to_path_buf(),
+        device,
+        deleted_keys,
+        forgotten_keys,
+        entries,
+    })
+}
+
+/// Plan and execute a synchronisation, unless this is a [dry run](SyncOptions::dry_run)
+pub fn sync(direction: SyncDirection, device_folder: &Object, local_dir: &Path, options: SyncOptions) -> Result<SyncReport, MtpError> {
+    let mut plan = plan(direction, device_folder, local_dir, &options)?;
+    if options.dry_run {
+        let failed = std::mem::take(&mut plan.device.rejected);
+        return Ok(SyncReport{ plan, done: Vec::new(), failed });
+    }
+    plan.execute(options)
+}
+
+/// Plan and execute a pull with [`plan_with_state`], unless this is a [dry run](SyncOptions::dry_run), then [update](SyncState::update) `state`
+///
+/// Saving `state` is up to the caller.
+pub fn sync_with_state(device_folder: &Object, local_dir: &Path, state: &mut SyncState, options: SyncOptions) -> Result<SyncReport, MtpError> {
+    let mut plan = plan_with_state(device_folder, local_dir, state, &options)?;
+    if options.dry_run {
+        let failed = std::mem::take(&mut plan.device.rejected);
+        return Ok(SyncReport{ plan, done: Vec::new(), failed });
+    }
+    let report = plan.execute(options)?;
+    state.update(&report);
+    Ok(report)
+}
+
+fn has_changed(source: &EntryState, destination: &EntryState, compare: ComparePolicy) -> bool {
+    if source.is_folder || destination.is_folder {
+        return source.is_folder != destination.is_folder;
+    }
+    if source.size != destination.size {
+        return true;
+    }
+    match (compare, source.modified, destination.modified) {
+        (ComparePolicy::SizeAndModified{ tolerance }, Some(source_modified), Some(destination_modified)) => {
+            source_modified.duration_since(destination_modified).is_ok_and(|newer_by| newer_by > tolerance)
+        },
+        _ => false,
+    }
+}
+
+/// Whether a device entry differs from what a [`SyncState`] recorded. Unlike [`has_changed`], a modification date going back in time counts too.
+fn differs_from_record(device_state: &EntryState, record: &StateEntry, compare: ComparePolicy) -> bool {
+    if device_state.is_folder || record.is_folder {
+        return device_state.is_folder != record.is_folder;
+    }
+    if device_state.size != record.size {
+        return true;
+    }
+    match (compare, device_state.modified, record.modified) {
+        (ComparePolicy::SizeAndModified{ tolerance }, Some(device_modified), Some(recorded_modified)) => {
+            let difference = device_modified.duration_since(recorded_modified).unwrap_or_else(|err| err.duration());
+            difference > tolerance
+        },
+        _ => false,
+    }
+}
+
+/// Paths are matched and recorded with `/` separators, whatever the platform
+fn slash_path(path: &Path) -> String {
+    path.iter().map(|component| component.to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+fn is_folder(object: &Object) -> bool {
+    matches!(object.object_type(), ObjectType::Folder | ObjectType::FunctionalObject)
+}
+
+/// Objects without a persistent unique ID are keyed by their paths
+fn list_device(folder: &Object, relative_dir: PathBuf, options: &SyncOptions, listing: &mut DeviceListing) -> Result<(), MtpError> {
+    for (child, values) in folder.children_with_properties(&[WPD_OBJECT_SIZE, WPD_OBJECT_DATE_MODIFIED, WPD_OBJECT_PERSISTENT_UNIQUE_ID])? {
+        let relative_path = match join_object_name(&relative_dir, &child.original_file_name().unwrap_or(child.name()).to_os_string()) {
+            Ok(relative_path) => relative_path,
+            Err(rejected) => {
+                listing.rejected.push(rejected);
+                continue;
+            },
+        };
+        if options.is_excluded(&relative_path) {
+            continue;
+        }
+        let child_is_folder = is_folder(&child);
+        let key = values.get_string(&WPD_OBJECT_PERSISTENT_UNIQUE_ID)
+            .ok()
+            .map(|unique_id| unique_id.to_string_lossy())
+            .filter(|unique_id| !unique_id.is_empty())
+            .unwrap_or_else(|| format!("path:{}", slash_path(&relative_path)));
+        listing.keys.insert(relative_path.clone(), key);
+        if child_is_folder {
+            listing.entries.insert(relative_path.clone(), EntryState{ is_folder: true, size: None, modified: None });
+            list_device(&child, relative_path.clone(), options, listing)?;
+        } else {
+            let state = EntryState{
+                is_folder: false,
+                size: values.get_u64(&WPD_OBJECT_SIZE).ok(),
+                modified: values.get_date(&WPD_OBJECT_DATE_MODIFIED).ok(),
+            };
+            listing.entries.insert(relative_path.clone(), state);
+        }
+        listing.objects.insert(relative_path, child);
+    }
+    Ok(())
+}
+
+/// Symbolic links are ignored
+fn list_local(dir: &Path, relative_dir: PathBuf, options: &SyncOptions, entries: &mut BTreeMap<PathBuf, EntryState>) -> Result<(), MtpError> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let relative_path = relative_dir.join(entry.file_name());
+        let metadata = std::fs::symlink_metadata(entry.path())?;
+        if metadata.file_type().is_symlink() || options.is_excluded(&relative_path) {
+            continue;
+        }
+        entries.insert(relative_path.clone(), EntryState::from_metadata(&metadata));
+        if metadata.is_dir() {
+            list_local(&entry.path(), relative_path, options, entries)?;
+        }
+    }
+    Ok(())
+}
+
+/// The state of a single local file or folder, if it exists (and is not a symbolic link)
+fn local_entry_state(path: &Path) -> Option<EntryState> {
+    std::fs::symlink_metadata(path)
+        .ok()
+        .filter(|metadata| !metadata.file_type().is_symlink())
+        .map(|metadata| EntryState::from_metadata(&metadata))
+}
+
+impl EntryState {
+    fn from_metadata(metadata: &std::fs::Metadata) -> Self {
+        match metadata.is_dir() {
+            true => Self{ is_folder: true, size: None, modified: None },
+            false => Self{ is_folder: false, size: Some(metadata.len()), modified: metadata.modified().ok() },
+        }
+    }
+}
+
+impl SyncPlan {
+    pub fn direction(&self) -> SyncDirection {
+        self.direction
+    }
+
+    pub fn created(&self) -> impl Iterator<Item = &SyncEntry> {
+        self.entries_with(SyncAction::Create)
+    }
+
+    pub fn updated(&self) -> impl Iterator<Item = &SyncEntry> {
+        self.entries_with(SyncAction::Update)
+    }
+
+    pub fn deleted(&self) -> impl Iterator<Item = &SyncEntry> {
+        self.entries_with(SyncAction::Delete)
+    }
+
+    pub fn unchanged(&self) -> impl Iterator<Item = &SyncEntry> {
+        self.entries_with(SyncAction::Unchanged)
+    }
+
+    pub fn moved(&self) -> impl Iterator<Item = &SyncEntry> {
+        self.entries_with(SyncAction::Move)
+    }
+
+    /// Every entry that is not [`SyncAction::Unchanged`]
+    pub fn changes(&self) -> impl Iterator<Item = &SyncEntry> {
+        self.entries.iter().filter(|entry| entry.action != SyncAction::Unchanged)
+    }
+
+    /// The number of bytes the execution of this plan would transfer
+    pub fn bytes_to_transfer(&self) -> u64 {
+        self.entries.iter().filter_map(|entry| entry.size).sum()
+    }
+
+    fn entries_with(&self, action: SyncAction) -> impl Iterator<Item = &SyncEntry> {
+        self.entries.iter().filter(move |entry| entry.action == action)
+    }
+
+    /// Apply this plan
+    ///
+    /// Moves come first, then deletions (children before their parents), then copies (parents before their children).<br/>
+    /// Failures that only affect an entry are listed in the returned report, and do not prevent the other entries from being applied.
+    /// An error is only returned if the whole synchronisation failed, e.g. if the device is gone, or if it has been cancelled.
+    /// Pulled files keep the dates of the objects, so that they are not considered as changed by the next synchronisation.<br/>
+    /// Objects whose names cannot be used as local file names (e.g. `..`, or names with separators) are never synchronised, and are reported as failed.
+    pub fn execute(mut self, mut options: SyncOptions) -> Result<SyncReport, MtpError> {
+        let mut done = Vec::new();
+        let mut failed = std::mem::take(&mut self.device.rejected);
+        let mut progress = AggregatedProgress::new(options.progress.take(), self.bytes_to_transfer());
+
+        let moves: Vec<SyncEntry> = self.moved().cloned().collect();
+        let mut deletions: Vec<SyncEntry> = self.deleted().cloned().collect();
+        deletions.reverse();
+        let copies: Vec<SyncEntry> = self.entries.iter()
+            .filter(|entry| matches!(entry.action, SyncAction::Create | SyncAction::Update))
+            .cloned()
+            .collect();
+
+        // Local copies are moved out of the way first, so that objects that swapped their names are handled too
+        // and they are put back where they were if the execution stops before they have been moved
+        let mut parked_files = ParkedFiles(moves.iter()
+            .enumerate()
+            .map(|(index, entry)| self.park(entry, index))
+            .collect());
+        for (index, entry) in moves.iter().enumerate() {
+            if options.cancellation.is_cancelled() {
+                return Err(MtpError::Cancelled);
+            }
+            let parked_file = parked_files.0[index].take().map(|(parked_file, _original_path)| parked_file);
+            let result = self.finish_move(entry, parked_file, &mut progress, &options.cancellation);
+            progress.next_file(entry.size.unwrap_or(0));
+            record_outcome(entry, result, &mut done, &mut failed)?;
+        }
+        // Folders that have been moved are left empty, unless something has been added to them locally
+        let mut moved_folders: Vec<&PathBuf> = moves.iter().filter(|entry| entry.is_folder).filter_map(|entry| entry.moved_from.as_ref()).collect();
+        moved_folders.sort();
+        for moved_folder in moved_folders.into_iter().rev() {
+            let _ = std::fs::remove_dir(self.local_dir.join(moved_folder));
+        }
+
+        for entry in deletions.iter().chain(&copies) {
+            if options.cancellation.is_cancelled() {
+                return Err(MtpError::Cancelled);
+            }
+            let result = match entry.action {
+                SyncAction::Delete => self.delete(entry),
+                _ => self.copy(entry, &mut progress, &options.cancellation),
+            };
+            progress.next_file(entry.size.unwrap_or(0));
+            record_outcome(entry, result, &mut done, &mut failed)?;
+        }
+        Ok(SyncReport{ plan: self, done, failed })
+    }
+
+    /// Rename the local copy of a moved file to a temporary name, and return it along with its original path. Local copies that cannot be moved will be pulled again.
+    fn park(&self, entry: &SyncEntry, index: usize) -> Option<(PathBuf, PathBuf)> {
+        let moved_from = entry.moved_from.as_ref().filter(|_| !entry.is_folder)?;
+        let original_path = self.local_dir.join(moved_from);
+        let parked_file = self.local_dir.join(format!(".winmtp-move.{}.{}", std::process::id(), index));
+        std::fs::rename(&original_path, &parked_file).ok()?;
+        Some((parked_file, original_path))
+    }
+
+    fn finish_move(&mut self, entry: &SyncEntry, parked_file: Option<PathBuf>, progress: &mut AggregatedProgress, cancellation: &CancellationToken) -> Result<(), MtpError> {
+        let local_path = self.local_dir.join(&entry.path);
+        let parked_file = match parked_file {
+            Some(parked_file) if entry.size.is_none() => parked_file,
+            Some(parked_file) => {
+                // Its content has changed anyway
+                std::fs::remove_file(parked_file)?;
+                return self.copy(entry, progress, cancellation);
+            },
+            None => return self.copy(entry, progress, cancellation),
+        };
+        let result = local_path.parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| std::fs::rename(&parked_file, &local_path));
+        if result.is_err() {
+            // This is the only local copy: it stays where it was
+            if let Some(moved_from) = &entry.moved_from {
+                let _ = std::fs::rename(&parked_file, self.local_dir.join(moved_from));
+            }
+        }
+        result.map_err(MtpError::from)
+    }
+
+    fn delete(&mut self, entry: &SyncEntry) -> Result<(), MtpError> {
+        match self.direction {
+            SyncDirection::Pull => delete_local(&self.local_dir.join(&entry.path)),
+            SyncDirection::Push => {
+                let object = self.device.objects.remove(&entry.path).ok_or(MtpError::ObjectNotFound)?;
+                object.delete(true)
+            },
+        }
+    }
+
+    fn copy(&mut self, entry: &SyncEntry, progress: &mut AggregatedProgress, cancellation: &CancellationToken) -> Result<(), MtpError> {
+        let local_path = self.local_dir.join(&entry.path);
+        match self.direction {
+            SyncDirection::Pull => {
+                // A file may replace a folder, or the other way round
+                if std::fs::symlink_metadata(&local_path).is_ok_and(|metadata| metadata.is_dir() != entry.is_folder) {
+                    delete_local(&local_path)?;
+                }
+                if entry.is_folder {
+                    std::fs::create_dir_all(&local_path)?;
+                    return Ok(());
+                }
+                if let Some(parent) = local_path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let object = self.device.objects.get(&entry.path).ok_or(MtpError::ObjectNotFound)?;
+                let pull_options = PullOptions::new()
+                    .overwrite(OverwritePolicy::Overwrite)
+                    .progress(progress)
+                    .cancellation(cancellation.clone());
+                object.pull_to_file(&local_path, pull_options)?;
+                Ok(())
+            },
+            SyncDirection::Push => {
+                if let Some(existing) = self.device.objects.get(&entry.path) {
+                    if is_folder(existing) != entry.is_folder {
+                        existing.delete(true)?;
+                        self.device.objects.remove(&entry.path);
+                    }
+                }
+                let parent = match entry.path.parent() {
+                    Some(parent) if parent != Path::new("") => self.device.objects.get(parent).ok_or(MtpError::ObjectNotFound)?,
+                    _ => &self.device_folder,
+                };
+                let name = entry.path.file_name().ok_or(MtpError::InvalidLocalFile)?;
+                if entry.is_folder {
+                    if !self.device.objects.contains_key(&entry.path) {
+                        let folder_id = parent.create_subfolder(name)?;
+                        let folder = parent.device_content().object_by_id(folder_id)?;
+                        self.device.objects.insert(entry.path.clone(), folder);
+                    }
+                    return Ok(());
+                }
+                parent.push_file_with_progress(&local_path, true, progress, cancellation)
+            },
+        }
+    }
+}
+
+/// Local copies of moved files that have been renamed to temporary names, along with their original paths.
+///
+/// Those that are still there when this is dropped (e.g. because the execution has been cancelled) are put back where they were.
+struct ParkedFiles(Vec<Option<(PathBuf, PathBuf)>>);
+
+impl Drop for ParkedFiles {
+    fn drop(&mut self) {
+        for (parked_file, original_path) in self.0.iter_mut().filter_map(Option::take) {
+            let _ = std::fs::rename(parked_file, original_path);
+        }
+    }
+}
+
+/// Only errors that affect the whole synchronisation are returned
+fn record_outcome(entry: &SyncEntry, result: Result<(), MtpError>, done: &mut Vec<PathBuf>, failed: &mut Vec<(PathBuf, MtpError)>) -> Result<(), MtpError> {
+    match result {
+        Ok(()) => done.push(entry.path.clone()),
+        Err(err) if matches!(err.kind(), ErrorKind::DeviceGone | ErrorKind::Cancelled) => return Err(err),
+        Err(err) => failed.push((entry.path.clone(), err)),
+    }
+    Ok(())
+}
+
+fn delete_local(path: &Path) -> Result<(), MtpError> {
+    if std::fs::symlink_metadata(path)?.is_dir() {
+        std::fs::remove_dir_all(path)?;
+    } else {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,173 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::MtpError;
+use crate::utils::is_plain_file_name;
+use super::{SyncAction, SyncDirection, SyncReport, slash_path};
+
+/// The version of the format of the files written by [`SyncState::save`]
+const STATE_VERSION: u32 = 1;
+
+/// What has been pulled from a device, as remembered between synchronisations
+///
+/// Records are keyed by the persistent unique IDs of the objects (or by their paths, for devices that have no such IDs),
+/// which do not change when objects are renamed or moved, and unlike object IDs, are kept from a session to another.
+/// Nothing here depends on the name of the device either.<br/>
+/// See [`plan_with_state`](super::plan_with_state) and [`sync_with_state`](super::sync_with_state).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncState {
+    version: u32,
+    entries: BTreeMap<String, StateEntry>,
+}
+
+/// What is known about an object that has been pulled
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateEntry {
+    /// The path of the local copy, relative to the synchronised directory, with `/` separators
+    pub local_path: String,
+    pub is_folder: bool,
+    /// The size of the object when it was pulled
+    pub size: Option<u64>,
+    /// The modification date of the object when it was pulled
+    pub modified: Option<SystemTime>,
+    /// A hash of the content, that callers are free to compute and store.
+    ///
+    /// It is kept as long as the content of the object does not change.
+    pub hash: Option<String>,
+}
+
+impl StateEntry {
+    /// The path of the local copy, relative to the synchronised directory
+    pub fn path(&self) -> PathBuf {
+        self.local_path.split('/').collect()
+    }
+
+    /// Whether [`Self::local_path`] stays inside the synchronised directory, i.e. it only has plain names (no `..`, no root...)
+    pub fn has_valid_path(&self) -> bool {
+        self.local_path.split('/').all(|name| is_plain_file_name(name.as_ref()))
+    }
+}
+
+impl SyncState {
+    /// An empty state, e.g. before the first synchronisation
+    pub fn new() -> Self {
+        Self{ version: STATE_VERSION, entries: BTreeMap::new() }
+    }
+
+    /// Read a state file written by [`Self::save`]. A missing file gives an empty state.
+    ///
+    /// States with paths that would escape the synchronised directory (see [`StateEntry::has_valid_path`]) are rejected, as synchronising moves and deletes local files at these paths.
+    pub fn load(path: &Path) -> Result<Self, MtpError> {
+        let content = match std::fs::read(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let state: Self = serde_json::from_slice(&content).map_err(std::io::Error::from)?;
+        if state.version > STATE_VERSION {
+            return Err(MtpError::InvalidArgument(format!("Unsupported sync state version {}", state.version)));
+        }
+        if let Some(entry) = state.entries.values().find(|entry| !entry.has_valid_path()) {
+            return Err(MtpError::InvalidArgument(format!("Invalid path in sync state: {:?}", entry.local_path)));
+        }
+        Ok(state)
+    }
+
+    /// Write this state to a file.
+    ///
+    /// The file is replaced atomically, so that an interrupted save does not lose the previous state.
+    pub fn save(&self, path: &Path) -> Result<(), MtpError> {
+        let file_name = path.file_name().ok_or(MtpError::InvalidLocalFile)?;
+        let mut temp_name = std::ffi::OsString::from(".");
+        temp_name.push(file_name);
+        temp_name.push(format!(".{}.part", std::process::id()));
+        let temp_path = path.with_file_name(temp_name);
+
+        let content = serde_json::to_vec_pretty(self).map_err(std::io::Error::from)?;
+        let result = std::fs::write(&temp_path, content)
+            .and_then(|()| std::fs::File::open(&temp_path)?.sync_all())
+            .and_then(|()| std::fs::rename(&temp_path, path));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp_path);
+        }
+        result.map_err(MtpError::from)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&StateEntry> {
+        self.entries.get(key)
+    }
+
+    /// E.g. to set the [hash](StateEntry::hash) of an entry
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut StateEntry> {
+        self.entries.get_mut(key)
+    }
+
+    /// Entries and their keys, sorted by key
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &StateEntry)> {
+        self.entries.iter().map(|(key, entry)| (key.as_str(), entry))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Record what a synchronisation has done
+    ///
+    /// Entries that failed keep their previous records.
+    /// This does nothing for [`SyncDirection::Push`] reports, as the device is then the destination.
+    pub fn update(&mut self, report: &SyncReport) {
+        let plan = &report.plan;
+        if plan.direction != SyncDirection::Pull {
+            return;
+        }
+        let done: HashSet<&Path> = report.done.iter().map(PathBuf::as_path).collect();
+        let mut recorded_keys = HashSet::new();
+        let mut recorded_paths = HashSet::new();
+        for entry in &plan.entries {
+            if entry.action != SyncAction::Unchanged && !done.contains(entry.path.as_path()) {
+                continue;
+            }
+            if entry.action == SyncAction::Delete {
+                if let Some(key) = plan.deleted_keys.get(&entry.path) {
+                    self.entries.remove(key);
+                }
+                continue;
+            }
+            let (Some(key), Some(device_state)) = (plan.device.keys.get(&entry.path), plan.device.entries.get(&entry.path)) else {
+                continue;
+            };
+            // Entries that are transferred again have a new content
+            let hash = match entry.size {
+                None => self.entries.get(key).and_then(|previous| previous.hash.clone()),
+                Some(_) => None,
+            };
+            self.entries.insert(key.clone(), StateEntry{
+                local_path: slash_path(&entry.path),
+                is_folder: device_state.is_folder,
+                size: device_state.size,
+                modified: device_state.modified,
+                hash,
+            });
+            recorded_keys.insert(key.as_str());
+            recorded_paths.insert(entry.path.clone());
+        }
+        for key in &plan.forgotten_keys {
+            self.entries.remove(key);
+        }
+        // Objects that have been replaced by other ones
+        self.entries.retain(|key, entry| recorded_keys.contains(key.as_str()) || !recorded_paths.contains(&entry.path()));
+    }
+}
+
+impl Default for SyncState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,5 +1,8 @@
 use widestring::{U16CStr, U16CString};
 use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::MtpError;
 
 /// Compare paths, with or without case folding
 pub fn are_path_eq(left: &U16CStr, right: &OsStr, case_sensitive: bool) -> bool {
@@ -16,3 +19,107 @@ pub fn are_path_eq(left: &U16CStr, right: &OsStr, case_sensitive: bool) -> bool
         }
     }
 }
+
+/// Match a `/`-separated relative path against a glob pattern, with or without case folding
+///
+/// `*` matches any sequence of characters but `/`, `?` matches a single character, `[abc]`, `[a-z]` and `[!abc]` match character classes,
+/// and a `**` segment matches any number of folders.<br/>
+/// Patterns without any `/` are matched against the last segment of the path only (e.g. `*.jpg` matches `DCIM/Camera/IMG_0001.jpg`).
+///
+/// ```
+/// use winmtp::utils::glob_match;
+///
+/// assert!(glob_match("*.jpg", "DCIM/Camera/IMG_0001.JPG", false));
+/// assert!(glob_match("DCIM/**/IMG_????.jpg", "DCIM/Camera/IMG_0001.jpg", true));
+/// assert!(!glob_match("DCIM/*.jpg", "DCIM/Camera/IMG_0001.jpg", true));
+/// ```
+pub fn glob_match(pattern: &str, path: &str, case_sensitive: bool) -> bool {
+    let fold = |s: &str| -> Vec<char> {
+        if case_sensitive { s.chars().collect() } else { s.chars().flat_map(char::to_lowercase).collect() }
+    };
+    let path_segments: Vec<Vec<char>> = path.split('/').filter(|segment| !segment.is_empty()).map(fold).collect();
+    if !pattern.contains('/') {
+        return path_segments.last().is_some_and(|name| segment_match(&fold(pattern), name));
+    }
+    let pattern_segments: Vec<Vec<char>> = pattern.split('/').filter(|segment| !segment.is_empty()).map(fold).collect();
+    segments_match(&pattern_segments, &path_segments)
+}
+
+fn segments_match(pattern: &[Vec<char>], path: &[Vec<char>]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first[..] == ['*', '*'] => (0..=path.len()).any(|skipped| segments_match(rest, &path[skipped..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((segment, path_rest)) => segment_match(first, segment) && segments_match(rest, path_rest),
+            None => false,
+        },
+    }
+}
+
+fn segment_match(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skipped| segment_match(rest, &name[skipped..])),
+        Some(('?', rest)) => !name.is_empty() && segment_match(rest, &name[1..]),
+        Some(('[', rest)) => match (rest.iter().position(|c| *c == ']'), name.split_first()) {
+            // An unclosed bracket is a literal
+            (None, _) => name.first() == Some(&'[') && segment_match(rest, &name[1..]),
+            (Some(_), None) => false,
+            (Some(end), Some((c, name_rest))) => class_match(&rest[..end], *c) && segment_match(&rest[end + 1..], name_rest),
+        },
+        Some((literal, rest)) => name.first() == Some(literal) && segment_match(rest, &name[1..]),
+    }
+}
+
+fn class_match(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.split_first() {
+        Some(('!', rest)) => (true, rest),
+        _ => (false, class),
+    };
+    let mut matched = false;
+    let mut index = 0;
+    while index < class.len() {
+        if index + 2 < class.len() && class[index + 1] == '-' {
+            matched |= (class[index]..=class[index + 2]).contains(&c);
+            index += 3;
+        } else {
+            matched |= class[index] == c;
+            index += 1;
+        }
+    }
+    matched != negated
+}
+
+/// Whether a name is a single normal path component, that can safely be joined to a local path
+///
+/// Names that come from a device may not be: they may contain separators, or be `..`, a root or a prefix (e.g. `C:`), that would escape the local directory.
+///
+/// ```
+/// use std::ffi::OsStr;
+/// use winmtp::utils::is_plain_file_name;
+///
+/// assert!(is_plain_file_name(OsStr::new("IMG_0001.jpg")));
+/// assert!(!is_plain_file_name(OsStr::new("../../.bashrc")));
+/// assert!(!is_plain_file_name(OsStr::new("/etc/passwd")));
+/// assert!(!is_plain_file_name(OsStr::new("..")));
+/// ```
+pub fn is_plain_file_name(name: &OsStr) -> bool {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        // `Path::components` drops trailing separators
+        (Some(Component::Normal(component)), None) => component == name,
+        _ => false,
+    }
+}
+
+/// Join the name of an object to a local relative path, failing for names that [`is_plain_file_name`] rejects
+///
+/// The path to report is returned along with the error: separators are replaced in it, so that it stays under `relative_dir`.
+pub(crate) fn join_object_name(relative_dir: &Path, name: &OsStr) -> Result<PathBuf, (PathBuf, MtpError)> {
+    if is_plain_file_name(name) {
+        Ok(relative_dir.join(name))
+    } else {
+        let lossy_name = name.to_string_lossy();
+        Err((relative_dir.join(lossy_name.replace(std::path::is_separator, "_")), MtpError::UnsafeFileName(lossy_name.into_owned())))
+    }
+}
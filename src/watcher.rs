@@ -0,0 +1,212 @@
+//! Notifications of devices being connected and disconnected
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+use crate::backend::ProviderBackend;
+use crate::device::{BasicDevice, Subscription};
+use crate::error::MtpError;
+
+/// How often backends that cannot report changes are enumerated again, unless set by [`DeviceWatcher::with_poll_interval`]
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A device that has been connected or disconnected, as reported by a [`DeviceWatcher`]
+#[derive(Clone)]
+pub enum DeviceChange {
+    Connected(BasicDevice),
+    /// The device that had this [ID](BasicDevice::device_id) is gone
+    Disconnected(String),
+}
+
+impl std::fmt::Debug for DeviceChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceChange::Connected(device) => f.debug_tuple("Connected").field(&device.device_id()).finish(),
+            DeviceChange::Disconnected(device_id) => f.debug_tuple("Disconnected").field(device_id).finish(),
+        }
+    }
+}
+
+/// Where backends tell that their devices may have changed
+///
+/// Backends do not need to tell what has changed: their devices are enumerated again, and compared with the previous ones.
+/// This can be called from any thread.
+#[derive(Clone)]
+pub struct WatchSink(Arc<dyn Fn() + Send + Sync>);
+
+impl WatchSink {
+    pub fn new<F>(callback: F) -> Self
+    where F: Fn() + Send + Sync + 'static
+    {
+        Self(Arc::new(callback))
+    }
+
+    pub fn notify(&self) {
+        (self.0)()
+    }
+}
+
+impl std::fmt::Debug for WatchSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WatchSink").finish_non_exhaustive()
+    }
+}
+
+/// The watchers of a backend that knows when its devices change, because they are added and removed by the application
+#[derive(Default)]
+pub(crate) struct WatchSinks {
+    sinks: Rc<RefCell<Vec<(u64, WatchSink)>>>,
+    next_id: RefCell<u64>,
+}
+
+impl WatchSinks {
+    pub(crate) fn add(&self, sink: WatchSink) -> Subscription {
+        let id = self.next_id.replace_with(|id| *id + 1);
+        self.sinks.borrow_mut().push((id, sink));
+        let sinks: Weak<RefCell<Vec<(u64, WatchSink)>>> = Rc::downgrade(&self.sinks);
+        Subscription::new(move || {
+            if let Some(sinks) = sinks.upgrade() {
+                sinks.borrow_mut().retain(|(sink_id, _)| *sink_id != id);
+            }
+        })
+    }
+
+    pub(crate) fn notify(&self) {
+        for (_, sink) in self.sinks.borrow().iter() {
+            sink.notify();
+        }
+    }
+}
+
+struct WatchedBackend {
+    backend: Rc<dyn ProviderBackend>,
+    device_ids: Vec<String>,
+    /// `None` for backends that are polled
+    subscription: Option<Subscription>,
+}
+
+/// Reports devices being connected and disconnected, see [`Provider::watch_devices`](crate::Provider::watch_devices)
+///
+/// This is an iterator, that blocks until something changes. See [`Self::recv_timeout`] for a non-blocking alternative.
+pub struct DeviceWatcher {
+    backends: Vec<WatchedBackend>,
+    pending: VecDeque<DeviceChange>,
+    /// Indices of the backends that reported a change
+    notifications: Receiver<usize>,
+    poll_interval: Duration,
+    next_poll: Instant,
+}
+
+impl DeviceWatcher {
+    pub(crate) fn new(backends: &[Rc<dyn ProviderBackend>]) -> Result<Self, MtpError> {
+        let (sender, notifications) = mpsc::channel();
+        let mut watcher = Self {
+            backends: backends.iter().enumerate().map(|(index, backend)| WatchedBackend{
+                backend: Rc::clone(backend),
+                device_ids: Vec::new(),
+                // Backends that cannot be watched (for any reason) are polled instead
+                subscription: backend.watch(notifying_sink(&sender, index)).ok(),
+            }).collect(),
+            pending: VecDeque::new(),
+            notifications,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            next_poll: Instant::now() + DEFAULT_POLL_INTERVAL,
+        };
+        // The initial snapshot
+        for index in 0..watcher.backends.len() {
+            watcher.refresh(index)?;
+        }
+        Ok(watcher)
+    }
+
+    /// Change how often backends that cannot report changes are enumerated again
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.next_poll = Instant::now() + poll_interval;
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Wait for the next change, for at most `timeout`. `Ok(None)` means nothing has changed in the meantime.
+    ///
+    /// An error means enumerating the devices of a backend has failed. The watcher can still be used afterwards.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<DeviceChange>, MtpError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(change) = self.pending.pop_front() {
+                return Ok(Some(change));
+            }
+            let now = Instant::now();
+            if now >= self.next_poll {
+                self.next_poll = now + self.poll_interval;
+                for index in 0..self.backends.len() {
+                    if self.backends[index].subscription.is_none() {
+                        self.refresh(index)?;
+                    }
+                }
+                continue;
+            }
+            if now >= deadline {
+                return Ok(None);
+            }
+            match self.notifications.recv_timeout(deadline.min(self.next_poll) - now) {
+                Ok(index) => self.refresh(index)?,
+                Err(RecvTimeoutError::Timeout) => {},
+                // Our own sender is kept by the sinks of the backends, that may all be polled
+                Err(RecvTimeoutError::Disconnected) => std::thread::sleep(deadline.min(self.next_poll) - now),
+            }
+        }
+    }
+
+    /// Compare the devices of a backend with the ones it had
+    fn refresh(&mut self, index: usize) -> Result<(), MtpError> {
+        let watched = &mut self.backends[index];
+        let devices = watched.backend.enumerate_devices()?;
+        let device_ids: Vec<String> = devices.iter().map(BasicDevice::device_id).collect();
+        for device_id in &watched.device_ids {
+            if !device_ids.contains(device_id) {
+                self.pending.push_back(DeviceChange::Disconnected(device_id.clone()));
+            }
+        }
+        for device in devices {
+            if !watched.device_ids.contains(&device.device_id()) {
+                self.pending.push_back(DeviceChange::Connected(device));
+            }
+        }
+        watched.device_ids = device_ids;
+        Ok(())
+    }
+}
+
+fn notifying_sink(sender: &Sender<usize>, index: usize) -> WatchSink {
+    let sender = sender.clone();
+    WatchSink::new(move || {
+        let _ = sender.send(index);
+    })
+}
+
+impl Iterator for DeviceWatcher {
+    type Item = Result<DeviceChange, MtpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.recv_timeout(self.poll_interval) {
+                Ok(Some(change)) => return Some(Ok(change)),
+                Ok(None) => {},
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for DeviceWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeviceWatcher")
+            .field("pending", &self.pending)
+            .field("poll_interval", &self.poll_interval)
+            .finish_non_exhaustive()
+    }
+}
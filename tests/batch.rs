@@ -0,0 +1,84 @@
+//! Checks of batch operations, that report an outcome for every object
+
+mod common;
+
+use std::path::Path;
+
+use winmtp::backend::memory::MemoryDevice;
+use winmtp::device::BasicDevice;
+use winmtp::error::ErrorKind;
+use winmtp::object::{ObjectId, ObjectIdRef};
+
+use common::{fake_phone, loopback_device, memory_device, open_content};
+
+fn phone_with_photos() -> (MemoryDevice, Vec<ObjectId>) {
+    let (device, storage_id) = fake_phone();
+    let dcim_id = device.add_folder(&storage_id, "DCIM").unwrap();
+    let trash_id = device.add_folder(&storage_id, "Trash").unwrap();
+    device.add_file(&trash_id, "old.jpg", b"old").unwrap();
+    let photo_ids = (0..20)
+        .map(|index| device.add_file(&dcim_id, &format!("IMG_{:04}.JPG", index), b"not really a JPEG").unwrap())
+        .collect();
+    (device, photo_ids)
+}
+
+fn check_batches(basic_device: &BasicDevice, photo_ids: &[ObjectId]) {
+    let content = open_content(basic_device);
+    let storage = content.root().unwrap().object_by_path(Path::new(common::STORAGE_NAME)).unwrap();
+    let dcim = storage.object_by_path(Path::new("DCIM")).unwrap();
+    let trash = storage.object_by_path(Path::new("Trash")).unwrap();
+
+    // Move half of the photos, and an object that does not exist
+    let missing: ObjectId = "o9999".parse().unwrap();
+    let mut to_move: Vec<&ObjectIdRef> = photo_ids[..10].iter().map(|id| &**id).collect();
+    to_move.insert(3, &missing);
+    let outcomes = content.move_many(&to_move, trash.id()).unwrap();
+    assert_eq!(outcomes.len(), 11);
+    for (outcome, object_id) in outcomes.iter().zip(&to_move) {
+        assert_eq!(outcome.object_id, *object_id);
+        if outcome.object_id == missing {
+            assert_eq!(outcome.error_kind(), Some(ErrorKind::NotFound));
+        } else {
+            assert!(outcome.is_ok(), "{:?}", outcome);
+        }
+    }
+    assert_eq!(dcim.children().unwrap().count(), 10);
+    assert_eq!(trash.children().unwrap().count(), 11);
+    let outcomes = content.move_many(&[trash.id()], trash.id()).unwrap();
+    assert_eq!(outcomes[0].error_kind(), Some(ErrorKind::InvalidArgument));
+
+    // A folder that is not empty cannot be deleted without recursion, but that does not prevent the others from being deleted
+    let to_delete: Vec<&ObjectIdRef> = photo_ids[10..].iter().map(|id| &**id).chain([trash.id()]).collect();
+    let outcomes = content.delete_many(&to_delete, false).unwrap();
+    assert!(outcomes[..10].iter().all(|outcome| outcome.is_ok()));
+    assert_eq!(outcomes[10].error_kind(), Some(ErrorKind::InvalidArgument));
+    assert_eq!(dcim.children().unwrap().count(), 0);
+
+    let outcomes = content.delete_many(&[trash.id()], true).unwrap();
+    assert!(outcomes[0].is_ok());
+    assert!(content.delete_many(&[], true).unwrap().is_empty());
+    let names: Vec<_> = storage.children().unwrap().map(|child| child.name().to_string_lossy()).collect();
+    assert_eq!(names, ["DCIM"]);
+}
+
+#[test]
+fn batches_in_memory() {
+    let (device, photo_ids) = phone_with_photos();
+    check_batches(&memory_device(device), &photo_ids);
+}
+
+#[test]
+fn batches_through_mtp_responder() {
+    let (device, photo_ids) = phone_with_photos();
+    let basic_device = loopback_device(device);
+    // Handles are assigned by the responder, in the order objects are listed
+    let content = open_content(&basic_device);
+    let dcim = content.root().unwrap().object_by_path(Path::new("Internal shared storage/DCIM")).unwrap();
+    let mut photos: Vec<_> = dcim.children().unwrap().collect();
+    photos.sort_by_key(|photo| photo.name().to_string_lossy());
+    assert_eq!(photos.len(), photo_ids.len());
+    let handles: Vec<ObjectId> = photos.iter().map(|photo| photo.id().to_owned()).collect();
+    drop(content);
+
+    check_batches(&basic_device, &handles);
+}
@@ -0,0 +1,63 @@
+//! Checks of the capabilities of devices (commands, content types, formats, properties), through an MTP responder
+
+use std::rc::Rc;
+
+use winmtp::Provider;
+use winmtp::PortableDevices::{
+    WPD_COMMAND_OBJECT_MANAGEMENT_DELETE_OBJECTS, WPD_COMMAND_OBJECT_MANAGEMENT_COPY_OBJECTS, WPD_COMMAND_STORAGE_FORMAT,
+    WPD_OBJECT_FORMAT_UNSPECIFIED, WPD_OBJECT_FORMAT_PROPERTIES_ONLY, WPD_OBJECT_FORMAT_FLAC,
+    WPD_OBJECT_ISHIDDEN, WPD_OBJECT_SIZE, WPD_OBJECT_ORIGINAL_FILE_NAME, WPD_OBJECT_PARENT_ID, WPD_OBJECT_DATE_AUTHORED,
+};
+use winmtp::backend::memory::{MemoryDevice, MemoryProvider};
+use winmtp::device::{AllowedValues, FunctionalCategory};
+use winmtp::device::device_values::PropertyValue;
+use winmtp::error::ErrorKind;
+use winmtp::object::ObjectType;
+use winmtp::responder::Responder;
+
+#[test]
+fn capabilities_through_mtp_responder() {
+    let device = MemoryDevice::new("In-memory phone");
+    device.add_storage("Internal shared storage");
+    let responder = Responder::new(Rc::new(device), "winmtp", "Emulated phone");
+    let device = responder.into_loopback_device("Emulated phone").open(&winmtp::make_current_app_identifiers!(), true).unwrap();
+    let capabilities = device.capabilities().unwrap();
+
+    assert!(capabilities.supports_command(&WPD_COMMAND_OBJECT_MANAGEMENT_DELETE_OBJECTS).unwrap());
+    assert!(!capabilities.supports_command(&WPD_COMMAND_OBJECT_MANAGEMENT_COPY_OBJECTS).unwrap());
+    assert!(!capabilities.supports_command(&WPD_COMMAND_STORAGE_FORMAT).unwrap());
+
+    assert_eq!(capabilities.functional_categories().unwrap(), [FunctionalCategory::Device, FunctionalCategory::Storage]);
+    assert_eq!(capabilities.content_types(FunctionalCategory::Storage).unwrap(), [ObjectType::GenericFile, ObjectType::Folder]);
+    assert!(capabilities.content_types(FunctionalCategory::StillImageCapture).unwrap().is_empty());
+
+    assert_eq!(capabilities.formats(ObjectType::GenericFile).unwrap(), [WPD_OBJECT_FORMAT_UNSPECIFIED]);
+    assert_eq!(capabilities.formats(ObjectType::All).unwrap(), [WPD_OBJECT_FORMAT_UNSPECIFIED, WPD_OBJECT_FORMAT_PROPERTIES_ONLY]);
+    assert!(capabilities.formats(ObjectType::Audio).unwrap().is_empty());
+    assert!(capabilities.supports_format(&WPD_OBJECT_FORMAT_UNSPECIFIED).unwrap());
+    assert!(!capabilities.supports_format(&WPD_OBJECT_FORMAT_FLAC).unwrap());
+
+    let properties = capabilities.format_properties(&WPD_OBJECT_FORMAT_UNSPECIFIED).unwrap();
+    assert!(properties.contains(&WPD_OBJECT_ORIGINAL_FILE_NAME));
+    assert!(properties.contains(&WPD_OBJECT_ISHIDDEN));
+    assert!(!properties.contains(&WPD_OBJECT_DATE_AUTHORED));
+
+    let hidden = capabilities.property_description(&WPD_OBJECT_FORMAT_UNSPECIFIED, &WPD_OBJECT_ISHIDDEN).unwrap();
+    assert!(hidden.writable);
+    assert_eq!(hidden.default_value, Some(PropertyValue::Bool(false)));
+    let size = capabilities.property_description(&WPD_OBJECT_FORMAT_UNSPECIFIED, &WPD_OBJECT_SIZE).unwrap();
+    assert!(size.is_read_only());
+    assert_eq!(size.default_value, Some(PropertyValue::U64(0)));
+    let parent = capabilities.property_description(&WPD_OBJECT_FORMAT_UNSPECIFIED, &WPD_OBJECT_PARENT_ID).unwrap();
+    assert_eq!(parent.allowed_values, AllowedValues::ObjectId);
+}
+
+#[test]
+fn capabilities_unsupported() {
+    let memory_provider = MemoryProvider::new();
+    memory_provider.add_device(MemoryDevice::new("In-memory phone"));
+    let mut provider = Provider::empty();
+    provider.add_backend(Rc::new(memory_provider));
+    let device = provider.enumerate_devices().unwrap()[0].open(&winmtp::make_current_app_identifiers!(), true).unwrap();
+    assert_eq!(device.capabilities().unwrap_err().kind(), ErrorKind::Unsupported);
+}
@@ -0,0 +1,50 @@
+//! Helpers shared by the checks that run on an in-memory device, either directly or through an MTP responder
+
+// Every test only uses some of them
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use winmtp::Provider;
+use winmtp::backend::memory::{MemoryDevice, MemoryProvider};
+use winmtp::device::{BasicDevice, Content};
+use winmtp::object::ObjectId;
+use winmtp::responder::Responder;
+
+/// The name of the storage of [`fake_phone`], like the one of Android phones
+pub const STORAGE_NAME: &str = "Internal shared storage";
+
+/// An in-memory phone with a single, empty storage. Also returns the ID of this storage.
+pub fn fake_phone() -> (MemoryDevice, ObjectId) {
+    let device = MemoryDevice::new("Fake phone");
+    let storage_id = device.add_storage(STORAGE_NAME);
+    (device, storage_id)
+}
+
+/// `device`, as listed by a provider that only has a memory backend
+pub fn memory_device(device: MemoryDevice) -> BasicDevice {
+    let memory_provider = MemoryProvider::new();
+    memory_provider.add_device(device);
+    let mut provider = Provider::empty();
+    provider.add_backend(Rc::new(memory_provider));
+    provider.enumerate_devices().unwrap().remove(0)
+}
+
+/// `device`, served by an MTP responder that is reached through a loopback transport
+pub fn loopback_device(device: MemoryDevice) -> BasicDevice {
+    let responder = Responder::new(Rc::new(device), "winmtp", "Emulated phone");
+    responder.into_loopback_device("Emulated phone")
+}
+
+/// Open a device, with case-sensitive paths, and get its content
+pub fn open_content(basic_device: &BasicDevice) -> Content {
+    basic_device.open(&winmtp::make_current_app_identifiers!(), true).unwrap().content().unwrap()
+}
+
+/// A local folder that does not exist yet. Tests may run concurrently on several device kinds, `name` tells them apart.
+pub fn local_dir(prefix: &str, name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("winmtp-{}-{}-{}", prefix, std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&path);
+    path
+}
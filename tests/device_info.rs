@@ -0,0 +1,74 @@
+//! Checks of the info of devices (model, type, battery...), on an in-memory device, directly or through an MTP responder
+
+mod common;
+
+use std::rc::Rc;
+
+use winmtp::PortableDevices::{WPD_DEVICE_MANUFACTURER, WPD_DEVICE_MODEL, WPD_DEVICE_SERIAL_NUMBER, WPD_DEVICE_TYPE, WPD_DEVICE_POWER_LEVEL, WPD_DEVICE_POWER_SOURCE};
+use winmtp::backend::memory::MemoryDevice;
+use winmtp::device::{BasicDevice, DeviceInfo, DeviceType, PowerSource};
+use winmtp::device::device_values::PropertyValue;
+use winmtp::responder::Responder;
+use widestring::U16CString;
+
+use common::{loopback_device, memory_device};
+
+fn string(value: &str) -> PropertyValue {
+    PropertyValue::String(U16CString::from_str_truncate(value))
+}
+
+fn emulated_device() -> MemoryDevice {
+    let device = MemoryDevice::new("In-memory phone");
+    device.set_device_property(WPD_DEVICE_MANUFACTURER, string("ACME"));
+    device.set_device_property(WPD_DEVICE_MODEL, string("Phone 3"));
+    device.set_device_property(WPD_DEVICE_SERIAL_NUMBER, string("0123456789"));
+    device.set_device_property(WPD_DEVICE_TYPE, PropertyValue::U32(DeviceType::Phone.as_u32()));
+    device.set_device_property(WPD_DEVICE_POWER_SOURCE, PropertyValue::U32(0));
+    device.set_device_property(WPD_DEVICE_POWER_LEVEL, PropertyValue::U32(15));
+    device
+}
+
+fn device_info(basic_device: &BasicDevice) -> DeviceInfo {
+    basic_device.open(&winmtp::make_current_app_identifiers!(), true).unwrap().info().unwrap()
+}
+
+#[test]
+fn info_in_memory() {
+    let info = device_info(&memory_device(emulated_device()));
+    assert_eq!(info.manufacturer.as_deref(), Some("ACME"));
+    assert_eq!(info.model.as_deref(), Some("Phone 3"));
+    assert_eq!(info.serial_number.as_deref(), Some("0123456789"));
+    assert_eq!(info.device_type, Some(DeviceType::Phone));
+    assert_eq!(info.power_source, Some(PowerSource::Battery));
+    assert_eq!(info.battery_level, Some(15));
+    assert!(info.is_battery_below(20));
+    assert!(!info.is_battery_below(10));
+
+    // Devices that tell nothing are assumed to be fine
+    let info = device_info(&memory_device(MemoryDevice::new("Bare device")));
+    assert_eq!(info.model, None);
+    assert_eq!(info.battery_level, None);
+    assert!(!info.is_battery_below(20));
+}
+
+#[test]
+fn info_through_mtp_responder() {
+    let info = device_info(&loopback_device(emulated_device()));
+    assert_eq!(info.manufacturer.as_deref(), Some("winmtp"));
+    assert_eq!(info.model.as_deref(), Some("Emulated phone"));
+    assert_eq!(info.serial_number.as_deref(), Some("0123456789"));
+    assert_eq!(info.firmware_version.as_deref(), Some(env!("CARGO_PKG_VERSION")));
+    assert_eq!(info.protocol.as_deref(), Some("MTP: 1.00"));
+    assert_eq!(info.device_type, Some(DeviceType::Phone));
+    // MTP does not tell the power source
+    assert_eq!(info.power_source, None);
+    assert_eq!(info.battery_level, Some(15));
+
+    // Device properties are not supported when the backend does not have them
+    let responder = Responder::new(Rc::new(MemoryDevice::new("Bare device")), "winmtp", "Emulated camera");
+    let info = device_info(&responder.into_loopback_device("Emulated camera"));
+    assert_eq!(info.protocol.as_deref(), Some("MTP: 1.00"));
+    assert_eq!(info.serial_number, None);
+    assert_eq!(info.device_type, None);
+    assert_eq!(info.battery_level, None);
+}
@@ -0,0 +1,114 @@
+//! Checks that errors are classified the same way, whatever the backend that raised them
+
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::rc::Rc;
+
+use winmtp::Provider;
+use winmtp::backend::memory::{MemoryDevice, MemoryProvider};
+use winmtp::backend::mtp::{StreamTransport, Transport};
+use winmtp::backend::ptpip::PtpIpProvider;
+use winmtp::error::{ErrorKind, MtpError, RawErrorCode};
+use winmtp::object::ObjectId;
+use winmtp::protocol::codes::ResponseCode;
+use winmtp::responder::Responder;
+
+#[test]
+fn classification() {
+    // HRESULT_FROM_WIN32(ERROR_GEN_FAILURE), E_ACCESSDENIED, HRESULT_FROM_WIN32(ERROR_DISK_FULL), E_WPD_DEVICE_IS_HUNG, HRESULT_FROM_WIN32(ERROR_CANCELLED)
+    assert_eq!(ErrorKind::from_hresult(0x8007001F_u32 as i32), ErrorKind::DeviceGone);
+    assert_eq!(ErrorKind::from_hresult(0x80070005_u32 as i32), ErrorKind::AccessDenied);
+    assert_eq!(ErrorKind::from_hresult(0x80070070_u32 as i32), ErrorKind::StorageFull);
+    assert_eq!(ErrorKind::from_hresult(0x802A0006_u32 as i32), ErrorKind::Busy);
+    assert_eq!(ErrorKind::from_hresult(0x800704C7_u32 as i32), ErrorKind::Cancelled);
+    assert_eq!(ErrorKind::from_hresult(0x80004005_u32 as i32), ErrorKind::Other);
+
+    let err = MtpError::Response(ResponseCode::StoreFull);
+    assert_eq!(err.kind(), ErrorKind::StorageFull);
+    assert_eq!(err.raw_code(), Some(RawErrorCode::Response(ResponseCode::StoreFull)));
+    assert_eq!(MtpError::Response(ResponseCode::InvalidParentObject).kind(), ErrorKind::NotFound);
+    assert_eq!(MtpError::Response(ResponseCode::Other(0xA8FF)).kind(), ErrorKind::Other);
+
+    // The end of a stream only means that the device is gone when this is the connection to the device, not e.g. a truncated local file
+    assert_eq!(MtpError::from(io::Error::from(io::ErrorKind::UnexpectedEof)).kind(), ErrorKind::Other);
+    assert_eq!(MtpError::from_connection(io::Error::from(io::ErrorKind::UnexpectedEof)).kind(), ErrorKind::DeviceGone);
+    assert_eq!(MtpError::from(io::Error::from(io::ErrorKind::PermissionDenied)).kind(), ErrorKind::AccessDenied);
+    // Errors that went through `std::io` streams keep their classification
+    let wrapped = MtpError::from(io::Error::other(MtpError::Response(ResponseCode::DeviceBusy)));
+    assert_eq!(wrapped.kind(), ErrorKind::Busy);
+    assert_eq!(wrapped.raw_code(), Some(RawErrorCode::Response(ResponseCode::DeviceBusy)));
+    #[cfg(target_os = "linux")]
+    {
+        let unplugged = MtpError::from(io::Error::from_raw_os_error(libc::ENODEV));
+        assert_eq!(unplugged.kind(), ErrorKind::DeviceGone);
+        assert_eq!(unplugged.raw_code(), Some(RawErrorCode::Os(libc::ENODEV)));
+    }
+}
+
+fn emulated_phone() -> (MemoryDevice, ObjectId) {
+    let device = MemoryDevice::new("Fake phone");
+    let storage_id = device.add_storage("Internal shared storage");
+    let download_id = device.add_folder(&storage_id, "Download").unwrap();
+    device.add_file(&download_id, "a.txt", b"a").unwrap();
+    (device, storage_id)
+}
+
+#[test]
+fn same_kinds_across_backends() {
+    let (memory_device, _) = emulated_phone();
+    let memory_provider = MemoryProvider::new();
+    memory_provider.add_device(memory_device);
+    let (emulated_device, _) = emulated_phone();
+    let loopback_device = Responder::new(Rc::new(emulated_device), "winmtp", "Fake phone").into_loopback_device("Emulated phone");
+
+    let mut provider = Provider::empty();
+    provider.add_backend(Rc::new(memory_provider));
+    let mut basic_devices = provider.enumerate_devices().unwrap();
+    basic_devices.push(loopback_device);
+
+    let app_identifiers = winmtp::make_current_app_identifiers!();
+    for basic_device in &basic_devices {
+        let content = basic_device.open(&app_identifiers, true).unwrap().content().unwrap();
+        let root = content.root().unwrap();
+        let download = root.object_by_path(Path::new("Internal shared storage/Download")).unwrap();
+        let file = download.object_by_path(Path::new("a.txt")).unwrap();
+
+        assert_eq!(root.object_by_path(Path::new("Internal shared storage/nope")).unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(download.object_by_path(Path::new("/a.txt")).unwrap_err().kind(), ErrorKind::InvalidArgument);
+        assert_eq!(download.push_data("a.txt".as_ref(), b"b", false).unwrap_err().kind(), ErrorKind::AlreadyExists);
+        assert_eq!(download.create_subfolder("a.txt".as_ref()).unwrap_err().kind(), ErrorKind::AlreadyExists);
+        assert_eq!(download.delete(false).unwrap_err().kind(), ErrorKind::InvalidArgument, "on {}", basic_device.friendly_name());
+
+        file.delete(false).unwrap();
+        assert_eq!(file.delete(false).unwrap_err().kind(), ErrorKind::NotFound);
+    }
+}
+
+#[test]
+fn closed_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    drop(listener.accept().unwrap());
+    let mut transport = StreamTransport::new(stream);
+    assert_eq!(transport.receive().unwrap_err().kind(), ErrorKind::DeviceGone);
+}
+
+#[test]
+fn unreachable_device() {
+    let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    // Nobody listens there anymore
+    let ptpip_provider = PtpIpProvider::new();
+    ptpip_provider.add_device(&address.to_string());
+    let mut provider = Provider::empty();
+    provider.add_backend(Rc::new(ptpip_provider));
+    let basic_device = &provider.enumerate_devices().unwrap()[0];
+
+    let app_identifiers = winmtp::make_current_app_identifiers!();
+    let err = match basic_device.open(&app_identifiers, true) {
+        Ok(_) => panic!("Nobody should have answered"),
+        Err(err) => err,
+    };
+    assert_eq!(err.kind(), ErrorKind::DeviceGone);
+    assert!(matches!(err.raw_code(), Some(RawErrorCode::Os(_))));
+}
@@ -0,0 +1,127 @@
+//! Checks of device events, sent by an in-memory device, directly or through an MTP responder
+
+mod common;
+
+use std::ffi::OsStr;
+use std::net::TcpListener;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+
+use winmtp::Provider;
+use winmtp::backend::memory::{MemoryDevice, MemoryProvider};
+use winmtp::backend::ptpip::PtpIpProvider;
+use winmtp::device::{BasicDevice, DeviceEvent};
+use winmtp::object::ObjectId;
+use winmtp::protocol::codes::EventCode;
+use winmtp::protocol::container::Container;
+use winmtp::protocol::mapping::{device_event, object_id_from_handle, object_id_from_storage_id};
+use winmtp::responder::Responder;
+
+const EVENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn emulated_device() -> MemoryDevice {
+    let device = MemoryDevice::new("In-memory camera");
+    let storage_id = device.add_storage("SD card");
+    device.add_folder(&storage_id, "DCIM").unwrap();
+    device
+}
+
+#[test]
+fn events_in_memory() {
+    let device = emulated_device();
+    let memory_provider = Rc::new(MemoryProvider::new());
+    memory_provider.add_device(device.clone());
+    let mut provider = Provider::empty();
+    provider.add_backend(Rc::clone(&memory_provider) as _);
+    let basic_device = provider.enumerate_devices().unwrap().remove(0);
+
+    check_events(&basic_device);
+
+    // Events that are specific to this backend
+    let opened_device = basic_device.open(&winmtp::make_current_app_identifiers!(), true).unwrap();
+    let (sender, events) = mpsc::channel();
+    let _subscription = opened_device.subscribe(move |event| sender.send(event).unwrap()).unwrap();
+    let storage_id = device.add_storage("Internal storage");
+    assert_eq!(next_event(&events), DeviceEvent::StorageChanged{ id: Some(storage_id) });
+    memory_provider.remove_device(device.device_id());
+    assert_eq!(next_event(&events), DeviceEvent::DeviceRemoved);
+}
+
+#[test]
+fn events_through_mtp_responder() {
+    check_events(&common::loopback_device(emulated_device()));
+}
+
+#[test]
+fn events_over_ptpip() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    // The server thread is not joined: it keeps serving until the test process exits
+    std::thread::spawn(move || {
+        let mut responder = Responder::new(Rc::new(emulated_device()), "winmtp", "Networked camera");
+        loop {
+            responder.serve_ptpip(&listener).unwrap();
+        }
+    });
+
+    let ptpip_provider = PtpIpProvider::new();
+    ptpip_provider.add_device(&address.to_string());
+    let mut provider = Provider::empty();
+    provider.add_backend(Rc::new(ptpip_provider));
+    check_events(&provider.enumerate_devices().unwrap()[0]);
+}
+
+#[test]
+fn mtp_events() {
+    let added = Container::event(EventCode::ObjectAdded, 0, &[0x12]);
+    assert_eq!(device_event(&added), Some(DeviceEvent::ObjectAdded{ id: ObjectId::from(object_id_from_handle(0x12)), parent: None }));
+    let changed = Container::event(EventCode::StorageInfoChanged, 0, &[0xFFFF_FFFF]);
+    assert_eq!(device_event(&changed), Some(DeviceEvent::StorageChanged{ id: None }));
+    let changed = Container::event(EventCode::StoreFull, 0, &[0x10001]);
+    assert_eq!(device_event(&changed), Some(DeviceEvent::StorageChanged{ id: Some(ObjectId::from(object_id_from_storage_id(0x10001))) }));
+    let unknown = Container::event(EventCode::DevicePropChanged, 0, &[0x5001]);
+    assert_eq!(device_event(&unknown), None);
+}
+
+fn next_event(events: &Receiver<DeviceEvent>) -> DeviceEvent {
+    events.recv_timeout(EVENT_TIMEOUT).unwrap()
+}
+
+fn check_events(basic_device: &BasicDevice) {
+    let device = basic_device.open(&winmtp::make_current_app_identifiers!(), false).unwrap();
+    let content = device.content().unwrap();
+    let storage = content.root().unwrap().object_by_path(Path::new("SD card")).unwrap();
+    let dcim = storage.object_by_path(Path::new("DCIM")).unwrap();
+
+    let (subscription, events) = device.subscribe_channel().unwrap();
+
+    dcim.push_data(OsStr::new("IMG_0001.JPG"), b"not really a JPEG", false).unwrap();
+    let mut photo = dcim.object_by_path(Path::new("IMG_0001.JPG")).unwrap();
+    match next_event(&events) {
+        DeviceEvent::ObjectAdded{ id, parent } => {
+            assert_eq!(id, photo.id().to_owned());
+            // MTP events do not tell the parent
+            assert!(parent.is_none() || parent.as_deref() == Some(dcim.id()));
+        },
+        other => panic!("Unexpected event {:?}", other),
+    }
+
+    photo.rename(OsStr::new("IMG_0002.JPG")).unwrap();
+    let updated = DeviceEvent::ObjectUpdated{ id: photo.id().to_owned() };
+    assert_eq!(next_event(&events), updated);
+
+    photo.delete(false).unwrap();
+    // MTP initiators set properties one at a time, each of them being reported
+    let mut event = next_event(&events);
+    while event == updated {
+        event = next_event(&events);
+    }
+    assert_eq!(event, DeviceEvent::ObjectRemoved{ id: photo.id().to_owned() });
+
+    // No more events once the subscription is dropped
+    drop(subscription);
+    dcim.push_data(OsStr::new("IMG_0003.JPG"), b"not really a JPEG", false).unwrap();
+    assert!(events.recv_timeout(Duration::from_millis(300)).is_err());
+}
@@ -1,84 +1,106 @@
 //! These test should succeed when an Android device is connected.
+//!
+//! The same scenarios are also run against an in-memory device, so that they can run on any platform, without any device connected.
+//! They are run a third time against an in-memory device that is served by an MTP responder, so that the MTP protocol implementation is exercised as well.
 
 use std::ffi::OsStr;
-use std::io::Write;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::TcpListener;
 use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
+
+use std::rc::Rc;
 
 use widestring::U16CString;
 
 use winmtp::PortableDevices::{WPD_OBJECT_SIZE, WPD_OBJECT_DATE_MODIFIED};
 use winmtp::Provider;
+use winmtp::backend::memory::{MemoryDevice, MemoryProvider};
+use winmtp::backend::ptpip::PtpIpProvider;
 use winmtp::device::BasicDevice;
-use winmtp::object::ObjectType;
+use winmtp::device::device_values::{DeviceValues, PropertyValue};
+use winmtp::error::ErrorKind;
+use winmtp::io::Progress;
+use winmtp::responder::Responder;
+use winmtp::object::{ObjectFormat, ObjectType};
 use winmtp::object::Object;
+use winmtp::object::{OverwritePolicy, PullOptions};
 
-const EXAMPLE_SONG: &str = r"tests\assets\Rough Draft (open source mp3 from audiohub.com).mp3";
+const EXAMPLE_SONG: &str = r"tests/assets/Rough Draft (open source mp3 from audiohub.com).mp3";
 const PLAYLIST_CONTENT: &str = "This is not a valid M3U file, but ideally it should";
 
 #[derive(Debug, Clone, Copy)]
 enum DeviceKind {
     GenericAndroid,
-    Kindle
+    Kindle,
+    InMemory,
+    /// An in-memory device, behind an MTP responder
+    Emulated,
+    /// An in-memory device, behind an MTP responder reached over PTP/IP
+    PtpIp,
 }
 
 impl DeviceKind {
     fn storage_root_name(&self) -> &'static str {
         match self {
-            DeviceKind::GenericAndroid => "Internal shared storage",
+            DeviceKind::GenericAndroid | DeviceKind::InMemory | DeviceKind::Emulated | DeviceKind::PtpIp => "Internal shared storage",
             DeviceKind::Kindle => "Internal Storage",
         }
     }
 
     fn downloads_dir_name(&self) -> &'static str {
         match self {
-            DeviceKind::GenericAndroid => "Download",
+            DeviceKind::GenericAndroid | DeviceKind::InMemory | DeviceKind::Emulated | DeviceKind::PtpIp => "Download",
             DeviceKind::Kindle => "downloads",
         }
     }
 
     fn downloads_dir_path(&self) -> PathBuf {
-        // r"Internal shared storage\Download\"
-        PathBuf::from(format!(r"{}\{}\", self.storage_root_name(), self.downloads_dir_name()))
+        // r"Internal shared storage/Download/"
+        PathBuf::from(format!(r"{}/{}/", self.storage_root_name(), self.downloads_dir_name()))
     }
     fn downloads_dir_path_with_dot_segments(&self) -> PathBuf {
-        // r"Internal shared storage\.\.\Download\.\"
-        PathBuf::from(format!(r"{}\.\.\{}\.\", self.storage_root_name(), self.downloads_dir_name()))
+        // r"Internal shared storage/././Download/./"
+        PathBuf::from(format!(r"{}/././{}/./", self.storage_root_name(), self.downloads_dir_name()))
     }
     fn download_path_playlist_file(&self) -> PathBuf {
-        // r"Internal shared storage\.\.\Download\winmtp_test\.\some_playlist.m3u"
-        PathBuf::from(format!(r"{}\.\.\{}\winmtp_test\.\some_playlist.m3u", self.storage_root_name(), self.downloads_dir_name()))
+        // r"Internal shared storage/././Download/winmtp_test/./some_playlist.m3u"
+        PathBuf::from(format!(r"{}/././{}/winmtp_test/./some_playlist.m3u", self.storage_root_name(), self.downloads_dir_name()))
     }
     fn downloads_dir_path_with_redundant_separators(&self) -> PathBuf {
-        // r"Internal shared storage\\\Download\\\"
-        PathBuf::from(format!(r"{}\\\{}\\\", self.storage_root_name(), self.downloads_dir_name()))
+        // r"Internal shared storage///Download///"
+        PathBuf::from(format!(r"{}///{}///", self.storage_root_name(), self.downloads_dir_name()))
     }
     fn nonexistent_path_under_downloads(&self) -> PathBuf {
-        // r"Internal shared storage\\\Download\\\this_does_not_exist"
-        PathBuf::from(format!(r"{}\\\{}\\\this_does_not_exist", self.storage_root_name(), self.downloads_dir_name()))
+        // r"Internal shared storage///Download///this_does_not_exist"
+        PathBuf::from(format!(r"{}///{}///this_does_not_exist", self.storage_root_name(), self.downloads_dir_name()))
     }
     fn downloads_dir_path_with_parent_roundtrip(&self) -> PathBuf {
-        // r"Internal shared storage\\\Download\\\..\Download"
-        PathBuf::from(format!(r"{}\\\{}\\\..\{}", self.storage_root_name(), self.downloads_dir_name(), self.downloads_dir_name()))
+        // r"Internal shared storage///Download///../Download"
+        PathBuf::from(format!(r"{}///{}///../{}", self.storage_root_name(), self.downloads_dir_name(), self.downloads_dir_name()))
     }
     fn downloads_dir_path_with_nested_parent_segments(&self) -> PathBuf {
-        // r"Internal shared storage\\\Download\\\winmtp_test\..\..\Download"
-        PathBuf::from(format!(r"{}\\\{}\\\winmtp_test\..\..\{}", self.storage_root_name(), self.downloads_dir_name(), self.downloads_dir_name()))
+        // r"Internal shared storage///Download///winmtp_test/../../Download"
+        PathBuf::from(format!(r"{}///{}///winmtp_test/../../{}", self.storage_root_name(), self.downloads_dir_name(), self.downloads_dir_name()))
     }
     fn storage_root_parent_path(&self) -> PathBuf {
-        // r"Internal shared storage\.."
-        PathBuf::from(format!(r"{}\..", self.storage_root_name()))
+        // r"Internal shared storage/.."
+        PathBuf::from(format!(r"{}/..", self.storage_root_name()))
     }
     fn storage_root_parent_path_with_trailing_slash(&self) -> PathBuf {
-        // r"Internal shared storage\..\"
-        PathBuf::from(format!(r"{}\..\", self.storage_root_name()))
+        // r"Internal shared storage/../"
+        PathBuf::from(format!(r"{}/../", self.storage_root_name()))
     }
     fn uploaded_mp3_path(&self) -> PathBuf {
-        // r"Internal shared storage\Download\winmtp_test\Rough Draft (open source mp3 from audiohub.com).mp3"
-        PathBuf::from(format!(r"{}\{}\winmtp_test\Rough Draft (open source mp3 from audiohub.com).mp3", self.storage_root_name(), self.downloads_dir_name()))
+        // r"Internal shared storage/Download/winmtp_test/Rough Draft (open source mp3 from audiohub.com).mp3"
+        PathBuf::from(format!(r"{}/{}/winmtp_test/Rough Draft (open source mp3 from audiohub.com).mp3", self.storage_root_name(), self.downloads_dir_name()))
     }
     fn write_stream_file_path(&self) -> PathBuf {
-        PathBuf::from(format!(r"{}\{}\winmtp_test\file_pushed_via_create_write_stream.mp3", self.storage_root_name(), self.downloads_dir_name()))
+        PathBuf::from(format!(r"{}/{}/winmtp_test/file_pushed_via_create_write_stream.mp3", self.storage_root_name(), self.downloads_dir_name()))
+    }
+    /// Where to store a pulled file locally. Scenarios may run concurrently on several device kinds.
+    fn local_pulled_file_path(&self, name: &str) -> PathBuf {
+        PathBuf::from(format!(r"tests/assets/{}-{:?}.dat", name, self))
     }
 }
 
@@ -86,26 +108,93 @@ fn get_device_kind(basic_device: &BasicDevice) -> DeviceKind {
     match basic_device.friendly_name().to_lowercase() {
         s if s.contains("kindle") => DeviceKind::Kindle,
         s if s.contains("android") || s.contains("moto") => DeviceKind::GenericAndroid,
+        s if s.contains("in-memory") => DeviceKind::InMemory,
+        s if s.contains("emulated") => DeviceKind::Emulated,
+        s if s.contains("ptp/ip") => DeviceKind::PtpIp,
         s => panic!("No testing paths for friendly name {}", s)
     }
 }
 
+#[cfg(windows)]
 #[test]
 fn file_access() {
     // This is a manual smoke test rather than a proper automated test, as this requires a device to be connected, with some assumptions about its content
 
     let provider = Provider::new().unwrap();
+    run_scenarios(&provider);
+}
+
+#[test]
+fn file_access_in_memory() {
+    let device = MemoryDevice::new("In-memory device");
+    let storage_id = device.add_storage("Internal shared storage");
+    device.add_folder(&storage_id, "Download").unwrap();
+    device.add_folder(&storage_id, "DCIM").unwrap();
+
+    let memory_provider = MemoryProvider::new();
+    memory_provider.add_device(device);
+    let mut provider = Provider::empty();
+    provider.add_backend(Rc::new(memory_provider));
+
+    run_scenarios(&provider);
+}
+
+#[test]
+fn file_access_through_mtp_responder() {
+    let device = MemoryDevice::new("In-memory device");
+    let storage_id = device.add_storage("Internal shared storage");
+    device.add_folder(&storage_id, "Download").unwrap();
+    device.add_folder(&storage_id, "DCIM").unwrap();
+
+    let responder = Responder::new(Rc::new(device), "winmtp", "Emulated device");
+    let basic_device = responder.into_loopback_device("Emulated device");
+    run_scenarios_on(&basic_device);
+}
+
+#[test]
+fn file_access_over_ptpip() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    // The server thread is not joined: it keeps serving until the test process exits
+    std::thread::spawn(move || {
+        let device = MemoryDevice::new("In-memory device");
+        let storage_id = device.add_storage("Internal shared storage");
+        device.add_folder(&storage_id, "Download").unwrap();
+        device.add_folder(&storage_id, "DCIM").unwrap();
+        let mut responder = Responder::new(Rc::new(device), "winmtp", "Networked device");
+        loop {
+            responder.serve_ptpip(&listener).unwrap();
+        }
+    });
+
+    let ptpip_provider = PtpIpProvider::new();
+    ptpip_provider.add_device(&address.to_string());
+    let mut provider = Provider::empty();
+    provider.add_backend(Rc::new(ptpip_provider));
+
+    run_scenarios(&provider);
+}
+
+fn run_scenarios(provider: &Provider) {
     let devices = provider.enumerate_devices().unwrap();
-    let first_device = devices.get(0).expect("a device to be connected");
+    let first_device = devices.first().expect("a device to be connected");
+    run_scenarios_on(first_device);
+}
+
+fn run_scenarios_on(first_device: &BasicDevice) {
     let device_kind = get_device_kind(first_device);
 
     println!("Testing on {}:", first_device.friendly_name());
     let (min_date, max_date) = push_content(first_device, device_kind);
     access_by_path(first_device, device_kind);
     access_by_id(first_device, device_kind);
+    read_metadata(first_device, device_kind);
     pull_content(first_device, device_kind, min_date, max_date);
+    read_ranges(first_device, device_kind);
     write_file_via_create_write_stream(first_device, device_kind);
     verify_file_written_via_create_write_stream(first_device, device_kind);
+    write_properties(first_device, device_kind);
+    move_and_delete(first_device, device_kind);
 }
 
 fn access_by_path(basic_device: &BasicDevice, device_kind: DeviceKind) {
@@ -164,10 +253,39 @@ fn access_by_id(basic_device: &BasicDevice, device_kind: DeviceKind) {
 
     let root_obj = content.root().unwrap();
     let download_folder_by_path = root_obj.object_by_path(&device_kind.downloads_dir_path()).unwrap();
-    let download_folder_by_id = content.object_by_id(download_folder_by_path.id().to_ucstring()).unwrap();
+    let download_folder_by_id = content.object_by_id(download_folder_by_path.id().to_owned()).unwrap();
     assert_eq!(download_folder_by_id.name(), &U16CString::from_str_truncate(device_kind.downloads_dir_name()));
 }
 
+fn read_metadata(basic_device: &BasicDevice, device_kind: DeviceKind) {
+    let app_ident = winmtp::make_current_app_identifiers!();
+    let device = basic_device.open(&app_ident, true).unwrap();
+    let content = device.content().unwrap();
+
+    let root_metadata = content.root().unwrap().metadata().unwrap();
+    assert_eq!(root_metadata.content_type, ObjectType::FunctionalObject);
+    assert_eq!(root_metadata.parent_id, None);
+
+    let test_folder = content.root().unwrap().object_by_path(&device_kind.uploaded_mp3_path().with_file_name("")).unwrap();
+    let object = content.root().unwrap().object_by_path(&device_kind.uploaded_mp3_path()).unwrap();
+    let metadata = object.metadata().unwrap();
+    assert_eq!(metadata.name.as_ucstr(), object.name());
+    assert_eq!(metadata.original_file_name.as_deref(), object.original_file_name());
+    assert_eq!(metadata.content_type, object.object_type());
+    assert_eq!(metadata.size, Some(std::fs::metadata(Path::new(EXAMPLE_SONG)).unwrap().len()));
+    assert_eq!(metadata.parent_id.as_deref(), Some(test_folder.id()));
+    assert!(metadata.date_modified.is_some());
+    assert_ne!(metadata.can_delete, Some(false));
+    assert_ne!(metadata.is_read_only, Some(true));
+    if let DeviceKind::InMemory | DeviceKind::Emulated | DeviceKind::PtpIp = device_kind {
+        assert_eq!(metadata.format, Some(ObjectFormat::Mp3));
+        assert_eq!(metadata.content_type, ObjectType::Audio);
+    }
+    if let DeviceKind::Emulated | DeviceKind::PtpIp = device_kind {
+        assert!(metadata.persistent_unique_id.is_some());
+    }
+}
+
 fn prepare_upload_folder(basic_device: &BasicDevice, device_kind: DeviceKind) -> Object {
     let app_identifiers = winmtp::make_current_app_identifiers!();
     let device = basic_device.open(&app_identifiers, true).unwrap();
@@ -175,8 +293,8 @@ fn prepare_upload_folder(basic_device: &BasicDevice, device_kind: DeviceKind) ->
     let download_folder = content.root().unwrap().object_by_path(&device_kind.downloads_dir_path()).unwrap();
     let test_folder_id = match download_folder.create_subfolder(OsStr::new("winmtp_test")) {
         Ok(id) => id,
-        Err(winmtp::error::CreateFolderError::AlreadyExists) => {
-            let mut existing_folder = download_folder.object_by_path(Path::new("winmtp_test")).unwrap();
+        Err(winmtp::error::MtpError::AlreadyExists) => {
+            let existing_folder = download_folder.object_by_path(Path::new("winmtp_test")).unwrap();
             existing_folder.delete(true).unwrap();
             // and try again
             download_folder.create_subfolder(OsStr::new("winmtp_test")).unwrap()
@@ -184,8 +302,7 @@ fn prepare_upload_folder(basic_device: &BasicDevice, device_kind: DeviceKind) ->
         Err(err) => panic!("{}", err),
     };
 
-    let test_folder = content.object_by_id(test_folder_id).unwrap();
-    test_folder
+    content.object_by_id(test_folder_id).unwrap()
 }
 
 /// Write some files, that will also be used for reading tests
@@ -241,11 +358,82 @@ fn pull_content(basic_device: &BasicDevice, device_kind: DeviceKind, min_expecte
     // let delta_max = max_expected_date.duration_since(creation_date);
     // assert!(creation_date > min_expected_date, "Min creation date mismatch ({creation_date:?} vs. {min_expected_date:?} = {delta_min:?}). Are the clock of the device and its time zone correctly set?");
     // assert!(creation_date < max_expected_date, "Max creation date mismatch ({creation_date:?} vs. {max_expected_date:?} = {delta_max:?}). Are the clock of the device and its time zone correctly set?");
+    // There is no such issue with the in-memory device, that shares our clock
+    if let DeviceKind::InMemory = device_kind {
+        assert!(creation_date >= min_expected_date && creation_date <= max_expected_date);
+    }
+    // MTP dates have a one-second resolution
+    if let DeviceKind::Emulated | DeviceKind::PtpIp = device_kind {
+        assert!(creation_date + Duration::from_secs(1) > min_expected_date && creation_date <= max_expected_date);
+    }
 
     // Download the file
     let mut input_stream = object.open_read_stream().unwrap();
-    let mut output_file = std::fs::File::create(r"tests\assets\pulled-from-device.dat").unwrap();
+    let local_path = device_kind.local_pulled_file_path("pulled-from-device");
+    let mut output_file = std::fs::File::create(&local_path).unwrap();
     std::io::copy(&mut input_stream, &mut output_file).unwrap();
+    assert_eq!(std::fs::read(&local_path).unwrap(), std::fs::read(EXAMPLE_SONG).unwrap());
+
+    // Download the file, the easy way
+    let local_path = device_kind.local_pulled_file_path("pulled-to-file");
+    let _ = std::fs::remove_file(&local_path);
+    let pulled_size = object.pull_to_file(&local_path, PullOptions::new()).unwrap();
+    assert_eq!(pulled_size, original_size);
+    assert_eq!(std::fs::read(&local_path).unwrap(), std::fs::read(EXAMPLE_SONG).unwrap());
+    assert_eq!(std::fs::metadata(&local_path).unwrap().modified().unwrap(), creation_date);
+    assert_eq!(object.pull_to_file(&local_path, PullOptions::new()).unwrap_err().kind(), ErrorKind::AlreadyExists);
+    std::fs::write(&local_path, b"to be overwritten").unwrap();
+    object.pull_to_file(&local_path, PullOptions::new().overwrite(OverwritePolicy::Overwrite).preserve_timestamps(false)).unwrap();
+    assert_eq!(std::fs::metadata(&local_path).unwrap().len(), original_size);
+    // Nor is a file that has been created during the transfer
+    std::fs::remove_file(&local_path).unwrap();
+    let mut create_destination = |_: &Progress| {
+        if !local_path.exists() {
+            std::fs::write(&local_path, b"created meanwhile").unwrap();
+        }
+    };
+    let result = object.pull_to_file(&local_path, PullOptions::new().progress(&mut create_destination));
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::AlreadyExists);
+    assert_eq!(std::fs::read(&local_path).unwrap(), b"created meanwhile");
+    // No temporary file is left behind
+    let file_name = local_path.file_name().unwrap().to_string_lossy().into_owned();
+    let leftovers = std::fs::read_dir(local_path.parent().unwrap()).unwrap()
+        .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().starts_with(&format!(".{}", file_name)))
+        .count();
+    assert_eq!(leftovers, 0);
+}
+
+fn read_ranges(basic_device: &BasicDevice, device_kind: DeviceKind) {
+    let app_identifiers = winmtp::make_current_app_identifiers!();
+    let device = basic_device.open(&app_identifiers, true).unwrap();
+    let object = device.content().unwrap().root().unwrap().object_by_path(&device_kind.uploaded_mp3_path()).unwrap();
+    let original = std::fs::read(EXAMPLE_SONG).unwrap();
+
+    let range = match object.read_range(1000, 64) {
+        Ok(range) => range,
+        // Streams of real devices may not be able to seek
+        Err(err) if err.kind() == ErrorKind::Unsupported && matches!(device_kind, DeviceKind::GenericAndroid | DeviceKind::Kindle) => return,
+        Err(err) => panic!("{}", err),
+    };
+    assert_eq!(range, &original[1000..1064]);
+    // Ranges are truncated at the end of the object
+    assert_eq!(object.read_range(original.len() as u64 - 10, 64).unwrap(), &original[original.len() - 10..]);
+    assert!(object.read_range(original.len() as u64 + 10, 64).unwrap().is_empty());
+
+    let mut input_stream = object.open_read_stream().unwrap();
+    let mut tail = Vec::new();
+    assert_eq!(input_stream.seek(SeekFrom::End(-100)).unwrap(), original.len() as u64 - 100);
+    input_stream.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, &original[original.len() - 100..]);
+
+    let mut head = [0; 16];
+    input_stream.seek(SeekFrom::Start(0)).unwrap();
+    input_stream.read_exact(&mut head).unwrap();
+    assert_eq!(head, original[..16]);
+    input_stream.seek(SeekFrom::Current(100)).unwrap();
+    input_stream.read_exact(&mut head).unwrap();
+    assert_eq!(head, original[116..132]);
+    assert!(input_stream.seek(SeekFrom::Current(-1000)).is_err());
 }
 
 fn verify_file_written_via_create_write_stream(basic_device: &BasicDevice, device_kind: DeviceKind) {
@@ -261,7 +449,68 @@ fn verify_file_written_via_create_write_stream(basic_device: &BasicDevice, devic
 
     // Download the file
     let mut input_stream = object.open_read_stream().unwrap();
-    let mut output_file = std::fs::File::create(r"tests\assets\created-via-write-stream.dat").unwrap();
+    let local_path = device_kind.local_pulled_file_path("created-via-write-stream");
+    let mut output_file = std::fs::File::create(&local_path).unwrap();
     std::io::copy(&mut input_stream, &mut output_file).unwrap();
+    assert_eq!(std::fs::read(&local_path).unwrap(), std::fs::read(EXAMPLE_SONG).unwrap());
+}
+
+
+fn move_and_delete(basic_device: &BasicDevice, device_kind: DeviceKind) {
+    let test_folder = prepare_upload_folder(basic_device, device_kind);
+    test_folder.push_data(OsStr::new("to_be_moved.txt"), b"moving around", true).unwrap();
+    let sub_folder_id = test_folder.create_subfolder(OsStr::new("sub_folder")).unwrap();
+
+    let file = test_folder.object_by_path(Path::new("to_be_moved.txt")).unwrap();
+    file.move_to(&sub_folder_id).unwrap();
+    assert!(test_folder.object_by_path(Path::new("to_be_moved.txt")).is_err());
+    let moved_file = test_folder.object_by_path(Path::new("sub_folder/to_be_moved.txt")).unwrap();
+    assert_eq!(moved_file.parent_id().unwrap(), sub_folder_id);
+
+    moved_file.delete(false).unwrap();
+    assert!(test_folder.object_by_path(Path::new("sub_folder/to_be_moved.txt")).is_err());
 }
 
+fn write_properties(basic_device: &BasicDevice, device_kind: DeviceKind) {
+    let test_folder = prepare_upload_folder(basic_device, device_kind);
+    test_folder.push_data(OsStr::new("to_be_renamed.txt"), b"renaming", true).unwrap();
+    let mut file = test_folder.object_by_path(Path::new("to_be_renamed.txt")).unwrap();
+    let emulated = matches!(device_kind, DeviceKind::InMemory | DeviceKind::Emulated | DeviceKind::PtpIp);
+
+    let outcomes = file.rename(OsStr::new("renamed.txt")).unwrap();
+    assert!(outcomes.iter().any(|outcome| outcome.is_ok()));
+    if emulated {
+        assert!(outcomes.iter().all(|outcome| outcome.is_ok()), "{:?}", outcomes);
+        assert_eq!(file.name().to_string_lossy(), "renamed.txt");
+    }
+    let renamed = test_folder.object_by_path(Path::new("renamed.txt")).unwrap();
+    assert_eq!(renamed.id(), file.id());
+    assert!(test_folder.object_by_path(Path::new("to_be_renamed.txt")).is_err());
+
+    // MTP dates have a one-second resolution
+    let date = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    let outcomes = file.set_date_modified(date).unwrap();
+    if outcomes[0].is_ok() {
+        assert_eq!(file.metadata().unwrap().date_modified, Some(date));
+    }
+    if emulated {
+        assert!(file.set_date_created(date).unwrap()[0].is_ok());
+        assert!(file.set_hidden(true).unwrap()[0].is_ok());
+        let metadata = file.metadata().unwrap();
+        assert_eq!(metadata.date_modified, Some(date));
+        assert_eq!(metadata.date_created, Some(date));
+        assert_eq!(metadata.is_hidden, Some(true));
+
+        // Rejected properties do not prevent the others from being written
+        let values = DeviceValues::new()
+            .with(WPD_OBJECT_SIZE, PropertyValue::U64(1))
+            .with(WPD_OBJECT_DATE_MODIFIED, PropertyValue::Date(date + Duration::from_secs(60)));
+        let outcomes = file.set_properties(&values).unwrap();
+        assert_eq!(outcomes[0].key, WPD_OBJECT_SIZE);
+        assert_eq!(outcomes[0].error_kind(), Some(ErrorKind::AccessDenied));
+        assert!(outcomes[1].is_ok());
+        let metadata = file.metadata().unwrap();
+        assert_eq!(metadata.size, Some(8));
+        assert_eq!(metadata.date_modified, Some(date + Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,46 @@
+//! Checks of the formats of uploaded files, inferred from their names or contents, through an MTP responder
+
+mod common;
+
+use std::ffi::OsStr;
+
+use winmtp::io::{CancellationToken, Progress};
+use winmtp::object::{ObjectFormat, ObjectType};
+
+#[test]
+fn detection() {
+    assert_eq!(ObjectFormat::detect(OsStr::new("song.MP3"), &[]), ObjectFormat::Mp3);
+    assert_eq!(ObjectFormat::detect(OsStr::new("list.m3u"), &[]), ObjectFormat::M3uPlaylist);
+    // The extension wins over the content
+    assert_eq!(ObjectFormat::detect(OsStr::new("notes.txt"), b"%PDF-1.7"), ObjectFormat::Text);
+    assert_eq!(ObjectFormat::detect(OsStr::new("scan"), b"%PDF-1.7"), ObjectFormat::Pdf);
+    assert_eq!(ObjectFormat::detect(OsStr::new("IMG_0001"), b"\xFF\xD8\xFF\xE0"), ObjectFormat::Jpeg);
+    assert_eq!(ObjectFormat::detect(OsStr::new("blob.bin"), &[0; 16]), ObjectFormat::Unspecified);
+
+    assert_eq!(ObjectFormat::Pdf.object_type(), ObjectType::Document);
+    assert_eq!(ObjectFormat::Flac.object_type(), ObjectType::Audio);
+    assert_eq!(ObjectFormat::from_guid(ObjectFormat::Png.as_guid()), ObjectFormat::Png);
+}
+
+#[test]
+fn uploads_through_mtp_responder() {
+    let (device, _storage_id) = common::fake_phone();
+    let content = common::open_content(&common::loopback_device(device));
+    let storage = content.root().unwrap().children().unwrap().next().unwrap();
+
+    // Detected out of the first bytes, as the file has no extension
+    storage.push_data(OsStr::new("IMG_0001"), b"\x89PNG\r\n\x1A\n\0\0\0\x0DIHDR", false).unwrap();
+    // Chosen by the caller
+    let local_file = std::env::temp_dir().join(format!("winmtp-format-{}.dat", std::process::id()));
+    std::fs::write(&local_file, b"not really a video").unwrap();
+    storage.push_file_with_format(&local_file, ObjectFormat::Mp4, false, &mut |_: &Progress| {}, &CancellationToken::new()).unwrap();
+    std::fs::remove_file(&local_file).unwrap();
+
+    let mut children = storage.children().unwrap();
+    let image = children.next().unwrap().metadata().unwrap();
+    assert_eq!(image.format, Some(ObjectFormat::Png));
+    assert_eq!(image.content_type, ObjectType::Image);
+    let video = children.next().unwrap().metadata().unwrap();
+    assert_eq!(video.format, Some(ObjectFormat::Mp4));
+    assert_eq!(video.content_type, ObjectType::Video);
+}
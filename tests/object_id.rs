@@ -0,0 +1,50 @@
+//! Checks of object IDs, that can be stored in maps and databases, and used to get objects back
+
+mod common;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use widestring::U16CString;
+
+use winmtp::error::ErrorKind;
+use winmtp::object::{ObjectId, ObjectIdRef};
+
+#[test]
+fn conversions() {
+    let id: ObjectId = "o2C".parse().unwrap();
+    assert_eq!(id.to_string(), "o2C");
+    assert_eq!(format!("{:?}", id), "\"o2C\"");
+    assert_eq!(id, *ObjectIdRef::from_ucstr(&U16CString::from_str_truncate("o2C")));
+    assert_eq!(id.clone().into_ucstring(), U16CString::from_str_truncate("o2C"));
+    assert_eq!("o\0".parse::<ObjectId>().unwrap_err().kind(), ErrorKind::InvalidArgument);
+    assert_eq!(ObjectId::device_root().to_string(), "DEVICE");
+
+    let json = serde_json::to_string(&id).unwrap();
+    assert_eq!(json, "\"o2C\"");
+    assert_eq!(serde_json::from_str::<ObjectId>(&json).unwrap(), id);
+    assert!(serde_json::from_str::<ObjectId>("\"o\\u0000\"").is_err());
+}
+
+#[test]
+fn ids_as_keys() {
+    let (device, storage_id) = common::fake_phone();
+    let music_id = device.add_folder(&storage_id, "Music").unwrap();
+    device.add_file(&music_id, "a.mp3", b"a").unwrap();
+    device.add_file(&music_id, "b.mp3", b"b").unwrap();
+
+    let content = common::open_content(&common::memory_device(device));
+    assert_eq!(content.root().unwrap().id(), ObjectId::device_root());
+    let music = content.root().unwrap().object_by_path(Path::new("Internal shared storage/Music")).unwrap();
+    assert_eq!(music.id(), music_id);
+    let names: HashMap<ObjectId, String> = music.children().unwrap()
+        .map(|child| (child.id().to_owned(), child.name().to_string_lossy()))
+        .collect();
+
+    for child in music.children().unwrap() {
+        // Borrowed IDs are enough to look owned ones up
+        assert_eq!(names[child.id()], child.name().to_string_lossy());
+        let by_id = content.object_by_id(child.id().to_owned()).unwrap();
+        assert_eq!(by_id.parent_id().unwrap(), music_id);
+    }
+}
@@ -0,0 +1,101 @@
+//! Checks of the progress reports and of the cancellation of transfers
+
+mod common;
+
+use std::ffi::OsStr;
+use std::io::Read;
+use std::path::Path;
+
+use winmtp::backend::memory::MemoryDevice;
+use winmtp::device::BasicDevice;
+use winmtp::error::ErrorKind;
+use winmtp::io::{CancellationToken, Progress};
+
+use common::{fake_phone, loopback_device, memory_device, open_content};
+
+const EXAMPLE_SONG: &str = r"tests/assets/Rough Draft (open source mp3 from audiohub.com).mp3";
+
+fn phone_with_music() -> MemoryDevice {
+    let (device, storage_id) = fake_phone();
+    device.add_folder(&storage_id, "Music").unwrap();
+    device
+}
+
+fn check_transfers(basic_device: &BasicDevice) {
+    let content = open_content(basic_device);
+    let music = content.root().unwrap().object_by_path(Path::new("Internal shared storage/Music")).unwrap();
+    let song_size = std::fs::metadata(EXAMPLE_SONG).unwrap().len();
+
+    // Progress is reported from 0 to the total size
+    let mut reports: Vec<Progress> = Vec::new();
+    music.push_file_with_progress(Path::new(EXAMPLE_SONG), false, &mut |progress: &Progress| reports.push(*progress), &CancellationToken::new()).unwrap();
+    assert!(reports.len() > 2);
+    assert_eq!(reports.first().unwrap().bytes_done, 0);
+    assert_eq!(reports.last().unwrap().bytes_done, song_size);
+    assert!(reports.iter().all(|progress| progress.bytes_total == Some(song_size)));
+    assert!(reports.windows(2).all(|pair| pair[0].bytes_done < pair[1].bytes_done && pair[0].elapsed <= pair[1].elapsed));
+    assert_eq!(reports.last().unwrap().fraction(), Some(1.0));
+    assert_eq!(reports.last().unwrap().eta(), Some(std::time::Duration::ZERO));
+
+    let song = music.object_by_path(Path::new(EXAMPLE_SONG).file_name().unwrap().as_ref()).unwrap();
+    let mut pulled = Vec::new();
+    let mut pull_reports = 0;
+    let pulled_size = song.pull_with_progress(&mut pulled, &mut |progress: &Progress| {
+        assert_eq!(progress.bytes_total, Some(song_size));
+        pull_reports += 1;
+    }, &CancellationToken::new()).unwrap();
+    assert_eq!(pulled_size, song_size);
+    assert_eq!(pulled, std::fs::read(EXAMPLE_SONG).unwrap());
+    assert!(pull_reports > 2);
+
+    // Cancelling halfway does not leave a partial object
+    let cancellation = CancellationToken::new();
+    let result = music.push_data_with_progress(OsStr::new("cancelled.bin"), &vec![0xAB; 1024 * 1024], false, &mut |progress: &Progress| {
+        if progress.bytes_done > 0 {
+            cancellation.cancel();
+        }
+    }, &cancellation);
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::Cancelled);
+    assert_eq!(music.object_by_path(Path::new("cancelled.bin")).unwrap_err().kind(), ErrorKind::NotFound);
+
+    // A token that is already cancelled prevents the transfer from starting
+    let mut pulled = Vec::new();
+    let result = song.pull_with_progress(&mut pulled, &mut |_: &Progress| {}, &cancellation);
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::Cancelled);
+    assert!(pulled.is_empty());
+
+    // Cancelled transfers do not prevent later ones
+    music.push_data(OsStr::new("cancelled.bin"), b"not cancelled", false).unwrap();
+    assert_eq!(music.children().unwrap().count(), 2);
+
+    // Cancelling an overwrite keeps the existing file
+    let cancellation = CancellationToken::new();
+    let result = music.push_data_with_progress(OsStr::new("cancelled.bin"), &vec![0xAB; 1024 * 1024], true, &mut |progress: &Progress| {
+        if progress.bytes_done > 0 {
+            cancellation.cancel();
+        }
+    }, &cancellation);
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::Cancelled);
+    let read_back = |name: &str| {
+        let mut data = Vec::new();
+        music.object_by_path(Path::new(name)).unwrap().open_read_stream().unwrap().read_to_end(&mut data).unwrap();
+        data
+    };
+    assert_eq!(read_back("cancelled.bin"), b"not cancelled");
+    assert_eq!(music.children().unwrap().count(), 2);
+
+    // A successful overwrite replaces it, without leaving anything else behind
+    music.push_data(OsStr::new("cancelled.bin"), b"overwritten", true).unwrap();
+    assert_eq!(read_back("cancelled.bin"), b"overwritten");
+    assert_eq!(music.children().unwrap().count(), 2);
+}
+
+#[test]
+fn transfers_in_memory() {
+    check_transfers(&memory_device(phone_with_music()));
+}
+
+#[test]
+fn transfers_through_mtp_responder() {
+    check_transfers(&loopback_device(phone_with_music()));
+}
@@ -0,0 +1,352 @@
+//! Checks of the MTP protocol codec against byte fixtures
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use widestring::u16cstr;
+
+use winmtp::PortableDevices::{WPD_OBJECT_FORMAT_MP3, WPD_OBJECT_FORMAT_PROPERTIES_ONLY, WPD_OBJECT_SIZE, WPD_OBJECT_PARENT_ID};
+use winmtp::device::device_values::PropertyValue;
+use winmtp::error::ProtocolError;
+use winmtp::object::ObjectType;
+use winmtp::protocol::codes::{DataType, DevicePropertyCode, EventCode, ObjectFormatCode, ObjectPropertyCode, OperationCode, ResponseCode};
+use winmtp::protocol::container::{Container, ContainerHeader, ContainerType};
+use winmtp::protocol::data::{Reader, Value, Writer};
+use winmtp::protocol::datasets::{DeviceInfo, DevicePropDesc, ObjectInfo, ObjectPropDesc, PropertyForm, StorageInfo};
+use winmtp::protocol::datetime::{format_datetime, parse_datetime};
+use winmtp::protocol::mapping;
+use winmtp::protocol::ptpip::{DataPhase, Packet, PROTOCOL_VERSION};
+
+#[test]
+fn containers() {
+    // GetObjectHandles(storage 0x10001, all formats, root folder), transaction 7
+    let fixture = [
+        0x18, 0x00, 0x00, 0x00,  0x01, 0x00,  0x07, 0x10,  0x07, 0x00, 0x00, 0x00,
+        0x01, 0x00, 0x01, 0x00,  0x00, 0x00, 0x00, 0x00,  0xFF, 0xFF, 0xFF, 0xFF,
+    ];
+    let command = Container::command(OperationCode::GetObjectHandles, 7, &[0x10001, 0, 0xFFFFFFFF]);
+    assert_eq!(command.encode(), fixture);
+
+    let decoded = Container::decode(&fixture).unwrap();
+    assert_eq!(decoded, command);
+    assert_eq!(decoded.operation_code(), OperationCode::GetObjectHandles);
+    assert_eq!(decoded.param(0), 0x10001);
+    assert_eq!(decoded.param(4), 0);
+
+    // OK response, without parameters
+    let fixture = [0x0C, 0x00, 0x00, 0x00,  0x03, 0x00,  0x01, 0x20,  0x07, 0x00, 0x00, 0x00];
+    let response = Container::decode(&fixture).unwrap();
+    assert_eq!(response.container_type, ContainerType::Response);
+    assert_eq!(response.response_code(), ResponseCode::Ok);
+    assert!(response.params().unwrap().is_empty());
+
+    // ObjectAdded event
+    let fixture = [0x10, 0x00, 0x00, 0x00,  0x04, 0x00,  0x02, 0x40,  0x00, 0x00, 0x00, 0x00,  0x2C, 0x00, 0x00, 0x00];
+    let event = Container::decode(&fixture).unwrap();
+    assert_eq!(event.event_code(), EventCode::ObjectAdded);
+    assert_eq!(event.params().unwrap(), [0x2C]);
+
+    // Data phase
+    let data = Container::data(OperationCode::GetObject, 3, b"hello".to_vec());
+    let encoded = data.encode();
+    assert_eq!(&encoded[..12], [0x11, 0x00, 0x00, 0x00,  0x02, 0x00,  0x09, 0x10,  0x03, 0x00, 0x00, 0x00]);
+    assert_eq!(ContainerHeader::decode(&encoded).unwrap().payload_len(), 5);
+
+    // Vendor codes are kept as they are
+    let vendor = Container::command(OperationCode::from_u16(0x9999), 1, &[]);
+    assert_eq!(Container::decode(&vendor.encode()).unwrap().operation_code(), OperationCode::Other(0x9999));
+
+    // Errors
+    assert_eq!(Container::decode(&fixture[..10]), Err(ProtocolError::Truncated));
+    assert_eq!(Container::decode(&[0x0C, 0, 0, 0, 0x09, 0, 0x01, 0x20, 0, 0, 0, 0]), Err(ProtocolError::InvalidContainerType(9)));
+    assert_eq!(Container::decode(&[0x20, 0, 0, 0, 0x03, 0, 0x01, 0x20, 0, 0, 0, 0]), Err(ProtocolError::InvalidLength(0x20)));
+
+    // Streams are read as they come, whatever length the header claims
+    let mut stream = std::io::Cursor::new(encoded);
+    assert_eq!(Container::read_from(&mut stream).unwrap(), data);
+    let mut stream: &[u8] = &[0xF0, 0xFF, 0xFF, 0x7F,  0x02, 0x00,  0x09, 0x10,  0x03, 0x00, 0x00, 0x00,  0x68, 0x65];
+    assert_eq!(Container::read_from(&mut stream).unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+    let mut stream: &[u8] = &[0xFF, 0xFF, 0xFF, 0xFF,  0x02, 0x00,  0x09, 0x10,  0x03, 0x00, 0x00, 0x00];
+    assert_eq!(Container::read_from(&mut stream).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+    // Hand-built headers may be shorter than themselves
+    let header = ContainerHeader{ length: 4, ..ContainerHeader::data(OperationCode::GetObject, 1, 0) };
+    assert_eq!(header.payload_len(), 0);
+}
+
+#[test]
+fn strings_and_arrays() {
+    let mut writer = Writer::new();
+    writer.put_string("ab");
+    writer.put_string("");
+    writer.put_u16_array(&[0x1001, 0x1002]);
+    let bytes = writer.into_bytes();
+    assert_eq!(bytes, [
+        0x03, b'a', 0x00, b'b', 0x00, 0x00, 0x00,
+        0x00,
+        0x02, 0x00, 0x00, 0x00, 0x01, 0x10, 0x02, 0x10,
+    ]);
+
+    let mut reader = Reader::new(&bytes);
+    assert_eq!(reader.get_string().unwrap(), "ab");
+    assert_eq!(reader.get_string().unwrap(), "");
+    assert_eq!(reader.get_u16_array().unwrap(), [0x1001, 0x1002]);
+    assert_eq!(reader.remaining(), 0);
+
+    // Non-ASCII, and a missing null terminator
+    let mut reader = Reader::new(&[0x02, 0xE9, 0x00, 0x3D, 0xD8]);
+    assert_eq!(reader.get_string(), Err(ProtocolError::InvalidString));
+    let mut reader = Reader::new(&[0x01, 0xE9, 0x00]);
+    assert_eq!(reader.get_string().unwrap(), "é");
+
+    // Long strings are truncated, without splitting surrogate pairs
+    let mut writer = Writer::new();
+    writer.put_string(&format!("{}\u{1F600}", "a".repeat(253)));
+    let bytes = writer.into_bytes();
+    assert_eq!(bytes[0], 254);
+    assert_eq!(Reader::new(&bytes).get_string().unwrap(), "a".repeat(253));
+
+    // An array that claims more elements than there are bytes
+    let mut reader = Reader::new(&[0xFF, 0xFF, 0xFF, 0x0F, 0x01, 0x00]);
+    assert_eq!(reader.get_u16_array(), Err(ProtocolError::Truncated));
+
+    let mut reader = Reader::new(&[0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00]);
+    assert_eq!(reader.get_value(DataType::ArrayUint32).unwrap(), Value::Array(vec![Value::U32(1), Value::U32(2)]));
+}
+
+#[test]
+fn device_info() {
+    let fixture = [
+        0x64, 0x00,                                     // standard version 1.00
+        0x06, 0x00, 0x00, 0x00,                         // vendor extension: Microsoft
+        0x64, 0x00,                                     // vendor extension version
+        0x05, b'm', 0x00, b't', 0x00, b'p', 0x00, b':', 0x00, 0x00, 0x00,
+        0x00, 0x00,                                     // functional mode
+        0x02, 0x00, 0x00, 0x00, 0x01, 0x10, 0x02, 0x10, // operations
+        0x01, 0x00, 0x00, 0x00, 0x02, 0x40,             // events
+        0x00, 0x00, 0x00, 0x00,                         // device properties
+        0x00, 0x00, 0x00, 0x00,                         // capture formats
+        0x01, 0x00, 0x00, 0x00, 0x09, 0x30,             // playback formats
+        0x02, b'A', 0x00, 0x00, 0x00,                   // manufacturer
+        0x02, b'B', 0x00, 0x00, 0x00,                   // model
+        0x02, b'1', 0x00, 0x00, 0x00,                   // version
+        0x00,                                           // serial number
+    ];
+
+    let info = DeviceInfo::decode(&fixture).unwrap();
+    assert_eq!(info, DeviceInfo{
+        standard_version: 100,
+        vendor_extension_id: 6,
+        vendor_extension_version: 100,
+        vendor_extension_desc: "mtp:".to_string(),
+        functional_mode: 0,
+        operations_supported: vec![0x1001, 0x1002],
+        events_supported: vec![0x4002],
+        device_properties_supported: vec![],
+        capture_formats: vec![],
+        playback_formats: vec![ObjectFormatCode::Mp3.as_u16()],
+        manufacturer: "A".to_string(),
+        model: "B".to_string(),
+        device_version: "1".to_string(),
+        serial_number: String::new(),
+    });
+    assert_eq!(info.encode(), fixture);
+
+    assert_eq!(DeviceInfo::decode(&fixture[..30]), Err(ProtocolError::Truncated));
+}
+
+#[test]
+fn storage_info() {
+    let fixture = [
+        0x03, 0x00,  0x02, 0x00,  0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,   // 4 GiB
+        0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00,   // 2 GiB
+        0xFF, 0xFF, 0xFF, 0xFF,
+        0x02, b'X', 0x00, 0x00, 0x00,
+        0x00,
+    ];
+    let info = StorageInfo::decode(&fixture).unwrap();
+    assert_eq!(info.storage_type, 3);
+    assert_eq!(info.filesystem_type, 2);
+    assert_eq!(info.max_capacity, 4 << 30);
+    assert_eq!(info.free_space_in_bytes, 2 << 30);
+    assert_eq!(info.free_space_in_objects, u32::MAX);
+    assert_eq!(info.storage_description, "X");
+    assert_eq!(info.encode(), fixture);
+}
+
+#[test]
+fn object_info() {
+    let info = ObjectInfo{
+        storage_id: 0x10001,
+        object_format: ObjectFormatCode::Mp3,
+        object_compressed_size: 1234,
+        parent_object: 0x2C,
+        filename: "song.mp3".to_string(),
+        date_modified: "20240229T235959".to_string(),
+        ..Default::default()
+    };
+    let bytes = info.encode();
+    assert_eq!(&bytes[..8], [0x01, 0x00, 0x01, 0x00,  0x09, 0x30,  0x00, 0x00]);
+    assert_eq!(ObjectInfo::decode(&bytes).unwrap(), info);
+
+    // Some devices omit the trailing strings
+    let filename_end = bytes.len() - 1 - 1 - (1 + 16 * 2);
+    let truncated = ObjectInfo::decode(&bytes[..filename_end]).unwrap();
+    assert_eq!(truncated.filename, "song.mp3");
+    assert_eq!(truncated.date_modified, "");
+}
+
+#[test]
+fn object_prop_desc() {
+    // Hidden: UINT16, read-write, default 0, enumeration of 0 and 1
+    let fixture = [
+        0x0D, 0xDC,  0x04, 0x00,  0x01,  0x00, 0x00,  0x00, 0x00, 0x00, 0x00,
+        0x02,  0x02, 0x00,  0x00, 0x00,  0x01, 0x00,
+    ];
+    let desc = ObjectPropDesc::decode(&fixture).unwrap();
+    assert_eq!(desc, ObjectPropDesc{
+        property_code: ObjectPropertyCode::Hidden,
+        data_type: DataType::Uint16,
+        writable: true,
+        default_value: Value::U16(0),
+        group_code: 0,
+        form: PropertyForm::Enumeration(vec![Value::U16(0), Value::U16(1)]),
+    });
+    assert_eq!(desc.encode(), fixture);
+
+    // Object size: UINT64, read-only, range
+    let desc = ObjectPropDesc{
+        property_code: ObjectPropertyCode::ObjectSize,
+        data_type: DataType::Uint64,
+        writable: false,
+        default_value: Value::U64(0),
+        group_code: 0,
+        form: PropertyForm::Range{ min: Value::U64(0), max: Value::U64(u64::MAX), step: Value::U64(1) },
+    };
+    assert_eq!(ObjectPropDesc::decode(&desc.encode()).unwrap(), desc);
+
+    // File name: string, with a regular expression
+    let desc = ObjectPropDesc{
+        property_code: ObjectPropertyCode::ObjectFileName,
+        data_type: DataType::String,
+        writable: true,
+        default_value: Value::Str(String::new()),
+        group_code: 0,
+        form: PropertyForm::RegularExpression("[^/]+".to_string()),
+    };
+    assert_eq!(ObjectPropDesc::decode(&desc.encode()).unwrap(), desc);
+}
+
+#[test]
+fn device_prop_desc() {
+    // Battery level: UINT8, read-only, 50% out of a 0 to 100 range
+    let fixture = [
+        0x01, 0x50,  0x02, 0x00,  0x00,  0x64,  0x32,
+        0x01,  0x00,  0x64,  0x01,
+    ];
+    let desc = DevicePropDesc::decode(&fixture).unwrap();
+    assert_eq!(desc, DevicePropDesc{
+        property_code: DevicePropertyCode::BatteryLevel,
+        data_type: DataType::Uint8,
+        writable: false,
+        factory_default_value: Value::U8(100),
+        current_value: Value::U8(50),
+        form: PropertyForm::Range{ min: Value::U8(0), max: Value::U8(100), step: Value::U8(1) },
+    });
+    assert_eq!(desc.encode(), fixture);
+}
+
+#[test]
+fn dates() {
+    let leap_day = UNIX_EPOCH + Duration::from_secs(1_709_251_199);
+    assert_eq!(parse_datetime("20240229T235959").unwrap(), leap_day);
+    assert_eq!(parse_datetime("20240229T235959.5Z").unwrap(), leap_day);
+    assert_eq!(parse_datetime("20240301T015959+0200").unwrap(), leap_day);
+    assert_eq!(format_datetime(leap_day), "20240229T235959");
+    assert_eq!(format_datetime(UNIX_EPOCH - Duration::from_secs(1)), "19691231T235959");
+
+    assert!(parse_datetime("2024-02-29").is_err());
+    assert!(parse_datetime("20241329T000000").is_err());
+    assert!(parse_datetime("20240231T000000").is_err());
+    assert!(parse_datetime("20230229T000000").is_err());
+    assert!(parse_datetime("20240431T000000").is_err());
+    // Whether this is representable depends on the platform, but it never panics
+    let _ = parse_datetime("00000101T000000");
+    assert!(parse_datetime("").is_err());
+}
+
+#[test]
+fn mapping_to_wpd_concepts() {
+    assert_eq!(mapping::object_id_from_handle(0x2C), u16cstr!("o2C"));
+    assert_eq!(mapping::handle_from_object_id(u16cstr!("o2C")), Some(0x2C));
+    assert_eq!(mapping::handle_from_object_id(u16cstr!("s10001")), None);
+    assert_eq!(mapping::storage_id_from_object_id(u16cstr!("s10001")), Some(0x10001));
+    assert_eq!(mapping::storage_id_from_object_id(u16cstr!("DEVICE")), None);
+
+    assert_eq!(ObjectFormatCode::Mp3.as_guid(), WPD_OBJECT_FORMAT_MP3);
+    assert_eq!(ObjectFormatCode::from_guid(WPD_OBJECT_FORMAT_PROPERTIES_ONLY), Some(ObjectFormatCode::Association));
+    assert_eq!(ObjectFormatCode::Association.object_type(), ObjectType::Folder);
+    assert_eq!(ObjectFormatCode::ExifJpeg.object_type(), ObjectType::Image);
+    assert_eq!(ObjectFormatCode::M3uPlaylist.object_type(), ObjectType::Playlist);
+
+    assert_eq!(ObjectPropertyCode::ObjectSize.property_key(), Some(WPD_OBJECT_SIZE));
+    assert_eq!(ObjectPropertyCode::from_property_key(&WPD_OBJECT_PARENT_ID), Some(ObjectPropertyCode::ParentObject));
+    assert_eq!(
+        ObjectPropertyCode::ParentObject.to_property_value(&Value::U32(0x2C)),
+        Some(PropertyValue::String(mapping::object_id_from_handle(0x2C)))
+    );
+    assert_eq!(ObjectPropertyCode::ParentObject.to_property_value(&Value::U32(0)), None);
+    let parent_value = |id: &str| ObjectPropertyCode::ParentObject.to_value(&PropertyValue::String(widestring::U16CString::from_str_truncate(id)));
+    assert_eq!(parent_value("o2C"), Some(Value::U32(0x2C)));
+    assert_eq!(parent_value("s10001"), Some(Value::U32(0)));
+    assert_eq!(parent_value("not an ID"), None);
+    assert_eq!(
+        ObjectPropertyCode::DateModified.to_value(&PropertyValue::Date(UNIX_EPOCH)),
+        Some(Value::Str("19700101T000000".to_string()))
+    );
+    assert_eq!(
+        ObjectPropertyCode::PersistentUniqueObjectIdentifier.to_property_value(&Value::U128(0xAB)),
+        Some(PropertyValue::String(widestring::U16CString::from_str_truncate("000000000000000000000000000000AB")))
+    );
+}
+
+#[test]
+fn ptpip_packets() {
+    // InitCommandRequest from "ab"
+    let mut fixture = vec![0x22, 0x00, 0x00, 0x00,  0x01, 0x00, 0x00, 0x00];
+    fixture.extend_from_slice(b"winmtp-initiator");
+    fixture.extend_from_slice(&[b'a', 0x00, b'b', 0x00, 0x00, 0x00,  0x00, 0x00, 0x01, 0x00]);
+    let request = Packet::InitCommandRequest{ guid: *b"winmtp-initiator", friendly_name: "ab".to_string(), protocol_version: PROTOCOL_VERSION };
+    assert_eq!(request.encode(), fixture);
+    assert_eq!(Packet::decode(&fixture).unwrap(), request);
+
+    // GetObject(handle 3), transaction 5, with a data phase from the responder
+    let fixture = [
+        0x16, 0x00, 0x00, 0x00,  0x06, 0x00, 0x00, 0x00,  0x01, 0x00, 0x00, 0x00,  0x09, 0x10,
+        0x05, 0x00, 0x00, 0x00,  0x03, 0x00, 0x00, 0x00,
+    ];
+    let command = Container::command(OperationCode::GetObject, 5, &[3]);
+    let packet = Packet::operation_request(&command, DataPhase::NoDataOrDataIn).unwrap();
+    assert_eq!(packet.encode(), fixture);
+    assert_eq!(Packet::decode(&fixture).unwrap().to_container().unwrap(), command);
+
+    let response = Container::response(ResponseCode::Ok, 5, &[]);
+    let packet = Packet::from_container(&response).unwrap();
+    assert_eq!(packet, Packet::OperationResponse{ response: 0x2001, transaction_id: 5, params: Vec::new() });
+    assert_eq!(Packet::decode(&packet.encode()).unwrap().to_container().unwrap(), response);
+
+    let end_data = Packet::EndData{ transaction_id: 5, payload: b"data".to_vec() };
+    let mut stream: &[u8] = &[Packet::InitEventAck.encode(), end_data.encode()].concat();
+    assert_eq!(Packet::read_from(&mut stream).unwrap(), Packet::InitEventAck);
+    assert_eq!(Packet::read_from(&mut stream).unwrap(), end_data);
+    assert!(stream.is_empty());
+    // Packets longer than allowed are rejected before their payload is read, truncated ones are detected
+    let encoded = end_data.encode();
+    assert_eq!(Packet::read_from_limited(&mut &encoded[..], encoded.len() - 1).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(Packet::read_from(&mut &encoded[..encoded.len() - 1]).unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+
+    assert!(matches!(Packet::decode(&[0x08, 0x00, 0x00, 0x00,  0x20, 0x00, 0x00, 0x00]), Err(ProtocolError::InvalidPacketType(0x20))));
+    assert!(matches!(Packet::decode(&[0x09, 0x00, 0x00, 0x00,  0x0D, 0x00, 0x00, 0x00]), Err(ProtocolError::InvalidLength(9))));
+    // Parameters are 32-bit wide
+    assert!(Packet::decode(&[0x0C, 0x00, 0x00, 0x00,  0x07, 0x00, 0x00, 0x00,  0x01, 0x20, 0x05, 0x00]).is_err());
+}
@@ -0,0 +1,95 @@
+//! Checks of the PTP/IP connection setup, against the responder of this crate and against misbehaving peers
+
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::rc::Rc;
+
+use winmtp::Provider;
+use winmtp::backend::memory::MemoryDevice;
+use winmtp::backend::ptpip::{PtpIpProvider, PtpIpTransport, DEFAULT_INITIATOR_GUID};
+use winmtp::error::{MtpError, ProtocolError};
+use winmtp::protocol::ptpip::{Packet, PROTOCOL_VERSION};
+use winmtp::responder::Responder;
+
+#[test]
+fn devices_are_added_by_address() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let server = std::thread::spawn(move || {
+        let device = MemoryDevice::new("Camera");
+        let storage_id = device.add_storage("SD card");
+        device.add_file(&storage_id, "IMG_0001.JPG", b"not really a JPEG").unwrap();
+        let mut responder = Responder::new(Rc::new(device), "ACME", "Camera");
+        responder.serve_ptpip(&listener).unwrap();
+    });
+
+    let ptpip_provider = Rc::new(PtpIpProvider::new());
+    ptpip_provider.add_device(&address);
+    ptpip_provider.add_device(&address);
+    let mut provider = Provider::empty();
+    provider.add_backend(Rc::clone(&ptpip_provider) as _);
+
+    let devices = provider.enumerate_devices().unwrap();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].device_id(), format!("ptpip:{}", address));
+    {
+        let app_identifiers = winmtp::make_current_app_identifiers!();
+        let content = devices[0].open(&app_identifiers, true).unwrap().content().unwrap();
+        let picture = content.root().unwrap().object_by_path(Path::new("SD card/IMG_0001.JPG")).unwrap();
+        assert_eq!(picture.name().to_string_lossy(), "IMG_0001.JPG");
+    }
+    server.join().unwrap();
+
+    ptpip_provider.remove_device(&address);
+    assert!(provider.enumerate_devices().unwrap().is_empty());
+}
+
+#[test]
+fn handshake() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let server = std::thread::spawn(move || {
+        // A responder that turns initiators down
+        let (mut stream, _) = listener.accept().unwrap();
+        assert!(matches!(Packet::read_from(&mut stream).unwrap(), Packet::InitCommandRequest{ friendly_name, .. } if friendly_name == "tester"));
+        Packet::InitFail{ reason: 0x1 }.write_to(&mut stream).unwrap();
+
+        // A responder that accepts them, then sends an event
+        let (mut command_channel, _) = listener.accept().unwrap();
+        Packet::read_from(&mut command_channel).unwrap();
+        Packet::InitCommandAck{ connection_number: 7, guid: [0; 16], friendly_name: "Camera".to_string(), protocol_version: PROTOCOL_VERSION }
+            .write_to(&mut command_channel).unwrap();
+        let (mut event_channel, _) = listener.accept().unwrap();
+        assert_eq!(Packet::read_from(&mut event_channel).unwrap(), Packet::InitEventRequest{ connection_number: 7 });
+        Packet::InitEventAck.write_to(&mut event_channel).unwrap();
+        Packet::ProbeRequest.write_to(&mut event_channel).unwrap();
+        assert_eq!(Packet::read_from(&mut event_channel).unwrap(), Packet::ProbeResponse);
+        Packet::Event{ event: 0x4002, transaction_id: 0, params: vec![12] }.write_to(&mut event_channel).unwrap();
+    });
+
+    let refused = PtpIpTransport::connect(&address, DEFAULT_INITIATOR_GUID, "tester");
+    assert!(matches!(refused, Err(MtpError::Protocol(ProtocolError::ConnectionRefused(0x1)))));
+
+    let mut transport = PtpIpTransport::connect(&address, DEFAULT_INITIATOR_GUID, "tester").unwrap();
+    assert_eq!(transport.connection_number(), 7);
+    assert_eq!(transport.responder_name(), "Camera");
+    let event = transport.receive_event().unwrap();
+    assert_eq!(event.event_code().as_u16(), 0x4002);
+    assert_eq!(event.params().unwrap(), [12]);
+    server.join().unwrap();
+}
+
+#[test]
+fn responder_rejects_other_protocols() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let mut responder = Responder::new(Rc::new(MemoryDevice::new("Camera")), "ACME", "Camera");
+        responder.serve_ptpip(&listener)
+    });
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    Packet::ProbeRequest.write_to(&mut stream).unwrap();
+    assert!(matches!(Packet::read_from(&mut stream).unwrap(), Packet::InitFail{ .. }));
+    assert!(server.join().unwrap().is_err());
+}
@@ -0,0 +1,251 @@
+//! Checks of the MTP responder: local folders are served over TCP, and talked to by the MTP backend of this crate
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::rc::Rc;
+
+use widestring::U16CString;
+
+use winmtp::PortableDevices::{WPD_OBJECT_PERSISTENT_UNIQUE_ID, WPD_OBJECT_SIZE};
+use winmtp::backend::directory::DirectoryDevice;
+use winmtp::backend::memory::MemoryDevice;
+use winmtp::backend::mtp::{MtpDevice, StreamTransport, Transport, ALL};
+use winmtp::device::BasicDevice;
+use winmtp::error::ErrorKind;
+use winmtp::protocol::codes::{ObjectPropertyCode, OperationCode, ResponseCode};
+use winmtp::protocol::container::{Container, ContainerType};
+use winmtp::protocol::data::Reader;
+use winmtp::responder::Responder;
+
+const SONG: &[u8] = b"not really an mp3";
+
+#[test]
+fn serve_a_directory_over_tcp() {
+    let root = std::env::temp_dir().join(format!("winmtp-responder-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("Music")).unwrap();
+    fs::write(root.join("Music/song.mp3"), SONG).unwrap();
+    set_readonly(&root.join("Music/song.mp3"), true);
+    fs::write(root.join("notes.txt"), b"some notes").unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let served_root = root.clone();
+    let server = std::thread::spawn(move || {
+        let device = DirectoryDevice::new("Shared folder");
+        device.add_storage("Shared", served_root);
+        let mut responder = Responder::new(Rc::new(device), "winmtp", "Shared folder");
+        // One connection per test session below
+        for _ in 0..2 {
+            let (stream, _) = listener.accept().unwrap();
+            stream.set_nodelay(true).unwrap();
+            responder.serve(stream).unwrap();
+        }
+    });
+
+    let backend = MtpDevice::new(move || {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        Ok(Box::new(StreamTransport::new(stream)) as Box<dyn Transport>)
+    });
+    let basic_device = BasicDevice::new(U16CString::from_str_truncate("tcp"), "Shared folder over TCP".to_string(), Rc::new(backend));
+    let app_identifiers = winmtp::make_current_app_identifiers!();
+
+    {
+        let device = basic_device.open(&app_identifiers, true).unwrap();
+        let content = device.content().unwrap();
+        let storage = content.root().unwrap().object_by_path(Path::new("Shared")).unwrap();
+
+        let song = storage.object_by_path(Path::new("Music/song.mp3")).unwrap();
+        let mut data = Vec::new();
+        song.open_read_stream().unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, SONG);
+        let properties = song.properties(&[WPD_OBJECT_SIZE, WPD_OBJECT_PERSISTENT_UNIQUE_ID]).unwrap();
+        assert_eq!(properties.get_u64(&WPD_OBJECT_SIZE).unwrap(), SONG.len() as u64);
+        assert!(properties.get_string(&WPD_OBJECT_PERSISTENT_UNIQUE_ID).is_ok());
+        let metadata = song.metadata().unwrap();
+        assert_eq!(metadata.size, Some(SONG.len() as u64));
+        assert_eq!(metadata.is_read_only, Some(true));
+        assert_eq!(metadata.can_delete, Some(true));
+        assert_eq!(storage.object_by_path(Path::new("notes.txt")).unwrap().metadata().unwrap().is_read_only, Some(false));
+
+        let music = storage.object_by_path(Path::new("Music")).unwrap();
+        // Local folders cannot be hidden
+        assert_eq!(music.set_hidden(true).unwrap()[0].error_kind(), Some(ErrorKind::AccessDenied));
+        music.push_data(OsStr::new("pushed.txt"), b"pushed over TCP", false).unwrap();
+        assert_eq!(fs::read(root.join("Music/pushed.txt")).unwrap(), b"pushed over TCP");
+
+        let archive_id = storage.create_subfolder(OsStr::new("Archive")).unwrap();
+        assert!(root.join("Archive").is_dir());
+        storage.object_by_path(Path::new("notes.txt")).unwrap().move_to(&archive_id).unwrap();
+        assert!(root.join("Archive/notes.txt").is_file());
+        assert!(!root.join("notes.txt").exists());
+
+        content.object_by_id(archive_id).unwrap().delete(true).unwrap();
+        assert!(!root.join("Archive").exists());
+    }
+
+    {
+        // Closing the connection closed the session, a new one can be opened
+        let device = basic_device.open(&app_identifiers, true).unwrap();
+        let storage = device.content().unwrap().root().unwrap().object_by_path(Path::new("Shared")).unwrap();
+        let mut names: Vec<_> = storage.children().unwrap().map(|child| child.name().to_string_lossy()).collect();
+        names.sort();
+        assert_eq!(names, ["Music"]);
+    }
+
+    server.join().unwrap();
+    set_readonly(&root.join("Music/song.mp3"), false);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+fn set_readonly(path: &Path, readonly: bool) {
+    let mut permissions = fs::metadata(path).unwrap().permissions();
+    permissions.set_readonly(readonly);
+    fs::set_permissions(path, permissions).unwrap();
+}
+
+fn transaction(responder: &mut Responder, transaction_id: u32, operation: OperationCode, params: &[u32], data: Option<Vec<u8>>) -> Vec<Container> {
+    let mut replies = responder.process(Container::command(operation, transaction_id, params));
+    if let Some(data) = data {
+        assert!(replies.is_empty());
+        replies = responder.process(Container::data(operation, transaction_id, data));
+    }
+    assert_eq!(replies.last().unwrap().container_type, ContainerType::Response);
+    replies
+}
+
+fn response_code(replies: &[Container]) -> ResponseCode {
+    replies.last().unwrap().response_code()
+}
+
+#[test]
+fn protocol_errors() {
+    let device = MemoryDevice::new("Fake phone");
+    let storage_id = device.add_storage("Internal shared storage");
+    let folder_id = device.add_folder(&storage_id, "Download").unwrap();
+    device.add_file(&folder_id, "a.txt", b"a").unwrap();
+    device.add_file(&storage_id, "b.txt", b"b").unwrap();
+    let mut responder = Responder::new(Rc::new(device), "winmtp", "Fake phone");
+
+    assert_eq!(response_code(&transaction(&mut responder, 0, OperationCode::GetDeviceInfo, &[], None)), ResponseCode::Ok);
+    assert_eq!(response_code(&transaction(&mut responder, 1, OperationCode::GetStorageIds, &[], None)), ResponseCode::SessionNotOpen);
+    assert_eq!(response_code(&transaction(&mut responder, 0, OperationCode::OpenSession, &[0], None)), ResponseCode::InvalidParameter);
+    assert_eq!(response_code(&transaction(&mut responder, 0, OperationCode::OpenSession, &[1], None)), ResponseCode::Ok);
+    assert_eq!(response_code(&transaction(&mut responder, 0, OperationCode::OpenSession, &[1], None)), ResponseCode::SessionAlreadyOpen);
+
+    // Every object, whatever its depth, or only the ones at the root of the storage
+    let replies = transaction(&mut responder, 1, OperationCode::GetObjectHandles, &[ALL, 0, 0], None);
+    assert_eq!(Reader::new(&replies[0].payload).get_u32_array().unwrap().len(), 3);
+    let replies = transaction(&mut responder, 2, OperationCode::GetObjectHandles, &[0x10001, 0, ALL], None);
+    assert_eq!(Reader::new(&replies[0].payload).get_u32_array().unwrap().len(), 2);
+    let replies = transaction(&mut responder, 3, OperationCode::GetNumObjects, &[ALL, 0x3001, 0], None);
+    assert_eq!(replies[0].params().unwrap(), [1]);
+
+    assert_eq!(response_code(&transaction(&mut responder, 4, OperationCode::GetStorageInfo, &[0x20001], None)), ResponseCode::InvalidStorageId);
+    assert_eq!(response_code(&transaction(&mut responder, 5, OperationCode::GetObjectInfo, &[0x1234], None)), ResponseCode::InvalidObjectHandle);
+    assert_eq!(response_code(&transaction(&mut responder, 6, OperationCode::SendObject, &[], Some(b"orphan".to_vec()))), ResponseCode::NoValidObjectInfo);
+    assert_eq!(response_code(&transaction(&mut responder, 7, OperationCode::GetObjectPropDesc, &[0xDCFF, 0], None)), ResponseCode::InvalidObjectPropCode);
+    let size_code = ObjectPropertyCode::ObjectSize.as_u16() as u32;
+    assert_eq!(response_code(&transaction(&mut responder, 8, OperationCode::SetObjectPropValue, &[1, size_code], Some(vec![0; 8]))), ResponseCode::AccessDenied);
+    assert_eq!(response_code(&transaction(&mut responder, 9, OperationCode::CopyObject, &[1, 0x10001, 0], None)), ResponseCode::OperationNotSupported);
+    // Datasets are gathered in memory, so they cannot be arbitrarily large
+    let huge_dataset = vec![0; 2 * 1024 * 1024];
+    assert_eq!(response_code(&transaction(&mut responder, 10, OperationCode::SendObjectInfo, &[0x10001, 0], Some(huge_dataset))), ResponseCode::InvalidDataset);
+
+    assert_eq!(response_code(&transaction(&mut responder, 11, OperationCode::CloseSession, &[], None)), ResponseCode::Ok);
+    assert_eq!(response_code(&transaction(&mut responder, 12, OperationCode::GetObjectInfo, &[1], None)), ResponseCode::SessionNotOpen);
+}
+
+#[test]
+fn partial_objects() {
+    let device = MemoryDevice::new("Fake phone");
+    let storage_id = device.add_storage("Internal shared storage");
+    let content: Vec<u8> = (0..100).collect();
+    device.add_file(&storage_id, "partial.bin", &content).unwrap();
+    let mut responder = Responder::new(Rc::new(device), "winmtp", "Fake phone");
+    assert_eq!(response_code(&transaction(&mut responder, 0, OperationCode::OpenSession, &[1], None)), ResponseCode::Ok);
+    let replies = transaction(&mut responder, 1, OperationCode::GetObjectHandles, &[ALL, 0, 0], None);
+    let handle = Reader::new(&replies[0].payload).get_u32_array().unwrap()[0];
+
+    // Initiators may ask for up to 4 GiB, only what the object holds is sent
+    let replies = transaction(&mut responder, 2, OperationCode::GetPartialObject, &[handle, 90, u32::MAX], None);
+    assert_eq!(replies[0].payload, &content[90..]);
+    assert_eq!(replies[1].params().unwrap(), [10]);
+    let replies = transaction(&mut responder, 3, OperationCode::GetPartialObject64, &[handle, 95, 0, u32::MAX], None);
+    assert_eq!(replies[0].payload, &content[95..]);
+    assert_eq!(replies[1].params().unwrap(), [5]);
+    let replies = transaction(&mut responder, 4, OperationCode::GetPartialObject, &[handle, 200, 16], None);
+    assert!(replies[0].payload.is_empty());
+    assert_eq!(replies[1].params().unwrap(), [0]);
+}
+
+/// What an initiator sent, and what the responder answered
+struct Wire {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl Read for Wire {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for Wire {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn serve_bogus_lengths() {
+    let device = MemoryDevice::new("Fake phone");
+    device.add_storage("Internal shared storage");
+    let mut responder = Responder::new(Rc::new(device), "winmtp", "Fake phone");
+    let serve = |responder: &mut Responder, containers: &[Container], trailer: &[u8]| {
+        let mut input = Vec::new();
+        for container in containers {
+            input.extend_from_slice(&container.encode());
+        }
+        input.extend_from_slice(trailer);
+        let mut wire = Wire{ input: Cursor::new(input), output: Vec::new() };
+        let result = responder.serve(&mut wire);
+        let mut output = Cursor::new(wire.output);
+        let mut replies = Vec::new();
+        while let Ok(reply) = Container::read_from(&mut output) {
+            replies.push(reply);
+        }
+        (result, replies)
+    };
+
+    // Commands only have a few parameters: a huge one is rejected before its payload is read
+    let mut huge_command = Container::command(OperationCode::GetDeviceInfo, 0, &[]).encode();
+    huge_command[..4].copy_from_slice(&0x7FFF_FFF0u32.to_le_bytes());
+    let (result, replies) = serve(&mut responder, &[], &huge_command);
+    assert!(result.is_err());
+    assert!(replies.is_empty());
+
+    // Data phases are read as they arrive, whatever length they claim
+    let open_session = Container::command(OperationCode::OpenSession, 0, &[1]);
+    let send_object_info = Container::command(OperationCode::SendObjectInfo, 1, &[0x10001, 0]);
+    let mut huge_data = Container::data(OperationCode::SendObjectInfo, 1, vec![0; 16]).encode();
+    huge_data[..4].copy_from_slice(&0x7FFF_FFF0u32.to_le_bytes());
+    let (result, replies) = serve(&mut responder, &[open_session.clone(), send_object_info], &huge_data);
+    assert!(result.is_err());
+    assert_eq!(response_code(&replies), ResponseCode::Ok);
+
+    // Data phases of unknown length cannot be told apart from the next container
+    let send_object_info = Container::command(OperationCode::SendObjectInfo, 1, &[0x10001, 0]);
+    let mut unknown_data = Container::data(OperationCode::SendObjectInfo, 1, vec![0; 16]).encode();
+    unknown_data[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+    let (result, _) = serve(&mut responder, &[open_session, send_object_info], &unknown_data);
+    assert!(result.is_err());
+}
@@ -0,0 +1,144 @@
+//! Checks of storage info (capacity, free space, case sensitivity, etc.), on an in-memory device, directly or through an MTP responder
+
+mod common;
+
+use std::ffi::OsStr;
+use std::path::Path;
+
+use winmtp::backend::memory::MemoryDevice;
+use winmtp::device::{BasicDevice, StorageAccess, StorageType};
+use winmtp::error::ErrorKind;
+
+use common::{fake_phone, loopback_device, memory_device};
+
+const CAPACITY: u64 = 1 << 20;
+
+fn emulated_device() -> MemoryDevice {
+    let (device, internal_id) = fake_phone();
+    device.set_storage_capacity(&internal_id, CAPACITY).unwrap();
+    device.add_file(&internal_id, "notes.txt", &[b'a'; 1000]).unwrap();
+    device.add_storage("SD card");
+    device
+}
+
+#[test]
+fn storages_in_memory() {
+    check_storages(&memory_device(emulated_device()), None);
+}
+
+#[test]
+fn storages_through_mtp_responder() {
+    check_storages(&loopback_device(emulated_device()), Some("Generic hierarchical"));
+}
+
+fn check_storages(basic_device: &BasicDevice, filesystem_type: Option<&str>) {
+    let device = basic_device.open(&winmtp::make_current_app_identifiers!(), true).unwrap();
+    let content = device.content().unwrap();
+    let mut storages = content.storages().unwrap();
+    assert_eq!(storages.len(), 2);
+
+    let internal = &mut storages[0];
+    assert_eq!(internal.name().to_string_lossy(), "Internal shared storage");
+    assert_eq!(internal.storage_type, StorageType::FixedRam);
+    assert_eq!(internal.access, Some(StorageAccess::ReadWrite));
+    assert!(!internal.is_read_only());
+    assert_eq!(internal.filesystem_type.as_deref(), filesystem_type);
+    assert_eq!(internal.capacity, Some(CAPACITY));
+    assert_eq!(internal.free_bytes, Some(CAPACITY - 1000));
+    assert!(internal.has_room_for(CAPACITY - 1000));
+    assert!(!internal.has_room_for(CAPACITY));
+
+    // Storages can be browsed
+    let notes = internal.object().children().unwrap().next().unwrap();
+    assert_eq!(notes.name().to_string_lossy(), "notes.txt");
+
+    // Free space is only updated on demand
+    internal.object().push_data(OsStr::new("photo.jpg"), &[0; 24], false).unwrap();
+    assert_eq!(internal.free_bytes, Some(CAPACITY - 1000));
+    internal.refresh().unwrap();
+    assert_eq!(internal.free_bytes, Some(CAPACITY - 1024));
+
+    // Storages of unknown capacity have room for anything
+    let sd_card = &storages[1];
+    assert_eq!(sd_card.name().to_string_lossy(), "SD card");
+    assert_eq!(sd_card.capacity, None);
+    assert_eq!(sd_card.free_bytes, None);
+    assert!(sd_card.has_room_for(u64::MAX));
+}
+
+fn device_with_case_insensitive_storage() -> MemoryDevice {
+    let (device, internal_id) = fake_phone();
+    device.set_storage_case_insensitive(&internal_id).unwrap();
+    device.add_file(&internal_id, "notes.txt", b"hello").unwrap();
+    let sd_card_id = device.add_storage("SD card");
+    device.add_file(&sd_card_id, "notes.txt", b"hello").unwrap();
+    device
+}
+
+#[test]
+fn case_sensitivity_in_memory() {
+    check_case_sensitivity(&memory_device(device_with_case_insensitive_storage()));
+}
+
+#[test]
+fn case_sensitivity_through_mtp_responder() {
+    check_case_sensitivity(&loopback_device(device_with_case_insensitive_storage()));
+}
+
+fn check_case_sensitivity(basic_device: &BasicDevice) {
+    let device = basic_device.open(&winmtp::make_current_app_identifiers!(), true).unwrap();
+    let content = device.content().unwrap();
+    let storages = content.storages().unwrap();
+    let (internal, sd_card) = (&storages[0], &storages[1]);
+    assert_eq!(internal.case_sensitivity_hint(), None);
+
+    // Nothing is probed unless asked to
+    assert!(content.case_sensitive_fs_for(internal.id()).unwrap());
+    assert!(internal.object().object_by_path(Path::new("NOTES.TXT")).is_err());
+
+    assert!(!content.detect_case_sensitivity(internal).unwrap());
+    assert!(content.detect_case_sensitivity(sd_card).unwrap());
+    // Probe folders are gone
+    assert_eq!(internal.object().children().unwrap().count(), 1);
+    assert_eq!(sd_card.object().children().unwrap().count(), 1);
+
+    // Paths are now resolved the way each storage expects, including from other handles on the content
+    assert!(!content.case_sensitive_fs_for(internal.id()).unwrap());
+    let notes = content.root().unwrap().object_by_path(Path::new("Internal shared storage/NOTES.TXT")).unwrap();
+    assert!(!content.case_sensitive_fs_for(notes.id()).unwrap());
+    assert!(sd_card.object().object_by_path(Path::new("NOTES.TXT")).is_err());
+    assert_eq!(internal.object().create_subfolder(OsStr::new("NOTES.txt")).unwrap_err().kind(), ErrorKind::AlreadyExists);
+}
+
+#[test]
+fn case_sensitivity_per_storage() {
+    let content = common::open_content(&memory_device(device_with_case_insensitive_storage()));
+    let storages = content.storages().unwrap();
+    let (internal, sd_card) = (&storages[0], &storages[1]);
+
+    internal.set_case_sensitive_fs(false);
+    assert!(!internal.case_sensitive_fs().unwrap());
+    assert!(sd_card.case_sensitive_fs().unwrap());
+    // Overrides win over detection
+    assert!(!content.detect_case_sensitivity(internal).unwrap());
+
+    // Paths are resolved with the semantics of the storage they lead to, wherever they start from
+    let root = content.root().unwrap();
+    assert!(root.object_by_path(Path::new("Internal shared storage/NOTES.TXT")).is_ok());
+    assert!(root.object_by_path(Path::new("SD card/NOTES.TXT")).is_err());
+    internal.object().create_subfolder_recursive(Path::new("Music/Albums")).unwrap();
+    internal.object().create_subfolder_recursive(Path::new("MUSIC/albums/Live")).unwrap();
+    assert!(internal.object().object_by_path(Path::new("music/ALBUMS/live")).is_ok());
+
+    // Existing files are found whatever their case, so they can be replaced
+    assert_eq!(internal.object().push_data(OsStr::new("NOTES.TXT"), b"hi", false).unwrap_err().kind(), ErrorKind::AlreadyExists);
+    internal.object().push_data(OsStr::new("NOTES.TXT"), b"hi", true).unwrap();
+    assert_eq!(internal.object().children().unwrap().filter(|child| child.name().to_string_lossy().eq_ignore_ascii_case("notes.txt")).count(), 1);
+    sd_card.object().push_data(OsStr::new("NOTES.TXT"), b"hi", false).unwrap();
+    assert_eq!(sd_card.object().children().unwrap().count(), 2);
+
+    // Failing to find the storage of an object is not mistaken for the default
+    let notes = internal.object().object_by_path(Path::new("notes.txt")).unwrap();
+    notes.delete(false).unwrap();
+    assert_eq!(content.case_sensitive_fs_for(notes.id()).unwrap_err().kind(), ErrorKind::NotFound);
+}
@@ -0,0 +1,274 @@
+//! Checks of the synchronisation of device folders with local directories
+
+mod common;
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use winmtp::PortableDevices::{WPD_OBJECT_SIZE, WPD_OBJECT_DATE_MODIFIED, WPD_OBJECT_PERSISTENT_UNIQUE_ID, WPD_OBJECT_PARENT_ID};
+use winmtp::backend::memory::MemoryDevice;
+use winmtp::device::BasicDevice;
+use winmtp::error::ErrorKind;
+use winmtp::io::CancellationToken;
+use winmtp::sync::{ComparePolicy, SyncAction, SyncDirection, SyncMode, SyncOptions, SyncPlan, SyncState};
+
+use common::{fake_phone, loopback_device, memory_device, open_content};
+
+fn phone_with_photos() -> MemoryDevice {
+    let (device, storage_id) = fake_phone();
+    let dcim_id = device.add_folder(&storage_id, "DCIM").unwrap();
+    let camera_id = device.add_folder(&dcim_id, "Camera").unwrap();
+    device.add_file(&camera_id, "a.jpg", b"photo a").unwrap();
+    device.add_file(&camera_id, "b.jpg", b"photo b").unwrap();
+    let thumbnails_id = device.add_folder(&dcim_id, ".thumbnails").unwrap();
+    device.add_file(&thumbnails_id, "a.jpg", b"thumbnail").unwrap();
+    device.add_folder(&storage_id, "Backup").unwrap();
+    device
+}
+
+fn local_dir(name: &str) -> PathBuf {
+    common::local_dir("sync", name)
+}
+
+fn options<'a>() -> SyncOptions<'a> {
+    SyncOptions::new().exclude(".thumbnails")
+}
+
+fn paths(plan: &SyncPlan, action: SyncAction) -> Vec<String> {
+    plan.entries.iter()
+        .filter(|entry| entry.action == action)
+        .map(|entry| entry.path.to_string_lossy().replace('\\', "/"))
+        .collect()
+}
+
+fn check_sync(basic_device: &BasicDevice, name: &str) {
+    let content = open_content(basic_device);
+    let storage = content.root().unwrap().object_by_path(Path::new("Internal shared storage")).unwrap();
+    let dcim = storage.object_by_path(Path::new("DCIM")).unwrap();
+    let camera = dcim.object_by_path(Path::new("Camera")).unwrap();
+    let backup_dir = local_dir(&format!("{}-pull", name));
+
+    // A dry run does not modify anything
+    let report = winmtp::sync::sync(SyncDirection::Pull, &dcim, &backup_dir, options().dry_run(true)).unwrap();
+    assert_eq!(paths(&report.plan, SyncAction::Create), ["Camera", "Camera/a.jpg", "Camera/b.jpg"]);
+    assert_eq!(report.plan.bytes_to_transfer(), 14);
+    assert!(report.done.is_empty());
+    assert!(!backup_dir.exists());
+
+    let report = winmtp::sync::sync(SyncDirection::Pull, &dcim, &backup_dir, options()).unwrap();
+    assert!(report.is_success(), "{:?}", report.failed);
+    assert_eq!(std::fs::read(backup_dir.join("Camera/b.jpg")).unwrap(), b"photo b");
+    assert!(!backup_dir.join(".thumbnails").exists());
+    let plan = winmtp::sync::plan(SyncDirection::Pull, &dcim, &backup_dir, &options()).unwrap();
+    assert_eq!(plan.changes().count(), 0);
+    assert_eq!(plan.unchanged().count(), 3);
+
+    // Changes on the device
+    camera.object_by_path(Path::new("a.jpg")).unwrap().delete(false).unwrap();
+    camera.push_data(OsStr::new("b.jpg"), b"photo b, edited", true).unwrap();
+    camera.push_data(OsStr::new("c.jpg"), b"photo c", false).unwrap();
+    std::fs::write(backup_dir.join("local_only.txt"), b"kept unless mirroring").unwrap();
+
+    let plan = winmtp::sync::plan(SyncDirection::Pull, &dcim, &backup_dir, &options()).unwrap();
+    assert_eq!(paths(&plan, SyncAction::Create), ["Camera/c.jpg"]);
+    assert_eq!(paths(&plan, SyncAction::Update), ["Camera/b.jpg"]);
+    assert!(paths(&plan, SyncAction::Delete).is_empty());
+
+    let mut last_progress = None;
+    let mut record_progress = |progress: &winmtp::io::Progress| last_progress = Some(*progress);
+    let plan = winmtp::sync::plan(SyncDirection::Pull, &dcim, &backup_dir, &options().mode(SyncMode::Mirror)).unwrap();
+    assert_eq!(paths(&plan, SyncAction::Delete), ["Camera/a.jpg", "local_only.txt"]);
+    let report = plan.execute(options().mode(SyncMode::Mirror).progress(&mut record_progress)).unwrap();
+    assert!(report.is_success(), "{:?}", report.failed);
+    assert_eq!(report.done.len(), 4);
+    assert_eq!(last_progress.unwrap().bytes_done, 22);
+    assert_eq!(last_progress.unwrap().bytes_total, Some(22));
+    assert!(!backup_dir.join("Camera/a.jpg").exists());
+    assert!(!backup_dir.join("local_only.txt").exists());
+    assert_eq!(std::fs::read(backup_dir.join("Camera/b.jpg")).unwrap(), b"photo b, edited");
+    let plan = winmtp::sync::plan(SyncDirection::Pull, &dcim, &backup_dir, &options().mode(SyncMode::Mirror)).unwrap();
+    assert_eq!(plan.changes().count(), 0);
+
+    // Push the backup to another folder of the device
+    let device_backup = storage.object_by_path(Path::new("Backup")).unwrap();
+    let report = winmtp::sync::sync(SyncDirection::Push, &device_backup, &backup_dir, SyncOptions::new()).unwrap();
+    assert!(report.is_success(), "{:?}", report.failed);
+    assert_eq!(device_backup.object_by_path(Path::new("Camera/c.jpg")).unwrap().read_range(0, 1024).unwrap(), b"photo c");
+    let plan = winmtp::sync::plan(SyncDirection::Push, &device_backup, &backup_dir, &SyncOptions::new()).unwrap();
+    assert_eq!(plan.changes().count(), 0);
+
+    // A file that has been touched is only considered as changed when comparing dates
+    let touched = std::fs::File::options().write(true).open(backup_dir.join("Camera/c.jpg")).unwrap();
+    touched.set_modified(SystemTime::now() + Duration::from_secs(3600)).unwrap();
+    let plan = winmtp::sync::plan(SyncDirection::Push, &device_backup, &backup_dir, &SyncOptions::new().compare(ComparePolicy::Size)).unwrap();
+    assert_eq!(plan.changes().count(), 0);
+    let plan = winmtp::sync::plan(SyncDirection::Push, &device_backup, &backup_dir, &SyncOptions::new()).unwrap();
+    assert_eq!(paths(&plan, SyncAction::Update), ["Camera/c.jpg"]);
+
+    // Mirroring deletes what is missing locally
+    std::fs::remove_dir_all(backup_dir.join("Camera")).unwrap();
+    let report = winmtp::sync::sync(SyncDirection::Push, &device_backup, &backup_dir, SyncOptions::new().mode(SyncMode::Mirror)).unwrap();
+    assert!(report.is_success(), "{:?}", report.failed);
+    assert_eq!(paths(&report.plan, SyncAction::Delete), ["Camera", "Camera/b.jpg", "Camera/c.jpg"]);
+    assert_eq!(device_backup.children().unwrap().count(), 0);
+
+    let _ = std::fs::remove_dir_all(&backup_dir);
+}
+
+fn moves(plan: &SyncPlan) -> Vec<(String, String)> {
+    plan.moved()
+        .map(|entry| (
+            entry.moved_from.as_ref().unwrap().to_string_lossy().replace('\\', "/"),
+            entry.path.to_string_lossy().replace('\\', "/"),
+        ))
+        .collect()
+}
+
+fn check_sync_state(basic_device: &BasicDevice, name: &str) {
+    let content = open_content(basic_device);
+    let storage = content.root().unwrap().object_by_path(Path::new("Internal shared storage")).unwrap();
+    let dcim = storage.object_by_path(Path::new("DCIM")).unwrap();
+    let camera = dcim.object_by_path(Path::new("Camera")).unwrap();
+    let backup_dir = local_dir(&format!("{}-state", name));
+    let state_file = local_dir(&format!("{}-state.json", name));
+
+    let mut state = SyncState::load(&state_file).unwrap();
+    assert!(state.is_empty());
+    let report = winmtp::sync::sync_with_state(&dcim, &backup_dir, &mut state, options()).unwrap();
+    assert!(report.is_success(), "{:?}", report.failed);
+    assert_eq!(state.len(), 3);
+    let (key, _) = state.iter().find(|(_, entry)| entry.local_path == "Camera/a.jpg").unwrap();
+    let key = key.to_string();
+    state.get_mut(&key).unwrap().hash = Some("hash of a".to_string());
+    state.save(&state_file).unwrap();
+    let mut state = SyncState::load(&state_file).unwrap();
+    assert_eq!(state.get(&key).unwrap().hash.as_deref(), Some("hash of a"));
+
+    // Objects renamed or moved on the device are moved locally, without being pulled again
+    let mut a = camera.object_by_path(Path::new("a.jpg")).unwrap();
+    a.rename(OsStr::new("renamed.jpg")).unwrap();
+    let moved_id = dcim.create_subfolder(OsStr::new("Moved")).unwrap();
+    camera.object_by_path(Path::new("b.jpg")).unwrap().move_to(&moved_id).unwrap();
+    let plan = winmtp::sync::plan_with_state(&dcim, &backup_dir, &state, &options()).unwrap();
+    assert_eq!(moves(&plan), [("Camera/a.jpg".to_string(), "Camera/renamed.jpg".to_string()), ("Camera/b.jpg".to_string(), "Moved/b.jpg".to_string())]);
+    assert_eq!(paths(&plan, SyncAction::Create), ["Moved"]);
+    assert_eq!(plan.bytes_to_transfer(), 0);
+
+    // Local copies that were about to be moved are put back when the synchronisation stops early
+    let cancellation = CancellationToken::new();
+    cancellation.cancel();
+    let err = winmtp::sync::sync_with_state(&dcim, &backup_dir, &mut state, options().cancellation(cancellation)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Cancelled);
+    assert_eq!(std::fs::read(backup_dir.join("Camera/a.jpg")).unwrap(), b"photo a");
+    assert_eq!(std::fs::read(backup_dir.join("Camera/b.jpg")).unwrap(), b"photo b");
+    assert_eq!(std::fs::read_dir(&backup_dir).unwrap().count(), 1);
+
+    let report = winmtp::sync::sync_with_state(&dcim, &backup_dir, &mut state, options()).unwrap();
+    assert!(report.is_success(), "{:?}", report.failed);
+    assert_eq!(std::fs::read(backup_dir.join("Camera/renamed.jpg")).unwrap(), b"photo a");
+    assert_eq!(std::fs::read(backup_dir.join("Moved/b.jpg")).unwrap(), b"photo b");
+    assert!(!backup_dir.join("Camera/a.jpg").exists());
+    assert!(!backup_dir.join("Camera/b.jpg").exists());
+    assert_eq!(state.get(&key).unwrap().local_path, "Camera/renamed.jpg");
+    assert_eq!(state.get(&key).unwrap().hash.as_deref(), Some("hash of a"));
+    assert_eq!(winmtp::sync::plan_with_state(&dcim, &backup_dir, &state, &options()).unwrap().changes().count(), 0);
+
+    // Only the local copies of deleted objects are deleted when mirroring, and replaced objects are pulled again
+    camera.object_by_path(Path::new("renamed.jpg")).unwrap().delete(false).unwrap();
+    let moved = dcim.object_by_path(Path::new("Moved")).unwrap();
+    moved.push_data(OsStr::new("b.jpg"), b"photo b, edited", true).unwrap();
+    std::fs::write(backup_dir.join("local_only.txt"), b"never pulled").unwrap();
+    let plan = winmtp::sync::plan_with_state(&dcim, &backup_dir, &state, &options().mode(SyncMode::Mirror)).unwrap();
+    assert_eq!(paths(&plan, SyncAction::Delete), ["Camera/renamed.jpg"]);
+    assert_eq!(paths(&plan, SyncAction::Update), ["Moved/b.jpg"]);
+    let report = winmtp::sync::sync_with_state(&dcim, &backup_dir, &mut state, options().mode(SyncMode::Mirror)).unwrap();
+    assert!(report.is_success(), "{:?}", report.failed);
+    assert!(!backup_dir.join("Camera/renamed.jpg").exists());
+    assert!(backup_dir.join("local_only.txt").exists());
+    assert_eq!(std::fs::read(backup_dir.join("Moved/b.jpg")).unwrap(), b"photo b, edited");
+    assert_eq!(state.len(), 3);
+    assert!(state.get(&key).is_none());
+
+    let _ = std::fs::remove_dir_all(&backup_dir);
+    let _ = std::fs::remove_file(&state_file);
+}
+
+#[test]
+fn sync_in_memory() {
+    check_sync(&memory_device(phone_with_photos()), "memory");
+}
+
+#[test]
+fn sync_state_in_memory() {
+    check_sync_state(&memory_device(phone_with_photos()), "memory");
+}
+
+#[test]
+fn sync_through_mtp_responder() {
+    check_sync(&loopback_device(phone_with_photos()), "emulated");
+}
+
+#[test]
+fn sync_state_through_mtp_responder() {
+    check_sync_state(&loopback_device(phone_with_photos()), "emulated");
+}
+
+#[test]
+fn children_properties_through_mtp_responder() {
+    let content = open_content(&loopback_device(phone_with_photos()));
+    let dcim = content.root().unwrap().object_by_path(Path::new("Internal shared storage/DCIM")).unwrap();
+    let camera = dcim.object_by_path(Path::new("Camera")).unwrap();
+
+    // Read with a single `GetObjectPropList`, they must match what is read object by object
+    let keys = [WPD_OBJECT_SIZE, WPD_OBJECT_DATE_MODIFIED, WPD_OBJECT_PERSISTENT_UNIQUE_ID, WPD_OBJECT_PARENT_ID];
+    for folder in [&dcim, &camera] {
+        let children = folder.children_with_properties(&keys).unwrap();
+        let expected: Vec<_> = folder.children().unwrap().collect();
+        assert_eq!(children.len(), expected.len());
+        for ((child, values), expected) in children.iter().zip(&expected) {
+            assert_eq!(child.id(), expected.id());
+            assert_eq!(child.name(), expected.name());
+            assert_eq!(child.object_type(), expected.object_type());
+            let expected_values = expected.properties(&keys).unwrap();
+            for key in &keys {
+                assert_eq!(values.get(key), expected_values.get(key));
+            }
+        }
+    }
+}
+
+#[test]
+fn unsafe_names_are_not_synced() {
+    let (device, storage_id) = fake_phone();
+    let folder_id = device.add_folder(&storage_id, "Music").unwrap();
+    device.add_file(&folder_id, "../escaped.txt", b"outside").unwrap();
+    device.add_folder(&folder_id, "..").unwrap();
+    device.add_file(&folder_id, "song.mp3", b"inside").unwrap();
+    let content = open_content(&memory_device(device));
+    let music = content.root().unwrap().object_by_path(Path::new("Internal shared storage/Music")).unwrap();
+
+    let parent_dir = local_dir("unsafe");
+    let backup_dir = parent_dir.join("backup");
+    std::fs::create_dir_all(&backup_dir).unwrap();
+    std::fs::write(parent_dir.join("keep.txt"), b"outside the backup").unwrap();
+    let report = winmtp::sync::sync(SyncDirection::Pull, &music, &backup_dir, SyncOptions::new().mode(SyncMode::Mirror)).unwrap();
+    assert_eq!(report.failed.len(), 2);
+    assert!(report.failed.iter().all(|(path, err)| path.is_relative() && err.kind() == winmtp::error::ErrorKind::InvalidArgument));
+    assert_eq!(report.done, [PathBuf::from("song.mp3")]);
+    assert!(!parent_dir.join("escaped.txt").exists());
+    assert_eq!(std::fs::read(parent_dir.join("keep.txt")).unwrap(), b"outside the backup");
+
+    let _ = std::fs::remove_dir_all(&parent_dir);
+}
+
+#[test]
+fn unsafe_state_paths_are_rejected() {
+    let state_file = local_dir("unsafe-state.json");
+    for local_path in ["../outside.txt", "/etc/passwd", "Camera//a.jpg"] {
+        let content = format!(r#"{{"version": 1, "entries": {{"key": {{"local_path": {:?}, "is_folder": false, "size": 1, "modified": null, "hash": null}}}}}}"#, local_path);
+        std::fs::write(&state_file, content).unwrap();
+        assert_eq!(SyncState::load(&state_file).unwrap_err().kind(), winmtp::error::ErrorKind::InvalidArgument, "{}", local_path);
+    }
+    let _ = std::fs::remove_file(&state_file);
+}
@@ -0,0 +1,147 @@
+//! Checks of the copies of whole folder trees
+
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use winmtp::backend::memory::MemoryDevice;
+use winmtp::device::BasicDevice;
+use winmtp::error::ErrorKind;
+use winmtp::io::{CancellationToken, Progress};
+use winmtp::object::{OverwritePolicy, SkipReason, SymlinkPolicy, TreeOptions, TreeOutcome, TreeReport};
+
+use common::{fake_phone, loopback_device, memory_device, open_content};
+
+fn phone_with_photos() -> MemoryDevice {
+    let (device, storage_id) = fake_phone();
+    let dcim_id = device.add_folder(&storage_id, "DCIM").unwrap();
+    let camera_id = device.add_folder(&dcim_id, "Camera").unwrap();
+    device.add_file(&camera_id, "IMG_0001.jpg", b"first photo").unwrap();
+    device.add_file(&camera_id, "IMG_0002.JPG", b"second photo").unwrap();
+    let thumbnails_id = device.add_folder(&camera_id, ".thumbnails").unwrap();
+    device.add_file(&thumbnails_id, "IMG_0001.jpg", b"thumbnail").unwrap();
+    device.add_file(&dcim_id, "notes.txt", b"not a photo").unwrap();
+    device.add_folder(&storage_id, "Upload").unwrap();
+    device
+}
+
+/// A local folder that is emptied first
+fn local_dir(name: &str) -> PathBuf {
+    let path = common::local_dir("tree", name);
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+fn skip_reason(report: &TreeReport, path: &str) -> Option<SkipReason> {
+    report.entries.iter()
+        .find(|entry| entry.path == Path::new(path))
+        .and_then(|entry| match entry.outcome {
+            TreeOutcome::Skipped(reason) => Some(reason),
+            _ => None,
+        })
+}
+
+fn check_trees(basic_device: &BasicDevice, name: &str) {
+    let content = open_content(basic_device);
+    let storage = content.root().unwrap().object_by_path(Path::new("Internal shared storage")).unwrap();
+    let dcim = storage.object_by_path(Path::new("DCIM")).unwrap();
+
+    // Pull photos, except thumbnails
+    let backup_dir = local_dir(&format!("{}-pull", name));
+    let mut reports: Vec<Progress> = Vec::new();
+    let mut record_progress = |progress: &Progress| reports.push(*progress);
+    let options = TreeOptions::new().include("*.jpg").exclude(".thumbnails").progress(&mut record_progress);
+    let report = dcim.pull_tree(&backup_dir, options).unwrap();
+    assert!(report.is_success(), "{:?}", report);
+    assert_eq!(report.copied().filter(|entry| !entry.is_folder).count(), 2);
+    assert_eq!(report.bytes_copied(), 23);
+    assert_eq!(skip_reason(&report, "notes.txt"), Some(SkipReason::Excluded));
+    assert_eq!(skip_reason(&report, "Camera/.thumbnails"), Some(SkipReason::Excluded));
+    assert_eq!(std::fs::read(backup_dir.join("Camera/IMG_0002.JPG")).unwrap(), b"second photo");
+    assert!(!backup_dir.join("notes.txt").exists());
+    assert!(!backup_dir.join("Camera/.thumbnails").exists());
+    assert!(reports.iter().all(|progress| progress.bytes_total == Some(23)));
+    assert_eq!(reports.last().unwrap().bytes_done, 23);
+    assert!(reports.windows(2).all(|pair| pair[0].bytes_done <= pair[1].bytes_done));
+
+    // Existing files are skipped, unless they are to be overwritten
+    std::fs::write(backup_dir.join("Camera/IMG_0001.jpg"), b"modified").unwrap();
+    let report = dcim.pull_tree(&backup_dir, TreeOptions::new().include("*.jpg").exclude(".thumbnails")).unwrap();
+    assert_eq!(skip_reason(&report, "Camera/IMG_0001.jpg"), Some(SkipReason::AlreadyExists));
+    assert_eq!(std::fs::read(backup_dir.join("Camera/IMG_0001.jpg")).unwrap(), b"modified");
+    let report = dcim.pull_tree(&backup_dir, TreeOptions::new().overwrite(OverwritePolicy::Overwrite)).unwrap();
+    assert_eq!(report.copied().filter(|entry| !entry.is_folder).count(), 4);
+    assert_eq!(std::fs::read(backup_dir.join("Camera/IMG_0001.jpg")).unwrap(), b"first photo");
+
+    // Push a bundle
+    let bundle_dir = local_dir(&format!("{}-push", name));
+    std::fs::create_dir_all(bundle_dir.join("books/drafts")).unwrap();
+    std::fs::write(bundle_dir.join("books/novel.epub"), b"a novel").unwrap();
+    std::fs::write(bundle_dir.join("books/drafts/draft.epub"), b"a draft").unwrap();
+    std::fs::write(bundle_dir.join("readme.txt"), b"read me").unwrap();
+    std::fs::write(bundle_dir.join("Thumbs.db"), b"junk").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(bundle_dir.join("books"), bundle_dir.join("linked_books")).unwrap();
+
+    let upload = storage.object_by_path(Path::new("Upload")).unwrap();
+    let report = upload.push_tree(&bundle_dir, TreeOptions::new().exclude("thumbs.db")).unwrap();
+    assert!(report.is_success(), "{:?}", report);
+    assert_eq!(report.copied().filter(|entry| !entry.is_folder).count(), 3);
+    assert_eq!(skip_reason(&report, "Thumbs.db"), Some(SkipReason::Excluded));
+    let draft = upload.object_by_path(Path::new("books/drafts/draft.epub")).unwrap();
+    assert_eq!(draft.read_range(0, 100).unwrap(), b"a draft");
+    assert!(upload.object_by_path(Path::new("Thumbs.db")).is_err());
+
+    // Pushing again skips everything, and links can be followed
+    #[cfg(unix)]
+    {
+        assert_eq!(skip_reason(&report, "linked_books"), Some(SkipReason::Symlink));
+        let report = upload.push_tree(&bundle_dir, TreeOptions::new().exclude("thumbs.db").symlinks(SymlinkPolicy::Follow)).unwrap();
+        assert_eq!(skip_reason(&report, "books/novel.epub"), Some(SkipReason::AlreadyExists));
+        assert!(report.copied().any(|entry| entry.path == Path::new("linked_books/drafts/draft.epub")));
+        upload.object_by_path(Path::new("linked_books/novel.epub")).unwrap();
+    }
+
+    // Cancelled copies stop at once
+    let cancellation = CancellationToken::new();
+    cancellation.cancel();
+    let result = dcim.pull_tree(&local_dir(&format!("{}-cancelled", name)), TreeOptions::new().cancellation(cancellation));
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::Cancelled);
+
+    let _ = std::fs::remove_dir_all(&backup_dir);
+    let _ = std::fs::remove_dir_all(&bundle_dir);
+}
+
+#[test]
+fn trees_in_memory() {
+    check_trees(&memory_device(phone_with_photos()), "memory");
+}
+
+#[test]
+fn trees_through_mtp_responder() {
+    check_trees(&loopback_device(phone_with_photos()), "emulated");
+}
+
+#[test]
+fn unsafe_names_are_not_pulled() {
+    let (device, storage_id) = fake_phone();
+    let folder_id = device.add_folder(&storage_id, "Music").unwrap();
+    device.add_file(&folder_id, "../escaped.txt", b"outside").unwrap();
+    device.add_file(&folder_id, "/tmp/absolute.txt", b"outside").unwrap();
+    device.add_folder(&folder_id, "..").unwrap();
+    device.add_file(&folder_id, "song.mp3", b"inside").unwrap();
+    let content = open_content(&memory_device(device));
+    let music = content.root().unwrap().object_by_path(Path::new("Internal shared storage/Music")).unwrap();
+
+    let parent_dir = local_dir("unsafe");
+    let backup_dir = parent_dir.join("backup");
+    let report = music.pull_tree(&backup_dir, TreeOptions::new()).unwrap();
+    assert_eq!(report.failed().count(), 3);
+    assert!(report.failed().all(|entry| matches!(&entry.outcome, TreeOutcome::Failed(err) if err.kind() == ErrorKind::InvalidArgument)));
+    assert!(report.failed().all(|entry| entry.path.is_relative()));
+    assert_eq!(std::fs::read(backup_dir.join("song.mp3")).unwrap(), b"inside");
+    assert!(!parent_dir.join("escaped.txt").exists());
+    assert!(!Path::new("/tmp/absolute.txt").exists());
+
+    let _ = std::fs::remove_dir_all(&parent_dir);
+}
@@ -0,0 +1,487 @@
+//! Checks of the USB backend, without any hardware: devices are discovered in a fake sysfs tree,
+//! and MTP traffic goes through an emulated phone, that behaves like a pair of bulk endpoints.
+
+#![cfg(target_os = "linux")]
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use widestring::U16CString;
+
+use winmtp::backend::mtp::{DataReader, MtpDevice, Transport};
+use winmtp::backend::usb::{BulkPipe, BulkTransport, UsbProvider};
+use winmtp::backend::ProviderBackend;
+use winmtp::device::BasicDevice;
+use winmtp::error::{ErrorKind, MtpError, RawErrorCode};
+use winmtp::object::ObjectType;
+use winmtp::protocol::codes::{ObjectFormatCode, OperationCode, ResponseCode};
+use winmtp::protocol::container::{Container, ContainerHeader, ContainerType};
+use winmtp::protocol::data::Writer;
+use winmtp::protocol::datasets::{DeviceInfo, ObjectInfo, StorageInfo};
+
+const MAX_PACKET_SIZE: usize = 512;
+const STORAGE_ID: u32 = 0x10001;
+
+#[derive(Clone)]
+struct PhoneObject {
+    parent: u32,
+    name: String,
+    format: ObjectFormatCode,
+    data: Vec<u8>,
+}
+
+#[derive(Default)]
+struct PhoneState {
+    /// Bytes of the container that is being received
+    received: Vec<u8>,
+    /// A command that waits for its data phase
+    pending_command: Option<Container>,
+    /// The object that will receive the data of the next SendObject
+    pending_object: Option<u32>,
+    /// Packets that wait to be read by the initiator
+    packets: VecDeque<Vec<u8>>,
+    zero_length_packets_received: usize,
+    objects: BTreeMap<u32, PhoneObject>,
+    next_handle: u32,
+}
+
+/// A phone that answers the basic MTP operations, packet by packet
+#[derive(Clone, Default)]
+struct EmulatedPhone(Rc<RefCell<PhoneState>>);
+
+impl EmulatedPhone {
+    fn new() -> Self {
+        let phone = Self::default();
+        phone.0.borrow_mut().next_handle = 1;
+        phone
+    }
+
+    fn add(&self, parent: u32, name: &str, format: ObjectFormatCode, data: &[u8]) -> u32 {
+        let mut state = self.0.borrow_mut();
+        let handle = state.next_handle;
+        state.next_handle += 1;
+        state.objects.insert(handle, PhoneObject{ parent, name: name.to_string(), format, data: data.to_vec() });
+        handle
+    }
+
+    fn find(&self, name: &str) -> Option<(u32, PhoneObject)> {
+        self.0.borrow().objects.iter().find(|(_, object)| object.name == name).map(|(handle, object)| (*handle, object.clone()))
+    }
+
+    fn device(&self) -> BasicDevice {
+        let phone = self.clone();
+        let backend = MtpDevice::new(move || Ok(Box::new(BulkTransport::new(phone.clone())) as Box<dyn Transport>));
+        BasicDevice::new(U16CString::from_str_truncate("usb:001:002"), "Emulated phone".to_string(), Rc::new(backend))
+    }
+}
+
+impl PhoneState {
+    fn handle_container(&mut self, container: Container) {
+        match container.container_type {
+            ContainerType::Command => match container.operation_code() {
+                OperationCode::SendObjectInfo | OperationCode::SendObject => self.pending_command = Some(container),
+                _ => self.answer(&container, None),
+            },
+            ContainerType::Data => {
+                let command = self.pending_command.take().expect("data phase without any command");
+                self.answer(&command, Some(container.payload));
+            },
+            other => panic!("Unexpected {:?} container", other),
+        }
+    }
+
+    fn answer(&mut self, command: &Container, data: Option<Vec<u8>>) {
+        let (code, params, data_in) = self.run(command, data);
+        if let Some(data_in) = data_in {
+            self.queue(Container::data(command.operation_code(), command.transaction_id, data_in));
+        }
+        self.queue(Container::response(code, command.transaction_id, &params));
+    }
+
+    fn queue(&mut self, container: Container) {
+        let bytes = container.encode();
+        self.packets.extend(bytes.chunks(MAX_PACKET_SIZE).map(|packet| packet.to_vec()));
+        if bytes.len().is_multiple_of(MAX_PACKET_SIZE) {
+            self.packets.push_back(Vec::new());
+        }
+    }
+
+    fn run(&mut self, command: &Container, data: Option<Vec<u8>>) -> (ResponseCode, Vec<u32>, Option<Vec<u8>>) {
+        let params = command.params().unwrap();
+        let param = |i: usize| params.get(i).copied().unwrap_or(0);
+        match command.operation_code() {
+            OperationCode::GetDeviceInfo => {
+                let info = DeviceInfo{
+                    standard_version: 100,
+                    operations_supported: [
+                        OperationCode::GetDeviceInfo, OperationCode::OpenSession, OperationCode::CloseSession,
+                        OperationCode::GetStorageIds, OperationCode::GetStorageInfo, OperationCode::GetObjectHandles,
+                        OperationCode::GetObjectInfo, OperationCode::GetObject, OperationCode::SendObjectInfo,
+                        OperationCode::SendObject, OperationCode::DeleteObject, OperationCode::MoveObject,
+                    ].iter().map(|op| op.as_u16()).collect(),
+                    manufacturer: "Emulated".to_string(),
+                    model: "Phone".to_string(),
+                    ..Default::default()
+                };
+                (ResponseCode::Ok, vec![], Some(info.encode()))
+            },
+            OperationCode::OpenSession | OperationCode::CloseSession => (ResponseCode::Ok, vec![], None),
+            OperationCode::GetStorageIds => {
+                let mut writer = Writer::new();
+                writer.put_u32_array(&[STORAGE_ID]);
+                (ResponseCode::Ok, vec![], Some(writer.into_bytes()))
+            },
+            OperationCode::GetStorageInfo => {
+                let info = StorageInfo{
+                    storage_type: 3,
+                    filesystem_type: 2,
+                    max_capacity: 1 << 30,
+                    free_space_in_bytes: 1 << 29,
+                    storage_description: "Internal shared storage".to_string(),
+                    ..Default::default()
+                };
+                (ResponseCode::Ok, vec![], Some(info.encode()))
+            },
+            OperationCode::GetObjectHandles => {
+                let parent = if param(2) == 0xFFFF_FFFF { 0 } else { param(2) };
+                let handles: Vec<u32> = self.objects.iter().filter(|(_, object)| object.parent == parent).map(|(handle, _)| *handle).collect();
+                let mut writer = Writer::new();
+                writer.put_u32_array(&handles);
+                (ResponseCode::Ok, vec![], Some(writer.into_bytes()))
+            },
+            OperationCode::GetObjectInfo => match self.objects.get(&param(0)) {
+                None => (ResponseCode::InvalidObjectHandle, vec![], None),
+                Some(object) => {
+                    let info = ObjectInfo{
+                        storage_id: STORAGE_ID,
+                        object_format: object.format,
+                        object_compressed_size: object.data.len() as u32,
+                        parent_object: object.parent,
+                        association_type: if object.format == ObjectFormatCode::Association { 1 } else { 0 },
+                        filename: object.name.clone(),
+                        date_modified: "20240229T120000".to_string(),
+                        ..Default::default()
+                    };
+                    (ResponseCode::Ok, vec![], Some(info.encode()))
+                },
+            },
+            OperationCode::GetObject => match self.objects.get(&param(0)) {
+                None => (ResponseCode::InvalidObjectHandle, vec![], None),
+                Some(object) => (ResponseCode::Ok, vec![], Some(object.data.clone())),
+            },
+            OperationCode::SendObjectInfo => {
+                let info = ObjectInfo::decode(&data.unwrap()).unwrap();
+                let parent = if param(1) == 0xFFFF_FFFF { 0 } else { param(1) };
+                let handle = self.next_handle;
+                self.next_handle += 1;
+                self.objects.insert(handle, PhoneObject{ parent, name: info.filename, format: info.object_format, data: Vec::new() });
+                if info.object_format != ObjectFormatCode::Association {
+                    self.pending_object = Some(handle);
+                }
+                (ResponseCode::Ok, vec![STORAGE_ID, param(1), handle], None)
+            },
+            OperationCode::SendObject => match self.pending_object.take() {
+                None => (ResponseCode::NoValidObjectInfo, vec![], None),
+                Some(handle) => {
+                    self.objects.get_mut(&handle).unwrap().data = data.unwrap();
+                    (ResponseCode::Ok, vec![], None)
+                },
+            },
+            OperationCode::DeleteObject => {
+                if self.objects.remove(&param(0)).is_none() {
+                    return (ResponseCode::InvalidObjectHandle, vec![], None);
+                }
+                // Remove orphans, until there is none left
+                loop {
+                    let orphans: Vec<u32> = self.objects.iter()
+                        .filter(|(_, object)| object.parent != 0 && !self.objects.contains_key(&object.parent))
+                        .map(|(handle, _)| *handle)
+                        .collect();
+                    if orphans.is_empty() {
+                        break;
+                    }
+                    for orphan in orphans {
+                        self.objects.remove(&orphan);
+                    }
+                }
+                (ResponseCode::Ok, vec![], None)
+            },
+            OperationCode::MoveObject => match self.objects.get_mut(&param(0)) {
+                None => (ResponseCode::InvalidObjectHandle, vec![], None),
+                Some(object) => {
+                    object.parent = param(2);
+                    (ResponseCode::Ok, vec![], None)
+                },
+            },
+            _ => (ResponseCode::OperationNotSupported, vec![], None),
+        }
+    }
+}
+
+impl BulkPipe for EmulatedPhone {
+    fn read_bulk(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut state = self.0.borrow_mut();
+        if state.packets.is_empty() {
+            return Err(std::io::ErrorKind::TimedOut.into());
+        }
+        // Like a real host controller, concatenate packets until a short one, or until the buffer is full
+        let mut len = 0;
+        while let Some(packet) = state.packets.front() {
+            if len + packet.len() > buf.len() {
+                break;
+            }
+            let packet = state.packets.pop_front().unwrap();
+            buf[len..len + packet.len()].copy_from_slice(&packet);
+            len += packet.len();
+            if packet.len() < MAX_PACKET_SIZE {
+                break;
+            }
+        }
+        Ok(len)
+    }
+
+    fn write_bulk(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let mut state = self.0.borrow_mut();
+        if data.is_empty() {
+            state.zero_length_packets_received += 1;
+            return Ok(0);
+        }
+        state.received.extend_from_slice(data);
+        while let Ok(header) = ContainerHeader::decode(&state.received) {
+            if state.received.len() < header.length as usize {
+                break;
+            }
+            let bytes: Vec<u8> = state.received.drain(..header.length as usize).collect();
+            state.handle_container(Container::decode(&bytes).unwrap());
+        }
+        Ok(data.len())
+    }
+
+    fn max_packet_size(&self) -> usize {
+        MAX_PACKET_SIZE
+    }
+}
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[test]
+fn browse_and_transfer() {
+    let phone = EmulatedPhone::new();
+    let dcim = phone.add(0, "DCIM", ObjectFormatCode::Association, &[]);
+    phone.add(dcim, "photo.jpg", ObjectFormatCode::ExifJpeg, &pattern(3000));
+    phone.add(0, "Download", ObjectFormatCode::Association, &[]);
+
+    let app_identifiers = winmtp::make_current_app_identifiers!();
+    let device = phone.device().open(&app_identifiers, true).unwrap();
+    let content = device.content().unwrap();
+
+    let storages = content.functional_objects().unwrap();
+    assert_eq!(storages.len(), 1);
+    let storage = &storages[0];
+    assert_eq!(storage.name().to_string_lossy(), "Internal shared storage");
+    let root_names: Vec<String> = storage.children().unwrap().map(|child| child.name().to_string_lossy()).collect();
+    assert_eq!(root_names, ["DCIM", "Download"]);
+
+    // Reading
+    let photo = storage.object_by_path(Path::new("DCIM/photo.jpg")).unwrap();
+    assert_eq!(photo.object_type(), ObjectType::Image);
+    let mut pulled = Vec::new();
+    photo.open_read_stream().unwrap().read_to_end(&mut pulled).unwrap();
+    assert_eq!(pulled, pattern(3000));
+    // Without partial reads, the object is read up to the end of the range, the rest of it is skipped
+    assert_eq!(photo.read_range(1000, 64).unwrap(), &pattern(3000)[1000..1064]);
+    assert!(photo.read_range(5000, 64).unwrap().is_empty());
+
+    // Writing: 500 bytes of data make a 512-byte container, that is terminated by a zero-length packet
+    let download = storage.object_by_path(Path::new("Download")).unwrap();
+    download.push_data("exact.bin".as_ref(), &pattern(500), false).unwrap();
+    assert_eq!(phone.0.borrow().zero_length_packets_received, 1);
+    assert_eq!(phone.find("exact.bin").unwrap().1.data, pattern(500));
+    // Reading it back makes the phone send a zero-length packet too
+    let mut pulled = Vec::new();
+    download.object_by_path(Path::new("exact.bin")).unwrap().open_read_stream().unwrap().read_to_end(&mut pulled).unwrap();
+    assert_eq!(pulled, pattern(500));
+
+    // Many transfers
+    download.push_data("large.bin".as_ref(), &pattern(100_000), false).unwrap();
+    let mut pulled = Vec::new();
+    download.object_by_path(Path::new("large.bin")).unwrap().open_read_stream().unwrap().read_to_end(&mut pulled).unwrap();
+    assert_eq!(pulled, pattern(100_000));
+    assert!(matches!(download.push_data("large.bin".as_ref(), b"", false), Err(winmtp::error::MtpError::AlreadyExists)));
+
+    // Uploads are streamed: the data reaches the phone as it is written
+    let mut stream = download.create_write_stream("streamed.bin".as_ref(), 100_000, false).unwrap();
+    stream.get_mut().write_all(&pattern(100_000)[..60_000]).unwrap();
+    assert!(phone.0.borrow().received.len() > 40_000);
+    // Nothing else can happen meanwhile
+    assert_eq!(download.object_by_path(Path::new("large.bin")).unwrap_err().kind(), ErrorKind::Busy);
+    stream.get_mut().write_all(&pattern(100_000)[60_000..]).unwrap();
+    stream.flush().unwrap();
+    assert_eq!(phone.find("streamed.bin").unwrap().1.data, pattern(100_000));
+
+    // Uploads that are given up midway are completed with zeros, then deleted
+    let mut stream = download.create_write_stream("aborted.bin".as_ref(), 100_000, false).unwrap();
+    stream.get_mut().write_all(&pattern(30_000)).unwrap();
+    stream.get_mut().abort().unwrap();
+    assert!(phone.find("aborted.bin").is_none());
+    let mut stream = download.create_write_stream("dropped.bin".as_ref(), 100_000, false).unwrap();
+    stream.get_mut().write_all(&pattern(30_000)).unwrap();
+    drop(stream);
+    assert!(phone.find("dropped.bin").is_none());
+    assert!(download.object_by_path(Path::new("streamed.bin")).is_ok());
+
+    // Folders, moves and deletions
+    let backup_id = download.create_subfolder("Backup".as_ref()).unwrap();
+    let (backup_handle, backup) = phone.find("Backup").unwrap();
+    assert_eq!(backup.format, ObjectFormatCode::Association);
+    download.object_by_path(Path::new("exact.bin")).unwrap().move_to(&backup_id).unwrap();
+    assert_eq!(phone.find("exact.bin").unwrap().1.parent, backup_handle);
+
+    let backup = download.object_by_path(Path::new("Backup")).unwrap();
+    assert!(backup.delete(false).is_err());
+    backup.delete(true).unwrap();
+    assert!(phone.find("Backup").is_none());
+    assert!(phone.find("exact.bin").is_none());
+
+    let err = content.object_by_id("o999".parse().unwrap()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    assert_eq!(err.raw_code(), Some(RawErrorCode::Response(ResponseCode::InvalidObjectHandle)));
+}
+
+fn write_attributes(dir: &Path, attributes: &[(&str, &str)]) {
+    fs::create_dir_all(dir).unwrap();
+    for (name, value) in attributes {
+        fs::write(dir.join(name), format!("{}\n", value)).unwrap();
+    }
+}
+
+fn write_endpoint(interface_dir: &Path, address: &str, ty: &str, max_packet_size: &str) {
+    write_attributes(&interface_dir.join(format!("ep_{}", address)), &[
+        ("bEndpointAddress", address),
+        ("type", ty),
+        ("wMaxPacketSize", max_packet_size),
+    ]);
+}
+
+#[test]
+fn sysfs_discovery() {
+    let sysfs_root = std::env::temp_dir().join(format!("winmtp-sysfs-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&sysfs_root);
+
+    // An Android phone
+    let phone = sysfs_root.join("1-1");
+    write_attributes(&phone, &[
+        ("busnum", "1"), ("devnum", "5"), ("idVendor", "18d1"), ("idProduct", "4ee1"),
+        ("manufacturer", "Google"), ("product", "Pixel 7"), ("serial", "ABC123"),
+    ]);
+    let interface = phone.join("1-1:1.0");
+    write_attributes(&interface, &[
+        ("bInterfaceClass", "ff"), ("bInterfaceSubClass", "ff"), ("bInterfaceProtocol", "00"),
+        ("bInterfaceNumber", "00"), ("interface", "MTP"),
+    ]);
+    write_endpoint(&interface, "81", "Bulk", "0200");
+    write_endpoint(&interface, "01", "Bulk", "0200");
+    write_endpoint(&interface, "82", "Interrupt", "001c");
+
+    // A camera, that uses the still image class
+    let camera = sysfs_root.join("2-3");
+    write_attributes(&camera, &[("busnum", "2"), ("devnum", "12"), ("idVendor", "04a9"), ("idProduct", "3218"), ("product", "Camera")]);
+    let interface = camera.join("2-3:1.0");
+    write_attributes(&interface, &[
+        ("bInterfaceClass", "06"), ("bInterfaceSubClass", "01"), ("bInterfaceProtocol", "01"), ("bInterfaceNumber", "00"),
+    ]);
+    write_endpoint(&interface, "81", "Bulk", "0040");
+    write_endpoint(&interface, "02", "Bulk", "0040");
+
+    // A keyboard, and a root hub
+    let keyboard = sysfs_root.join("1-2");
+    write_attributes(&keyboard, &[("busnum", "1"), ("devnum", "3"), ("idVendor", "046d"), ("idProduct", "c31c")]);
+    write_attributes(&keyboard.join("1-2:1.0"), &[
+        ("bInterfaceClass", "03"), ("bInterfaceSubClass", "01"), ("bInterfaceProtocol", "01"), ("bInterfaceNumber", "00"),
+    ]);
+    write_attributes(&sysfs_root.join("usb1"), &[("busnum", "1"), ("devnum", "1")]);
+
+    let provider = UsbProvider::with_roots(&sysfs_root, sysfs_root.join("no-devfs"));
+    let interfaces = provider.mtp_interfaces().unwrap();
+    assert_eq!(interfaces.len(), 2);
+    assert_eq!(interfaces[0].vendor_id, 0x18d1);
+    assert_eq!(interfaces[0].serial_number.as_deref(), Some("ABC123"));
+    assert_eq!((interfaces[0].bulk_in, interfaces[0].bulk_out, interfaces[0].interrupt_in), (0x81, 0x01, Some(0x82)));
+    assert_eq!(interfaces[0].max_packet_size, 512);
+    assert_eq!(interfaces[1].max_packet_size, 64);
+    assert_eq!(interfaces[1].interrupt_in, None);
+
+    let devices = provider.enumerate_devices().unwrap();
+    let names: Vec<&str> = devices.iter().map(|device| device.friendly_name()).collect();
+    assert_eq!(names, ["Google Pixel 7", "Camera"]);
+    assert_eq!(devices[0].device_id(), "usb:001:005");
+
+    // There is no device node to open
+    let app_identifiers = winmtp::make_current_app_identifiers!();
+    assert!(matches!(devices[0].open(&app_identifiers, true), Err(MtpError::Io(_))));
+
+    fs::remove_dir_all(&sysfs_root).unwrap();
+    assert!(UsbProvider::with_roots(&sysfs_root, "/dev/bus/usb").mtp_interfaces().unwrap().is_empty());
+}
+
+#[test]
+fn bulk_framing() {
+    // Containers that span several packets, with and without a terminating zero-length packet
+    let phone = EmulatedPhone::new();
+    let mut transport = BulkTransport::new(phone.clone());
+    for payload_len in [2000, 1012, 100_000] {
+        phone.0.borrow_mut().queue(Container::data(OperationCode::GetObject, 4, pattern(payload_len)));
+        phone.0.borrow_mut().queue(Container::response(ResponseCode::Ok, 4, &[]));
+
+        assert_eq!(transport.receive().unwrap().container_type, ContainerType::Data);
+        let mut payload = Vec::new();
+        DataReader(&mut transport).read_to_end(&mut payload).unwrap();
+        assert_eq!(payload, pattern(payload_len));
+        assert_eq!(transport.receive().unwrap().response_code(), ResponseCode::Ok);
+    }
+
+    // Data phases of unknown length end with a short packet, which is a zero-length one when the data fills whole packets
+    for payload_len in [2000, 1012, 100_000] {
+        let mut bytes = ContainerHeader::data(OperationCode::GetObject, 5, u64::MAX).encode().to_vec();
+        bytes.extend_from_slice(&pattern(payload_len));
+        let mut state = phone.0.borrow_mut();
+        state.packets.extend(bytes.chunks(MAX_PACKET_SIZE).map(|packet| packet.to_vec()));
+        if bytes.len().is_multiple_of(MAX_PACKET_SIZE) {
+            state.packets.push_back(Vec::new());
+        }
+        state.queue(Container::response(ResponseCode::Ok, 5, &[]));
+        drop(state);
+
+        assert_eq!(transport.receive().unwrap().container_type, ContainerType::Data);
+        let mut payload = Vec::new();
+        DataReader(&mut transport).read_to_end(&mut payload).unwrap();
+        assert_eq!(payload, pattern(payload_len));
+        assert_eq!(transport.receive().unwrap().response_code(), ResponseCode::Ok);
+    }
+
+    // Data phases are sent as they come, whatever the size of the pieces
+    let phone = EmulatedPhone::new();
+    let handle = phone.add(0, "a.bin", ObjectFormatCode::Undefined, &[]);
+    phone.0.borrow_mut().pending_object = Some(handle);
+    let mut transport = BulkTransport::new(phone.clone());
+    transport.send(&Container::command(OperationCode::SendObject, 6, &[])).unwrap();
+    transport.start_data(OperationCode::SendObject, 6, 20_468).unwrap();
+    for piece in pattern(20_468).chunks(3000) {
+        transport.send_data(piece).unwrap();
+    }
+    assert!(transport.send_data(b"extra").is_err());
+    transport.end_data().unwrap();
+    // 20,468 bytes of data make a 20,480-byte container
+    assert_eq!(phone.0.borrow().zero_length_packets_received, 1);
+    assert_eq!(phone.find("a.bin").unwrap().1.data, pattern(20_468));
+    assert_eq!(transport.receive().unwrap().response_code(), ResponseCode::Ok);
+
+    // Nothing left to read
+    assert!(matches!(transport.receive(), Err(MtpError::Io(_))));
+}
@@ -0,0 +1,79 @@
+//! Checks of the device watcher, over backends that report their changes, and over ones that must be polled
+
+use std::rc::Rc;
+use std::time::Duration;
+
+use winmtp::{DeviceChange, Provider};
+use winmtp::backend::ProviderBackend;
+use winmtp::backend::memory::{MemoryDevice, MemoryProvider};
+use winmtp::backend::ptpip::PtpIpProvider;
+use winmtp::device::BasicDevice;
+use winmtp::error::MtpError;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A backend that cannot report its changes
+struct PolledProvider(MemoryProvider);
+
+impl ProviderBackend for PolledProvider {
+    fn enumerate_devices(&self) -> Result<Vec<BasicDevice>, MtpError> {
+        self.0.enumerate_devices()
+    }
+}
+
+fn connected_id(change: Option<DeviceChange>) -> String {
+    match change {
+        Some(DeviceChange::Connected(device)) => device.device_id(),
+        other => panic!("Unexpected change {:?}", other),
+    }
+}
+
+#[test]
+fn watch_reported_changes() {
+    let memory_provider = Rc::new(MemoryProvider::new());
+    memory_provider.add_device(MemoryDevice::new("Phone"));
+    let ptpip_provider = Rc::new(PtpIpProvider::new());
+    let mut provider = Provider::empty();
+    provider.add_backend(Rc::clone(&memory_provider) as _);
+    provider.add_backend(Rc::clone(&ptpip_provider) as _);
+
+    let mut watcher = provider.watch_devices().unwrap();
+    // The initial snapshot
+    assert_eq!(connected_id(watcher.recv_timeout(TIMEOUT).unwrap()), "memory:Phone");
+    assert!(watcher.recv_timeout(Duration::ZERO).unwrap().is_none());
+
+    memory_provider.add_device(MemoryDevice::new("Camera"));
+    let camera = match watcher.recv_timeout(TIMEOUT).unwrap() {
+        Some(DeviceChange::Connected(device)) => device,
+        other => panic!("Unexpected change {:?}", other),
+    };
+    assert_eq!(camera.friendly_name(), "Camera");
+    assert!(camera.open(&winmtp::make_current_app_identifiers!(), true).is_ok());
+
+    ptpip_provider.add_device("192.0.2.1:15740");
+    assert_eq!(connected_id(watcher.recv_timeout(TIMEOUT).unwrap()), "ptpip:192.0.2.1:15740");
+
+    memory_provider.remove_device("memory:Phone");
+    assert!(matches!(watcher.next(), Some(Ok(DeviceChange::Disconnected(device_id))) if device_id == "memory:Phone"));
+    assert!(watcher.recv_timeout(Duration::from_millis(100)).unwrap().is_none());
+
+    // No more notifications once the watcher is dropped
+    drop(watcher);
+    memory_provider.add_device(MemoryDevice::new("Tablet"));
+}
+
+#[test]
+fn watch_polled_changes() {
+    let polled_provider = Rc::new(PolledProvider(MemoryProvider::new()));
+    let mut provider = Provider::empty();
+    provider.add_backend(Rc::clone(&polled_provider) as _);
+
+    let mut watcher = provider.watch_devices().unwrap().with_poll_interval(Duration::from_millis(20));
+    assert!(watcher.recv_timeout(Duration::from_millis(100)).unwrap().is_none());
+
+    polled_provider.0.add_device(MemoryDevice::new("Phone"));
+    assert_eq!(connected_id(watcher.recv_timeout(TIMEOUT).unwrap()), "memory:Phone");
+
+    polled_provider.0.remove_device("memory:Phone");
+    assert!(matches!(watcher.recv_timeout(TIMEOUT).unwrap(), Some(DeviceChange::Disconnected(device_id)) if device_id == "memory:Phone"));
+}